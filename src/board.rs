@@ -0,0 +1,1933 @@
+//! Boards, their layouts, the camera and the lifecycle of a run.
+
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::{OrthographicProjection, ScalingMode, Viewport};
+use bevy::sprite::Anchor;
+use bevy::window::PrimaryWindow;
+use bevy_rapier2d::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::bot::Bot;
+use crate::damage::DAMAGE_IMPULSE_THRESHOLD;
+use crate::effects::{CameraShake, IMPACT_SHAKE_IMPULSE};
+use crate::hazards::{HazardBanner, HazardScheduler, HazardTicker, HAZARD_BANNER_FONT_SIZE};
+use crate::input::{update_action_state, Action, ActionState, InputMap};
+use crate::modes::{Drill, DrillHud, DrillTemplate, GameMode, JunkRecycler};
+use crate::online::OnlineSession;
+use crate::physics::{
+    block_death_detection, PhysicsTuning, Stability, StabilityGauge, Stamina, StaminaBar,
+    TrajectoryDot, STABILITY_GAUGE_HEIGHT, STABILITY_GAUGE_WIDTH, STAMINA_BAR_HEIGHT,
+    TRAJECTORY_DOTS, TRAJECTORY_DOT_SIZE,
+};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{
+    BoardStats, CascadeChain, Finale, HealthBar, HealthEdge, HealthWarning, HighlightBuffer,
+    PaceIndicator, RestartRequested, ScoreHud, FINALE_ZOOM, HEALTH_BAR_HEIGHT, HEALTH_EDGE_WIDTH,
+};
+use crate::settings::Settings;
+use crate::tetromino::{
+    spawn_tetromino, ActivePieces, Block, FuseBar, MirrorModifier, PieceSet, TetrominoKind,
+    FUSE_BAR_HEIGHT,
+};
+use crate::ui::{
+    spawn_toast, Fonts, ResponsiveAnchor, TelemetryHud, WorldHudText, WORLD_TEXT_FONT_SIZE,
+    WORLD_TEXT_SCALE,
+};
+use crate::versus::GarbageQueue;
+use crate::AppState;
+
+/// Boards and their camera, and starting a new run when one is over.
+pub struct BoardPlugin;
+
+impl Plugin for BoardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RunStarted>()
+            .insert_resource(FixedSeed::from_args())
+            .add_startup_system(setup_game)
+            .add_system(
+                game_over_state
+                    .after(block_death_detection)
+                    .run_if(in_state(AppState::Playing).or_else(in_state(AppState::GameOver))),
+            )
+            .add_system(attach_active_events)
+            .add_system(toggle_side_walls)
+            .add_system(apply_side_walls)
+            .add_system(cycle_board_layout)
+            .add_system(highlight_near_complete_rows)
+            .add_system(relayout_health_bars)
+            .add_system(camera_controller.after(swap_inset))
+            .add_system(cycle_camera_mode)
+            .insert_resource(PictureInPicture::default())
+            .add_system(swap_inset.after(update_action_state))
+            .add_system(inset_camera.after(swap_inset));
+    }
+}
+
+// In terms of block size:
+pub(crate) const FLOOR_BLOCK_HEIGHT: f32 = 2.0;
+pub(crate) const SIDE_WALL_WIDTH: f32 = 0.5;
+
+pub(crate) const CAMERA_VERTICAL_MARGIN: f32 = 7.0;
+pub(crate) const CAMERA_HORIZONTAL_MARGIN: f32 = 2.0;
+// Fraction of the active piece's offset from the board center that the follow camera moves
+pub(crate) const CAMERA_FOLLOW_STRENGTH: f32 = 0.15;
+// Extra zoom-out of the auto-zoom camera with a full board, starting from a half full board
+pub(crate) const CAMERA_AUTO_ZOOM: f32 = 0.3;
+pub(crate) const CAMERA_EASING: f32 = 3.0;
+
+// Height of the picture-in-picture inset, as a fraction of the window height, and its distance
+// from the corner of the window in logical pixels
+pub(crate) const INSET_HEIGHT: f32 = 0.35;
+pub(crate) const INSET_MARGIN: f32 = 16.0;
+pub(crate) const INSET_BACKGROUND: Color = Color::rgb(0.08, 0.08, 0.1);
+
+pub(crate) const GAME_OVER_TIME_SCALE: f32 = 0.5;
+// Space between the boards of Versus mode, leaving room for their bars
+pub(crate) const VERSUS_BOARD_GAP: f32 = 6.0;
+// In scaled seconds:
+pub(crate) const GAME_OVER_DURATION: f32 = 1.5;
+
+// Area checked for blocks in the way before a piece spawns, see `Board::spawn_area`
+pub(crate) const SPAWN_AREA_WIDTH: f32 = 4.0;
+// Blocks only just touching the spawn area, such as a stack reaching right up to its bottom,
+// aren't in the way
+pub(crate) const SPAWN_AREA_MARGIN: f32 = 0.1;
+
+// A row is full when its blocks cover the board width, give or take this much area
+pub(crate) const ROW_COVERAGE_TOLERANCE: f32 = 0.5;
+// Grid of points each row is sampled on for `RowClearing::Coverage`: per unit of width, and
+// across the height of the row
+pub(crate) const COVERAGE_SAMPLES_PER_UNIT: usize = 8;
+pub(crate) const COVERAGE_SAMPLES_PER_ROW: usize = 4;
+pub(crate) const LANE_SEPARATOR_WIDTH: f32 = 0.04;
+
+pub(crate) const ROW_HIGHLIGHT_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.0);
+pub(crate) const ROW_HIGHLIGHT_MIN_ALPHA: f32 = 0.06;
+pub(crate) const ROW_HIGHLIGHT_MAX_ALPHA: f32 = 0.18;
+pub(crate) const ROW_HIGHLIGHT_PULSE_PERIOD: f32 = 1.2;
+
+/// How the camera moves during play.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum CameraMode {
+    #[default]
+    Fixed,
+    /// Slightly follow the active piece
+    Follow,
+    /// Zoom out as the stack grows taller
+    AutoZoom,
+}
+
+impl CameraMode {
+    pub(crate) const ALL: [Self; 3] = [Self::Fixed, Self::Follow, Self::AutoZoom];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Fixed => "fixed",
+            Self::Follow => "follow",
+            Self::AutoZoom => "auto-zoom",
+        }
+    }
+
+    pub(crate) fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// How a row is found to be full.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum RowClearing {
+    /// By the area of the block cells centered in it, see `Block::cells`
+    #[default]
+    Cells,
+    /// By the share of it covered by resting blocks, however they are tilted
+    Coverage { threshold: f32 },
+}
+
+impl RowClearing {
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Cells,
+        Self::Coverage { threshold: 0.8 },
+        Self::Coverage { threshold: 0.85 },
+        Self::Coverage { threshold: 0.9 },
+        Self::Coverage { threshold: 0.95 },
+    ];
+
+    pub(crate) fn name(&self) -> String {
+        match self {
+            Self::Cells => "Cells".to_string(),
+            Self::Coverage { threshold } => format!("Coverage {:.0}%", threshold * 100.0),
+        }
+    }
+
+    pub(crate) fn cycle(&self, direction: i32) -> Self {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap_or(0);
+        Self::ALL[(index as i32 + direction).rem_euclid(Self::ALL.len() as i32) as usize]
+    }
+}
+
+/// Choices of the share of a row to cover for it to clear as a partial row.
+pub(crate) const PARTIAL_ROW_THRESHOLDS: [f32; 3] = [0.85, 0.9, 0.95];
+
+/// Where new pieces enter the board, as defined by the game mode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum SpawnEntry {
+    /// Dropped in from the middle of the top
+    TopCenter,
+    /// Dropped in from the top left and top right corners by turns
+    AlternatingCorners,
+    /// Slid in along the top from the left wall, at this horizontal speed
+    SideChute { speed: f32 },
+}
+
+/// Sent whenever a new run begins on a board, including restarts.
+pub(crate) struct RunStarted {
+    pub(crate) board: Entity,
+}
+
+/// Seed given with `--seed <number>`, so that runs can be reproduced.
+#[derive(Resource, Clone, Copy, Default)]
+pub(crate) struct FixedSeed(pub(crate) Option<u64>);
+
+impl FixedSeed {
+    pub(crate) fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let fixed_seed = args
+            .iter()
+            .position(|arg| arg == "--seed")
+            .and_then(|index| args.get(index + 1))
+            .and_then(|seed| match seed.parse() {
+                Ok(seed) => Some(seed),
+                Err(_) => {
+                    warn!("ignoring invalid seed {seed}");
+                    None
+                }
+            });
+
+        Self(fixed_seed)
+    }
+}
+
+/// Source of everything random in a run on a board: the piece sequence, drill rows, hazards,
+/// junk and garbage. Each board has its own, so that the seed of a run reproduces it.
+///
+/// Every run is reseeded, from the `FixedSeed` if given, or else from a fresh random seed.
+#[derive(Component)]
+pub(crate) struct GameRng {
+    pub(crate) seed: u64,
+    pub(crate) fixed_seed: Option<u64>,
+    rng: StdRng,
+}
+
+impl GameRng {
+    pub(crate) fn new(fixed_seed: Option<u64>) -> Self {
+        let seed = fixed_seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+        Self {
+            seed,
+            fixed_seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub(crate) fn start_run(&mut self) {
+        *self = Self::new(self.fixed_seed);
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Lane and row layout of a board, defined in `assets/boards/`.
+#[derive(Deserialize)]
+pub(crate) struct BoardLayout {
+    pub(crate) name: String,
+    /// Width of each lane, in blocks
+    pub(crate) lane_widths: Vec<f32>,
+    pub(crate) n_rows: usize,
+    /// Separate floors, with gaps between them that blocks drop out of.
+    /// One floor under all lanes when empty.
+    #[serde(default)]
+    pub(crate) floors: Vec<FloorSegment>,
+}
+
+/// A floor under a run of lanes, possibly raised. Its rows are cleared on their own.
+#[derive(Clone, Deserialize)]
+pub(crate) struct FloorSegment {
+    /// The first lane, and the lane after the last
+    pub(crate) lanes: (usize, usize),
+    /// Rows above the bottom of the board
+    #[serde(default)]
+    pub(crate) height: usize,
+}
+
+pub(crate) const BOARD_LAYOUTS: [&str; 3] = [
+    include_str!("../assets/boards/standard.ron"),
+    include_str!("../assets/boards/wide-center.ron"),
+    include_str!("../assets/boards/islands.ron"),
+];
+
+impl BoardLayout {
+    pub(crate) fn all() -> Vec<Self> {
+        BOARD_LAYOUTS
+            .iter()
+            .map(|source| ron::from_str(source).expect("invalid board layout"))
+            .collect()
+    }
+
+    /// The layout with the given name, or the first (standard) one.
+    pub(crate) fn named(name: &str) -> Self {
+        let mut layouts = Self::all();
+        let index = layouts
+            .iter()
+            .position(|layout| layout.name == name)
+            .unwrap_or(0);
+        layouts.swap_remove(index)
+    }
+}
+
+/// A playfield. Blocks, HUD and per-run state refer to their board entity,
+/// so that several boards can be played side by side.
+#[derive(Component)]
+pub(crate) struct Board {
+    /// Name of the board layout
+    pub(crate) layout: String,
+    /// Width of each lane, in blocks
+    pub(crate) lane_widths: Vec<f32>,
+    pub(crate) n_rows: usize,
+    /// At least one, left to right
+    pub(crate) floors: Vec<FloorSegment>,
+    /// World position of the center of the board
+    pub(crate) center: Vec2,
+    pub(crate) mode: GameMode,
+    /// Pieces that haven't settled when their fuse runs out are settled by force
+    pub(crate) fuse: bool,
+    pub(crate) mirror: MirrorModifier,
+    /// Pushes drain a stamina meter, and weaken as it runs low
+    pub(crate) stamina: bool,
+    /// Walls keep blocks from being pushed off the sides
+    pub(crate) side_walls: bool,
+    pub(crate) physics: PhysicsTuning,
+    pub(crate) row_clearing: RowClearing,
+    /// Cleared rows cut through tilted blocks, leaving the parts outside the rows in play
+    pub(crate) slicing: bool,
+    /// Hard knocks crack blocks and finally shatter them, see `damage`
+    pub(crate) block_damage: bool,
+    /// The joints of a piece snap when slammed hard enough, see `break_joints`
+    pub(crate) breakable_joints: bool,
+    /// Share of a row that resting blocks must cover for it to clear as a partial row, when
+    /// partial rows are on, see `PARTIAL_ROW_POINTS_FACTOR`
+    pub(crate) partial_rows: Option<f32>,
+    /// Kind of piece practiced in a nemesis practice session, dealt more often than the others
+    pub(crate) practice: Option<TetrominoKind>,
+    /// Index of the player on this board, when set up for Versus mode at launch
+    pub(crate) player: Option<usize>,
+    /// The opponent's board in online Versus, simulated from the opponent's input and runs,
+    /// see `online`
+    pub(crate) remote: bool,
+    /// Played by the bot, see `bot`
+    pub(crate) bot: bool,
+    pub(crate) pieces: PieceSet,
+    /// The piece definitions as the run started, so that reloading them doesn't change a run
+    /// midway
+    pub(crate) piece_definitions: PieceDefinitions,
+}
+
+impl Board {
+    /// Events the blocks of this board report: contact forces for impact shakes and block
+    /// damage, and collisions for the contact graph of Sticky mode.
+    pub(crate) fn active_events(&self) -> ActiveEvents {
+        let mut events = ActiveEvents::CONTACT_FORCE_EVENTS;
+        if self.mode == GameMode::Sticky {
+            events |= ActiveEvents::COLLISION_EVENTS;
+        }
+        events
+    }
+
+    /// Contact impulses the blocks of this board report, the least that shakes the camera or
+    /// damages a block.
+    pub(crate) fn contact_impulse_threshold(&self) -> f32 {
+        if self.block_damage {
+            DAMAGE_IMPULSE_THRESHOLD.min(IMPACT_SHAKE_IMPULSE)
+        } else {
+            IMPACT_SHAKE_IMPULSE
+        }
+    }
+
+    pub(crate) fn new(layout: BoardLayout, mode: GameMode, center: Vec2) -> Self {
+        let mut floors = layout.floors;
+        if floors.is_empty() {
+            floors.push(FloorSegment {
+                lanes: (0, layout.lane_widths.len()),
+                height: 0,
+            });
+        }
+        floors.sort_by_key(|segment| segment.lanes.0);
+
+        Self {
+            layout: layout.name,
+            lane_widths: layout.lane_widths,
+            n_rows: layout.n_rows,
+            floors,
+            center,
+            mode,
+            fuse: false,
+            mirror: MirrorModifier::Off,
+            stamina: false,
+            side_walls: false,
+            physics: PhysicsTuning::default(),
+            row_clearing: RowClearing::default(),
+            slicing: false,
+            block_damage: false,
+            breakable_joints: false,
+            partial_rows: None,
+            practice: None,
+            player: None,
+            remote: false,
+            bot: false,
+            pieces: PieceSet::All,
+            piece_definitions: PieceDefinitions::built_in(),
+        }
+    }
+
+    pub(crate) fn top_y(&self) -> f32 {
+        self.center.y + (self.n_rows as f32) * 0.5
+    }
+
+    pub(crate) fn floor_y(&self) -> f32 {
+        self.center.y - (self.n_rows as f32) * 0.5
+    }
+
+    /// Top of the lowest floor, below which blocks hang off the board.
+    pub(crate) fn floor_edge_y(&self) -> f32 {
+        self.floor_y() - 0.5
+    }
+
+    pub(crate) fn n_lanes(&self) -> usize {
+        self.lane_widths.len()
+    }
+
+    pub(crate) fn width(&self) -> f32 {
+        self.lane_widths.iter().sum()
+    }
+
+    pub(crate) fn is_uniform(&self) -> bool {
+        self.lane_widths.iter().all(|width| *width == 1.0)
+    }
+
+    pub(crate) fn left_wall_x(&self) -> f32 {
+        self.center.x - self.width() * 0.5
+    }
+
+    /// Left edge of a lane.
+    pub(crate) fn lane_left_x(&self, lane: usize) -> f32 {
+        self.left_wall_x() + self.lane_widths[..lane].iter().sum::<f32>()
+    }
+
+    pub(crate) fn lane_at(&self, x: f32) -> Option<usize> {
+        (0..self.n_lanes())
+            .find(|lane| x >= self.lane_left_x(*lane) && x < self.lane_left_x(lane + 1))
+    }
+
+    /// Number of pieces live at the same time.
+    pub(crate) fn n_pieces(&self) -> usize {
+        if self.mode == GameMode::Twin {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Left edge of the block column that new pieces with this index are spawned from.
+    /// A single piece spawns in the middle, twin pieces each in their own half.
+    pub(crate) fn spawn_x(&self, piece: usize) -> f32 {
+        let lane = self.n_lanes() * (2 * piece + 1) / (2 * self.n_pieces());
+        self.lane_left_x(lane.max(1) - 1)
+    }
+
+    /// Left edge of the block column that new pieces with this index enter from, and their
+    /// velocity on entry, by the spawn entry of the mode. Each piece enters within its own
+    /// part of the board. `spawned` counts the pieces before, for entries taking turns.
+    pub(crate) fn spawn_entry(
+        &self,
+        piece: usize,
+        spawned: usize,
+        piece_width: f32,
+    ) -> (f32, Vec2) {
+        let first_lane = self.n_lanes() * piece / self.n_pieces();
+        let end_lane = self.n_lanes() * (piece + 1) / self.n_pieces();
+        let left_x = self.lane_left_x(first_lane);
+
+        match self.mode.spawn_entry() {
+            SpawnEntry::TopCenter => (self.spawn_x(piece), Vec2::ZERO),
+            SpawnEntry::AlternatingCorners if spawned.is_multiple_of(2) => (left_x, Vec2::ZERO),
+            SpawnEntry::AlternatingCorners => (
+                (self.lane_left_x(end_lane) - piece_width).max(left_x),
+                Vec2::ZERO,
+            ),
+            SpawnEntry::SideChute { speed } => (left_x, Vec2::new(speed, 0.0)),
+        }
+    }
+
+    /// Where new pieces with this index appear: the top rows under their entry, as deep as the
+    /// tallest piece that can be dealt reaches and as wide as the widest tetromino. `spawned` as
+    /// for `spawn_entry`.
+    pub(crate) fn spawn_area(&self, piece: usize, spawned: usize) -> Rect {
+        let (left_x, _) = self.spawn_entry(piece, spawned, SPAWN_AREA_WIDTH);
+        let right_x = (left_x + SPAWN_AREA_WIDTH).min(self.left_wall_x() + self.width());
+
+        // Rows of a piece go downwards from the top row of the board at 0, see `spawn_tetromino`
+        let definitions = &self.piece_definitions;
+        let (bottom_row, top_row) = self
+            .pieces
+            .kinds(definitions)
+            .into_iter()
+            .chain(self.practice)
+            .flat_map(|kind| kind.layout(definitions).coords)
+            .fold((0, 0), |(bottom, top), (_, row)| {
+                (bottom.min(row), top.max(row))
+            });
+
+        Rect::new(
+            left_x,
+            self.top_y() + (bottom_row - 1) as f32,
+            right_x,
+            self.top_y() + top_row as f32,
+        )
+    }
+
+    /// Whether a row that isn't full is covered enough to clear as a partial row, for fewer
+    /// points. The area is from `covered_row_areas`.
+    pub(crate) fn row_is_partial(&self, row: &Rect, covered_area: f32) -> bool {
+        matches!(self.partial_rows, Some(threshold) if covered_area >= row.width() * threshold)
+    }
+
+    /// Whether blocks covering this much area fill a row, one of `rows()`. The area is of the
+    /// cells in the row, or from `covered_row_areas` when clearing by coverage.
+    pub(crate) fn row_is_full(&self, row: &Rect, covered_area: f32) -> bool {
+        match self.row_clearing {
+            RowClearing::Cells => covered_area >= row.width() - ROW_COVERAGE_TOLERANCE,
+            RowClearing::Coverage { threshold } => covered_area >= row.width() * threshold,
+        }
+    }
+
+    /// The area of each of the rows covered by the blocks, given by their center, rotation and
+    /// size. The rows are rasterized on a grid of samples, and each block only tests the samples
+    /// within its reach.
+    pub(crate) fn covered_row_areas(
+        &self,
+        rows: &[Rect],
+        blocks: &[(Vec2, Quat, f32)],
+    ) -> Vec<f32> {
+        rows.iter()
+            .map(|row| {
+                let n_columns =
+                    ((row.width() * COVERAGE_SAMPLES_PER_UNIT as f32).round() as usize).max(1);
+                let n_samples = n_columns * COVERAGE_SAMPLES_PER_ROW;
+                let sample_size =
+                    row.size() / Vec2::new(n_columns as f32, COVERAGE_SAMPLES_PER_ROW as f32);
+                let mut covered = vec![false; n_samples];
+
+                for (center, rotation, size) in blocks {
+                    let reach = size * std::f32::consts::SQRT_2 * 0.5;
+                    if center.x + reach < row.min.x
+                        || center.x - reach > row.max.x
+                        || center.y + reach < row.min.y
+                        || center.y - reach > row.max.y
+                    {
+                        continue;
+                    }
+
+                    let first_column =
+                        ((center.x - reach - row.min.x) / sample_size.x).max(0.0) as usize;
+                    let last_column = (((center.x + reach - row.min.x) / sample_size.x).ceil()
+                        as usize)
+                        .min(n_columns);
+                    let inverse = rotation.inverse();
+
+                    for column in first_column..last_column {
+                        for sample_row in 0..COVERAGE_SAMPLES_PER_ROW {
+                            let sample = row.min
+                                + (Vec2::new(column as f32, sample_row as f32) + 0.5) * sample_size;
+                            let local = inverse * (sample - *center).extend(0.0);
+
+                            if local.x.abs() <= size * 0.5 && local.y.abs() <= size * 0.5 {
+                                covered[sample_row * n_columns + column] = true;
+                            }
+                        }
+                    }
+                }
+
+                let n_covered = covered.iter().filter(|covered| **covered).count();
+                row.width() * n_covered as f32 / n_samples as f32
+            })
+            .collect()
+    }
+
+    /// `floor_y` of a floor segment, raised by its height.
+    pub(crate) fn segment_floor_y(&self, segment: &FloorSegment) -> f32 {
+        self.floor_y() + segment.height as f32
+    }
+
+    pub(crate) fn segment_n_rows(&self, segment: &FloorSegment) -> usize {
+        self.n_rows.saturating_sub(segment.height)
+    }
+
+    /// Floor level under `x`, `None` over a gap between floors or outside the board.
+    pub(crate) fn floor_y_at(&self, x: f32) -> Option<f32> {
+        let lane = self.lane_at(x)?;
+        self.floors
+            .iter()
+            .find(|segment| (segment.lanes.0..segment.lanes.1).contains(&lane))
+            .map(|segment| self.segment_floor_y(segment))
+    }
+
+    /// Every row of every floor segment, segment by segment and bottom to top,
+    /// as indexed by `row_at`.
+    pub(crate) fn rows(&self) -> Vec<Rect> {
+        self.floors
+            .iter()
+            .flat_map(|segment| {
+                let left_x = self.lane_left_x(segment.lanes.0);
+                let right_x = self.lane_left_x(segment.lanes.1);
+                let floor_y = self.segment_floor_y(segment);
+
+                (0..self.segment_n_rows(segment)).map(move |row| {
+                    let bottom_y = floor_y + row as f32 - 0.5;
+                    Rect::new(left_x, bottom_y, right_x, bottom_y + 1.0)
+                })
+            })
+            .collect()
+    }
+
+    /// Center x of every block slot, left to right.
+    pub(crate) fn slot_centers(&self) -> Vec<f32> {
+        (0..self.n_lanes())
+            .flat_map(|lane| {
+                let left_x = self.lane_left_x(lane);
+                let n_slots = self.lane_widths[lane].floor().max(1.0) as usize;
+                (0..n_slots).map(move |slot| left_x + slot as f32 + 0.5)
+            })
+            .collect()
+    }
+
+    /// Signed distance of a block center from the nearest block slot, if inside the board.
+    /// Wide lanes have several block slots, counted from the lane's left edge.
+    pub(crate) fn slot_offset(&self, x: f32) -> Option<f32> {
+        self.lane_at(x).map(|lane| {
+            let lane_position = x - self.lane_left_x(lane) - 0.5;
+            lane_position - lane_position.round()
+        })
+    }
+
+    /// Precision of a resting block: how close it is to a block slot in its lane and to an upright rotation.
+    pub(crate) fn placement_precision(&self, transform: &Transform) -> f32 {
+        let lane_offset = self
+            .slot_offset(transform.translation.x)
+            .map_or(0.5, f32::abs);
+
+        let (_, _, angle) = transform.rotation.to_euler(EulerRot::XYZ);
+        let angle_offset = (angle - (angle / FRAC_PI_2).round() * FRAC_PI_2).abs();
+
+        (1.0 - 0.5 * (lane_offset / 0.5 + angle_offset / FRAC_PI_4)).clamp(0.0, 1.0)
+    }
+
+    pub(crate) fn health_bar_width(&self) -> f32 {
+        self.width() - 2.0
+    }
+
+    pub(crate) fn health_bar_y(&self) -> f32 {
+        self.floor_y() - (FLOOR_BLOCK_HEIGHT / 2.0)
+    }
+
+    /// Left end of the health bar, relative to the board center.
+    pub(crate) fn health_bar_offset(&self) -> Vec2 {
+        Vec2::new(self.left_wall_x() + 1.0, self.health_bar_y()) - self.center
+    }
+
+    /// Left end of the stamina meter, just below the health bar, relative to the board center.
+    pub(crate) fn stamina_bar_offset(&self) -> Vec2 {
+        self.health_bar_offset()
+            - Vec2::new(0.0, (HEALTH_BAR_HEIGHT + STAMINA_BAR_HEIGHT) * 0.5 + 0.1)
+    }
+
+    /// Bottom of the stability gauge, right of the board, relative to the board center.
+    pub(crate) fn stability_gauge_offset(&self) -> Vec2 {
+        Vec2::new(self.left_wall_x() + self.width() + 0.5, self.floor_y()) - self.center
+    }
+
+    /// The row (index into `rows()`) a block cell centered here belongs to,
+    /// if it is above a floor segment and inside the board.
+    pub(crate) fn row_at(&self, cell_center: Vec2) -> Option<usize> {
+        let lane = self.lane_at(cell_center.x)?;
+        let mut first_row = 0;
+
+        for segment in &self.floors {
+            let n_rows = self.segment_n_rows(segment);
+
+            if (segment.lanes.0..segment.lanes.1).contains(&lane) {
+                let floor_distance = cell_center.y + 0.5 - self.segment_floor_y(segment);
+
+                // The center of a block on the floor is 0.5 above the floor, so .floor() the number ;)
+                let row = floor_distance.floor() as i32;
+
+                return (row >= 0 && row < n_rows as i32).then(|| first_row + row as usize);
+            }
+
+            first_row += n_rows;
+        }
+
+        None
+    }
+}
+
+/// Smoothed state of the camera, moved according to `Settings::camera_mode`.
+#[derive(Component)]
+pub(crate) struct CameraController {
+    pub(crate) offset: Vec2,
+    pub(crate) zoom: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
+}
+
+/// A board shown small in the corner of the window, picture-in-picture, so that a player can keep
+/// an eye on it while the main camera shows the other boards. Swapped, it is the other way around.
+#[derive(Resource, Default)]
+pub(crate) struct PictureInPicture {
+    /// The board in the inset, `None` to show all boards in the main camera
+    pub(crate) inset: Option<Entity>,
+    pub(crate) swapped: bool,
+}
+
+impl PictureInPicture {
+    /// Whether the main camera shows the board, rather than the inset.
+    pub(crate) fn is_large(&self, board_entity: Entity) -> bool {
+        match self.inset {
+            Some(inset) => (board_entity == inset) == self.swapped,
+            None => true,
+        }
+    }
+}
+
+/// Camera of the picture-in-picture inset, see `inset_camera`.
+#[derive(Component)]
+pub(crate) struct InsetCamera;
+
+/// Fit all boards (with margins) inside a window of the given aspect ratio,
+/// so narrow windows don't crop the sides of the boards. The camera is centered on `center`.
+pub(crate) fn camera_scaling_mode<'a>(
+    boards: impl Iterator<Item = &'a Board>,
+    center: Vec2,
+    window_aspect: f32,
+) -> ScalingMode {
+    let (mut width, mut height) = (0.0_f32, 0.0_f32);
+
+    for board in boards {
+        let offset = (board.center - center).abs();
+        width = width.max(2.0 * offset.x + board.width());
+        height = height.max(2.0 * offset.y + board.n_rows as f32);
+    }
+
+    let height = height + CAMERA_VERTICAL_MARGIN;
+    let width = width + CAMERA_HORIZONTAL_MARGIN;
+
+    if window_aspect * height < width {
+        ScalingMode::FixedHorizontal(width)
+    } else {
+        ScalingMode::FixedVertical(height)
+    }
+}
+
+pub(crate) fn setup_game(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    fixed_seed: Res<FixedSeed>,
+    piece_definitions: Res<PieceDefinitions>,
+    online: Option<Res<OnlineSession>>,
+    fonts: Res<Fonts>,
+    mut picture_in_picture: ResMut<PictureInPicture>,
+) {
+    let far = 1000.0;
+
+    // The projection is fitted to the boards by `camera_controller`
+    commands
+        .spawn(Camera2dBundle {
+            projection: OrthographicProjection {
+                far,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert((CameraController::default(), CameraShake::default()));
+
+    // Drawn over the main camera, without the UI, which the main camera already draws.
+    // Off until there is a board to show in it, see `inset_camera`.
+    commands.spawn((
+        Camera2dBundle {
+            camera: Camera {
+                order: 1,
+                is_active: false,
+                ..Default::default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::Custom(INSET_BACKGROUND),
+            },
+            projection: OrthographicProjection {
+                far,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        UiCameraConfig { show_ui: false },
+        InsetCamera,
+    ));
+
+    // Versus mode is played on two boards side by side, one per player, as is online play
+    let players: &[Option<usize>] = if settings.mode == GameMode::Versus || online.is_some() {
+        &[Some(0), Some(1)]
+    } else {
+        &[None]
+    };
+
+    for player in players {
+        let mut board = Board {
+            fuse: settings.fuse_modifier,
+            mirror: settings.mirror_modifier,
+            stamina: settings.stamina_modifier,
+            row_clearing: settings.row_clearing,
+            slicing: settings.slicing,
+            block_damage: settings.block_damage,
+            breakable_joints: settings.breakable_joints,
+            partial_rows: settings.partial_rows.get(&settings.mode).copied(),
+            player: *player,
+            remote: online
+                .as_ref()
+                .is_some_and(|online| *player != Some(online.local_player)),
+            pieces: settings.piece_set(settings.mode),
+            piece_definitions: piece_definitions.clone(),
+            ..Board::new(
+                BoardLayout::named(&settings.board_layout),
+                settings.mode,
+                Vec2::ZERO,
+            )
+        };
+        if let Some(player) = player {
+            let offset = (board.width() + VERSUS_BOARD_GAP) * 0.5;
+            board.center.x = if *player == 0 { -offset } else { offset };
+        }
+
+        // Positioned at the board center, so that HUD attached as children follows the board
+        let board_entity = commands
+            .spawn(SpatialBundle::from_transform(Transform::from_translation(
+                board.center.extend(0.0),
+            )))
+            .id();
+
+        setup_board(&mut commands, &fonts, board_entity, &board);
+
+        // Online, the player's own board fills the window, with the opponent's in the inset
+        if board.remote {
+            picture_in_picture.inset = Some(board_entity);
+        }
+
+        // The first run starts from the main menu
+        commands.entity(board_entity).insert((
+            board,
+            ActivePieces::default(),
+            BoardStats::default(),
+            HazardScheduler::default(),
+            HighlightBuffer::default(),
+            CascadeChain::default(),
+            HealthWarning::default(),
+            JunkRecycler::default(),
+            GarbageQueue::default(),
+            Bot::default(),
+            GameRng::new(fixed_seed.0),
+            Stamina::default(),
+            Stability::default(),
+        ));
+    }
+}
+
+/// Tint behind a row that is one block away from being cleared.
+#[derive(Component)]
+pub(crate) struct RowHighlight {
+    pub(crate) board: Entity,
+    pub(crate) row: usize,
+}
+
+/// Wall on one side of a board, only solid and visible when the board has side walls.
+#[derive(Component)]
+pub(crate) struct SideWall {
+    pub(crate) board: Entity,
+}
+
+pub(crate) fn setup_board(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    board_entity: Entity,
+    board: &Board,
+) {
+    let floor_y = board.floor_y();
+
+    // Add floors, one per segment
+    for segment in &board.floors {
+        let left_x = board.lane_left_x(segment.lanes.0);
+        let width = board.lane_left_x(segment.lanes.1) - left_x;
+
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_xyz(
+                    left_x + width * 0.5,
+                    board.segment_floor_y(segment) - (FLOOR_BLOCK_HEIGHT - 0.5),
+                    0.0,
+                ),
+                sprite: Sprite {
+                    color: Color::rgb(0.5, 0.5, 0.5),
+                    custom_size: Some(Vec2::new(width, FLOOR_BLOCK_HEIGHT)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(RigidBody::Fixed)
+            .insert(Collider::cuboid(width * 0.5, FLOOR_BLOCK_HEIGHT * 0.5));
+    }
+
+    // Add side walls from the bottom of the floor to the top of the board,
+    // enabled per run by `apply_side_walls`
+    let wall_bottom = floor_y - (FLOOR_BLOCK_HEIGHT - 0.5) - FLOOR_BLOCK_HEIGHT * 0.5;
+    let wall_height = board.top_y() - wall_bottom;
+    for wall_x in [
+        board.left_wall_x() - SIDE_WALL_WIDTH * 0.5,
+        board.left_wall_x() + board.width() + SIDE_WALL_WIDTH * 0.5,
+    ] {
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_xyz(wall_x, wall_bottom + wall_height * 0.5, 0.0),
+                sprite: Sprite {
+                    color: Color::rgb(0.5, 0.5, 0.5),
+                    custom_size: Some(Vec2::new(SIDE_WALL_WIDTH, wall_height)),
+                    ..Default::default()
+                },
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            })
+            .insert(RigidBody::Fixed)
+            .insert(Collider::cuboid(SIDE_WALL_WIDTH * 0.5, wall_height * 0.5))
+            .insert(ColliderDisabled)
+            .insert(SideWall {
+                board: board_entity,
+            });
+    }
+
+    // Add health bar, attached to the board and growing to the right from its left end
+    let health_bar = commands
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(1.0, 1.0, 1.0),
+                custom_size: Some(Vec2::new(board.health_bar_width(), HEALTH_BAR_HEIGHT)),
+                anchor: Anchor::CenterLeft,
+                ..Default::default()
+            },
+            transform: Transform {
+                translation: board.health_bar_offset().extend(2.0),
+                rotation: Quat::IDENTITY,
+                scale: Vec3::new(0.0, 1.0, 1.0),
+            },
+            ..Default::default()
+        })
+        .insert(HealthBar {
+            board: board_entity,
+            value: 0.0,
+        })
+        .id();
+    commands.entity(board_entity).add_child(health_bar);
+
+    // Add stamina meter, below the health bar
+    let stamina_bar = commands
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb_u8(80, 200, 255),
+                custom_size: Some(Vec2::new(board.health_bar_width(), STAMINA_BAR_HEIGHT)),
+                anchor: Anchor::CenterLeft,
+                ..Default::default()
+            },
+            transform: Transform::from_translation(board.stamina_bar_offset().extend(2.0)),
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        })
+        .insert(StaminaBar {
+            board: board_entity,
+        })
+        .id();
+    commands.entity(board_entity).add_child(stamina_bar);
+
+    // Add stability gauge, right of the board, filling up from its floor
+    let stability_gauge = commands
+        .spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::GREEN,
+                custom_size: Some(Vec2::new(STABILITY_GAUGE_WIDTH, STABILITY_GAUGE_HEIGHT)),
+                anchor: Anchor::BottomCenter,
+                ..Default::default()
+            },
+            transform: Transform::from_translation(board.stability_gauge_offset().extend(2.0)),
+            ..Default::default()
+        })
+        .insert(StabilityGauge {
+            board: board_entity,
+        })
+        .id();
+    commands.entity(board_entity).add_child(stability_gauge);
+
+    // Add pace indicator, right of the health bar
+    let pace_x = board.left_wall_x() + board.width() - 0.5;
+
+    commands
+        .spawn(Text2dBundle {
+            text: fonts
+                .text("", WORLD_TEXT_FONT_SIZE, Color::WHITE)
+                .with_alignment(TextAlignment::Center),
+            transform: Transform::from_xyz(pace_x, board.health_bar_y(), 2.0)
+                .with_scale(Vec3::new(WORLD_TEXT_SCALE, WORLD_TEXT_SCALE, 1.0)),
+            ..Default::default()
+        })
+        .insert(PaceIndicator {
+            board: board_entity,
+        })
+        .insert(WorldHudText);
+
+    // Mark the lane edges when lanes differ in width
+    if !board.is_uniform() {
+        for lane in 1..board.n_lanes() {
+            commands.spawn(SpriteBundle {
+                transform: Transform::from_xyz(board.lane_left_x(lane), board.center.y, -1.0),
+                sprite: Sprite {
+                    color: Color::rgba(1.0, 1.0, 1.0, 0.08),
+                    custom_size: Some(Vec2::new(LANE_SEPARATOR_WIDTH, board.n_rows as f32)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+        }
+    }
+
+    // Add near-complete row highlights, behind the blocks
+    for (row, rect) in board.rows().into_iter().enumerate() {
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_translation(rect.center().extend(-1.0)),
+                sprite: Sprite {
+                    color: ROW_HIGHLIGHT_COLOR,
+                    custom_size: Some(rect.size()),
+                    ..Default::default()
+                },
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            })
+            .insert(RowHighlight {
+                board: board_entity,
+                row,
+            });
+    }
+
+    // Add telemetry readout
+    commands
+        .spawn(fonts.text_bundle("", 18.0, Color::WHITE).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(16.0),
+                left: Val::Px(16.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+        .insert(TelemetryHud {
+            board: board_entity,
+        });
+
+    // Add fuse bar, above the board
+    commands
+        .spawn(SpriteBundle {
+            transform: Transform::from_xyz(board.center.x, board.top_y() + 0.5, 2.0),
+            sprite: Sprite {
+                color: Color::rgb_u8(252, 157, 0),
+                custom_size: Some(Vec2::new(board.width(), FUSE_BAR_HEIGHT)),
+                ..Default::default()
+            },
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        })
+        .insert(FuseBar {
+            board: board_entity,
+        });
+
+    // Add trajectory dots, fading out along the path, for up to two pieces
+    for piece in 0..2 {
+        for step in 1..=TRAJECTORY_DOTS {
+            let alpha = 0.4 * (1.0 - step as f32 / (TRAJECTORY_DOTS + 1) as f32);
+            commands
+                .spawn(SpriteBundle {
+                    transform: Transform::from_xyz(0.0, 0.0, 1.0),
+                    sprite: Sprite {
+                        color: Color::rgba(1.0, 1.0, 1.0, alpha),
+                        custom_size: Some(Vec2::splat(TRAJECTORY_DOT_SIZE)),
+                        ..Default::default()
+                    },
+                    visibility: Visibility::Hidden,
+                    ..Default::default()
+                })
+                .insert(TrajectoryDot {
+                    board: board_entity,
+                    piece,
+                    step,
+                });
+        }
+    }
+
+    // Add hazard ticker, below the boards in narrow windows, out of the way of the score
+    let ticker_position = UiRect {
+        top: Val::Px(16.0),
+        right: Val::Px(16.0),
+        ..Default::default()
+    };
+    commands
+        .spawn(fonts.text_bundle("", 20.0, Color::WHITE).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: ticker_position,
+            ..Default::default()
+        }))
+        .insert(HazardTicker {
+            board: board_entity,
+        })
+        .insert(ResponsiveAnchor {
+            wide: ticker_position,
+            narrow: UiRect {
+                bottom: Val::Px(16.0),
+                right: Val::Px(16.0),
+                ..Default::default()
+            },
+        });
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::width(Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(20.0),
+                    ..Default::default()
+                },
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn(fonts.text_bundle("", HAZARD_BANNER_FONT_SIZE, Color::WHITE))
+                .insert(HazardBanner {
+                    board: board_entity,
+                });
+        });
+
+    commands
+        .spawn(fonts.text_bundle("", 20.0, Color::WHITE).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(16.0),
+                left: Val::Px(16.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+        .insert(DrillHud {
+            board: board_entity,
+        });
+
+    // The first player of Versus mode keeps score on the left
+    let score_position = if board.player == Some(0) {
+        UiRect {
+            top: Val::Px(48.0),
+            left: Val::Px(16.0),
+            ..Default::default()
+        }
+    } else {
+        UiRect {
+            top: Val::Px(48.0),
+            right: Val::Px(16.0),
+            ..Default::default()
+        }
+    };
+    commands
+        .spawn(fonts.text_bundle("", 24.0, Color::WHITE).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: score_position,
+            ..Default::default()
+        }))
+        .insert(ScoreHud {
+            board: board_entity,
+        });
+
+    let edge_width = Val::Px(HEALTH_EDGE_WIDTH);
+    let edges = [
+        (
+            Size::new(Val::Percent(100.0), edge_width),
+            UiRect {
+                top: Val::Px(0.0),
+                left: Val::Px(0.0),
+                ..Default::default()
+            },
+        ),
+        (
+            Size::new(Val::Percent(100.0), edge_width),
+            UiRect {
+                bottom: Val::Px(0.0),
+                left: Val::Px(0.0),
+                ..Default::default()
+            },
+        ),
+        (
+            Size::new(edge_width, Val::Percent(100.0)),
+            UiRect {
+                top: Val::Px(0.0),
+                left: Val::Px(0.0),
+                ..Default::default()
+            },
+        ),
+        (
+            Size::new(edge_width, Val::Percent(100.0)),
+            UiRect {
+                top: Val::Px(0.0),
+                right: Val::Px(0.0),
+                ..Default::default()
+            },
+        ),
+    ];
+
+    for (size, position) in edges {
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    size,
+                    position,
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                background_color: Color::NONE.into(),
+                ..Default::default()
+            })
+            .insert(HealthEdge {
+                board: board_entity,
+            });
+    }
+}
+
+/// Clear a board and start a new run on it, with the mode and modifiers chosen for the next run.
+/// Returns the event announcing the run.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn start_run(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &mut Board,
+    active_pieces: &mut ActivePieces,
+    stats: &mut BoardStats,
+    settings: &Settings,
+    piece_definitions: &PieceDefinitions,
+    rng: &mut GameRng,
+    board_blocks: impl Iterator<Item = Entity>,
+) -> RunStarted {
+    for entity in board_blocks {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    rng.start_run();
+    info!("starting run with seed {}", rng.seed);
+
+    *stats = BoardStats {
+        seed: rng.seed,
+        ..Default::default()
+    };
+    active_pieces.pieces.clear();
+    active_pieces.held = None;
+    active_pieces.unheld = None;
+    active_pieces.breather = false;
+    active_pieces.spawned = 0;
+    active_pieces.drawn.clear();
+    board.practice = settings.practice;
+    // Practice is on the plain rules. Versus mode needs the two boards set up at launch,
+    // so choosing it or another mode only takes effect on the next launch.
+    board.mode = if board.practice.is_some() {
+        GameMode::Classic
+    } else if board.player.is_some() {
+        GameMode::Versus
+    } else if settings.mode == GameMode::Versus {
+        GameMode::Classic
+    } else {
+        settings.mode
+    };
+    board.fuse = settings.fuse_modifier;
+    board.mirror = settings.mirror_modifier;
+    board.stamina = settings.stamina_modifier;
+    board.side_walls = settings.side_walls.contains(&board.mode);
+    board.physics = settings.physics;
+    board.row_clearing = settings.row_clearing;
+    board.slicing = settings.slicing;
+    board.block_damage = settings.block_damage;
+    board.breakable_joints = settings.breakable_joints;
+    board.partial_rows = settings.partial_rows.get(&board.mode).copied();
+    board.pieces = if board.practice.is_some() {
+        PieceSet::All
+    } else {
+        settings.piece_set(board.mode)
+    };
+    board.piece_definitions = piece_definitions.clone();
+    // The demo hands its boards to the bot after starting their runs
+    board.bot = board.player == Some(1) && !board.remote && settings.versus_bot;
+    commands
+        .entity(board_entity)
+        .remove::<(Finale, RestartRequested)>()
+        .insert((Stamina::default(), Stability::default()));
+
+    if board.mode == GameMode::Drill {
+        let template = DrillTemplate::random(rng);
+        template.spawn_rows(commands, board_entity, board, rng);
+        active_pieces.supply = Some(template.pieces.iter().copied().collect());
+        commands.entity(board_entity).insert(Drill {
+            template,
+            completed: None,
+        });
+    } else {
+        // The opponent's pieces online are the ones the opponent draws, see `online`
+        if !board.remote {
+            active_pieces.supply = None;
+        }
+        commands.entity(board_entity).remove::<Drill>();
+    }
+
+    for index in 0..board.n_pieces() {
+        if !active_pieces.has_next() {
+            break;
+        }
+        spawn_tetromino(
+            commands,
+            board_entity,
+            board,
+            active_pieces,
+            index,
+            stats,
+            rng,
+        );
+    }
+
+    RunStarted {
+        board: board_entity,
+    }
+}
+
+/// Keep boards at their center, and their health and stamina bars and stability gauge fitted to
+/// them, when boards change.
+#[allow(clippy::type_complexity)]
+pub(crate) fn relayout_health_bars(
+    mut board_query: Query<(&Board, &mut Transform), Changed<Board>>,
+    mut health_bar_query: Query<(&HealthBar, &mut Sprite, &mut Transform), Without<Board>>,
+    mut stamina_bar_query: Query<
+        (&StaminaBar, &mut Sprite, &mut Transform),
+        (Without<Board>, Without<HealthBar>),
+    >,
+    mut stability_gauge_query: Query<
+        (&StabilityGauge, &mut Transform),
+        (Without<Board>, Without<HealthBar>, Without<StaminaBar>),
+    >,
+) {
+    for (board, mut transform) in board_query.iter_mut() {
+        transform.translation = board.center.extend(0.0);
+    }
+
+    for (health_bar, mut sprite, mut transform) in health_bar_query.iter_mut() {
+        let Ok((board, _)) = board_query.get(health_bar.board) else {
+            continue;
+        };
+
+        sprite.custom_size = Some(Vec2::new(board.health_bar_width(), HEALTH_BAR_HEIGHT));
+        transform.translation = board.health_bar_offset().extend(2.0);
+    }
+
+    for (stamina_bar, mut sprite, mut transform) in stamina_bar_query.iter_mut() {
+        let Ok((board, _)) = board_query.get(stamina_bar.board) else {
+            continue;
+        };
+
+        sprite.custom_size = Some(Vec2::new(board.health_bar_width(), STAMINA_BAR_HEIGHT));
+        transform.translation = board.stamina_bar_offset().extend(2.0);
+    }
+
+    for (stability_gauge, mut transform) in stability_gauge_query.iter_mut() {
+        let Ok((board, _)) = board_query.get(stability_gauge.board) else {
+            continue;
+        };
+
+        transform.translation = board.stability_gauge_offset().extend(2.0);
+    }
+}
+
+/// Fit the camera to the boards and the window, then follow or zoom as the camera mode says.
+/// With a board in the picture-in-picture inset, only the boards shown large, see `inset_camera`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn camera_controller(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    picture_in_picture: Res<PictureInPicture>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    board_query: Query<(Entity, &Board, &ActivePieces)>,
+    finale_query: Query<(&Board, &Finale)>,
+    block_query: Query<(&Block, &Transform, &Sleeping), Without<CameraController>>,
+    mut camera_query: Query<(
+        &mut CameraController,
+        &mut OrthographicProjection,
+        &mut Transform,
+    )>,
+) {
+    let window_aspect = window_query
+        .get_single()
+        .ok()
+        .filter(|window| window.height() > 0.0)
+        .map_or(1.0, |window| window.width() / window.height());
+
+    let finale = finale_query.iter().find(|(_, finale)| finale.is_playing());
+
+    let (target_offset, target_zoom) = if let Some((board, finale)) = finale {
+        // Slowly up the final stack, closer than usual
+        (Vec2::new(0.0, finale.pan_y() - board.center.y), FINALE_ZOOM)
+    } else {
+        match settings.camera_mode {
+            CameraMode::Fixed => (Vec2::ZERO, 1.0),
+            CameraMode::Follow => {
+                let positions: Vec<Vec2> = board_query
+                    .iter()
+                    .filter(|(board_entity, _, _)| picture_in_picture.is_large(*board_entity))
+                    .flat_map(|(_, board, active_pieces)| {
+                        active_pieces.blocks().filter_map(|block_entity| {
+                            block_query
+                                .get(*block_entity)
+                                .ok()
+                                .map(|(_, transform, _)| {
+                                    transform.translation.truncate() - board.center
+                                })
+                        })
+                    })
+                    .collect();
+
+                let offset = if positions.is_empty() {
+                    Vec2::ZERO
+                } else {
+                    positions.iter().sum::<Vec2>() / positions.len() as f32
+                };
+
+                (offset * CAMERA_FOLLOW_STRENGTH, 1.0)
+            }
+            CameraMode::AutoZoom => {
+                // The tallest stack of resting blocks on any board, as a fraction of its height
+                let fullness = block_query
+                    .iter()
+                    .filter(|(_, _, sleeping)| sleeping.sleeping)
+                    .filter_map(|(block, transform, _)| {
+                        let (_, board, _) = board_query.get(block.board).ok()?;
+                        Some((transform.translation.y - board.floor_y()) / board.n_rows as f32)
+                    })
+                    .fold(0.0_f32, f32::max);
+
+                let zoom = 1.0 + CAMERA_AUTO_ZOOM * ((fullness - 0.5) / 0.5).clamp(0.0, 1.0);
+
+                (Vec2::ZERO, zoom)
+            }
+        }
+    };
+
+    let easing = (CAMERA_EASING * time.delta_seconds()).min(1.0);
+
+    let large_boards: Vec<&Board> = board_query
+        .iter()
+        .filter(|(board_entity, _, _)| picture_in_picture.is_large(*board_entity))
+        .map(|(_, board, _)| board)
+        .collect();
+    let center = large_boards.iter().map(|board| board.center).sum::<Vec2>()
+        / large_boards.len().max(1) as f32;
+
+    for (mut controller, mut projection, mut transform) in camera_query.iter_mut() {
+        controller.offset = controller.offset.lerp(target_offset, easing);
+        controller.zoom += (target_zoom - controller.zoom) * easing;
+
+        projection.scaling_mode =
+            camera_scaling_mode(large_boards.iter().copied(), center, window_aspect);
+        projection.scale = controller.zoom;
+
+        transform.translation.x = center.x + controller.offset.x;
+        transform.translation.y = center.y + controller.offset.y;
+    }
+}
+
+pub(crate) fn cycle_camera_mode(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::CameraMode) {
+        return;
+    }
+
+    settings.camera_mode = settings.camera_mode.next();
+    settings.save();
+
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("Camera: {}", settings.camera_mode.name()),
+    );
+}
+
+pub(crate) fn swap_inset(
+    mut commands: Commands,
+    action_state: Res<ActionState>,
+    fonts: Res<Fonts>,
+    mut picture_in_picture: ResMut<PictureInPicture>,
+) {
+    if picture_in_picture.inset.is_none() || !action_state.just_pressed(Action::SwapInset) {
+        return;
+    }
+
+    picture_in_picture.swapped = !picture_in_picture.swapped;
+    spawn_toast(&mut commands, &fonts, "Swapped inset");
+}
+
+/// Fit the inset camera to the first board not shown large, in the top right corner
+/// of the window. Off while every board is shown large.
+pub(crate) fn inset_camera(
+    picture_in_picture: Res<PictureInPicture>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    board_query: Query<(Entity, &Board)>,
+    mut camera_query: Query<
+        (&mut Camera, &mut OrthographicProjection, &mut Transform),
+        With<InsetCamera>,
+    >,
+) {
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    let board = board_query
+        .iter()
+        .find(|(board_entity, _)| !picture_in_picture.is_large(*board_entity))
+        .map(|(_, board)| board);
+
+    for (mut camera, mut projection, mut transform) in camera_query.iter_mut() {
+        let Some(board) = board else {
+            if camera.is_active {
+                camera.is_active = false;
+            }
+            continue;
+        };
+
+        let height = window.height() * INSET_HEIGHT;
+        let aspect = (board.width() + CAMERA_HORIZONTAL_MARGIN)
+            / (board.n_rows as f32 + CAMERA_VERTICAL_MARGIN);
+        let size = Vec2::new(height * aspect, height);
+        let position = Vec2::new(window.width() - size.x - INSET_MARGIN, INSET_MARGIN);
+
+        let scale_factor = window.scale_factor() as f32;
+        let physical_position = (position.max(Vec2::ZERO) * scale_factor).as_uvec2();
+        let physical_size = (size * scale_factor).as_uvec2().max(UVec2::ONE);
+
+        let fitted = matches!(
+            &camera.viewport,
+            Some(viewport) if viewport.physical_position == physical_position
+                && viewport.physical_size == physical_size
+        );
+        if !fitted || !camera.is_active {
+            camera.is_active = true;
+            camera.viewport = Some(Viewport {
+                physical_position,
+                physical_size,
+                ..Default::default()
+            });
+        }
+
+        projection.scaling_mode =
+            camera_scaling_mode(std::iter::once(board), board.center, size.x / size.y);
+        transform.translation.x = board.center.x;
+        transform.translation.y = board.center.y;
+    }
+}
+
+/// Game over once every board is over, and back to playing when their runs restart.
+pub(crate) fn game_over_state(
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    board_query: Query<&BoardStats, With<ActivePieces>>,
+) {
+    if board_query.is_empty() {
+        return;
+    }
+
+    let all_over = board_query
+        .iter()
+        .all(|stats| stats.game_over_duration.is_some());
+
+    match (state.0, all_over) {
+        (AppState::Playing, true) => next_state.set(AppState::GameOver),
+        (AppState::GameOver, false) => next_state.set(AppState::Playing),
+        _ => {}
+    }
+}
+
+/// Switch side walls on or off for the mode of the next run.
+pub(crate) fn toggle_side_walls(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::SideWalls) {
+        return;
+    }
+
+    let mode = settings.mode;
+    let enabled = !settings.side_walls.contains(&mode);
+    if enabled {
+        settings.side_walls.insert(mode);
+    } else {
+        settings.side_walls.remove(&mode);
+    }
+    settings.save();
+
+    let state = if enabled { "on" } else { "off" };
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("{}: side walls {state} from the next run", mode.name()),
+    );
+}
+
+/// Let new blocks report the events of their board, see `Board::active_events`.
+pub(crate) fn attach_active_events(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    board_query: Query<&Board>,
+    block_query: Query<(Entity, &Block), Added<Block>>,
+) {
+    // Rapier reports forces, the impulse over a physics step
+    let dt = rapier_context.integration_parameters.dt;
+
+    for (block_entity, block) in block_query.iter() {
+        let Ok(board) = board_query.get(block.board) else {
+            continue;
+        };
+
+        commands.entity(block_entity).insert((
+            board.active_events(),
+            ContactForceEventThreshold(board.contact_impulse_threshold() / dt),
+        ));
+    }
+}
+
+pub(crate) fn apply_side_walls(
+    mut commands: Commands,
+    board_query: Query<&Board, Changed<Board>>,
+    mut wall_query: Query<(Entity, &SideWall, &mut Visibility)>,
+) {
+    for (wall_entity, wall, mut visibility) in wall_query.iter_mut() {
+        let Ok(board) = board_query.get(wall.board) else {
+            continue;
+        };
+
+        if board.side_walls {
+            commands.entity(wall_entity).remove::<ColliderDisabled>();
+            *visibility = Visibility::Inherited;
+        } else {
+            commands.entity(wall_entity).insert(ColliderDisabled);
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+pub(crate) fn cycle_board_layout(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::BoardLayout) {
+        return;
+    }
+
+    settings.cycle_board_layout(1);
+    settings.save();
+
+    // The board geometry is only built on startup
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("Board: {} (next launch)", settings.board_layout),
+    );
+}
+
+/// Pulse the rows that are missing exactly one block, showing which gap to aim for.
+pub(crate) fn highlight_near_complete_rows(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    board_query: Query<(Entity, &Board)>,
+    block_query: Query<(&Block, &Transform, &Sleeping)>,
+    mut highlight_query: Query<(&RowHighlight, &mut Sprite, &mut Visibility)>,
+) {
+    let mut resting_blocks: HashMap<Entity, Vec<(Vec2, Quat, f32)>> = HashMap::new();
+
+    let mut area_per_row: HashMap<Entity, Vec<(Rect, f32)>> = board_query
+        .iter()
+        .map(|(board_entity, board)| {
+            let rows = board.rows().into_iter().map(|row| (row, 0.0)).collect();
+            (board_entity, rows)
+        })
+        .collect();
+
+    for (block, transform, sleep) in block_query.iter() {
+        // Same as for clearing, only resting blocks count
+        if !sleep.sleeping {
+            continue;
+        }
+
+        let Ok((_, board)) = board_query.get(block.board) else {
+            continue;
+        };
+
+        let Some(rows) = area_per_row.get_mut(&block.board) else {
+            continue;
+        };
+
+        for (cell_center, area) in block.cells(transform) {
+            if let Some(row) = board.row_at(cell_center) {
+                rows[row].1 += area;
+            }
+        }
+
+        resting_blocks.entry(block.board).or_default().push((
+            transform.translation.truncate(),
+            transform.rotation,
+            block.size,
+        ));
+    }
+
+    // Same as for clearing, by coverage instead of cells when the board clears rows that way
+    for (board_entity, board) in board_query.iter() {
+        let (RowClearing::Coverage { .. }, Some(rows)) =
+            (board.row_clearing, area_per_row.get_mut(&board_entity))
+        else {
+            continue;
+        };
+
+        let row_rects: Vec<Rect> = rows.iter().map(|(row, _)| *row).collect();
+        let blocks = resting_blocks.remove(&board_entity).unwrap_or_default();
+        for ((_, area), covered_area) in rows
+            .iter_mut()
+            .zip(board.covered_row_areas(&row_rects, &blocks))
+        {
+            *area = covered_area;
+        }
+    }
+
+    let pulse = 0.5
+        - 0.5 * (time.elapsed_seconds() * std::f32::consts::TAU / ROW_HIGHLIGHT_PULSE_PERIOD).cos();
+    let alpha =
+        ROW_HIGHLIGHT_MIN_ALPHA + (ROW_HIGHLIGHT_MAX_ALPHA - ROW_HIGHLIGHT_MIN_ALPHA) * pulse;
+
+    for (highlight, mut sprite, mut visibility) in highlight_query.iter_mut() {
+        let near_complete = match (
+            board_query.get(highlight.board),
+            area_per_row.get(&highlight.board),
+        ) {
+            // A hint, left out of the clean feed
+            _ if settings.clean_feed => false,
+            (Ok((_, board)), Some(rows)) => {
+                let (row, area) = &rows[highlight.row];
+                !board.row_is_full(row, *area) && board.row_is_full(row, area + 1.0)
+            }
+            _ => false,
+        };
+
+        if near_complete {
+            *visibility = Visibility::Inherited;
+            sprite.color.set_a(alpha);
+        } else if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picture_in_picture_shows_the_inset_board_small_until_swapped() {
+        let (board, other_board) = (Entity::from_raw(1), Entity::from_raw(2));
+
+        let mut picture_in_picture = PictureInPicture::default();
+        assert!(picture_in_picture.is_large(board));
+        assert!(picture_in_picture.is_large(other_board));
+
+        picture_in_picture.inset = Some(board);
+        assert!(!picture_in_picture.is_large(board));
+        assert!(picture_in_picture.is_large(other_board));
+
+        picture_in_picture.swapped = true;
+        assert!(picture_in_picture.is_large(board));
+        assert!(!picture_in_picture.is_large(other_board));
+    }
+
+    #[test]
+    fn a_fixed_seed_deals_the_same_numbers_every_run() {
+        let mut rng = GameRng::new(Some(42));
+        let first: Vec<u32> = (0..8).map(|_| rng.gen()).collect();
+
+        rng.start_run();
+        let second: Vec<u32> = (0..8).map(|_| rng.gen()).collect();
+        assert_eq!(rng.seed, 42);
+        assert_eq!(first, second);
+
+        let mut other = GameRng::new(Some(43));
+        let third: Vec<u32> = (0..8).map(|_| other.gen()).collect();
+        assert_ne!(first, third);
+    }
+
+    #[test]
+    fn rows_covered_past_the_threshold_clear_as_partial_rows() {
+        let mut board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Classic,
+            Vec2::ZERO,
+        );
+        let row = board.rows()[0];
+        let width = row.width();
+        assert!(!board.row_is_partial(&row, width));
+
+        board.partial_rows = Some(0.9);
+        assert!(board.row_is_partial(&row, width * 0.9));
+        assert!(!board.row_is_partial(&row, width * 0.85));
+    }
+
+    #[test]
+    fn raised_floors_number_their_rows_after_the_lower_ones() {
+        let board = Board::new(BoardLayout::named("Islands"), GameMode::Classic, Vec2::ZERO);
+
+        let island_x = board.lane_left_x(1) + 0.5;
+        let gap_x = board.lane_left_x(3) + 0.5;
+        let main_x = board.lane_left_x(8) + 0.5;
+        assert_eq!(board.floor_y_at(island_x), Some(board.floor_y() + 4.0));
+        assert_eq!(board.floor_y_at(gap_x), None);
+        assert_eq!(board.floor_y_at(main_x), Some(board.floor_y()));
+
+        let rows = board.rows();
+        assert_eq!(rows.len(), 16 + 20);
+        for (index, row) in rows.iter().enumerate() {
+            assert_eq!(board.row_at(row.center()), Some(index));
+        }
+        assert_eq!(board.row_at(Vec2::new(gap_x, board.floor_y() + 4.0)), None);
+    }
+
+    #[test]
+    fn tilted_blocks_cover_rows_that_their_cells_do_not_fill() {
+        let board = Board {
+            row_clearing: RowClearing::Coverage { threshold: 0.8 },
+            ..Board::new(
+                BoardLayout::named("Standard"),
+                GameMode::Classic,
+                Vec2::ZERO,
+            )
+        };
+        let row = board.rows()[0];
+
+        // A full row of blocks, each a little tilted
+        let tilt = Quat::from_rotation_z(std::f32::consts::FRAC_PI_8);
+        let blocks: Vec<_> = board
+            .slot_centers()
+            .into_iter()
+            .map(|x| (Vec2::new(x, row.center().y), tilt, 1.0))
+            .collect();
+        let areas = board.covered_row_areas(&[row], &blocks);
+        assert!(areas[0] < row.width());
+        assert!(board.row_is_full(&row, areas[0]));
+
+        let half_row = board.covered_row_areas(&[row], &blocks[..blocks.len() / 2]);
+        assert!(!board.row_is_full(&row, half_row[0]));
+    }
+
+    #[test]
+    fn row_clearing_cycles_through_every_rule() {
+        assert_eq!(RowClearing::Cells.cycle(-1), RowClearing::ALL[4]);
+        assert_eq!(RowClearing::ALL[4].cycle(1), RowClearing::Cells);
+        assert_eq!(RowClearing::ALL[1].name(), "Coverage 80%");
+    }
+
+    #[test]
+    fn recycle_pieces_enter_from_the_top_corners_by_turns() {
+        let board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Recycle,
+            Vec2::ZERO,
+        );
+        let right_x = board.left_wall_x() + board.width();
+
+        assert_eq!(
+            board.spawn_entry(0, 0, 3.0),
+            (board.left_wall_x(), Vec2::ZERO)
+        );
+        assert_eq!(board.spawn_entry(0, 1, 3.0), (right_x - 3.0, Vec2::ZERO));
+        assert_eq!(
+            board.spawn_entry(0, 2, 3.0),
+            (board.left_wall_x(), Vec2::ZERO)
+        );
+    }
+
+    #[test]
+    fn chaos_pieces_slide_in_from_the_left_and_twin_pieces_keep_to_their_half() {
+        let chaos = Board::new(BoardLayout::named("Standard"), GameMode::Chaos, Vec2::ZERO);
+        let (x, velocity) = chaos.spawn_entry(0, 0, 2.0);
+        assert_eq!(x, chaos.left_wall_x());
+        assert!(velocity.x > 0.0 && velocity.y == 0.0);
+
+        let twin = Board::new(BoardLayout::named("Standard"), GameMode::Twin, Vec2::ZERO);
+        assert_eq!(twin.spawn_entry(0, 0, 2.0), (twin.spawn_x(0), Vec2::ZERO));
+        assert_eq!(twin.spawn_entry(1, 1, 2.0), (twin.spawn_x(1), Vec2::ZERO));
+        assert!(twin.spawn_x(0) < twin.center.x && twin.spawn_x(1) >= twin.center.x);
+    }
+}
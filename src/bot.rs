@@ -0,0 +1,533 @@
+//! The bot: it picks where the piece should land on a grid projection of the settled blocks, and
+//! steers it there with the same push, torque and drop input as a player. It is the opponent in
+//! Versus against the bot, and plays the demo behind the main menu.
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::{start_run, Board, GameRng, RunStarted};
+use crate::coop::CoopSession;
+use crate::input::{update_action_state, PieceInput};
+use crate::online::OnlineSession;
+use crate::physics::{tetromino_movement, GameTimeScale, TimeScaleSource};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{results_restart_prompt, BoardStats, RestartRequested};
+use crate::settings::Settings;
+use crate::tetromino::{hold_piece, pre_rotate_growing_pieces, ActivePieces, Block};
+use crate::ui::{pause_requests, Fonts, Menu};
+use crate::{AppState, GameplaySet};
+
+/// The bot, playing the boards handed to it, and the demo.
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            bot_input
+                .after(update_action_state)
+                .before(pre_rotate_growing_pieces)
+                .before(hold_piece)
+                .before(tetromino_movement)
+                .in_set(GameplaySet),
+        )
+        .add_system(bot_restart.after(results_restart_prompt))
+        .add_system(start_demo.run_if(in_state(AppState::MainMenu)))
+        .add_system(
+            end_demo
+                .after(pause_requests)
+                .run_if(resource_exists::<DemoMode>()),
+        );
+    }
+}
+
+// Seconds on the main menu without input before the demo starts
+pub(crate) const DEMO_IDLE_SECONDS: f32 = 30.0;
+// Scaled seconds the demo lingers once every board is over, before going back to the main menu
+pub(crate) const DEMO_GAME_OVER_SECONDS: f32 = 3.0;
+
+// Weights of a landing: stack height, rows cleared, holes under the surface, bumpiness of the
+// surface, and blocks sticking out above the board
+pub(crate) const BOT_HEIGHT_WEIGHT: f32 = -0.51;
+pub(crate) const BOT_ROWS_WEIGHT: f32 = 0.76;
+pub(crate) const BOT_HOLES_WEIGHT: f32 = -0.36;
+pub(crate) const BOT_BUMPINESS_WEIGHT: f32 = -0.18;
+pub(crate) const BOT_OVERFLOW_WEIGHT: f32 = -5.0;
+// Rows above the board in the grid, for stacks sticking out
+pub(crate) const BOT_GRID_HEADROOM: usize = 4;
+
+// Steering: input per block of distance to the target, less per block per second of speed
+pub(crate) const BOT_MOVEMENT_GAIN: f32 = 1.5;
+pub(crate) const BOT_MOVEMENT_DAMPING: f32 = 0.4;
+// Input per radian to turn, less per radian per second of spin
+pub(crate) const BOT_TORQUE_GAIN: f32 = 2.0;
+pub(crate) const BOT_TORQUE_DAMPING: f32 = 0.5;
+// The piece is soft dropped once this close to its target
+pub(crate) const BOT_ALIGNED_DISTANCE: f32 = 0.25;
+pub(crate) const BOT_ALIGNED_ANGLE: f32 = 0.15;
+
+/// What the bot is up to on a board, while the board is played by the bot.
+#[derive(Component, Default)]
+pub(crate) struct Bot {
+    /// A block of the piece the target was chosen for, to notice the next piece
+    pub(crate) piece: Option<Entity>,
+    pub(crate) target: Option<BotTarget>,
+    /// For the first piece, this frame
+    pub(crate) input: PieceInput,
+}
+
+/// Where the bot is steering the piece.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BotTarget {
+    /// Horizontal center of the piece where it lands
+    pub(crate) x: f32,
+    /// Angle of the piece where it lands
+    pub(crate) angle: f32,
+}
+
+/// The boards playing themselves behind the main menu, until a key is pressed.
+#[derive(Resource)]
+pub(crate) struct DemoMode;
+
+#[derive(Component)]
+pub(crate) struct DemoBanner;
+
+/// Settled blocks projected onto the block slots of a board, each column counted in rows up from
+/// the floor under it.
+#[derive(Clone)]
+pub(crate) struct BotGrid {
+    /// Center of each column
+    pub(crate) xs: Vec<f32>,
+    /// Columns with a floor under them, the others can't be landed in
+    pub(crate) floored: Vec<bool>,
+    /// Filled cells of each column, bottom up
+    pub(crate) cells: Vec<Vec<bool>>,
+    /// Rows of the board, not counting the headroom
+    pub(crate) board_rows: usize,
+}
+
+impl BotGrid {
+    pub(crate) fn new(board: &Board, blocks: impl Iterator<Item = Vec2>) -> Self {
+        let xs = board.slot_centers();
+        let floors: Vec<Option<f32>> = xs.iter().map(|x| board.floor_y_at(*x)).collect();
+        let n_rows = board.n_rows + BOT_GRID_HEADROOM;
+        let mut cells = vec![vec![false; n_rows]; xs.len()];
+
+        for position in blocks {
+            let Some(column) = xs.iter().position(|x| (position.x - x).abs() < 0.5) else {
+                continue;
+            };
+            let Some(floor_y) = floors[column] else {
+                continue;
+            };
+
+            let row = (position.y - floor_y - 0.5).round();
+            if row >= 0.0 && (row as usize) < n_rows {
+                cells[column][row as usize] = true;
+            }
+        }
+
+        Self {
+            xs,
+            floored: floors.iter().map(Option::is_some).collect(),
+            cells,
+            board_rows: board.n_rows,
+        }
+    }
+
+    pub(crate) fn height(&self, column: usize) -> usize {
+        self.cells[column]
+            .iter()
+            .rposition(|filled| *filled)
+            .map_or(0, |row| row + 1)
+    }
+
+    /// How good the stack is, the higher the better.
+    pub(crate) fn evaluate(&self) -> f32 {
+        let columns: Vec<usize> = (0..self.xs.len())
+            .filter(|column| self.floored[*column])
+            .collect();
+        let n_rows = self.cells.first().map_or(0, Vec::len);
+
+        let rows_cleared = (0..n_rows)
+            .filter(|row| columns.iter().all(|column| self.cells[*column][*row]))
+            .count();
+        let heights: Vec<usize> = columns.iter().map(|column| self.height(*column)).collect();
+        let holes: usize = columns
+            .iter()
+            .zip(&heights)
+            .map(|(column, height)| {
+                self.cells[*column][..*height]
+                    .iter()
+                    .filter(|filled| !**filled)
+                    .count()
+            })
+            .sum();
+        let bumpiness: usize = heights
+            .windows(2)
+            .map(|pair| pair[0].abs_diff(pair[1]))
+            .sum();
+        let overflow: usize = heights
+            .iter()
+            .map(|height| height.saturating_sub(self.board_rows))
+            .sum();
+        let height = heights
+            .iter()
+            .sum::<usize>()
+            .saturating_sub(rows_cleared * columns.len());
+
+        BOT_HEIGHT_WEIGHT * height as f32
+            + BOT_ROWS_WEIGHT * rows_cleared as f32
+            + BOT_HOLES_WEIGHT * holes as f32
+            + BOT_BUMPINESS_WEIGHT * bumpiness as f32
+            + BOT_OVERFLOW_WEIGHT * overflow as f32
+    }
+
+    /// Row where a piece lands when dropped straight down at `column`, given its cells as column
+    /// and row offsets from its lower left. `None` if it doesn't fit there.
+    pub(crate) fn landing_row(&self, cells: &[(usize, usize)], column: usize) -> Option<usize> {
+        let n_rows = self.cells.first().map_or(0, Vec::len);
+        let mut row = 0;
+
+        for (dx, dy) in cells {
+            let column = column + dx;
+            if column >= self.xs.len() || !self.floored[column] {
+                return None;
+            }
+            row = row.max(self.height(column).saturating_sub(*dy));
+        }
+
+        cells.iter().all(|(_, dy)| row + dy < n_rows).then_some(row)
+    }
+
+    /// The best landing of a piece, of every turn and column, given the offsets of its blocks from
+    /// its center at angle zero, in blocks.
+    pub(crate) fn plan(&self, offsets: &[Vec2]) -> Option<BotTarget> {
+        let mut best: Option<(f32, BotTarget)> = None;
+
+        for quarter_turns in 0..4 {
+            let angle = quarter_turns as f32 * FRAC_PI_2;
+            let rotated: Vec<Vec2> = offsets
+                .iter()
+                .map(|offset| Vec2::from_angle(angle).rotate(*offset))
+                .collect();
+            let min = rotated
+                .iter()
+                .fold(Vec2::splat(f32::INFINITY), |min, offset| min.min(*offset));
+            let cells: Vec<(usize, usize)> = rotated
+                .iter()
+                .map(|offset| {
+                    let cell = (*offset - min).round();
+                    (cell.x as usize, cell.y as usize)
+                })
+                .collect();
+
+            for column in 0..self.xs.len() {
+                let Some(row) = self.landing_row(&cells, column) else {
+                    continue;
+                };
+
+                let mut grid = self.clone();
+                for (dx, dy) in &cells {
+                    grid.cells[column + dx][row + dy] = true;
+                }
+                let score = grid.evaluate();
+
+                // Ties go to the least turning
+                if best.is_none_or(|(best_score, _)| score > best_score) {
+                    let x = cells
+                        .iter()
+                        .map(|(dx, _)| self.xs[column + dx])
+                        .sum::<f32>()
+                        / cells.len() as f32;
+                    best = Some((score, BotTarget { x, angle }));
+                }
+            }
+        }
+
+        best.map(|(_, target)| target)
+    }
+}
+
+/// Choose where each new piece on a bot's board goes, and steer the piece there: push it over,
+/// turn it, and soft drop it once it's lined up.
+pub(crate) fn bot_input(
+    mut board_query: Query<(Entity, &Board, &ActivePieces, &mut Bot)>,
+    block_query: Query<(Entity, &Block, &Transform, &Velocity)>,
+) {
+    for (board_entity, board, active_pieces, mut bot) in board_query.iter_mut() {
+        if !board.bot {
+            continue;
+        }
+
+        let blocks: Vec<(Entity, &Block, &Transform, &Velocity)> = active_pieces
+            .pieces
+            .first()
+            .map(|piece| {
+                piece
+                    .blocks
+                    .iter()
+                    .filter_map(|block_entity| block_query.get(*block_entity).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if blocks.is_empty() {
+            bot.input = PieceInput::default();
+            continue;
+        }
+
+        let n_blocks = blocks.len() as f32;
+        let center = blocks
+            .iter()
+            .map(|(_, _, transform, _)| transform.translation.truncate())
+            .sum::<Vec2>()
+            / n_blocks;
+        let velocity = blocks
+            .iter()
+            .map(|(_, _, _, velocity)| velocity.linvel)
+            .sum::<Vec2>()
+            / n_blocks;
+        let spin = blocks
+            .iter()
+            .map(|(_, _, _, velocity)| velocity.angvel)
+            .sum::<f32>()
+            / n_blocks;
+        let (_, _, angle) = blocks[0].2.rotation.to_euler(EulerRot::XYZ);
+
+        // A new piece, the lowest entity standing for it
+        let piece = blocks.iter().map(|(entity, ..)| *entity).min();
+        if bot.piece != piece {
+            bot.piece = piece;
+
+            let offsets: Vec<Vec2> = blocks
+                .iter()
+                .map(|(_, block, transform, _)| {
+                    Vec2::from_angle(-angle).rotate(transform.translation.truncate() - center)
+                        / block.size
+                })
+                .collect();
+            let grid = BotGrid::new(
+                board,
+                block_query
+                    .iter()
+                    .filter(|(entity, block, _, _)| {
+                        block.board == board_entity && !active_pieces.contains(entity)
+                    })
+                    .map(|(_, _, transform, _)| transform.translation.truncate()),
+            );
+            bot.target = grid.plan(&offsets);
+        }
+
+        let Some(target) = bot.target else {
+            bot.input = PieceInput::default();
+            continue;
+        };
+
+        let distance = target.x - center.x;
+        let turn = (target.angle - angle + PI).rem_euclid(TAU) - PI;
+        let aligned = distance.abs() < BOT_ALIGNED_DISTANCE && turn.abs() < BOT_ALIGNED_ANGLE;
+
+        bot.input = PieceInput {
+            movement: (distance * BOT_MOVEMENT_GAIN - velocity.x * BOT_MOVEMENT_DAMPING)
+                .clamp(-1.0, 1.0),
+            torque: (turn * BOT_TORQUE_GAIN - spin * BOT_TORQUE_DAMPING).clamp(-1.0, 1.0),
+            soft_drop: if aligned { 1.0 } else { 0.0 },
+            ..Default::default()
+        };
+    }
+}
+
+/// The bot's runs aren't shown results, so its board restarts along with the player's once the
+/// player dismisses theirs.
+pub(crate) fn bot_restart(
+    mut commands: Commands,
+    demo: Option<Res<DemoMode>>,
+    board_query: Query<(Entity, &Board, &BoardStats, Option<&RestartRequested>)>,
+) {
+    // The demo goes back to the main menu instead, see `end_demo`
+    if demo.is_some() {
+        return;
+    }
+
+    let requested = board_query
+        .iter()
+        .any(|(_, board, _, restart)| !board.bot && restart.is_some());
+    if !requested {
+        return;
+    }
+
+    for (board_entity, board, stats, restart) in board_query.iter() {
+        if board.bot && stats.game_over_duration.is_some() && restart.is_none() {
+            commands.entity(board_entity).insert(RestartRequested);
+        }
+    }
+}
+
+/// After a while on the main menu without input, start runs on every board and hand them to the
+/// bot.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn start_demo(
+    mut commands: Commands,
+    time: Res<Time>,
+    input: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    fonts: Res<Fonts>,
+    coop: Option<Res<CoopSession>>,
+    online: Option<Res<OnlineSession>>,
+    mut idle: Local<f32>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut run_started_events: EventWriter<RunStarted>,
+    menu_query: Query<(), With<Menu>>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    block_query: Query<(Entity, &Block)>,
+) {
+    let any_input = input.get_pressed().next().is_some()
+        || buttons.get_pressed().next().is_some()
+        || mouse.get_pressed().next().is_some();
+
+    // Only from the main menu itself, and not while another player is connected
+    if any_input || menu_query.iter().count() != 1 || coop.is_some() || online.is_some() {
+        *idle = 0.0;
+        return;
+    }
+
+    *idle += time.delta_seconds();
+    if *idle < DEMO_IDLE_SECONDS {
+        return;
+    }
+    *idle = 0.0;
+
+    for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in board_query.iter_mut() {
+        run_started_events.send(start_run(
+            &mut commands,
+            board_entity,
+            &mut board,
+            &mut active_pieces,
+            &mut stats,
+            &settings,
+            &piece_definitions,
+            &mut rng,
+            block_query
+                .iter()
+                .filter(|(_, block)| block.board == board_entity)
+                .map(|(entity, _)| entity),
+        ));
+        // Until the next run started, so that the demo's results aren't the player's
+        board.bot = true;
+    }
+
+    commands
+        .spawn(
+            fonts
+                .text_bundle("DEMO - press any key", 24.0, Color::WHITE)
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: Val::Px(32.0),
+                        left: Val::Px(16.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+        )
+        .insert(DemoBanner);
+
+    commands.insert_resource(DemoMode);
+    next_state.set(AppState::Playing);
+}
+
+/// Back to the main menu on any key, or a while after every board of the demo is over.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn end_demo(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mouse: Res<Input<MouseButton>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut time_scale: ResMut<GameTimeScale>,
+    board_query: Query<&BoardStats>,
+    banner_query: Query<Entity, With<DemoBanner>>,
+) {
+    let any_input = input.get_just_pressed().next().is_some()
+        || buttons.get_just_pressed().next().is_some()
+        || mouse.get_just_pressed().next().is_some();
+    let over = board_query.iter().all(|stats| {
+        stats
+            .game_over_duration
+            .is_some_and(|duration| duration > DEMO_GAME_OVER_SECONDS)
+    });
+
+    if !any_input && !over {
+        return;
+    }
+
+    for entity in banner_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // Cleared when a run restarts, which the demo doesn't get to
+    time_scale.clear(TimeScaleSource::GameOver);
+    commands.remove_resource::<DemoMode>();
+    next_state.set(AppState::MainMenu);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::board::BoardLayout;
+    use crate::modes::GameMode;
+
+    /// Blocks at the given columns and rows, up from the floor under them.
+    fn grid(board: &Board, cells: &[(usize, usize)]) -> BotGrid {
+        let xs = board.slot_centers();
+        let blocks = cells.iter().map(|(column, row)| {
+            let x = xs[*column];
+            Vec2::new(x, board.floor_y_at(x).unwrap() + 0.5 + *row as f32)
+        });
+        BotGrid::new(board, blocks)
+    }
+
+    #[test]
+    fn grid_counts_blocks_in_rows_up_from_the_floor() {
+        let board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Classic,
+            Vec2::ZERO,
+        );
+        let grid = grid(&board, &[(0, 0), (0, 1), (2, 3)]);
+
+        assert_eq!(grid.height(0), 2);
+        assert_eq!(grid.height(1), 0);
+        // Blocks over a hole still count towards the height
+        assert_eq!(grid.height(2), 4);
+        assert_eq!(grid.landing_row(&[(0, 0), (1, 0)], 0), Some(2));
+        assert_eq!(grid.landing_row(&[(0, 0), (1, 0)], grid.xs.len() - 1), None);
+    }
+
+    #[test]
+    fn plan_stands_a_line_piece_in_the_gap_of_an_almost_full_row() {
+        let board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Classic,
+            Vec2::ZERO,
+        );
+        let n_columns = board.slot_centers().len();
+        let cells: Vec<(usize, usize)> = (0..n_columns - 1).map(|column| (column, 0)).collect();
+        let grid = grid(&board, &cells);
+        let line = [-1.5, -0.5, 0.5, 1.5].map(|x| Vec2::new(x, 0.0));
+
+        let target = grid.plan(&line).unwrap();
+        assert_eq!(target.x, grid.xs[n_columns - 1]);
+        assert_eq!(target.angle, FRAC_PI_2);
+    }
+}
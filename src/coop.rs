@@ -0,0 +1,510 @@
+//! Networked co-op, where two players take turns over UDP.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::input::{ActionState, PieceInput};
+use crate::scoring::{BoardStats, HighlightBlock, Profile, RATING_MAX, RATING_MIN};
+use crate::tetromino::{tetromino_sleep_detection, ActivePieces, Block, PieceSettled};
+use crate::ui::{spawn_toast, Fonts};
+
+/// Networked co-op, active when started with a co-op address.
+pub struct CoopPlugin;
+
+impl Plugin for CoopPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_coop.in_base_set(StartupSet::PostStartup))
+            .add_system(coop_receive.run_if(resource_exists::<CoopSession>()))
+            .add_system(
+                coop_turn_handoff
+                    .after(tetromino_sleep_detection)
+                    .run_if(resource_exists::<CoopSession>()),
+            )
+            .add_system(
+                coop_send
+                    .after(coop_receive)
+                    .after(coop_turn_handoff)
+                    .run_if(resource_exists::<CoopSession>()),
+            )
+            .add_system(coop_status.run_if(resource_exists::<CoopSession>()));
+    }
+}
+
+// Guest input is held this long across lost packets, in real seconds
+pub(crate) const COOP_INPUT_TIMEOUT: f32 = 0.25;
+pub(crate) const COOP_MAX_DATAGRAM_SIZE: usize = 65507;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub(crate) enum CoopPlayer {
+    Host,
+    Guest,
+}
+
+impl CoopPlayer {
+    pub(crate) fn other(&self) -> Self {
+        match self {
+            Self::Host => Self::Guest,
+            Self::Guest => Self::Host,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum CoopMessage {
+    /// Guest to host, until the first snapshot arrives
+    Join { rating: f32 },
+    /// Guest to host, every frame while the guest owns the piece
+    Input { sequence: u32, input: PieceInput },
+    /// Host to guest, whenever the piece changes hands
+    TurnHandoff { turn: u32, owner: CoopPlayer },
+    /// Host to guest, every frame
+    Snapshot {
+        sequence: u32,
+        turn: u32,
+        owner: CoopPlayer,
+        stats: Box<BoardStats>,
+        blocks: Vec<HighlightBlock>,
+        rating: f32,
+    },
+}
+
+/// A rating received from another player, `None` unless it is a finite number.
+/// Clamped to the range of ratings players can reach.
+pub(crate) fn received_rating(rating: f32) -> Option<f32> {
+    rating
+        .is_finite()
+        .then_some(rating.clamp(RATING_MIN, RATING_MAX))
+}
+
+/// Whether a message from `from` is listened to: once the peer is pinned, nobody else is.
+///
+/// The guest pins the host it joins, the host pins the first guest to join.
+pub(crate) fn accepts_sender(peer: Option<SocketAddr>, from: SocketAddr) -> bool {
+    peer.is_none_or(|peer| peer == from)
+}
+
+/// Online co-op, two players alternating pieces on one shared board.
+///
+/// The host runs the simulation and streams snapshots, the guest only renders them
+/// and sends its input while it owns the current piece.
+#[derive(Resource)]
+pub(crate) struct CoopSession {
+    pub(crate) local: CoopPlayer,
+    pub(crate) socket: UdpSocket,
+    pub(crate) peer: Option<SocketAddr>,
+    /// Incremented on every handoff
+    pub(crate) turn: u32,
+    pub(crate) owner: CoopPlayer,
+    /// Sequence number of the last message sent
+    pub(crate) sent_sequence: u32,
+    /// Sequence number of the newest message received, older ones arrived out of order
+    pub(crate) received_sequence: u32,
+    /// Latest input from the guest, applied by the host
+    pub(crate) remote_input: PieceInput,
+    pub(crate) since_remote_input: f32,
+    pub(crate) connected: bool,
+    /// The partner's ladder rating, once a message has brought it
+    pub(crate) remote_rating: Option<f32>,
+}
+
+impl CoopSession {
+    /// Start a session from the `--coop-host <port>` or `--coop-join <address>` arguments.
+    pub(crate) fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let arg = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|index| args.get(index + 1))
+        };
+
+        let local = if arg("--coop-host").is_some() {
+            CoopPlayer::Host
+        } else if arg("--coop-join").is_some() {
+            CoopPlayer::Guest
+        } else {
+            return None;
+        };
+        let (socket, peer) = open_udp_socket("co-op", arg("--coop-host"), arg("--coop-join"))?;
+
+        Some(Self {
+            local,
+            socket,
+            peer,
+            turn: 0,
+            owner: CoopPlayer::Host,
+            sent_sequence: 0,
+            received_sequence: 0,
+            remote_input: PieceInput::default(),
+            since_remote_input: 0.0,
+            connected: false,
+            remote_rating: None,
+        })
+    }
+
+    pub(crate) fn send(&mut self, message: &CoopMessage) {
+        let Some(peer) = self.peer else {
+            return;
+        };
+
+        match ron::to_string(message) {
+            Ok(data) if data.len() <= COOP_MAX_DATAGRAM_SIZE => {
+                // Lost packets are expected, the next frame sends fresh state
+                let _ = self.socket.send_to(data.as_bytes(), peer);
+            }
+            Ok(_) => warn!("co-op: message too large"),
+            Err(err) => warn!("co-op: {err}"),
+        }
+    }
+
+    pub(crate) fn receive(&mut self) -> Vec<(SocketAddr, CoopMessage)> {
+        let mut buffer = vec![0; COOP_MAX_DATAGRAM_SIZE];
+        let mut messages = vec![];
+
+        while let Ok((len, from)) = self.socket.recv_from(&mut buffer) {
+            match ron::de::from_bytes(&buffer[..len]) {
+                Ok(message) => messages.push((from, message)),
+                Err(err) => warn!("co-op: invalid message from {from}: {err}"),
+            }
+        }
+
+        messages
+    }
+
+    /// Input for the current piece: whoever owns the turn controls it.
+    pub(crate) fn piece_input(&self, action_state: &ActionState) -> PieceInput {
+        if self.owner == self.local {
+            PieceInput::from_actions(action_state)
+        } else if self.since_remote_input < COOP_INPUT_TIMEOUT {
+            self.remote_input
+        } else {
+            PieceInput::default()
+        }
+    }
+
+    pub(crate) fn status(&self) -> &'static str {
+        if !self.connected {
+            match self.local {
+                CoopPlayer::Host => "Co-op: waiting for partner",
+                CoopPlayer::Guest => "Co-op: connecting",
+            }
+        } else if self.owner == self.local {
+            "Co-op: your piece"
+        } else {
+            "Co-op: partner's piece"
+        }
+    }
+
+    /// The status with the player's rating, and the partner's once known.
+    pub(crate) fn status_text(&self, rating: f32) -> String {
+        match self.remote_rating {
+            Some(remote_rating) => format!(
+                "{}\nRating {rating:.0}, partner {remote_rating:.0}",
+                self.status()
+            ),
+            None => format!("{}\nRating {rating:.0}", self.status()),
+        }
+    }
+}
+
+/// Bind a non-blocking UDP socket, to host on `port` or else to join the peer at `address`.
+/// Returns the socket, and the peer when joining. Problems are logged under `context`.
+pub(crate) fn open_udp_socket(
+    context: &str,
+    port: Option<&String>,
+    address: Option<&String>,
+) -> Option<(UdpSocket, Option<SocketAddr>)> {
+    let (bind_address, peer) = match (port, address) {
+        (Some(port), _) => (format!("0.0.0.0:{port}"), None),
+        (None, Some(address)) => {
+            let peer = address
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addresses| addresses.next());
+
+            if peer.is_none() {
+                warn!("{context}: could not resolve {address}");
+                return None;
+            }
+            ("0.0.0.0:0".to_string(), peer)
+        }
+        (None, None) => return None,
+    };
+
+    let socket = match UdpSocket::bind(&bind_address) {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!("{context}: could not bind {bind_address}: {err}");
+            return None;
+        }
+    };
+
+    if let Err(err) = socket.set_nonblocking(true) {
+        warn!("{context}: {err}");
+        return None;
+    }
+
+    Some((socket, peer))
+}
+
+/// Block rendered from a co-op snapshot on the guest.
+#[derive(Component)]
+pub(crate) struct RemoteBlock;
+
+#[derive(Component)]
+pub(crate) struct CoopStatus;
+
+/// Start a co-op session if requested on the command line. The guest doesn't simulate
+/// its own board, it shows the host's.
+pub(crate) fn setup_coop(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    board_query: Query<Entity, With<Board>>,
+    block_query: Query<Entity, With<Block>>,
+) {
+    let Some(coop) = CoopSession::from_args() else {
+        return;
+    };
+
+    if coop.local == CoopPlayer::Guest {
+        for board_entity in board_query.iter() {
+            commands.entity(board_entity).remove::<ActivePieces>();
+        }
+        for block_entity in block_query.iter() {
+            commands.entity(block_entity).despawn_recursive();
+        }
+    }
+
+    commands
+        .spawn(
+            fonts
+                .text_bundle(&coop.status_text(profile.rating), 20.0, Color::WHITE)
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(48.0),
+                        left: Val::Px(16.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+        )
+        .insert(CoopStatus);
+
+    commands.insert_resource(coop);
+}
+
+pub(crate) fn coop_receive(
+    mut commands: Commands,
+    time: Res<Time>,
+    fonts: Res<Fonts>,
+    mut coop: ResMut<CoopSession>,
+    mut board_query: Query<&mut BoardStats>,
+    remote_block_query: Query<Entity, With<RemoteBlock>>,
+) {
+    coop.since_remote_input += time.delta_seconds();
+
+    for (from, message) in coop.receive() {
+        if !accepts_sender(coop.peer, from) {
+            continue;
+        }
+
+        match (coop.local, message) {
+            (CoopPlayer::Host, CoopMessage::Join { rating }) if coop.peer.is_none() => {
+                coop.remote_rating = received_rating(rating);
+                info!("co-op: {from} joined");
+                coop.peer = Some(from);
+                coop.received_sequence = 0;
+                coop.connected = true;
+            }
+            (CoopPlayer::Host, CoopMessage::Input { sequence, input })
+                if sequence > coop.received_sequence =>
+            {
+                coop.received_sequence = sequence;
+                coop.remote_input = input;
+                coop.since_remote_input = 0.0;
+            }
+            (CoopPlayer::Guest, CoopMessage::TurnHandoff { turn, owner }) if turn > coop.turn => {
+                coop.turn = turn;
+                coop.owner = owner;
+                if owner == coop.local {
+                    spawn_toast(&mut commands, &fonts, "Your piece!");
+                }
+            }
+            (
+                CoopPlayer::Guest,
+                CoopMessage::Snapshot {
+                    sequence,
+                    turn,
+                    owner,
+                    stats,
+                    blocks,
+                    rating,
+                },
+            ) => {
+                if sequence <= coop.received_sequence {
+                    continue;
+                }
+                coop.received_sequence = sequence;
+                coop.connected = true;
+                coop.remote_rating = received_rating(rating);
+
+                // Handoff messages may be lost, snapshots carry the turn as well
+                if turn > coop.turn {
+                    coop.turn = turn;
+                    coop.owner = owner;
+                }
+
+                for mut board_stats in board_query.iter_mut() {
+                    *board_stats = (*stats).clone();
+                }
+
+                for entity in remote_block_query.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+
+                for block in blocks {
+                    commands
+                        .spawn(SpriteBundle {
+                            transform: Transform::from_translation(block.position.extend(0.0))
+                                .with_rotation(Quat::from_rotation_z(block.angle)),
+                            sprite: Sprite {
+                                color: block.color,
+                                custom_size: Some(Vec2::splat(block.size)),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .insert(RemoteBlock);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn coop_send(
+    action_state: Res<ActionState>,
+    profile: Res<Profile>,
+    mut coop: ResMut<CoopSession>,
+    board_query: Query<&BoardStats>,
+    block_query: Query<(&Block, &Transform, &Sprite)>,
+) {
+    coop.sent_sequence += 1;
+    let sequence = coop.sent_sequence;
+
+    match coop.local {
+        CoopPlayer::Host => {
+            if !coop.connected {
+                return;
+            }
+
+            let Ok(stats) = board_query.get_single() else {
+                return;
+            };
+
+            let blocks = block_query
+                .iter()
+                .map(|(block, transform, sprite)| HighlightBlock {
+                    position: transform.translation.truncate(),
+                    angle: transform.rotation.to_euler(EulerRot::XYZ).2,
+                    size: block.size,
+                    color: sprite.color,
+                })
+                .collect();
+
+            let message = CoopMessage::Snapshot {
+                sequence,
+                turn: coop.turn,
+                owner: coop.owner,
+                stats: Box::new(stats.clone()),
+                blocks,
+                rating: profile.rating,
+            };
+            coop.send(&message);
+        }
+        CoopPlayer::Guest => {
+            if !coop.connected {
+                coop.send(&CoopMessage::Join {
+                    rating: profile.rating,
+                });
+            } else if coop.owner == CoopPlayer::Guest {
+                let input = PieceInput::from_actions(&action_state);
+                coop.send(&CoopMessage::Input { sequence, input });
+            }
+        }
+    }
+}
+
+/// Hand the next piece to the other player.
+pub(crate) fn coop_turn_handoff(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    mut settled_events: EventReader<PieceSettled>,
+    mut coop: ResMut<CoopSession>,
+) {
+    if coop.local != CoopPlayer::Host || !coop.connected {
+        return;
+    }
+
+    for _ in settled_events.iter() {
+        coop.turn += 1;
+        coop.owner = coop.owner.other();
+
+        let message = CoopMessage::TurnHandoff {
+            turn: coop.turn,
+            owner: coop.owner,
+        };
+        coop.send(&message);
+
+        if coop.owner == coop.local {
+            spawn_toast(&mut commands, &fonts, "Your piece!");
+        }
+    }
+}
+
+pub(crate) fn coop_status(
+    coop: Res<CoopSession>,
+    profile: Res<Profile>,
+    fonts: Res<Fonts>,
+    mut status_query: Query<&mut Text, With<CoopStatus>>,
+    mut shown: Local<String>,
+) {
+    let status = coop.status_text(profile.rating);
+    if *shown == status {
+        return;
+    }
+
+    for mut text in status_query.iter_mut() {
+        *text = fonts.text(&status, 20.0, Color::WHITE);
+    }
+    *shown = status;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_pinned_peer_is_accepted() {
+        let host: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let stranger: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+
+        assert!(accepts_sender(None, stranger));
+        assert!(accepts_sender(Some(host), host));
+        assert!(!accepts_sender(Some(host), stranger));
+    }
+
+    #[test]
+    fn received_ratings_are_finite_and_in_range() {
+        assert_eq!(received_rating(1350.0), Some(1350.0));
+        assert_eq!(received_rating(f32::NAN), None);
+        assert_eq!(received_rating(f32::INFINITY), None);
+        assert_eq!(received_rating(f32::NEG_INFINITY), None);
+        assert_eq!(received_rating(-50.0), Some(RATING_MIN));
+        assert_eq!(received_rating(1.0e9), Some(RATING_MAX));
+    }
+}
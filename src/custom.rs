@@ -0,0 +1,774 @@
+//! Custom games: any rule set, modifiers, physics, board and piece set, combined in the custom
+//! game menu, saved by name to the profile and shared as challenge codes.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{start_run, Board, BoardLayout, GameRng, RunStarted};
+use crate::input::{Action, ActionState, InputMap};
+use crate::modes::GameMode;
+use crate::physics::{PhysicsProfile, PhysicsTuning};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{BoardStats, Profile};
+use crate::settings::Settings;
+use crate::tetromino::{ActivePieces, Block, MirrorModifier, PieceSet};
+use crate::ui::{
+    menu_navigation, spawn_toast, Fonts, Menu, MenuCancelled, MenuConfirmed, MenuItem,
+    CONTROLS_LAYER,
+};
+use crate::AppState;
+
+/// The custom game menu, and challenge codes given on the command line.
+pub struct CustomGamePlugin;
+
+impl Plugin for CustomGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(apply_challenge_argument.in_base_set(StartupSet::PreStartup))
+            .add_system(custom_game_input.after(menu_navigation))
+            .add_system(custom_game_labels.after(custom_game_input))
+            .add_system(
+                custom_game_name_entry
+                    .after(custom_game_input)
+                    .run_if(resource_exists::<CustomGameNameEntry>()),
+            );
+    }
+}
+
+// First part of every challenge code, changed when the format changes
+pub(crate) const CHALLENGE_CODE_PREFIX: &str = "NT2";
+pub(crate) const CUSTOM_GAME_NAME_MAX_LENGTH: usize = 24;
+
+/// Rules of a run, chosen in the custom game menu.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct CustomGame {
+    /// Empty until saved
+    pub(crate) name: String,
+    pub(crate) mode: GameMode,
+    pub(crate) fuse: bool,
+    pub(crate) mirror: MirrorModifier,
+    pub(crate) stamina: bool,
+    pub(crate) side_walls: bool,
+    pub(crate) physics: PhysicsTuning,
+    /// Name of the board layout, applied on next launch like in the settings
+    pub(crate) board_layout: String,
+    pub(crate) pieces: PieceSet,
+}
+
+impl Default for CustomGame {
+    fn default() -> Self {
+        Self::from_settings(&Settings::default())
+    }
+}
+
+impl CustomGame {
+    /// The rules chosen for the next run.
+    pub(crate) fn from_settings(settings: &Settings) -> Self {
+        Self {
+            name: String::new(),
+            mode: settings.mode,
+            fuse: settings.fuse_modifier,
+            mirror: settings.mirror_modifier,
+            stamina: settings.stamina_modifier,
+            side_walls: settings.side_walls.contains(&settings.mode),
+            physics: settings.physics,
+            board_layout: BoardLayout::named(&settings.board_layout).name,
+            pieces: settings.piece_set(settings.mode),
+        }
+    }
+
+    /// The rules of the run on a board.
+    pub(crate) fn from_board(board: &Board) -> Self {
+        Self {
+            name: String::new(),
+            mode: board.mode,
+            fuse: board.fuse,
+            mirror: board.mirror,
+            stamina: board.stamina,
+            side_walls: board.side_walls,
+            physics: board.physics,
+            board_layout: board.layout.clone(),
+            pieces: board.pieces,
+        }
+    }
+
+    /// Choose these rules for the next run.
+    pub(crate) fn apply(&self, settings: &mut Settings) {
+        settings.mode = self.mode;
+        settings.fuse_modifier = self.fuse;
+        settings.mirror_modifier = self.mirror;
+        settings.stamina_modifier = self.stamina;
+        if self.side_walls {
+            settings.side_walls.insert(self.mode);
+        } else {
+            settings.side_walls.remove(&self.mode);
+        }
+        settings.physics = self.physics;
+        settings.board_layout = self.board_layout.clone();
+        if self.pieces == PieceSet::default() {
+            settings.piece_sets.remove(&self.mode);
+        } else {
+            settings.piece_sets.insert(self.mode, self.pieces);
+        }
+    }
+
+    /// The rules as a short code to share, e.g. `NT2-Classic-1000-85.80.110-Standard-All`: the
+    /// mode, the fuse, mirror, stamina and side wall choices, the damping times ten, push force
+    /// and torque, the board layout and the piece set. The name is left out. Modes, board
+    /// layouts and piece sets are given by name, so that codes stay valid when more are added.
+    pub(crate) fn challenge_code(&self) -> String {
+        format!(
+            "{CHALLENGE_CODE_PREFIX}-{}-{}{}{}{}-{}.{}.{}-{}-{}",
+            code_name(self.mode.name()),
+            self.fuse as u8,
+            index_of(&MirrorModifier::ALL, self.mirror),
+            self.stamina as u8,
+            self.side_walls as u8,
+            (self.physics.linear_damping * 10.0).round() as i32,
+            self.physics.movement_force.round() as i32,
+            self.physics.torque.round() as i32,
+            code_name(&self.board_layout),
+            self.pieces.code_name(),
+        )
+    }
+
+    /// The rules of a challenge code, `None` if it isn't a valid one. Physics values out of
+    /// the ranges of the settings are brought within them.
+    pub(crate) fn from_challenge_code(code: &str) -> Option<Self> {
+        let code = code.trim();
+        let mut parts = code.split('-');
+        if parts.next()? != CHALLENGE_CODE_PREFIX {
+            return None;
+        }
+
+        let mode_name = parts.next()?;
+        let mode = GameMode::ALL
+            .into_iter()
+            .find(|mode| code_name(mode.name()) == mode_name)?;
+
+        let flags: Vec<u32> = parts
+            .next()?
+            .chars()
+            .map(|flag| flag.to_digit(10))
+            .collect::<Option<_>>()?;
+        let [fuse, mirror, stamina, side_walls] = flags[..] else {
+            return None;
+        };
+        let flag = |value: u32| (value <= 1).then_some(value == 1);
+
+        let mut physics = parts
+            .next()?
+            .split('.')
+            .map(|value| value.parse::<f32>().ok());
+        let physics = PhysicsTuning {
+            linear_damping: physics.next()?? / 10.0,
+            movement_force: physics.next()??,
+            torque: physics.next()??,
+        }
+        .clamped()?;
+
+        let layout_name = parts.next()?;
+        let board_layout = BoardLayout::all()
+            .into_iter()
+            .find(|layout| code_name(&layout.name) == layout_name)?
+            .name;
+        let pieces = PieceSet::from_code_name(parts.next()?)?;
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            name: format!("Challenge {code}"),
+            mode,
+            fuse: flag(fuse)?,
+            mirror: *MirrorModifier::ALL.get(mirror as usize)?,
+            stamina: flag(stamina)?,
+            side_walls: flag(side_walls)?,
+            physics,
+            board_layout,
+            pieces,
+        })
+    }
+}
+
+/// A name as it appears in challenge codes, its letters and digits.
+pub(crate) fn code_name(name: &str) -> String {
+    name.chars()
+        .filter(|character| character.is_ascii_alphanumeric())
+        .collect()
+}
+
+fn index_of<T: PartialEq>(all: &[T], value: T) -> usize {
+    all.iter().position(|item| *item == value).unwrap_or(0)
+}
+
+/// The choice `direction` steps after `value`, wrapping around at the ends.
+fn step<T: Copy + PartialEq>(all: &[T], value: T, direction: i32) -> T {
+    let index = index_of(all, value) as i32 + direction;
+    all[index.rem_euclid(all.len() as i32) as usize]
+}
+
+/// The custom game menu, with the rules being put together.
+#[derive(Component)]
+pub(crate) struct CustomGameScreen {
+    pub(crate) draft: CustomGame,
+    /// Index of the saved custom game to load
+    pub(crate) saved: usize,
+}
+
+#[derive(Component)]
+pub(crate) struct CustomGameLabel {
+    pub(crate) item: CustomGameItem,
+}
+
+/// Typing the name to save a custom game under. Menus don't take input meanwhile.
+#[derive(Resource)]
+pub(crate) struct CustomGameNameEntry {
+    pub(crate) name: String,
+}
+
+/// Entries of the custom game menu.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum CustomGameItem {
+    Rules,
+    Fuse,
+    Mirror,
+    Stamina,
+    SideWalls,
+    Physics,
+    Board,
+    Pieces,
+    Saved,
+    Play,
+    Save,
+    Export,
+    Back,
+}
+
+impl CustomGameItem {
+    pub(crate) const ALL: [Self; 13] = [
+        Self::Rules,
+        Self::Fuse,
+        Self::Mirror,
+        Self::Stamina,
+        Self::SideWalls,
+        Self::Physics,
+        Self::Board,
+        Self::Pieces,
+        Self::Saved,
+        Self::Play,
+        Self::Save,
+        Self::Export,
+        Self::Back,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Rules => "Rules",
+            Self::Fuse => "Fuse",
+            Self::Mirror => "Mirror",
+            Self::Stamina => "Stamina",
+            Self::SideWalls => "Side walls",
+            Self::Physics => "Physics",
+            Self::Board => "Board (next launch)",
+            Self::Pieces => "Pieces",
+            Self::Saved => "Load saved",
+            Self::Play => "Play",
+            Self::Save => "Save as...",
+            Self::Export => "Export challenge code",
+            Self::Back => "Back",
+        }
+    }
+
+    /// The current value, `None` for entries that aren't values.
+    pub(crate) fn value(
+        &self,
+        screen: &CustomGameScreen,
+        profile: &Profile,
+        piece_definitions: &PieceDefinitions,
+    ) -> Option<String> {
+        let on_off = |on: bool| if on { "On" } else { "Off" }.to_string();
+        let draft = &screen.draft;
+
+        match self {
+            Self::Rules => Some(draft.mode.name().to_string()),
+            Self::Fuse => Some(on_off(draft.fuse)),
+            Self::Mirror => Some(draft.mirror.name().to_string()),
+            Self::Stamina => Some(on_off(draft.stamina)),
+            Self::SideWalls => Some(on_off(draft.side_walls)),
+            Self::Physics => Some(
+                draft
+                    .physics
+                    .profile()
+                    .map_or("Custom", |profile| profile.name())
+                    .to_string(),
+            ),
+            Self::Board => Some(draft.board_layout.clone()),
+            Self::Pieces => Some(draft.pieces.name(piece_definitions).to_string()),
+            Self::Saved => Some(match profile.custom_games.get(screen.saved) {
+                Some(game) => format!(
+                    "{} ({}/{})",
+                    game.name,
+                    screen.saved + 1,
+                    profile.custom_games.len()
+                ),
+                None => "none yet".to_string(),
+            }),
+            Self::Play | Self::Save | Self::Export | Self::Back => None,
+        }
+    }
+
+    /// Step the value up or down, wrapping around at the ends.
+    pub(crate) fn adjust(
+        &self,
+        screen: &mut CustomGameScreen,
+        n_saved: usize,
+        piece_definitions: &PieceDefinitions,
+        direction: i32,
+    ) {
+        let draft = &mut screen.draft;
+
+        match self {
+            Self::Rules => draft.mode = step(&GameMode::ALL, draft.mode, direction),
+            Self::Fuse => draft.fuse = !draft.fuse,
+            Self::Mirror => draft.mirror = step(&MirrorModifier::ALL, draft.mirror, direction),
+            Self::Stamina => draft.stamina = !draft.stamina,
+            Self::SideWalls => draft.side_walls = !draft.side_walls,
+            Self::Physics => {
+                // Physics tuned in the settings steps to the profiles around the standard one
+                let profile = draft.physics.profile().unwrap_or(PhysicsProfile::Standard);
+                draft.physics = step(&PhysicsProfile::ALL, profile, direction).tuning();
+            }
+            Self::Board => {
+                let names: Vec<String> = BoardLayout::all()
+                    .into_iter()
+                    .map(|layout| layout.name)
+                    .collect();
+                let index = names
+                    .iter()
+                    .position(|name| *name == draft.board_layout)
+                    .unwrap_or(0) as i32;
+                draft.board_layout =
+                    names[(index + direction).rem_euclid(names.len() as i32) as usize].clone();
+            }
+            Self::Pieces => {
+                draft.pieces = step(&PieceSet::all(piece_definitions), draft.pieces, direction)
+            }
+            Self::Saved => {
+                if n_saved > 0 {
+                    screen.saved =
+                        (screen.saved as i32 + direction).rem_euclid(n_saved as i32) as usize;
+                }
+            }
+            Self::Play | Self::Save | Self::Export | Self::Back => {}
+        }
+    }
+}
+
+/// Opened from the main menu, starting from the rules chosen for the next run.
+pub(crate) fn spawn_custom_game_screen(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    settings: &Settings,
+) {
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.95).into(),
+            ..Default::default()
+        })
+        .insert(CustomGameScreen {
+            draft: CustomGame::from_settings(settings),
+            saved: 0,
+        })
+        .insert(Menu {
+            focused: 0,
+            layer: CONTROLS_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Custom game", 40.0, Color::WHITE));
+        // Filled in by `custom_game_labels`
+        for (index, item) in CustomGameItem::ALL.into_iter().enumerate() {
+            parent
+                .spawn(fonts.text_bundle("", 24.0, Color::WHITE))
+                .insert(MenuItem { menu, index })
+                .insert(CustomGameLabel { item });
+        }
+        parent.spawn(fonts.text_bundle(
+            "Left/right or confirm to change a value. Play with `--challenge <code>`",
+            16.0,
+            Color::WHITE,
+        ));
+    });
+}
+
+/// Saved names may be in any script, so labels are rebuilt with a section per script when
+/// they change.
+pub(crate) fn custom_game_labels(
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    piece_definitions: Res<PieceDefinitions>,
+    name_entry: Option<Res<CustomGameNameEntry>>,
+    screen_query: Query<&CustomGameScreen>,
+    mut label_query: Query<(&CustomGameLabel, &mut Text)>,
+) {
+    let Ok(screen) = screen_query.get_single() else {
+        return;
+    };
+
+    let unchanged = |text: &Text, value: &str| {
+        text.sections
+            .iter()
+            .map(|section| section.value.as_str())
+            .eq(fonts
+                .sections(value, 0.0, Color::WHITE)
+                .iter()
+                .map(|section| section.value.as_str()))
+    };
+
+    for (label, mut text) in label_query.iter_mut() {
+        let value = match (label.item, &name_entry) {
+            (CustomGameItem::Save, Some(entry)) => format!("Save as: {}_", entry.name),
+            (item, _) => match item.value(screen, &profile, &piece_definitions) {
+                Some(value) => format!("{}: {value}", item.name()),
+                None => item.name().to_string(),
+            },
+        };
+
+        // The color belongs to `menu_focus_highlight`
+        if !unchanged(&text, &value) {
+            *text = fonts.text(&value, 24.0, Color::WHITE);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn custom_game_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    action_state: Res<ActionState>,
+    fonts: Res<Fonts>,
+    name_entry: Option<Res<CustomGameNameEntry>>,
+    profile: Res<Profile>,
+    mut settings: ResMut<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut run_started_events: EventWriter<RunStarted>,
+    mut screen_query: Query<(Entity, &Menu, &mut CustomGameScreen)>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    block_query: Query<(Entity, &Block)>,
+) {
+    let Ok((screen_entity, menu, mut screen)) = screen_query.get_single_mut() else {
+        return;
+    };
+    let n_saved = profile.custom_games.len();
+
+    let mut close = cancelled_events
+        .iter()
+        .any(|event| event.menu == screen_entity);
+
+    for event in confirmed_events.iter() {
+        if event.menu != screen_entity {
+            continue;
+        }
+
+        match CustomGameItem::ALL[event.item] {
+            CustomGameItem::Saved => {
+                if let Some(game) = profile.custom_games.get(screen.saved) {
+                    screen.draft = game.clone();
+                    spawn_toast(&mut commands, &fonts, &format!("Loaded {}", game.name));
+                }
+            }
+            CustomGameItem::Play => {
+                // The boards are set up at launch, see `setup_game`
+                let versus_boards = board_query
+                    .iter()
+                    .any(|(_, board, _, _, _)| board.player.is_some());
+                if versus_boards != (screen.draft.mode == GameMode::Versus)
+                    || screen.draft.board_layout != BoardLayout::named(&settings.board_layout).name
+                {
+                    spawn_toast(
+                        &mut commands,
+                        &fonts,
+                        "The board and Versus mode change on the next launch",
+                    );
+                }
+
+                screen.draft.apply(&mut settings);
+                settings.practice = None;
+                settings.save();
+
+                for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in
+                    board_query.iter_mut()
+                {
+                    // Started by the opponent online
+                    if board.remote {
+                        continue;
+                    }
+                    run_started_events.send(start_run(
+                        &mut commands,
+                        board_entity,
+                        &mut board,
+                        &mut active_pieces,
+                        &mut stats,
+                        &settings,
+                        &piece_definitions,
+                        &mut rng,
+                        block_query
+                            .iter()
+                            .filter(|(_, block)| block.board == board_entity)
+                            .map(|(entity, _)| entity),
+                    ));
+                }
+                next_state.set(AppState::Playing);
+            }
+            CustomGameItem::Save => {
+                let name = if screen.draft.name.is_empty() {
+                    format!("Custom {}", n_saved + 1)
+                } else {
+                    screen.draft.name.clone()
+                };
+                commands.insert_resource(CustomGameNameEntry { name });
+            }
+            CustomGameItem::Export => {
+                let code = screen.draft.challenge_code();
+                info!("challenge code: {code}");
+
+                let message = if copy_to_clipboard(&code) {
+                    format!("Challenge code {code} copied")
+                } else {
+                    format!("Challenge code: {code}")
+                };
+                spawn_toast(&mut commands, &fonts, &message);
+            }
+            CustomGameItem::Back => close = true,
+            item => item.adjust(&mut screen, n_saved, &piece_definitions, 1),
+        }
+    }
+
+    // The arrow keys edit the name while it's being entered
+    let direction = action_state.just_pressed(Action::MoveRight) as i32
+        - action_state.just_pressed(Action::MoveLeft) as i32;
+    if direction != 0 && name_entry.is_none() {
+        CustomGameItem::ALL[menu.focused].adjust(
+            &mut screen,
+            n_saved,
+            &piece_definitions,
+            direction,
+        );
+    }
+
+    if close {
+        commands.entity(screen_entity).despawn_recursive();
+    }
+}
+
+/// Type the name of the custom game being saved. Return saves it, replacing any saved under
+/// the same name, and escape gives up.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn custom_game_name_entry(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    mut character_events: EventReader<ReceivedCharacter>,
+    fonts: Res<Fonts>,
+    mut entry: ResMut<CustomGameNameEntry>,
+    mut profile: ResMut<Profile>,
+    mut screen_query: Query<&mut CustomGameScreen>,
+) {
+    for event in character_events.iter() {
+        let allowed = event.char.is_alphanumeric() || event.char == ' ' || event.char == '-';
+        if allowed && entry.name.chars().count() < CUSTOM_GAME_NAME_MAX_LENGTH {
+            entry.name.push(event.char);
+        }
+    }
+
+    if input_map.just_pressed(&input, Action::EntryDelete) {
+        entry.name.pop();
+    }
+
+    let Ok(mut screen) = screen_query.get_single_mut() else {
+        commands.remove_resource::<CustomGameNameEntry>();
+        return;
+    };
+
+    if input_map.just_pressed(&input, Action::EntryCancel) {
+        commands.remove_resource::<CustomGameNameEntry>();
+        return;
+    }
+
+    let name = entry.name.trim().to_string();
+    if !input_map.just_pressed(&input, Action::EntryConfirm) || name.is_empty() {
+        return;
+    }
+
+    screen.draft.name = name.clone();
+    match profile
+        .custom_games
+        .iter()
+        .position(|game| game.name == name)
+    {
+        Some(index) => {
+            profile.custom_games[index] = screen.draft.clone();
+            screen.saved = index;
+        }
+        None => {
+            profile.custom_games.push(screen.draft.clone());
+            screen.saved = profile.custom_games.len() - 1;
+        }
+    }
+    profile.save();
+
+    spawn_toast(&mut commands, &fonts, &format!("Saved {name}"));
+    commands.remove_resource::<CustomGameNameEntry>();
+}
+
+/// Choose the rules of the challenge given with `--challenge <code>` for the next run.
+pub(crate) fn apply_challenge_argument(mut settings: ResMut<Settings>) {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(code) = args
+        .iter()
+        .position(|arg| arg == "--challenge")
+        .and_then(|index| args.get(index + 1))
+    else {
+        return;
+    };
+
+    match CustomGame::from_challenge_code(code) {
+        Some(game) => {
+            info!("playing challenge {code}");
+            game.apply(&mut settings);
+        }
+        None => warn!("ignoring invalid challenge code {code}"),
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &str) -> bool {
+    let result =
+        arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text.to_string()));
+
+    if let Err(err) = &result {
+        warn!("could not copy the challenge code: {err}");
+    }
+    result.is_ok()
+}
+
+/// Only with the `clipboard` feature.
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &str) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::{LINEAR_DAMPING_RANGE, MOVEMENT_FORCE_RANGE, TORQUE_RANGE};
+
+    #[test]
+    fn challenge_code_round_trip() {
+        let layouts = BoardLayout::all();
+        let games = [
+            CustomGame::default(),
+            CustomGame {
+                mode: GameMode::ALL[GameMode::ALL.len() - 1],
+                fuse: true,
+                mirror: MirrorModifier::Random,
+                stamina: true,
+                side_walls: true,
+                physics: PhysicsTuning {
+                    linear_damping: 12.5,
+                    movement_force: 140.0,
+                    torque: 60.0,
+                },
+                board_layout: layouts[layouts.len() - 1].name.clone(),
+                pieces: PieceSet::Custom(0),
+                ..CustomGame::default()
+            },
+        ];
+
+        for game in games {
+            let code = game.challenge_code();
+            let decoded = CustomGame::from_challenge_code(&code).expect("invalid code");
+
+            assert_eq!(decoded.mode, game.mode, "{code}");
+            assert_eq!(decoded.board_layout, game.board_layout, "{code}");
+            assert_eq!(
+                CustomGame {
+                    name: String::new(),
+                    ..decoded
+                },
+                game,
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_challenge_codes_are_rejected() {
+        let code = CustomGame::default().challenge_code();
+        let too_long = format!("{code}-0");
+        let cut_short = &code[..code.len() - 2];
+
+        for code in [
+            "",
+            "-",
+            "NT2",
+            "NT1-Classic-0000-85.80.110-Standard-All",
+            "NT2-Nonexistent-0000-85.80.110-Standard-All",
+            "NT2-Classic-0900-85.80.110-Standard-All",
+            "NT2-Classic-000-85.80.110-Standard-All",
+            "NT2-Classic-00000-85.80.110-Standard-All",
+            "NT2-Classic-0000-85.80-Standard-All",
+            "NT2-Classic-0000-x.y.z-Standard-All",
+            "NT2-Classic-0000-NaN.80.110-Standard-All",
+            "NT2-Classic-0000-85.inf.110-Standard-All",
+            "NT2-Classic-0000-85.80.-inf-Standard-All",
+            "NT2-Classic-0000-85.80.110-Nonexistent-All",
+            "NT2-Classic-0000-85.80.110-Standard-Nonexistent",
+            "NT2-Classic-0000-85.80.110-Standard-",
+            "NT2-Classic-0é00-85.80.110-Standard-All",
+            "NT2-0-0000-85.80.110-0-0",
+            too_long.as_str(),
+            cut_short,
+        ] {
+            assert_eq!(CustomGame::from_challenge_code(code), None, "{code}");
+        }
+    }
+
+    #[test]
+    fn challenge_code_physics_is_brought_within_the_settings_ranges() {
+        let code = CustomGame::default().challenge_code();
+        let code = code.replace(
+            &format!("-{}-", code.split('-').nth(3).unwrap()),
+            "-1000.0.99999-",
+        );
+        let game = CustomGame::from_challenge_code(&code).expect("invalid code");
+
+        assert_eq!(
+            game.physics,
+            PhysicsTuning {
+                linear_damping: LINEAR_DAMPING_RANGE.1,
+                movement_force: MOVEMENT_FORCE_RANGE.0,
+                torque: TORQUE_RANGE.1,
+            }
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Block damage: hard knocks between blocks, or against the floor, crack the blocks and finally
+//! shatter them. Only on boards with block damage on.
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::Board;
+use crate::effects::start_clear_flash;
+use crate::tetromino::{ActivePieces, Block};
+use crate::GameplaySet;
+
+/// Durability of the blocks, worn down by contact impulses.
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(attach_durability)
+            .add_system(block_damage.in_set(GameplaySet));
+    }
+}
+
+// Contact impulses below this are wear a block shrugs off, such as the weight of the stack on it.
+// A block landing at about this many blocks per second takes it.
+pub(crate) const DAMAGE_IMPULSE_THRESHOLD: f32 = 8.0;
+// Damage a block takes, beyond the threshold, before it shatters
+pub(crate) const BLOCK_DURABILITY: f32 = 30.0;
+// A block shows another crack every time its damage passes one of these shares of its durability
+pub(crate) const CRACK_STAGES: [f32; 2] = [0.3, 0.65];
+pub(crate) const CRACK_WIDTH: f32 = 0.06;
+pub(crate) const CRACK_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.55);
+
+/// Damage a block has taken, see `BLOCK_DURABILITY`.
+#[derive(Component, Default)]
+pub(crate) struct Durability {
+    pub(crate) damage: f32,
+    /// Cracks shown so far, see `CRACK_STAGES`
+    pub(crate) cracks: usize,
+}
+
+/// Crack drawn on a damaged block.
+#[derive(Component)]
+pub(crate) struct Crack;
+
+/// Give new blocks on boards with block damage a durability. Their contact forces are reported
+/// down to `DAMAGE_IMPULSE_THRESHOLD`, see `Board::contact_impulse_threshold`.
+pub(crate) fn attach_durability(
+    mut commands: Commands,
+    board_query: Query<&Board>,
+    block_query: Query<(Entity, &Block), Added<Block>>,
+) {
+    for (block_entity, block) in block_query.iter() {
+        let Ok(board) = board_query.get(block.board) else {
+            continue;
+        };
+        if !board.block_damage {
+            continue;
+        }
+
+        commands.entity(block_entity).insert(Durability::default());
+    }
+}
+
+/// Wear blocks down by the impulses of their hard contacts, crack them, and shatter the ones
+/// worn out once they are no longer part of a live piece.
+pub(crate) fn block_damage(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    piece_query: Query<&ActivePieces>,
+    board_query: Query<&Board>,
+    mut block_query: Query<(Entity, &Block, &mut Durability, Option<&Sprite>)>,
+) {
+    let dt = rapier_context.integration_parameters.dt;
+
+    for event in contact_force_events.iter() {
+        let impulse = event.total_force_magnitude * dt;
+        if impulse <= DAMAGE_IMPULSE_THRESHOLD {
+            continue;
+        }
+
+        // Both blocks take the knock, or just the one hitting the floor
+        for collider in [event.collider1, event.collider2] {
+            if let Ok((_, _, mut durability, _)) = block_query.get_mut(collider) {
+                durability.damage += impulse - DAMAGE_IMPULSE_THRESHOLD;
+            }
+        }
+    }
+
+    for (block_entity, block, mut durability, sprite) in block_query.iter_mut() {
+        let wear = durability.damage / BLOCK_DURABILITY;
+
+        if wear >= 1.0 {
+            let live = piece_query
+                .get(block.board)
+                .is_ok_and(|active_pieces| active_pieces.contains(&block_entity));
+            if !live {
+                let color = match sprite {
+                    Some(sprite) => sprite.color,
+                    None => board_query.get(block.board).map_or(Color::WHITE, |board| {
+                        block.kind.color(&board.piece_definitions)
+                    }),
+                };
+                start_clear_flash(&mut commands, block_entity, color, block.size);
+            }
+            continue;
+        }
+
+        let stage = CRACK_STAGES
+            .iter()
+            .filter(|threshold| wear >= **threshold)
+            .count();
+        while durability.cracks < stage {
+            spawn_crack(&mut commands, block_entity, block.size, durability.cracks);
+            durability.cracks += 1;
+        }
+    }
+}
+
+/// Draw the crack of a stage on a block, across it and at a different angle for each stage.
+pub(crate) fn spawn_crack(commands: &mut Commands, block_entity: Entity, size: f32, stage: usize) {
+    let (angle, offset) = match stage {
+        0 => (0.7, Vec2::new(-0.05, 0.05)),
+        _ => (-0.4, Vec2::new(0.1, -0.1)),
+    };
+
+    commands.entity(block_entity).with_children(|parent| {
+        parent
+            .spawn(SpriteBundle {
+                transform: Transform::from_translation((offset * size).extend(0.05))
+                    .with_rotation(Quat::from_rotation_z(angle)),
+                sprite: Sprite {
+                    color: CRACK_COLOR,
+                    custom_size: Some(Vec2::new(0.9 * size, CRACK_WIDTH * size)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Crack);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::effects::ClearFlash;
+    use crate::settings::test_world;
+    use crate::tetromino::{ActivePiece, TetrominoKind};
+
+    fn spawn_block(world: &mut World, board: Entity) -> Entity {
+        world
+            .spawn((
+                Block {
+                    board,
+                    kind: TetrominoKind::T,
+                    size: 1.0,
+                },
+                Durability::default(),
+            ))
+            .id()
+    }
+
+    fn knock(world: &mut World, collider1: Entity, collider2: Entity, impulse: f32) {
+        let dt = world.resource::<RapierContext>().integration_parameters.dt;
+        world.send_event(ContactForceEvent {
+            collider1,
+            collider2,
+            total_force: Vec2::ZERO,
+            total_force_magnitude: impulse / dt,
+            max_force_direction: Vec2::ZERO,
+            max_force_magnitude: impulse / dt,
+        });
+    }
+
+    fn run(world: &mut World) {
+        let mut schedule = Schedule::new();
+        schedule.add_system(block_damage);
+        schedule.run(world);
+        world.resource_mut::<Events<ContactForceEvent>>().update();
+    }
+
+    fn world() -> World {
+        let mut world = test_world();
+        world.init_resource::<RapierContext>();
+        world.init_resource::<Events<ContactForceEvent>>();
+        world
+    }
+
+    #[test]
+    fn hard_knocks_crack_both_blocks_and_soft_ones_do_not() {
+        let mut world = world();
+        let board = world.spawn(ActivePieces::default()).id();
+        let block = spawn_block(&mut world, board);
+        let other_block = spawn_block(&mut world, board);
+
+        knock(
+            &mut world,
+            block,
+            other_block,
+            DAMAGE_IMPULSE_THRESHOLD * 0.9,
+        );
+        run(&mut world);
+        assert_eq!(world.get::<Durability>(block).unwrap().damage, 0.0);
+
+        let impulse = DAMAGE_IMPULSE_THRESHOLD + BLOCK_DURABILITY * 0.5;
+        knock(&mut world, block, other_block, impulse);
+        run(&mut world);
+        for block in [block, other_block] {
+            assert_eq!(world.get::<Durability>(block).unwrap().cracks, 1);
+            assert_eq!(world.get::<Children>(block).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn worn_out_blocks_shatter_unless_part_of_the_piece_in_play() {
+        let mut world = world();
+        let board = world.spawn_empty().id();
+        let settled_block = spawn_block(&mut world, board);
+        let live_block = spawn_block(&mut world, board);
+        let mut piece = ActivePiece::default();
+        piece.blocks.insert(live_block);
+        let active_pieces = ActivePieces {
+            pieces: vec![piece],
+            ..Default::default()
+        };
+        world.entity_mut(board).insert(active_pieces);
+
+        let impulse = DAMAGE_IMPULSE_THRESHOLD + BLOCK_DURABILITY;
+        knock(&mut world, settled_block, live_block, impulse);
+        run(&mut world);
+
+        assert!(world.get::<ClearFlash>(settled_block).is_some());
+        assert!(world.get::<Block>(settled_block).is_none());
+        assert!(world.get::<ClearFlash>(live_block).is_none());
+    }
+}
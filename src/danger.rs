@@ -0,0 +1,242 @@
+//! Warning of a stack reaching up towards where new pieces spawn: the top of the board is tinted
+//! red, a warning sounds and a sign flashes above the board, before a piece spawns into the stack.
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::Board;
+use crate::settings::Settings;
+use crate::tetromino::Block;
+use crate::ui::{Fonts, WorldHudText, WORLD_TEXT_FONT_SIZE, WORLD_TEXT_SCALE};
+
+/// Warning of stacks close to the spawn zone.
+pub struct DangerPlugin;
+
+impl Plugin for DangerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(setup_danger_warning)
+            .add_system(stack_danger.after(setup_danger_warning));
+    }
+}
+
+// Rows between the top of the stack and the top of the board, where pieces spawn, at which the
+// warning starts
+pub(crate) const DANGER_ROWS: f32 = 3.0;
+pub(crate) const DANGER_COLOR: Color = Color::rgba(1.0, 0.1, 0.05, 0.0);
+// Alpha of the tint with the stack at the top of the board, pulsing down to a share of it...
+pub(crate) const DANGER_MAX_ALPHA: f32 = 0.3;
+pub(crate) const DANGER_PULSE_MIN: f32 = 0.5;
+// ...this often, in seconds. The sign flashes at the same pace.
+pub(crate) const DANGER_PULSE_PERIOD: f32 = 0.8;
+pub(crate) const DANGER_SOUND_PATH: &str = "sounds/danger.wav";
+
+/// Tint over the top rows of a board.
+#[derive(Component)]
+pub(crate) struct DangerZone {
+    pub(crate) board: Entity,
+    /// Whether the warning has sounded since the stack came close, to sound once each time
+    pub(crate) warned: bool,
+}
+
+/// Sign above a board, flashing while its stack is close to the top.
+#[derive(Component)]
+pub(crate) struct DangerIndicator {
+    pub(crate) board: Entity,
+}
+
+/// Add the tint and the sign of new boards, hidden until needed.
+pub(crate) fn setup_danger_warning(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    board_query: Query<(Entity, &Board), Added<Board>>,
+) {
+    for (board_entity, board) in board_query.iter() {
+        // Over the board dressing and the row highlights, behind the blocks
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_xyz(
+                    board.center.x,
+                    board.top_y() - DANGER_ROWS * 0.5,
+                    -0.5,
+                ),
+                sprite: Sprite {
+                    color: DANGER_COLOR,
+                    custom_size: Some(Vec2::new(board.width(), DANGER_ROWS)),
+                    ..Default::default()
+                },
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            })
+            .insert(DangerZone {
+                board: board_entity,
+                warned: false,
+            });
+
+        commands
+            .spawn(Text2dBundle {
+                text: fonts
+                    .text("DANGER", WORLD_TEXT_FONT_SIZE, Color::rgb(1.0, 0.2, 0.1))
+                    .with_alignment(TextAlignment::Center),
+                transform: Transform::from_xyz(board.center.x, board.top_y() + 1.5, 2.0)
+                    .with_scale(Vec3::new(WORLD_TEXT_SCALE, WORLD_TEXT_SCALE, 1.0)),
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            })
+            .insert(DangerIndicator {
+                board: board_entity,
+            })
+            .insert(WorldHudText);
+    }
+}
+
+/// Measure the highest resting block of each board, and warn while it is within `DANGER_ROWS`
+/// of the top. The closer, the stronger the tint.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn stack_danger(
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    // Missing without audio, such as in simulations
+    audio: Option<Res<Audio>>,
+    board_query: Query<&Board>,
+    block_query: Query<(&Block, &Transform, &Sleeping)>,
+    mut zone_query: Query<(&mut DangerZone, &mut Sprite, &mut Visibility)>,
+    mut indicator_query: Query<(&DangerIndicator, &mut Visibility), Without<DangerZone>>,
+) {
+    let mut stack_tops: HashMap<Entity, f32> = HashMap::new();
+    for (block, transform, sleeping) in block_query.iter() {
+        if !sleeping.sleeping {
+            continue;
+        }
+
+        let top = transform.translation.y + block.size * 0.5;
+        let stack_top = stack_tops.entry(block.board).or_insert(f32::MIN);
+        *stack_top = stack_top.max(top);
+    }
+
+    let phase = (time.elapsed_seconds() / DANGER_PULSE_PERIOD).fract();
+    let pulse = DANGER_PULSE_MIN + (1.0 - DANGER_PULSE_MIN) * (0.5 + 0.5 * (phase * TAU).cos());
+    let mut in_danger: HashMap<Entity, bool> = HashMap::new();
+
+    for (mut zone, mut sprite, mut visibility) in zone_query.iter_mut() {
+        let Ok(board) = board_query.get(zone.board) else {
+            continue;
+        };
+
+        let closeness = stack_tops.get(&zone.board).map_or(0.0, |stack_top| {
+            1.0 - (board.top_y() - stack_top) / DANGER_ROWS
+        });
+        let danger = closeness > 0.0;
+        in_danger.insert(zone.board, danger);
+
+        if danger && !zone.warned {
+            if let Some(audio) = &audio {
+                audio.play_with_settings(
+                    asset_server.load(DANGER_SOUND_PATH),
+                    PlaybackSettings::ONCE.with_volume(settings.volume),
+                );
+            }
+        }
+        zone.warned = danger;
+
+        if danger {
+            sprite
+                .color
+                .set_a(DANGER_MAX_ALPHA * closeness.min(1.0) * pulse);
+            *visibility = Visibility::Inherited;
+        } else {
+            *visibility = Visibility::Hidden;
+        }
+    }
+
+    for (indicator, mut visibility) in indicator_query.iter_mut() {
+        let danger = in_danger.get(&indicator.board).copied().unwrap_or(false);
+        *visibility = if danger && phase < 0.5 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::FileAssetIo;
+
+    use super::*;
+    use crate::board::BoardLayout;
+    use crate::modes::GameMode;
+    use crate::settings::test_world;
+    use crate::tetromino::TetrominoKind;
+
+    #[test]
+    fn resting_stacks_near_the_top_tint_the_board_and_flash_the_sign() {
+        let mut world = test_world();
+        world.insert_resource(Time::default());
+        world.insert_resource(AssetServer::new(FileAssetIo::new("assets", false)));
+        world.init_resource::<Settings>();
+        let board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Classic,
+            Vec2::ZERO,
+        );
+        let top_y = board.top_y();
+        let board = world.spawn(board).id();
+        let zone = world
+            .spawn((
+                DangerZone {
+                    board,
+                    warned: false,
+                },
+                Sprite {
+                    color: DANGER_COLOR,
+                    ..Default::default()
+                },
+                Visibility::Hidden,
+            ))
+            .id();
+        let indicator = world
+            .spawn((DangerIndicator { board }, Visibility::Hidden))
+            .id();
+        let block = |y: f32, sleeping: bool| {
+            (
+                Block {
+                    board,
+                    kind: TetrominoKind::O,
+                    size: 1.0,
+                },
+                Transform::from_xyz(0.0, y, 0.0),
+                Sleeping {
+                    sleeping,
+                    ..Default::default()
+                },
+            )
+        };
+        let falling = world.spawn(block(top_y - 0.5, false)).id();
+        let resting = world.spawn(block(top_y - DANGER_ROWS - 1.0, true)).id();
+        let mut schedule = Schedule::new();
+        schedule.add_system(stack_danger);
+
+        schedule.run(&mut world);
+        assert_eq!(world.get(zone), Some(&Visibility::Hidden));
+        assert_eq!(world.get(indicator), Some(&Visibility::Hidden));
+
+        world.despawn(falling);
+        world.get_mut::<Transform>(resting).unwrap().translation.y = top_y - 1.5;
+        schedule.run(&mut world);
+        let danger_zone = world.get::<DangerZone>(zone).unwrap();
+        assert!(danger_zone.warned);
+        assert_eq!(world.get(zone), Some(&Visibility::Inherited));
+        assert!(world.get::<Sprite>(zone).unwrap().color.a() > 0.0);
+        assert_eq!(world.get(indicator), Some(&Visibility::Inherited));
+
+        world.despawn(resting);
+        schedule.run(&mut world);
+        assert!(!world.get::<DangerZone>(zone).unwrap().warned);
+        assert_eq!(world.get(zone), Some(&Visibility::Hidden));
+        assert_eq!(world.get(indicator), Some(&Visibility::Hidden));
+    }
+}
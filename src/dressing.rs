@@ -0,0 +1,293 @@
+//! Board dressing: a background behind the playfield, a grid of its block slots and rows, and the
+//! edges of the well, so that the boundaries of the board are visible and not just its floor.
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::render::texture::DEFAULT_IMAGE_HANDLE;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Board, FLOOR_BLOCK_HEIGHT};
+use crate::settings::Settings;
+
+/// Background, grid and well edges of the boards.
+pub struct DressingPlugin;
+
+impl Plugin for DressingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_board_textures)
+            .add_system(dress_boards)
+            .add_system(apply_board_dressing.after(dress_boards));
+    }
+}
+
+pub(crate) const BACKGROUND_IMAGE_PATH: &str = "backgrounds/board.png";
+// Colors of the gradient background, at the top of the board and at the floor
+pub(crate) const GRADIENT_TOP_COLOR: [u8; 3] = [34, 40, 62];
+pub(crate) const GRADIENT_BOTTOM_COLOR: [u8; 3] = [8, 9, 14];
+pub(crate) const GRADIENT_STEPS: u32 = 64;
+pub(crate) const GRID_LINE_WIDTH: f32 = 0.02;
+pub(crate) const GRID_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.05);
+pub(crate) const WELL_EDGE_WIDTH: f32 = 0.08;
+pub(crate) const WELL_EDGE_COLOR: Color = Color::rgba(0.6, 0.6, 0.6, 0.5);
+
+/// What is shown behind the playfield.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum BoardBackground {
+    /// Nothing but the clear color
+    Plain,
+    /// Fading from slate at the top to black at the floor
+    #[default]
+    Gradient,
+    /// `BACKGROUND_IMAGE_PATH`, stretched over the board
+    Image,
+}
+
+impl BoardBackground {
+    pub(crate) const ALL: [Self; 3] = [Self::Plain, Self::Gradient, Self::Image];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Plain => "Plain",
+            Self::Gradient => "Gradient",
+            Self::Image => "Image",
+        }
+    }
+
+    pub(crate) fn cycle(&self, direction: i32) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|background| background == self)
+            .unwrap_or(0);
+        Self::ALL[(index as i32 + direction).rem_euclid(Self::ALL.len() as i32) as usize]
+    }
+}
+
+/// Textures of the backgrounds.
+#[derive(Resource)]
+pub(crate) struct BoardTextures {
+    pub(crate) gradient: Handle<Image>,
+    pub(crate) image: Handle<Image>,
+}
+
+/// Background of a board, behind everything on it.
+#[derive(Component)]
+pub(crate) struct BackgroundSprite;
+
+/// Line of the grid of a board.
+#[derive(Component)]
+pub(crate) struct GridLine;
+
+pub(crate) fn setup_board_textures(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    // A column of pixels, top row first, stretched over the board
+    let data = (0..GRADIENT_STEPS)
+        .flat_map(|step| {
+            let t = step as f32 / (GRADIENT_STEPS - 1) as f32;
+            let channel = |i: usize| {
+                let top = GRADIENT_TOP_COLOR[i] as f32;
+                let bottom = GRADIENT_BOTTOM_COLOR[i] as f32;
+                (top + (bottom - top) * t).round() as u8
+            };
+            [channel(0), channel(1), channel(2), 255]
+        })
+        .collect();
+    let gradient = Image::new(
+        Extent3d {
+            width: 1,
+            height: GRADIENT_STEPS,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+
+    commands.insert_resource(BoardTextures {
+        gradient: images.add(gradient),
+        image: asset_server.load(BACKGROUND_IMAGE_PATH),
+    });
+}
+
+/// Add the background, grid and well edges of new boards, behind the blocks and row highlights.
+pub(crate) fn dress_boards(mut commands: Commands, board_query: Query<&Board, Added<Board>>) {
+    for board in board_query.iter() {
+        let floor_bottom = board.floor_y() - 0.5;
+        let top_y = board.top_y();
+        let left_x = board.left_wall_x();
+        let right_x = left_x + board.width();
+        let line = |commands: &mut Commands, center: Vec2, size: Vec2, color: Color, z: f32| {
+            commands
+                .spawn(SpriteBundle {
+                    transform: Transform::from_translation(center.extend(z)),
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(size),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .id()
+        };
+
+        let background = line(
+            &mut commands,
+            Vec2::new(board.center.x, (floor_bottom + top_y) * 0.5),
+            Vec2::new(board.width(), top_y - floor_bottom),
+            Color::WHITE,
+            -3.0,
+        );
+        commands.entity(background).insert(BackgroundSprite);
+
+        // A line under every row, and the top of the board
+        for rect in board.rows() {
+            let grid_line = line(
+                &mut commands,
+                Vec2::new(rect.center().x, rect.min.y),
+                Vec2::new(rect.width(), GRID_LINE_WIDTH),
+                GRID_COLOR,
+                -2.0,
+            );
+            commands.entity(grid_line).insert(GridLine);
+        }
+        let grid_line = line(
+            &mut commands,
+            Vec2::new(board.center.x, top_y),
+            Vec2::new(board.width(), GRID_LINE_WIDTH),
+            GRID_COLOR,
+            -2.0,
+        );
+        commands.entity(grid_line).insert(GridLine);
+
+        // Lines between the block slots of each floor, up from the floor
+        for segment in &board.floors {
+            let segment_floor_y = board.segment_floor_y(segment) - 0.5;
+            let height = top_y - segment_floor_y;
+
+            for lane in segment.lanes.0..segment.lanes.1 {
+                let lane_left_x = board.lane_left_x(lane);
+                let n_slots = board.lane_widths[lane].floor().max(1.0) as usize;
+
+                for slot in 1..n_slots {
+                    let grid_line = line(
+                        &mut commands,
+                        Vec2::new(lane_left_x + slot as f32, segment_floor_y + height * 0.5),
+                        Vec2::new(GRID_LINE_WIDTH, height),
+                        GRID_COLOR,
+                        -2.0,
+                    );
+                    commands.entity(grid_line).insert(GridLine);
+                }
+                if lane > segment.lanes.0 {
+                    let grid_line = line(
+                        &mut commands,
+                        Vec2::new(lane_left_x, segment_floor_y + height * 0.5),
+                        Vec2::new(GRID_LINE_WIDTH, height),
+                        GRID_COLOR,
+                        -2.0,
+                    );
+                    commands.entity(grid_line).insert(GridLine);
+                }
+            }
+        }
+
+        // Edges of the well, from the bottom of the floor up, under the side walls when they
+        // are up
+        let wall_bottom = board.floor_y() - (FLOOR_BLOCK_HEIGHT - 0.5) - FLOOR_BLOCK_HEIGHT * 0.5;
+        let wall_height = top_y - wall_bottom;
+        for x in [
+            left_x - WELL_EDGE_WIDTH * 0.5,
+            right_x + WELL_EDGE_WIDTH * 0.5,
+        ] {
+            line(
+                &mut commands,
+                Vec2::new(x, wall_bottom + wall_height * 0.5),
+                Vec2::new(WELL_EDGE_WIDTH, wall_height),
+                WELL_EDGE_COLOR,
+                -1.0,
+            );
+        }
+    }
+}
+
+/// Show the background and the grid chosen in the settings, on new boards and when changed.
+pub(crate) fn apply_board_dressing(
+    settings: Res<Settings>,
+    textures: Res<BoardTextures>,
+    mut background_query: Query<(Ref<BackgroundSprite>, &mut Handle<Image>, &mut Visibility)>,
+    mut grid_query: Query<(Ref<GridLine>, &mut Visibility), Without<BackgroundSprite>>,
+) {
+    let changed = settings.is_changed();
+
+    for (background, mut texture, mut visibility) in background_query.iter_mut() {
+        if !changed && !background.is_added() {
+            continue;
+        }
+
+        *texture = match settings.board_background {
+            BoardBackground::Plain => DEFAULT_IMAGE_HANDLE.typed(),
+            BoardBackground::Gradient => textures.gradient.clone(),
+            BoardBackground::Image => textures.image.clone(),
+        };
+        *visibility = if settings.board_background == BoardBackground::Plain {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+
+    for (grid_line, mut visibility) in grid_query.iter_mut() {
+        if !changed && !grid_line.is_added() {
+            continue;
+        }
+
+        *visibility = if settings.board_grid {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::test_world;
+    use bevy::asset::HandleId;
+
+    #[test]
+    fn background_and_grid_follow_the_settings() {
+        let mut world = test_world();
+        world.init_resource::<Settings>();
+        let textures = BoardTextures {
+            gradient: Handle::weak(HandleId::random::<Image>()),
+            image: Handle::weak(HandleId::random::<Image>()),
+        };
+        let gradient = textures.gradient.clone();
+        world.insert_resource(textures);
+        let background = world
+            .spawn((
+                BackgroundSprite,
+                Handle::<Image>::default(),
+                Visibility::Hidden,
+            ))
+            .id();
+        let grid_line = world.spawn((GridLine, Visibility::Hidden)).id();
+        let mut schedule = Schedule::new();
+        schedule.add_system(apply_board_dressing);
+
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Handle<Image>>(background), Some(&gradient));
+        assert_eq!(world.get(background), Some(&Visibility::Inherited));
+        assert_eq!(world.get(grid_line), Some(&Visibility::Inherited));
+
+        let mut settings = world.resource_mut::<Settings>();
+        settings.board_background = BoardBackground::Plain;
+        settings.board_grid = false;
+        schedule.run(&mut world);
+        assert_eq!(world.get(background), Some(&Visibility::Hidden));
+        assert_eq!(world.get(grid_line), Some(&Visibility::Hidden));
+    }
+}
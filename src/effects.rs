@@ -0,0 +1,550 @@
+//! Visual effects playing out on top of the game, such as cleared blocks shattering, the camera
+//! shaking on hard impacts, and boards being torn down between runs.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::camera_controller;
+use crate::modes::Bomb;
+use crate::physics::GameTimeScale;
+use crate::scoring::RowCleared;
+use crate::tetromino::{Block, SpawnGrowth};
+
+/// Effects of cleared rows, flashing blocks and debris, of impacts shaking the camera, and of
+/// boards torn down between runs.
+pub struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(clear_flash)
+            .add_system(debris_fade)
+            .add_system(teardown_fade)
+            .add_system(impact_shake)
+            .add_system(row_clear_shake)
+            .add_system(camera_shake.after(camera_controller));
+    }
+}
+
+// In scaled seconds:
+pub(crate) const CLEAR_FLASH_DURATION: f32 = 0.2;
+pub(crate) const DEBRIS_LIFETIME: f32 = 0.8;
+// A shattered block splits into this many shards along each side
+pub(crate) const DEBRIS_SHARDS_PER_SIDE: usize = 2;
+pub(crate) const DEBRIS_SPEED: f32 = 5.0;
+// Extra upwards speed, so that the debris bursts up before falling
+pub(crate) const DEBRIS_LIFT: f32 = 3.0;
+pub(crate) const DEBRIS_SPIN: f32 = 8.0;
+// Sparks where a joint snaps, flying out evenly and fading out faster than debris
+pub(crate) const SPARK_COUNT: usize = 6;
+pub(crate) const SPARK_SIZE: f32 = 0.08;
+pub(crate) const SPARK_SPEED: f32 = 6.0;
+pub(crate) const SPARK_LIFETIME: f32 = 0.4;
+pub(crate) const SPARK_COLOR: Color = Color::rgb(1.0, 0.9, 0.6);
+
+// Camera shake, as a share of the strongest shake. A share of 1 takes this many seconds to
+// settle...
+pub(crate) const SHAKE_DURATION: f32 = 0.5;
+// ...from this far off at the start, and less than proportionally less for smaller shakes
+pub(crate) const SHAKE_AMPLITUDE: f32 = 0.35;
+// Contact impulses below this, between blocks or against the floor, don't shake the camera.
+// A piece landing at about this many blocks per second does.
+pub(crate) const IMPACT_SHAKE_IMPULSE: f32 = 12.0;
+// Shake per unit of impulse beyond that, up to at most a share of...
+pub(crate) const IMPACT_SHAKE_PER_IMPULSE: f32 = 0.02;
+pub(crate) const IMPACT_SHAKE_MAX: f32 = 0.4;
+pub(crate) const ROW_CLEAR_SHAKE: f32 = 0.5;
+pub(crate) const EXPLOSION_SHAKE: f32 = 1.0;
+
+// In real seconds, the game over slow motion is over by then
+pub(crate) const TEARDOWN_DURATION: f32 = 1.0;
+// Blocks of a board torn down are launched up at this speed...
+pub(crate) const TEARDOWN_LIFT: f32 = 12.0;
+// ...and sideways at this speed per unit of distance from the board center
+pub(crate) const TEARDOWN_SPREAD: f32 = 1.5;
+pub(crate) const TEARDOWN_SPIN: f32 = 4.0;
+
+/// Cleared block, out of the game, flashing before it shatters.
+#[derive(Component)]
+pub(crate) struct ClearFlash {
+    pub(crate) timer: Timer,
+    pub(crate) color: Color,
+    pub(crate) size: f32,
+}
+
+/// Shard of a shattered block, flying apart and fading out. Collides with nothing.
+#[derive(Component)]
+pub(crate) struct Debris {
+    pub(crate) timer: Timer,
+}
+
+/// On a board whose blocks are being scattered, before its next run starts.
+#[derive(Component)]
+pub(crate) struct Teardown {
+    pub(crate) timer: Timer,
+}
+
+/// Block of a board being torn down, out of the game, flying off and fading out.
+/// Collides with nothing.
+#[derive(Component)]
+pub(crate) struct TeardownBlock {
+    pub(crate) timer: Timer,
+}
+
+/// Scatter the blocks of a board to start its next run on a clean board: their joints are
+/// released, they are launched up and apart, and fade out over `TEARDOWN_DURATION`.
+pub(crate) fn start_teardown(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board_center: Vec2,
+    blocks: impl Iterator<Item = (Entity, Vec2)>,
+) {
+    for (block_entity, position) in blocks {
+        let offset = position.x - board_center.x;
+
+        commands.entity(block_entity).despawn_descendants();
+        commands
+            .entity(block_entity)
+            .remove::<(Block, Bomb, SpawnGrowth)>()
+            .insert((
+                RigidBody::Dynamic,
+                CollisionGroups::new(Group::NONE, Group::NONE),
+                // The impulse as a change of velocity, whatever the mass of the block
+                Velocity {
+                    linvel: Vec2::new(offset * TEARDOWN_SPREAD, TEARDOWN_LIFT),
+                    angvel: -offset.signum() * TEARDOWN_SPIN,
+                },
+                Sleeping::disabled(),
+                TeardownBlock {
+                    timer: Timer::from_seconds(TEARDOWN_DURATION, TimerMode::Once),
+                },
+            ));
+    }
+
+    commands.entity(board_entity).insert(Teardown {
+        timer: Timer::from_seconds(TEARDOWN_DURATION, TimerMode::Once),
+    });
+}
+
+/// Take a cleared block out of the game and the physics right away, and let it flash and
+/// shatter. Joints and glyphs attached to it go at once.
+pub(crate) fn start_clear_flash(
+    commands: &mut Commands,
+    block_entity: Entity,
+    color: Color,
+    size: f32,
+) {
+    commands.entity(block_entity).despawn_descendants();
+    commands
+        .entity(block_entity)
+        .remove::<(Block, Bomb, RigidBody, Collider)>()
+        .insert(ClearFlash {
+            timer: Timer::from_seconds(CLEAR_FLASH_DURATION, TimerMode::Once),
+            color,
+            size,
+        });
+}
+
+pub(crate) fn clear_flash(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut flash_query: Query<(Entity, &mut ClearFlash, &Transform, Option<&mut Sprite>)>,
+) {
+    let delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+
+    for (entity, mut flash, transform, sprite) in flash_query.iter_mut() {
+        flash.timer.tick(delta);
+
+        if !flash.timer.finished() {
+            // Sliced blocks are meshes instead, and don't flash
+            let Some(mut sprite) = sprite else {
+                continue;
+            };
+            // From white back towards the color of the block
+            let [r, g, b, a] = flash.color.as_rgba_f32();
+            let t = flash.timer.percent();
+            sprite.color = Color::rgba(
+                1.0 + (r - 1.0) * t,
+                1.0 + (g - 1.0) * t,
+                1.0 + (b - 1.0) * t,
+                a,
+            );
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+
+        let shard_size = flash.size / DEBRIS_SHARDS_PER_SIDE as f32;
+        let center = transform.translation.truncate();
+
+        for i in 0..DEBRIS_SHARDS_PER_SIDE {
+            for j in 0..DEBRIS_SHARDS_PER_SIDE {
+                let local = Vec3::new(
+                    (i as f32 + 0.5) * shard_size - flash.size * 0.5,
+                    (j as f32 + 0.5) * shard_size - flash.size * 0.5,
+                    0.0,
+                );
+                let offset = (transform.rotation * local).truncate();
+                // Flying apart from the center, spinning away from each other
+                let spin = if (i + j) % 2 == 0 {
+                    DEBRIS_SPIN
+                } else {
+                    -DEBRIS_SPIN
+                };
+
+                commands
+                    .spawn(SpriteBundle {
+                        transform: Transform::from_translation(
+                            (center + offset).extend(transform.translation.z),
+                        )
+                        .with_rotation(transform.rotation),
+                        sprite: Sprite {
+                            color: flash.color,
+                            custom_size: Some(Vec2::splat(shard_size)),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(RigidBody::Dynamic)
+                    .insert(Collider::cuboid(0.5 * shard_size, 0.5 * shard_size))
+                    .insert(CollisionGroups::new(Group::NONE, Group::NONE))
+                    .insert(Velocity {
+                        linvel: offset.normalize_or_zero() * DEBRIS_SPEED + Vec2::Y * DEBRIS_LIFT,
+                        angvel: spin,
+                    })
+                    .insert(Debris {
+                        timer: Timer::from_seconds(DEBRIS_LIFETIME, TimerMode::Once),
+                    });
+            }
+        }
+    }
+}
+
+/// Burst of sparks from where the joint between two blocks snapped. They fade out as debris.
+pub(crate) fn spawn_sparks(commands: &mut Commands, position: Vec2) {
+    for i in 0..SPARK_COUNT {
+        let direction = Vec2::from_angle(i as f32 * TAU / SPARK_COUNT as f32);
+
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_translation(position.extend(1.0)),
+                sprite: Sprite {
+                    color: SPARK_COLOR,
+                    custom_size: Some(Vec2::splat(SPARK_SIZE)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(RigidBody::Dynamic)
+            .insert(Collider::ball(0.5 * SPARK_SIZE))
+            .insert(CollisionGroups::new(Group::NONE, Group::NONE))
+            .insert(Velocity::linear(direction * SPARK_SPEED))
+            .insert(Debris {
+                timer: Timer::from_seconds(SPARK_LIFETIME, TimerMode::Once),
+            });
+    }
+}
+
+/// Fade out the blocks of torn down boards, and time the teardowns, in real time.
+pub(crate) fn teardown_fade(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut board_query: Query<&mut Teardown>,
+    mut block_query: Query<(Entity, &mut TeardownBlock, Option<&mut Sprite>)>,
+) {
+    for mut teardown in board_query.iter_mut() {
+        teardown.timer.tick(time.delta());
+    }
+
+    for (entity, mut teardown_block, sprite) in block_query.iter_mut() {
+        teardown_block.timer.tick(time.delta());
+
+        if teardown_block.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+        } else if let Some(mut sprite) = sprite {
+            sprite.color.set_a(1.0 - teardown_block.timer.percent());
+        }
+    }
+}
+
+pub(crate) fn debris_fade(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut debris_query: Query<(Entity, &mut Debris, &mut Sprite)>,
+) {
+    let delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+
+    for (entity, mut debris, mut sprite) in debris_query.iter_mut() {
+        debris.timer.tick(delta);
+
+        if debris.timer.finished() {
+            commands.entity(entity).despawn();
+        } else {
+            sprite.color.set_a(1.0 - debris.timer.percent());
+        }
+    }
+}
+
+/// Shaking of the camera, adding to the offset of `CameraController`. Shakes add up, and decay
+/// over time.
+#[derive(Component, Default)]
+pub(crate) struct CameraShake {
+    /// Share of the strongest shake, from 0 to 1
+    pub(crate) intensity: f32,
+}
+
+impl CameraShake {
+    pub(crate) fn add(&mut self, intensity: f32) {
+        self.intensity = (self.intensity + intensity).min(1.0);
+    }
+}
+
+/// Shake the camera for hard knocks, such as heavy landings, by the strongest impulse this frame.
+pub(crate) fn impact_shake(
+    rapier_context: Res<RapierContext>,
+    mut contact_force_events: EventReader<ContactForceEvent>,
+    block_query: Query<(), With<Block>>,
+    mut camera_query: Query<&mut CameraShake>,
+) {
+    let dt = rapier_context.integration_parameters.dt;
+    let impulse = contact_force_events
+        .iter()
+        .filter(|event| {
+            block_query.contains(event.collider1) || block_query.contains(event.collider2)
+        })
+        .map(|event| event.total_force_magnitude * dt)
+        .fold(0.0_f32, f32::max);
+    if impulse <= IMPACT_SHAKE_IMPULSE {
+        return;
+    }
+
+    let intensity =
+        ((impulse - IMPACT_SHAKE_IMPULSE) * IMPACT_SHAKE_PER_IMPULSE).min(IMPACT_SHAKE_MAX);
+    for mut shake in camera_query.iter_mut() {
+        shake.add(intensity);
+    }
+}
+
+pub(crate) fn row_clear_shake(
+    mut row_cleared_events: EventReader<RowCleared>,
+    mut camera_query: Query<&mut CameraShake>,
+) {
+    let n_rows = row_cleared_events.iter().count();
+    if n_rows == 0 {
+        return;
+    }
+
+    for mut shake in camera_query.iter_mut() {
+        shake.add(ROW_CLEAR_SHAKE * n_rows as f32);
+    }
+}
+
+/// Offset the camera by its shake, after `camera_controller` has placed it, and let the shake
+/// decay. Shakes grow with the square of their intensity, so that small ones stay subtle.
+pub(crate) fn camera_shake(
+    time: Res<Time>,
+    mut camera_query: Query<(&mut CameraShake, &mut Transform)>,
+) {
+    let t = time.elapsed_seconds();
+
+    for (mut shake, mut transform) in camera_query.iter_mut() {
+        if shake.intensity <= 0.0 {
+            continue;
+        }
+
+        let offset = Vec2::new((t * 53.0).sin(), (t * 71.0).cos())
+            * SHAKE_AMPLITUDE
+            * shake.intensity
+            * shake.intensity;
+        transform.translation.x += offset.x;
+        transform.translation.y += offset.y;
+
+        shake.intensity = (shake.intensity - time.delta_seconds() / SHAKE_DURATION).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+    use crate::settings::test_world;
+    use crate::tetromino::TetrominoKind;
+
+    #[test]
+    fn cleared_blocks_flash_then_shatter_into_shards() {
+        let mut world = test_world();
+        world.insert_resource(Time::default());
+        world.insert_resource(GameTimeScale::default());
+
+        let board = world.spawn_empty().id();
+        let block = world
+            .spawn(SpriteBundle::default())
+            .insert(Block {
+                board,
+                kind: TetrominoKind::O,
+                size: 1.0,
+            })
+            .insert(RigidBody::Dynamic)
+            .id();
+        let glyph = world.spawn_empty().id();
+        world.entity_mut(block).push_children(&[glyph]);
+
+        let mut queue = CommandQueue::default();
+        start_clear_flash(
+            &mut Commands::new(&mut queue, &world),
+            block,
+            Color::RED,
+            1.0,
+        );
+        queue.apply(&mut world);
+
+        assert!(world.get_entity(glyph).is_none());
+        assert!(!world.entity(block).contains::<Block>());
+        assert!(!world.entity(block).contains::<RigidBody>());
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(clear_flash);
+        schedule.run(&mut world);
+        assert!(world.get_entity(block).is_some());
+
+        let duration = std::time::Duration::from_secs_f32(CLEAR_FLASH_DURATION);
+        world
+            .get_mut::<ClearFlash>(block)
+            .unwrap()
+            .timer
+            .set_elapsed(duration);
+        schedule.run(&mut world);
+        assert!(world.get_entity(block).is_none());
+
+        let n_shards = world.query::<&Debris>().iter(&world).count();
+        assert_eq!(n_shards, DEBRIS_SHARDS_PER_SIDE * DEBRIS_SHARDS_PER_SIDE);
+    }
+
+    #[test]
+    fn torn_down_blocks_fly_apart_and_fade_out() {
+        let mut world = test_world();
+        world.insert_resource(Time::default());
+
+        let board = world.spawn_empty().id();
+        let blocks = [Vec2::new(-2.0, 0.0), Vec2::new(3.0, 0.0)].map(|position| {
+            let block = world
+                .spawn(SpriteBundle::default())
+                .insert(Block {
+                    board,
+                    kind: TetrominoKind::I,
+                    size: 1.0,
+                })
+                .id();
+            (block, position)
+        });
+
+        let mut queue = CommandQueue::default();
+        start_teardown(
+            &mut Commands::new(&mut queue, &world),
+            board,
+            Vec2::ZERO,
+            blocks.into_iter(),
+        );
+        queue.apply(&mut world);
+
+        assert!(world.entity(board).contains::<Teardown>());
+        let [(left, _), (right, _)] = blocks;
+        for block in [left, right] {
+            assert!(!world.entity(block).contains::<Block>());
+            assert!(world.get::<Velocity>(block).unwrap().linvel.y > 0.0);
+        }
+        assert!(world.get::<Velocity>(left).unwrap().linvel.x < 0.0);
+        assert!(world.get::<Velocity>(right).unwrap().linvel.x > 0.0);
+
+        let duration = std::time::Duration::from_secs_f32(TEARDOWN_DURATION);
+        world
+            .get_mut::<TeardownBlock>(left)
+            .unwrap()
+            .timer
+            .set_elapsed(duration);
+        let mut schedule = Schedule::new();
+        schedule.add_system(teardown_fade);
+        schedule.run(&mut world);
+        assert!(world.get_entity(left).is_none());
+        assert!(world.get_entity(right).is_some());
+    }
+
+    #[test]
+    fn sparks_fly_out_evenly_from_a_snapped_joint_without_colliding() {
+        let mut world = test_world();
+        let position = Vec2::new(1.0, 2.0);
+
+        let mut queue = CommandQueue::default();
+        spawn_sparks(&mut Commands::new(&mut queue, &world), position);
+        queue.apply(&mut world);
+
+        let mut spark_query = world.query::<(&Transform, &Velocity, &CollisionGroups, &Debris)>();
+        let sparks: Vec<_> = spark_query.iter(&world).collect();
+        assert_eq!(sparks.len(), SPARK_COUNT);
+
+        let mut momentum = Vec2::ZERO;
+        for (transform, velocity, groups, _) in sparks {
+            assert_eq!(transform.translation.truncate(), position);
+            assert!((velocity.linvel.length() - SPARK_SPEED).abs() < 1e-4);
+            assert_eq!(groups.filters, Group::NONE);
+            momentum += velocity.linvel;
+        }
+        assert!(momentum.length() < 1e-4);
+    }
+
+    #[test]
+    fn row_clears_shake_the_camera_until_the_shake_decays() {
+        let mut world = test_world();
+        let mut time = Time::default();
+        let startup = time.startup();
+        time.update_with_instant(startup);
+        time.update_with_instant(startup + std::time::Duration::from_secs_f32(0.1));
+        world.insert_resource(time);
+        world.init_resource::<Events<RowCleared>>();
+        let camera = world
+            .spawn((CameraShake::default(), Transform::default()))
+            .id();
+        let board = world.spawn_empty().id();
+        let row_cleared = || RowCleared {
+            board,
+            blocks: 10,
+            chain: 1,
+        };
+
+        world.send_event(row_cleared());
+        let mut schedule = Schedule::new();
+        schedule.add_system(row_clear_shake);
+        schedule.run(&mut world);
+        assert_eq!(
+            world.get::<CameraShake>(camera).unwrap().intensity,
+            ROW_CLEAR_SHAKE
+        );
+
+        world.send_event(row_cleared());
+        world.send_event(row_cleared());
+        schedule.run(&mut world);
+        assert_eq!(world.get::<CameraShake>(camera).unwrap().intensity, 1.0);
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(camera_shake);
+        schedule.run(&mut world);
+        let intensity = world.get::<CameraShake>(camera).unwrap().intensity;
+        assert!((intensity - (1.0 - 0.1 / SHAKE_DURATION)).abs() < 1e-4);
+        assert_ne!(
+            world.get::<Transform>(camera).unwrap().translation,
+            Vec3::ZERO
+        );
+
+        world.get_mut::<CameraShake>(camera).unwrap().intensity = 0.0;
+        *world.get_mut::<Transform>(camera).unwrap() = Transform::default();
+        schedule.run(&mut world);
+        assert_eq!(
+            world.get::<Transform>(camera).unwrap().translation,
+            Vec3::ZERO
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! Hazards striking the boards of a run, such as wind, earthquakes and pistons: scheduled from
+//! weighted tables per game mode, and announced ahead of time with a ticker and a countdown.
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+
+use crate::board::{Board, GameRng};
+use crate::physics::{reset_block_forces, GameTimeScale};
+use crate::scoring::BoardStats;
+use crate::tetromino::{ActivePieces, Block};
+use crate::ui::Fonts;
+use crate::GameplaySet;
+
+/// Scheduling, effects and announcements of hazards.
+pub struct HazardsPlugin;
+
+impl Plugin for HazardsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(hazard_scheduler.in_set(GameplaySet))
+            .add_system(
+                hazard_forces
+                    .after(hazard_scheduler)
+                    .after(reset_block_forces)
+                    .in_set(GameplaySet),
+            )
+            .add_system(hazard_ticker.after(hazard_scheduler))
+            .add_system(hazard_banner.after(hazard_scheduler));
+    }
+}
+
+// Number of upcoming hazards shown by the ticker
+pub(crate) const HAZARD_TICKER_LENGTH: usize = 3;
+// In scaled seconds, the banner counts down to the next hazard from this long before it strikes
+pub(crate) const HAZARD_TELEGRAPH_DURATION: f32 = 3.0;
+pub(crate) const HAZARD_BANNER_FONT_SIZE: f32 = 48.0;
+
+pub(crate) const WIND_FORCE: f32 = 25.0;
+pub(crate) const EARTHQUAKE_IMPULSE: f32 = 0.004;
+pub(crate) const PISTON_IMPULSE: f32 = 0.05;
+// Block size of the pieces spawned by the giant and tiny piece hazards
+pub(crate) const GIANT_PIECE_SCALE: f32 = 2.0;
+pub(crate) const TINY_PIECE_SCALE: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum Hazard {
+    Wind,
+    Earthquake,
+    GravityFlip,
+    Piston,
+    BombPiece,
+    GiantPiece,
+    TinyPiece,
+}
+
+impl Hazard {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Wind => "Wind",
+            Self::Earthquake => "Earthquake",
+            Self::GravityFlip => "Gravity flip",
+            Self::Piston => "Piston",
+            Self::BombPiece => "Bomb piece",
+            Self::GiantPiece => "Giant piece",
+            Self::TinyPiece => "Tiny piece",
+        }
+    }
+
+    /// How long the hazard lasts. Zero for one-shot hazards.
+    pub(crate) fn duration(&self) -> f32 {
+        match self {
+            Self::Wind => 4.0,
+            Self::Earthquake => 2.5,
+            Self::GravityFlip => 1.5,
+            Self::Piston | Self::BombPiece | Self::GiantPiece | Self::TinyPiece => 0.0,
+        }
+    }
+
+    /// Scaled seconds before the hazard may strike again, unless nothing else may.
+    pub(crate) fn cooldown(&self) -> f32 {
+        match self {
+            Self::Piston => 8.0,
+            Self::Wind | Self::Earthquake | Self::BombPiece => 16.0,
+            Self::GiantPiece | Self::TinyPiece => 24.0,
+            Self::GravityFlip => 32.0,
+        }
+    }
+
+    /// Whether the hazard comes from the left or the right.
+    pub(crate) fn directional(&self) -> bool {
+        matches!(self, Self::Wind | Self::Piston)
+    }
+}
+
+/// The hazards of a game mode, struck one at a time at a fixed interval.
+pub(crate) struct HazardTable {
+    /// In scaled seconds
+    pub(crate) interval: f32,
+    /// Relative likelihood of each hazard
+    pub(crate) weights: &'static [(Hazard, u32)],
+}
+
+pub(crate) const CHAOS_HAZARDS: HazardTable = HazardTable {
+    interval: 8.0,
+    weights: &[
+        (Hazard::Wind, 30),
+        (Hazard::Earthquake, 20),
+        (Hazard::GravityFlip, 10),
+        (Hazard::Piston, 25),
+        (Hazard::BombPiece, 15),
+        (Hazard::GiantPiece, 8),
+        (Hazard::TinyPiece, 8),
+    ],
+};
+
+#[derive(Clone, Copy)]
+pub(crate) struct ScheduledHazard {
+    pub(crate) hazard: Hazard,
+    /// When it strikes, on the clock of the scheduler
+    pub(crate) at: f32,
+    /// -1.0 or 1.0, for directional hazards
+    pub(crate) direction: f32,
+}
+
+impl ScheduledHazard {
+    /// Name and direction, e.g. "Wind →".
+    pub(crate) fn label(&self) -> String {
+        if !self.hazard.directional() {
+            self.hazard.name().to_string()
+        } else if self.direction < 0.0 {
+            format!("{} ←", self.hazard.name())
+        } else {
+            format!("{} →", self.hazard.name())
+        }
+    }
+}
+
+pub(crate) struct ActiveHazard {
+    pub(crate) hazard: Hazard,
+    pub(crate) remaining: f32,
+    /// -1.0 or 1.0, for directional hazards
+    pub(crate) direction: f32,
+}
+
+/// Picks hazards for a board from the table of its mode, and keeps a queue of upcoming ones so
+/// that they are always announced before they strike.
+#[derive(Component, Default)]
+pub(crate) struct HazardScheduler {
+    /// Scaled seconds since the run started
+    pub(crate) clock: f32,
+    pub(crate) upcoming: VecDeque<ScheduledHazard>,
+    pub(crate) active: Vec<ActiveHazard>,
+    /// One-shot hazards struck this frame
+    pub(crate) triggered: Vec<ActiveHazard>,
+    /// When each hazard was last scheduled to strike, for the cooldowns
+    pub(crate) last_at: HashMap<Hazard, f32>,
+}
+
+impl HazardScheduler {
+    /// Scaled seconds until the next hazard strikes.
+    pub(crate) fn until_next(&self) -> Option<f32> {
+        self.upcoming
+            .front()
+            .map(|scheduled| (scheduled.at - self.clock).max(0.0))
+    }
+
+    /// Queue up the next hazard from the table, by weight among those not cooling down then.
+    pub(crate) fn schedule(&mut self, table: &HazardTable, rng: &mut impl Rng) {
+        let at = self
+            .upcoming
+            .back()
+            .map_or(self.clock, |scheduled| scheduled.at)
+            + table.interval;
+
+        let ready: Vec<(Hazard, u32)> = table
+            .weights
+            .iter()
+            .copied()
+            .filter(|(hazard, _)| {
+                self.last_at
+                    .get(hazard)
+                    .is_none_or(|last_at| at - last_at >= hazard.cooldown())
+            })
+            .collect();
+        // With everything cooling down, the cooldowns give way rather than the schedule
+        let candidates = if ready.is_empty() {
+            table.weights.to_vec()
+        } else {
+            ready
+        };
+
+        let total: u32 = candidates.iter().map(|(_, weight)| weight).sum();
+        let mut pick = rng.gen_range(0..total.max(1));
+        let mut hazard = candidates[0].0;
+        for (candidate, weight) in candidates {
+            if pick < weight {
+                hazard = candidate;
+                break;
+            }
+            pick -= weight;
+        }
+
+        let direction = if hazard.directional() && rng.gen_bool(0.5) {
+            -1.0
+        } else {
+            1.0
+        };
+
+        self.last_at.insert(hazard, at);
+        self.upcoming.push_back(ScheduledHazard {
+            hazard,
+            at,
+            direction,
+        });
+    }
+}
+
+/// Shows the upcoming hazards of a board.
+#[derive(Component)]
+pub(crate) struct HazardTicker {
+    pub(crate) board: Entity,
+}
+
+/// Counts down to the next hazard of a board, when it is about to strike.
+#[derive(Component)]
+pub(crate) struct HazardBanner {
+    pub(crate) board: Entity,
+}
+
+pub(crate) fn hazard_scheduler(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut board_query: Query<(
+        &Board,
+        &BoardStats,
+        &mut ActivePieces,
+        &mut HazardScheduler,
+        &mut GameRng,
+    )>,
+) {
+    let delta = time_scale.delta_seconds(&time);
+
+    for (board, stats, mut active_pieces, mut scheduler, mut rng) in board_query.iter_mut() {
+        scheduler.triggered.clear();
+
+        let table = match board.mode.hazards() {
+            Some(table) if stats.game_over_duration.is_none() => table,
+            _ => {
+                *scheduler = HazardScheduler::default();
+                continue;
+            }
+        };
+
+        while scheduler.upcoming.len() < HAZARD_TICKER_LENGTH {
+            scheduler.schedule(table, &mut *rng);
+        }
+
+        // End expired hazards
+        for active in scheduler.active.iter_mut() {
+            active.remaining -= delta;
+        }
+        scheduler.active.retain(|active| active.remaining > 0.0);
+
+        scheduler.clock += delta;
+
+        while let Some(scheduled) = scheduler.upcoming.front().copied() {
+            if scheduled.at > scheduler.clock {
+                break;
+            }
+            scheduler.upcoming.pop_front();
+
+            match scheduled.hazard {
+                Hazard::BombPiece => active_pieces.next_is_bomb = true,
+                Hazard::GiantPiece => active_pieces.next_scale = Some(GIANT_PIECE_SCALE),
+                Hazard::TinyPiece => active_pieces.next_scale = Some(TINY_PIECE_SCALE),
+                _ => {}
+            }
+
+            let active = ActiveHazard {
+                hazard: scheduled.hazard,
+                remaining: scheduled.hazard.duration(),
+                direction: scheduled.direction,
+            };
+
+            if scheduled.hazard.duration() > 0.0 {
+                scheduler.active.push(active);
+            } else {
+                scheduler.triggered.push(active);
+            }
+        }
+    }
+}
+
+/// Apply the physical effects of hazards to the blocks of each board.
+pub(crate) fn hazard_forces(
+    time_scale: Res<GameTimeScale>,
+    mut board_query: Query<(Entity, &Board, &HazardScheduler, &mut GameRng)>,
+    mut block_query: Query<(
+        &Block,
+        &Transform,
+        &mut ExternalForce,
+        &mut ExternalImpulse,
+        &mut GravityScale,
+    )>,
+) {
+    // Impulses would pile up while the physics is paused
+    let paused = time_scale.value() <= 0.0;
+
+    for (board_entity, board, scheduler, mut rng) in board_query.iter_mut() {
+        let gravity_scale = if scheduler
+            .active
+            .iter()
+            .any(|active| active.hazard == Hazard::GravityFlip)
+        {
+            -1.0
+        } else {
+            1.0
+        };
+
+        let mut wind = 0.0;
+        let mut earthquake = false;
+        // Row center and direction of each piston
+        let mut pistons: Vec<(f32, f32)> = vec![];
+
+        if !paused {
+            for active in scheduler.active.iter().chain(scheduler.triggered.iter()) {
+                match active.hazard {
+                    Hazard::Wind => wind += active.direction * WIND_FORCE,
+                    Hazard::Earthquake => earthquake = true,
+                    Hazard::Piston => {
+                        // Shove one of the lower rows from the side
+                        let row = rng.gen_range(0..(board.n_rows / 2).max(1)) as f32;
+                        pistons.push((board.floor_y() + row + 0.5, active.direction));
+                    }
+                    Hazard::GravityFlip
+                    | Hazard::BombPiece
+                    | Hazard::GiantPiece
+                    | Hazard::TinyPiece => {}
+                }
+            }
+        }
+
+        for (block, transform, mut forces, mut impulse, mut gravity) in block_query.iter_mut() {
+            if block.board != board_entity {
+                continue;
+            }
+
+            // Blocks spawned during a flip are flipped too
+            if gravity.0 != gravity_scale {
+                gravity.0 = gravity_scale;
+            }
+
+            if wind != 0.0 {
+                forces.force.x += wind;
+            }
+
+            if earthquake {
+                impulse.impulse += Vec2::new(
+                    rng.gen_range(-1.0..1.0) * EARTHQUAKE_IMPULSE,
+                    rng.gen_range(0.0..1.0) * EARTHQUAKE_IMPULSE,
+                );
+            }
+
+            for (row_y, direction) in &pistons {
+                if (transform.translation.y - row_y).abs() < 0.5 {
+                    impulse.impulse.x += direction * PISTON_IMPULSE;
+                }
+            }
+        }
+    }
+}
+
+/// Show the upcoming hazards.
+pub(crate) fn hazard_ticker(
+    board_query: Query<(&Board, &HazardScheduler)>,
+    fonts: Res<Fonts>,
+    mut ticker_query: Query<(&HazardTicker, &mut Text)>,
+) {
+    for (ticker, mut text) in ticker_query.iter_mut() {
+        let Ok((board, scheduler)) = board_query.get(ticker.board) else {
+            continue;
+        };
+
+        let value = if board.mode.hazards().is_some() {
+            let title = board.mode.name().to_uppercase();
+            let mut upcoming = scheduler.upcoming.iter();
+            match (upcoming.next(), scheduler.until_next()) {
+                (Some(next), Some(until_next)) => {
+                    let later: Vec<String> = upcoming.map(ScheduledHazard::label).collect();
+                    format!(
+                        "{title}  {} in {:.0}s  › {}",
+                        next.label(),
+                        until_next.ceil(),
+                        later.join(" › ")
+                    )
+                }
+                _ => title,
+            }
+        } else {
+            String::new()
+        };
+
+        *text = fonts.text(&value, 20.0, Color::rgb_u8(252, 157, 0));
+    }
+}
+
+/// Count down to the next hazard in large letters, for the last seconds before it strikes.
+pub(crate) fn hazard_banner(
+    board_query: Query<&HazardScheduler>,
+    fonts: Res<Fonts>,
+    mut banner_query: Query<(&HazardBanner, &mut Text)>,
+) {
+    for (banner, mut text) in banner_query.iter_mut() {
+        let value = board_query
+            .get(banner.board)
+            .ok()
+            .and_then(|scheduler| Some((scheduler.upcoming.front()?, scheduler.until_next()?)))
+            .filter(|(_, until_next)| *until_next <= HAZARD_TELEGRAPH_DURATION)
+            .map_or(String::new(), |(next, until_next)| {
+                format!("{}  {:.0}", next.label().to_uppercase(), until_next.ceil())
+            });
+
+        *text = fonts.text(&value, HAZARD_BANNER_FONT_SIZE, Color::rgb_u8(252, 157, 0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn hazards_are_scheduled_an_interval_apart_and_wait_out_their_cooldowns() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut scheduler = HazardScheduler::default();
+        for _ in 0..100 {
+            scheduler.schedule(&CHAOS_HAZARDS, &mut rng);
+        }
+
+        let upcoming: Vec<ScheduledHazard> = scheduler.upcoming.iter().copied().collect();
+        for (index, scheduled) in upcoming.iter().enumerate() {
+            assert_eq!(scheduled.at, (index + 1) as f32 * CHAOS_HAZARDS.interval);
+
+            let previous = upcoming[..index]
+                .iter()
+                .rev()
+                .find(|other| other.hazard == scheduled.hazard);
+            if let Some(previous) = previous {
+                assert!(scheduled.at - previous.at >= scheduled.hazard.cooldown());
+            }
+        }
+    }
+
+    #[test]
+    fn cooldowns_give_way_when_every_hazard_is_cooling_down() {
+        let table = HazardTable {
+            interval: 1.0,
+            weights: &[(Hazard::Earthquake, 1)],
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut scheduler = HazardScheduler::default();
+        scheduler.schedule(&table, &mut rng);
+        scheduler.schedule(&table, &mut rng);
+
+        assert_eq!(scheduler.upcoming.len(), 2);
+        assert_eq!(scheduler.until_next(), Some(1.0));
+        assert_eq!(scheduler.upcoming[1].label(), "Earthquake");
+    }
+}
@@ -0,0 +1,294 @@
+//! Running the game without a window or rendering, stepped manually, e.g. from integration tests.
+
+use std::time::{Duration, Instant};
+
+use bevy::ecs::system::CommandQueue;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::render::camera::CameraProjection;
+use bevy::time::TimePlugin;
+use bevy::transform::TransformPlugin;
+use bevy::window::ExitCondition;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::{camera_controller, start_run, Board, FixedSeed, GameRng, RunStarted};
+use crate::online::{MatchReplay, Referee, REFEREE_OVERTIME};
+use crate::physics::{block_death_detection, world_checksum};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{BoardStats, Profile};
+use crate::settings::{disable_persistence, Settings};
+use crate::tetromino::{spawn_loose_block, ActivePieces, Block};
+use crate::{AppState, GamePlugin};
+
+/// Game time that passes on every update of a headless app.
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+
+/// Seed of every run in a headless app, so that tests are reproducible.
+// Changing it changes the pieces dealt in every simulation test
+pub const SEED: u64 = 2;
+
+// Size of the viewport the camera projection is fitted to, square like the assumed window
+const VIEWPORT_SIZE: f32 = 720.0;
+
+/// The whole game on top of `MinimalPlugins`, without a window or rendering.
+///
+/// Every `App::update` advances the game by [`TIMESTEP`], whatever the real time,
+/// and nothing is loaded from or saved to disk. Runs are seeded with [`SEED`]. A run starts right away,
+/// without the main menu or onboarding. Keys can be pressed through `Input<KeyCode>`.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        disable_persistence();
+
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>())
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Font>()
+            .add_asset::<Image>()
+            .insert_resource(Time::default())
+            .insert_resource(UiScale::default())
+            .add_plugin(GamePlugin)
+            // Never paused or sent back to the menu for being idle
+            .insert_resource(Settings {
+                idle_pause_seconds: 0.0,
+                idle_menu_seconds: 0.0,
+                ..Settings::default()
+            })
+            .insert_resource(Profile {
+                show_onboarding: false,
+                ..Profile::default()
+            })
+            .insert_resource(FixedSeed(Some(SEED)))
+            .add_startup_system(start_runs.in_base_set(StartupSet::PostStartup))
+            .add_system(advance_time.in_base_set(CoreSet::First))
+            .add_system(
+                fit_projection
+                    .after(camera_controller)
+                    .before(block_death_detection),
+            );
+    }
+}
+
+/// Outcome of the current run on a board.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardSummary {
+    pub generated_blocks: i32,
+    pub placed_blocks: i32,
+    pub cleared_blocks: i32,
+    pub cleared_rows: usize,
+    pub lost_blocks: i32,
+    pub score: i32,
+    /// Blocks of the pieces currently controlled
+    pub live_blocks: usize,
+    /// The run is over, and restarts shortly
+    pub game_over: bool,
+}
+
+/// Re-simulate the online Versus match in the file given with `--referee <file>`, see
+/// `online::Referee`, and print the host's score: 1 for a win, 0.5 for a draw and 0 for a loss,
+/// followed by the checksum of the physics world as the match ended.
+/// Returns whether started as the referee.
+pub fn run_referee() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--referee")
+        .and_then(|index| args.get(index + 1))
+    else {
+        return false;
+    };
+
+    let match_replay = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|data| ron::from_str(&data).ok());
+    match match_replay {
+        Some(match_replay) => {
+            let (score, checksum) = referee(&match_replay);
+            println!("{score} {checksum}");
+        }
+        None => eprintln!("invalid match {path}"),
+    }
+    true
+}
+
+/// The host's score in a match re-simulated from the replays of both players, and the checksum
+/// of the physics world as it ended. A draw when neither run is over by `REFEREE_OVERTIME` after
+/// the longer replay.
+pub(crate) fn referee(match_replay: &MatchReplay) -> (f32, u64) {
+    let rules = &match_replay.rules;
+    let referee = Referee::new(match_replay.replays.clone());
+    let duration = referee.duration() + REFEREE_OVERTIME;
+
+    let mut app = App::new();
+    app.add_plugin(HeadlessPlugin)
+        .insert_resource(Settings {
+            idle_pause_seconds: 0.0,
+            idle_menu_seconds: 0.0,
+            ..rules.settings()
+        })
+        .insert_resource(Profile {
+            show_onboarding: false,
+            assist_mode: rules.assist_mode,
+            ..Profile::default()
+        })
+        .insert_resource(rules.piece_definitions.clone())
+        .insert_resource(referee);
+
+    // However slowed down the game is, it doesn't play on forever
+    let mut verdict = 0.5;
+    for _ in 0..(4.0 * duration / TIMESTEP) as u32 {
+        app.update();
+
+        let referee = app.world.resource::<Referee>();
+        if let Some(referee_verdict) = referee.verdict {
+            verdict = referee_verdict;
+            break;
+        }
+        if referee.clock > duration {
+            break;
+        }
+    }
+    (verdict, physics_checksum(&mut app))
+}
+
+/// Summaries of all boards.
+pub fn board_summaries(app: &mut App) -> Vec<BoardSummary> {
+    app.world
+        .query::<(&BoardStats, &ActivePieces)>()
+        .iter(&app.world)
+        .map(|(stats, active_pieces)| BoardSummary {
+            generated_blocks: stats.generated_blocks,
+            placed_blocks: stats.placed_blocks,
+            cleared_blocks: stats.cleared_blocks,
+            cleared_rows: stats.clear_times.len(),
+            lost_blocks: stats.lost_blocks,
+            score: stats.score(),
+            live_blocks: active_pieces.blocks().count(),
+            game_over: stats.game_over_duration.is_some(),
+        })
+        .collect()
+}
+
+/// Checksum of the positions and velocities of every rigid body, equal between runs
+/// that play out exactly the same.
+pub fn physics_checksum(app: &mut App) -> u64 {
+    world_checksum(app.world.resource::<RapierContext>())
+}
+
+/// Update the app for this many seconds of game time.
+pub fn run_for(app: &mut App, seconds: f32) {
+    for _ in 0..(seconds / TIMESTEP).ceil() as u32 {
+        app.update();
+    }
+}
+
+/// Fill the bottom row of every floor of every board with resting blocks.
+pub fn fill_bottom_row(app: &mut App) {
+    let world = &mut app.world;
+    let board_entities: Vec<Entity> = world
+        .query_filtered::<Entity, With<Board>>()
+        .iter(world)
+        .collect();
+
+    for board_entity in board_entities {
+        // Taken off the board meanwhile, as the commands borrow the world
+        let Some(mut rng) = world.entity_mut(board_entity).take::<GameRng>() else {
+            continue;
+        };
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+
+        if let Some(board) = world.get::<Board>(board_entity) {
+            for x in board.slot_centers() {
+                let Some(floor_y) = board.floor_y_at(x) else {
+                    continue;
+                };
+
+                spawn_loose_block(
+                    &mut commands,
+                    board_entity,
+                    board,
+                    Vec2::new(x, floor_y + 0.5),
+                    true,
+                    &mut rng,
+                );
+            }
+        }
+
+        queue.apply(world);
+        world.entity_mut(board_entity).insert(rng);
+    }
+}
+
+/// Start a run on every board, like "New game" in the main menu. The referee's are played with
+/// the pieces of the replays.
+#[allow(clippy::too_many_arguments)]
+fn start_runs(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    referee: Option<Res<Referee>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut run_started_events: EventWriter<RunStarted>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    block_query: Query<(Entity, &Block)>,
+) {
+    for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in board_query.iter_mut() {
+        // Kept by `start_run` like the pieces of the opponent's board online
+        let replay = referee
+            .as_ref()
+            .zip(board.player)
+            .and_then(|(referee, player)| referee.replays.get(player));
+        if let Some(replay) = replay {
+            active_pieces.supply = Some(replay.kinds.iter().copied().collect());
+            board.remote = true;
+        }
+
+        run_started_events.send(start_run(
+            &mut commands,
+            board_entity,
+            &mut board,
+            &mut active_pieces,
+            &mut stats,
+            &settings,
+            &piece_definitions,
+            &mut rng,
+            block_query
+                .iter()
+                .filter(|(_, block)| block.board == board_entity)
+                .map(|(entity, _)| entity),
+        ));
+        board.remote = false;
+    }
+    next_state.set(AppState::Playing);
+}
+
+/// Advance time by a fixed step, in place of `TimePlugin` following the real clock.
+fn advance_time(mut time: ResMut<Time>, mut now: Local<Option<Instant>>) {
+    let next = now.unwrap_or_else(|| time.startup()) + Duration::from_secs_f32(TIMESTEP);
+    *now = Some(next);
+    time.update_with_instant(next);
+}
+
+/// Compute the visible area of the camera, which the render plugin would otherwise do.
+/// Blocks are lost relative to its bottom edge.
+fn fit_projection(mut projection_query: Query<&mut OrthographicProjection>) {
+    for mut projection in projection_query.iter_mut() {
+        projection.update(VIEWPORT_SIZE, VIEWPORT_SIZE);
+    }
+}
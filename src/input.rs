@@ -0,0 +1,1145 @@
+//! Logical actions bound to keyboard, gamepad and touch, and the per-piece input derived from them.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::bot::{Bot, DemoMode};
+use crate::coop::CoopSession;
+use crate::online::{OnlineSession, Referee};
+use crate::physics::{GameTimeScale, TimeScaleSource};
+use crate::scoring::Profile;
+use crate::settings::{load_ron, project_dirs, save_ron, Settings};
+use crate::tetromino::PieceSettled;
+use crate::touch::TouchActions;
+use crate::ui::{spawn_toast, Fonts, IdlePauseScreen, Menu, Onboarding};
+use crate::{AppState, GameplaySet};
+
+/// Keyboard and gamepad bindings. Named so as not to clash with Bevy's `InputPlugin`.
+pub struct GameInputPlugin;
+
+impl Plugin for GameInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(IdleTimer::default())
+            .insert_resource(ActionState::default())
+            .insert_resource(OneSwitch::default())
+            .insert_resource(GamepadProfiles::load())
+            .add_startup_system(setup_input_map)
+            .add_system(capture_key_binding.run_if(resource_exists::<KeyCapture>()))
+            .add_system(gamepad_remap_wizard)
+            .add_system(update_action_state)
+            .add_system(cycle_control_preset)
+            .add_system(
+                idle_detection
+                    .after(update_action_state)
+                    .in_set(GameplaySet),
+            );
+    }
+}
+
+// Trigger pressure below this is ignored
+pub(crate) const ANALOG_DEAD_ZONE: f32 = 0.05;
+// Sticks rest a little off center, so deflection below this is ignored
+pub(crate) const STICK_DEAD_ZONE: f32 = 0.2;
+
+/// Logical game actions, decoupled from the physical inputs that trigger them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub(crate) enum Action {
+    MoveLeft,
+    MoveRight,
+    RotateCcw,
+    RotateCw,
+    SoftDrop,
+    HardDrop,
+    Hold,
+    Pause,
+    /// Controls of the second piece in Twin mode, and of the second player in Versus mode
+    SecondMoveLeft,
+    SecondMoveRight,
+    SecondRotateCcw,
+    SecondRotateCw,
+    SecondSoftDrop,
+    SecondHardDrop,
+    /// The one button of the one-switch mode, see `OneSwitch`
+    Switch,
+    MenuUp,
+    MenuDown,
+    MenuConfirm,
+    MenuCancel,
+    SwapInset,
+    UiScaleDown,
+    UiScaleUp,
+    CleanFeed,
+    AssistMode,
+    CameraMode,
+    Trajectory,
+    SideWalls,
+    StaminaModifier,
+    ShareCard,
+    ControlPreset,
+    GamepadRemap,
+    Telemetry,
+    KindGlyphs,
+    GameMode,
+    FuseModifier,
+    BoardLayout,
+    DebugStepToggle,
+    DebugStep,
+    History,
+    MirrorModifier,
+    /// Name entry has its own confirm, cancel and delete, since space and backspace are typed there
+    EntryConfirm,
+    EntryCancel,
+    EntryDelete,
+}
+
+impl Action {
+    pub(crate) const ALL: [Self; 43] = [
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::RotateCcw,
+        Self::RotateCw,
+        Self::SoftDrop,
+        Self::HardDrop,
+        Self::Hold,
+        Self::Pause,
+        Self::SecondMoveLeft,
+        Self::SecondMoveRight,
+        Self::SecondRotateCcw,
+        Self::SecondRotateCw,
+        Self::SecondSoftDrop,
+        Self::SecondHardDrop,
+        Self::Switch,
+        Self::MenuUp,
+        Self::MenuDown,
+        Self::MenuConfirm,
+        Self::MenuCancel,
+        Self::SwapInset,
+        Self::UiScaleDown,
+        Self::UiScaleUp,
+        Self::CleanFeed,
+        Self::AssistMode,
+        Self::CameraMode,
+        Self::Trajectory,
+        Self::SideWalls,
+        Self::StaminaModifier,
+        Self::ShareCard,
+        Self::ControlPreset,
+        Self::GamepadRemap,
+        Self::Telemetry,
+        Self::KindGlyphs,
+        Self::GameMode,
+        Self::FuseModifier,
+        Self::BoardLayout,
+        Self::DebugStepToggle,
+        Self::DebugStep,
+        Self::History,
+        Self::MirrorModifier,
+        Self::EntryConfirm,
+        Self::EntryCancel,
+        Self::EntryDelete,
+    ];
+
+    /// The actions that can be bound to other keys from the key bindings screen
+    pub(crate) const REBINDABLE: [Self; 25] = [
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::RotateCcw,
+        Self::RotateCw,
+        Self::SoftDrop,
+        Self::HardDrop,
+        Self::Hold,
+        Self::Pause,
+        Self::SecondMoveLeft,
+        Self::SecondMoveRight,
+        Self::SecondRotateCcw,
+        Self::SecondRotateCw,
+        Self::SecondSoftDrop,
+        Self::SecondHardDrop,
+        Self::Switch,
+        Self::SwapInset,
+        Self::UiScaleDown,
+        Self::UiScaleUp,
+        Self::CleanFeed,
+        Self::AssistMode,
+        Self::CameraMode,
+        Self::Trajectory,
+        Self::SideWalls,
+        Self::StaminaModifier,
+        Self::ShareCard,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::MoveLeft => "Move left",
+            Self::MoveRight => "Move right",
+            Self::RotateCcw => "Rotate counter-clockwise",
+            Self::RotateCw => "Rotate clockwise",
+            Self::SoftDrop => "Soft drop",
+            Self::HardDrop => "Hard drop",
+            Self::Hold => "Hold",
+            Self::Pause => "Pause",
+            Self::SecondMoveLeft => "Second piece left",
+            Self::SecondMoveRight => "Second piece right",
+            Self::SecondRotateCcw => "Second piece counter-clockwise",
+            Self::SecondRotateCw => "Second piece clockwise",
+            Self::SecondSoftDrop => "Second piece soft drop",
+            Self::SecondHardDrop => "Second piece hard drop",
+            Self::Switch => "One switch",
+            Self::MenuUp => "Menu up",
+            Self::MenuDown => "Menu down",
+            Self::MenuConfirm => "Menu confirm",
+            Self::MenuCancel => "Menu cancel",
+            Self::SwapInset => "Swap inset",
+            Self::UiScaleDown => "Smaller UI",
+            Self::UiScaleUp => "Larger UI",
+            Self::CleanFeed => "Clean feed",
+            Self::AssistMode => "Assist Mode",
+            Self::CameraMode => "Camera mode",
+            Self::Trajectory => "Trajectory",
+            Self::SideWalls => "Side walls",
+            Self::StaminaModifier => "Stamina modifier",
+            Self::ShareCard => "Share card",
+            Self::ControlPreset => "Control preset",
+            Self::GamepadRemap => "Gamepad remap",
+            Self::Telemetry => "Telemetry",
+            Self::KindGlyphs => "Piece glyphs",
+            Self::GameMode => "Game mode",
+            Self::FuseModifier => "Fuse modifier",
+            Self::BoardLayout => "Board layout",
+            Self::DebugStepToggle => "Physics step mode",
+            Self::DebugStep => "Physics step",
+            Self::History => "History",
+            Self::MirrorModifier => "Mirror modifier",
+            Self::EntryConfirm => "Name confirm",
+            Self::EntryCancel => "Name cancel",
+            Self::EntryDelete => "Name delete",
+        }
+    }
+}
+
+/// Keys that are the same for every control preset.
+pub(crate) const SHARED_BINDINGS: [(Action, KeyCode); 10] = [
+    (Action::HardDrop, KeyCode::Space),
+    // Hard drop is off in one-switch mode, so the switch can share its key
+    (Action::Switch, KeyCode::Space),
+    (Action::Hold, KeyCode::LShift),
+    (Action::Pause, KeyCode::P),
+    (Action::SecondMoveLeft, KeyCode::J),
+    (Action::SecondMoveRight, KeyCode::L),
+    (Action::SecondRotateCcw, KeyCode::I),
+    (Action::SecondRotateCw, KeyCode::K),
+    (Action::SecondSoftDrop, KeyCode::M),
+    (Action::SecondHardDrop, KeyCode::U),
+];
+
+/// Menu and name entry keys, the same for every control preset.
+pub(crate) const MENU_BINDINGS: [(Action, KeyCode); 11] = [
+    (Action::MenuUp, KeyCode::Up),
+    (Action::MenuUp, KeyCode::W),
+    (Action::MenuDown, KeyCode::Down),
+    (Action::MenuDown, KeyCode::S),
+    (Action::MenuConfirm, KeyCode::Return),
+    (Action::MenuConfirm, KeyCode::Space),
+    (Action::MenuCancel, KeyCode::Escape),
+    (Action::MenuCancel, KeyCode::Back),
+    (Action::EntryConfirm, KeyCode::Return),
+    (Action::EntryCancel, KeyCode::Escape),
+    (Action::EntryDelete, KeyCode::Back),
+];
+
+/// View keys, the same for every control preset.
+pub(crate) const VIEW_BINDINGS: [(Action, KeyCode); 4] = [
+    (Action::SwapInset, KeyCode::Tab),
+    (Action::UiScaleDown, KeyCode::Minus),
+    (Action::UiScaleUp, KeyCode::Equals),
+    (Action::CleanFeed, KeyCode::V),
+];
+
+/// Keys toggling assists, views, modifiers and debug aids, the same for every control preset.
+/// Only the first few are on the key bindings screen, the function keys can be rebound in
+/// `keybindings.ron`.
+pub(crate) const TOGGLE_BINDINGS: [(Action, KeyCode); 17] = [
+    (Action::AssistMode, KeyCode::F1),
+    (Action::CameraMode, KeyCode::C),
+    (Action::Trajectory, KeyCode::T),
+    (Action::SideWalls, KeyCode::B),
+    (Action::StaminaModifier, KeyCode::N),
+    (Action::ShareCard, KeyCode::X),
+    (Action::ControlPreset, KeyCode::F2),
+    (Action::GamepadRemap, KeyCode::F3),
+    (Action::Telemetry, KeyCode::F4),
+    (Action::KindGlyphs, KeyCode::F5),
+    (Action::GameMode, KeyCode::F6),
+    (Action::FuseModifier, KeyCode::F7),
+    (Action::BoardLayout, KeyCode::F8),
+    (Action::DebugStepToggle, KeyCode::F9),
+    (Action::DebugStep, KeyCode::F10),
+    (Action::History, KeyCode::F11),
+    (Action::MirrorModifier, KeyCode::F12),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum ControlPreset {
+    /// Arrows to move, A/D to rotate
+    #[default]
+    ArrowsAd,
+    /// A/D to move, Q/E to rotate
+    WasdQe,
+    /// The default layout with the hands swapped
+    LeftHanded,
+    /// Everything on the arrow keys
+    OneHanded,
+}
+
+impl ControlPreset {
+    pub(crate) const ALL: [Self; 4] = [
+        Self::ArrowsAd,
+        Self::WasdQe,
+        Self::LeftHanded,
+        Self::OneHanded,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::ArrowsAd => "Arrows + A/D",
+            Self::WasdQe => "WASD + Q/E",
+            Self::LeftHanded => "Left-handed",
+            Self::OneHanded => "One-handed",
+        }
+    }
+
+    pub(crate) fn bindings(&self) -> &'static [(Action, KeyCode)] {
+        match self {
+            Self::ArrowsAd => &[
+                (Action::MoveLeft, KeyCode::Left),
+                (Action::MoveRight, KeyCode::Right),
+                (Action::RotateCcw, KeyCode::A),
+                (Action::RotateCw, KeyCode::D),
+                (Action::SoftDrop, KeyCode::Down),
+            ],
+            Self::WasdQe => &[
+                (Action::MoveLeft, KeyCode::A),
+                (Action::MoveRight, KeyCode::D),
+                (Action::RotateCcw, KeyCode::Q),
+                (Action::RotateCw, KeyCode::E),
+                (Action::SoftDrop, KeyCode::S),
+            ],
+            Self::LeftHanded => &[
+                (Action::MoveLeft, KeyCode::A),
+                (Action::MoveRight, KeyCode::D),
+                (Action::RotateCcw, KeyCode::Left),
+                (Action::RotateCw, KeyCode::Right),
+                (Action::SoftDrop, KeyCode::S),
+            ],
+            Self::OneHanded => &[
+                (Action::MoveLeft, KeyCode::Left),
+                (Action::MoveRight, KeyCode::Right),
+                (Action::RotateCcw, KeyCode::Up),
+                (Action::RotateCw, KeyCode::Down),
+                (Action::SoftDrop, KeyCode::RShift),
+            ],
+        }
+    }
+
+    pub(crate) fn next(&self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|preset| preset == self)
+            .unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Analog gamepad input, read as a value in `0.0..=1.0`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub(crate) enum AnalogBinding {
+    /// Pressure sensitive button, like a trigger
+    Button(GamepadButtonType),
+    AxisPositive(GamepadAxisType),
+    AxisNegative(GamepadAxisType),
+}
+
+impl AnalogBinding {
+    pub(crate) fn value(
+        &self,
+        gamepad: Gamepad,
+        button_axes: &Axis<GamepadButton>,
+        axes: &Axis<GamepadAxis>,
+    ) -> f32 {
+        let (value, dead_zone) = match self {
+            Self::Button(button_type) => (
+                button_axes
+                    .get(GamepadButton::new(gamepad, *button_type))
+                    .unwrap_or(0.0),
+                ANALOG_DEAD_ZONE,
+            ),
+            Self::AxisPositive(axis_type) => (
+                axes.get(GamepadAxis::new(gamepad, *axis_type))
+                    .unwrap_or(0.0),
+                STICK_DEAD_ZONE,
+            ),
+            Self::AxisNegative(axis_type) => (
+                -axes
+                    .get(GamepadAxis::new(gamepad, *axis_type))
+                    .unwrap_or(0.0),
+                STICK_DEAD_ZONE,
+            ),
+        };
+
+        // Rescaled so that the value still goes smoothly from 0 at the edge of the dead zone
+        if value < dead_zone {
+            0.0
+        } else {
+            ((value - dead_zone) / (1.0 - dead_zone)).min(1.0)
+        }
+    }
+}
+
+/// Maps logical actions to keys. Starts out from the control preset,
+/// with any custom bindings stored in `keybindings.ron`.
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct InputMap {
+    pub(crate) bindings: HashMap<Action, Vec<KeyCode>>,
+}
+
+impl InputMap {
+    pub(crate) fn from_preset(preset: ControlPreset) -> Self {
+        let mut input_map = Self::default();
+        for (action, key) in preset
+            .bindings()
+            .iter()
+            .chain(SHARED_BINDINGS.iter())
+            .chain(MENU_BINDINGS.iter())
+            .chain(VIEW_BINDINGS.iter())
+            .chain(TOGGLE_BINDINGS.iter())
+        {
+            input_map.bindings.entry(*action).or_default().push(*key);
+        }
+        input_map
+    }
+
+    pub(crate) fn path() -> Option<PathBuf> {
+        project_dirs().map(|dirs| dirs.config_dir().join("keybindings.ron"))
+    }
+
+    /// Custom bindings, with the preset filling in any action the file doesn't mention.
+    pub(crate) fn load(preset: ControlPreset) -> Self {
+        let mut input_map: Self = Self::path()
+            .and_then(|path| load_ron(&path))
+            .unwrap_or_default();
+
+        for (action, keys) in Self::from_preset(preset).bindings {
+            input_map.bindings.entry(action).or_insert(keys);
+        }
+        input_map
+    }
+
+    pub(crate) fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_ron(&path, self);
+        }
+    }
+
+    /// Bind `action` to `key` only, taking the key away from other rebindable actions.
+    pub(crate) fn rebind(&mut self, action: Action, key: KeyCode) {
+        for other in Action::REBINDABLE {
+            if let Some(keys) = self.bindings.get_mut(&other) {
+                keys.retain(|bound| *bound != key);
+            }
+        }
+        self.bindings.insert(action, vec![key]);
+    }
+
+    pub(crate) fn describe(&self, action: Action) -> String {
+        let keys: Vec<String> = self
+            .keys(action)
+            .iter()
+            .map(|key| format!("{key:?}"))
+            .collect();
+        if keys.is_empty() {
+            "unbound".to_string()
+        } else {
+            keys.join(", ")
+        }
+    }
+
+    pub(crate) fn keys(&self, action: Action) -> &[KeyCode] {
+        self.bindings
+            .get(&action)
+            .map(|keys| keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.any_pressed(self.keys(action).iter().copied())
+    }
+
+    pub(crate) fn just_pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.any_just_pressed(self.keys(action).iter().copied())
+    }
+}
+
+/// Button mapping for one kind of controller.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GamepadProfile {
+    pub(crate) buttons: HashMap<Action, GamepadButtonType>,
+    pub(crate) analog: HashMap<Action, Vec<AnalogBinding>>,
+}
+
+impl Default for GamepadProfile {
+    fn default() -> Self {
+        Self {
+            buttons: [
+                (Action::MoveLeft, GamepadButtonType::DPadLeft),
+                (Action::MoveRight, GamepadButtonType::DPadRight),
+                (Action::RotateCcw, GamepadButtonType::LeftTrigger),
+                (Action::RotateCw, GamepadButtonType::RightTrigger),
+                (Action::SoftDrop, GamepadButtonType::DPadDown),
+                (Action::HardDrop, GamepadButtonType::North),
+                (Action::Hold, GamepadButtonType::West),
+                (Action::Pause, GamepadButtonType::Start),
+                (Action::MenuUp, GamepadButtonType::DPadUp),
+                (Action::MenuDown, GamepadButtonType::DPadDown),
+                (Action::MenuConfirm, GamepadButtonType::South),
+                (Action::MenuCancel, GamepadButtonType::East),
+            ]
+            .into_iter()
+            .collect(),
+            // Force proportional to stick deflection, torque proportional to trigger pressure
+            analog: [
+                (
+                    Action::MoveLeft,
+                    vec![AnalogBinding::AxisNegative(GamepadAxisType::LeftStickX)],
+                ),
+                (
+                    Action::MoveRight,
+                    vec![AnalogBinding::AxisPositive(GamepadAxisType::LeftStickX)],
+                ),
+                (
+                    Action::SoftDrop,
+                    vec![AnalogBinding::AxisNegative(GamepadAxisType::LeftStickY)],
+                ),
+                (
+                    Action::RotateCcw,
+                    vec![AnalogBinding::Button(GamepadButtonType::LeftTrigger2)],
+                ),
+                (
+                    Action::RotateCw,
+                    vec![AnalogBinding::Button(GamepadButtonType::RightTrigger2)],
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+}
+
+impl GamepadProfile {
+    pub(crate) fn value(
+        &self,
+        action: Action,
+        gamepad: Gamepad,
+        buttons: &Input<GamepadButton>,
+        button_axes: &Axis<GamepadButton>,
+        axes: &Axis<GamepadAxis>,
+    ) -> f32 {
+        let digital = match self.buttons.get(&action) {
+            Some(button_type) if buttons.pressed(GamepadButton::new(gamepad, *button_type)) => 1.0,
+            _ => 0.0,
+        };
+
+        self.analog
+            .get(&action)
+            .into_iter()
+            .flatten()
+            .map(|binding| binding.value(gamepad, button_axes, axes))
+            .fold(digital, f32::max)
+    }
+}
+
+/// Gamepad mappings per controller, keyed by controller name so that
+/// different kinds of pads each keep their own layout.
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GamepadProfiles {
+    pub(crate) profiles: HashMap<String, GamepadProfile>,
+    #[serde(skip)]
+    pub(crate) fallback: GamepadProfile,
+}
+
+impl GamepadProfiles {
+    pub(crate) fn path() -> Option<PathBuf> {
+        project_dirs().map(|dirs| dirs.config_dir().join("gamepads.ron"))
+    }
+
+    pub(crate) fn load() -> Self {
+        Self::path()
+            .and_then(|path| load_ron(&path))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_ron(&path, self);
+        }
+    }
+
+    pub(crate) fn get(&self, controller_name: &str) -> &GamepadProfile {
+        self.profiles.get(controller_name).unwrap_or(&self.fallback)
+    }
+}
+
+/// In-game "press the button for X" remapping of one controller.
+#[derive(Resource)]
+pub(crate) struct GamepadRemapWizard {
+    pub(crate) gamepad: Option<Gamepad>,
+    pub(crate) step: usize,
+    pub(crate) profile: GamepadProfile,
+}
+
+impl GamepadRemapWizard {
+    pub(crate) const ACTIONS: [Action; 5] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::RotateCcw,
+        Action::RotateCw,
+        Action::SoftDrop,
+    ];
+
+    pub(crate) fn current_action(&self) -> Option<Action> {
+        Self::ACTIONS.get(self.step).copied()
+    }
+
+    pub(crate) fn prompt(&self) -> String {
+        match (self.gamepad, self.current_action()) {
+            (None, _) => "Press any button on the controller to remap\n(F3 to cancel)".to_string(),
+            (Some(_), Some(action)) => {
+                format!("Press the button for {}\n(F3 to cancel)", action.name())
+            }
+            (Some(_), None) => String::new(),
+        }
+    }
+}
+
+/// How strongly each action is currently activated, in `0.0..=1.0`.
+/// Keys are digital, analog inputs give intermediate values.
+#[derive(Resource, Default)]
+pub(crate) struct ActionState {
+    pub(crate) values: HashMap<Action, f32>,
+    pub(crate) previous_values: HashMap<Action, f32>,
+}
+
+impl ActionState {
+    pub(crate) fn value(&self, action: Action) -> f32 {
+        self.values.get(&action).copied().unwrap_or(0.0)
+    }
+
+    pub(crate) fn just_pressed(&self, action: Action) -> bool {
+        let previous = self.previous_values.get(&action).copied().unwrap_or(0.0);
+        self.value(action) > 0.5 && previous <= 0.5
+    }
+
+    /// Difference between two opposing actions, in `-1.0..=1.0`.
+    pub(crate) fn axis(&self, negative: Action, positive: Action) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+}
+
+/// Single-button play, for switch-access hardware. The lateral force on the piece sweeps from
+/// side to side on its own, and the switch commits to where the piece is: the sweep stops and
+/// the piece is soft dropped until it settles. Presses shorter than the acceptance time are
+/// ignored, so that tremors don't commit. Both timings are in the settings.
+#[derive(Resource, Default)]
+pub(crate) struct OneSwitch {
+    /// Of the sweep, in `0.0..1.0`
+    pub(crate) phase: f32,
+    /// Real seconds the switch has been held, `None` once accepted until released
+    pub(crate) held: Option<f32>,
+    pub(crate) committed: bool,
+}
+
+impl OneSwitch {
+    /// The actions driven by the assist instead of by the player while it is on.
+    pub(crate) const ASSISTED: [Action; 4] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+    ];
+
+    /// Drive the assisted actions from the switch, a frame of `sweep_delta` scaled seconds and
+    /// `real_delta` real seconds later.
+    pub(crate) fn apply(
+        &mut self,
+        action_state: &mut ActionState,
+        settings: &Settings,
+        sweep_delta: f32,
+        real_delta: f32,
+    ) {
+        if action_state.value(Action::Switch) > 0.5 {
+            if let Some(held) = self.held.as_mut() {
+                *held += real_delta;
+                if *held >= settings.one_switch_accept_seconds {
+                    self.held = None;
+                    self.committed = true;
+                }
+            }
+        } else {
+            self.held = Some(0.0);
+        }
+
+        let (movement, soft_drop) = if self.committed {
+            (0.0, 1.0)
+        } else {
+            self.phase = (self.phase + sweep_delta / settings.one_switch_sweep_seconds).fract();
+            ((self.phase * std::f32::consts::TAU).sin(), 0.0)
+        };
+
+        let values = &mut action_state.values;
+        values.insert(Action::MoveLeft, (-movement).max(0.0));
+        values.insert(Action::MoveRight, movement.max(0.0));
+        values.insert(Action::SoftDrop, soft_drop);
+        values.insert(Action::HardDrop, 0.0);
+    }
+
+    /// Sweep again from the middle, for the next piece.
+    pub(crate) fn reset(&mut self) {
+        self.phase = 0.0;
+        self.committed = false;
+    }
+}
+
+/// Real seconds since the last input during a run.
+#[derive(Resource, Default)]
+pub(crate) struct IdleTimer {
+    pub(crate) idle: f32,
+    pub(crate) paused: bool,
+}
+
+/// Movement and rotation input for the controlled piece, in `-1.0..=1.0`.
+/// Soft drop is in `0.0..=1.0`. Hard drop and hold are set on the frame they are pressed.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PieceInput {
+    pub(crate) movement: f32,
+    pub(crate) torque: f32,
+    pub(crate) soft_drop: f32,
+    pub(crate) hard_drop: bool,
+    pub(crate) hold: bool,
+}
+
+impl PieceInput {
+    pub(crate) fn from_actions(action_state: &ActionState) -> Self {
+        Self {
+            movement: action_state.axis(Action::MoveLeft, Action::MoveRight),
+            torque: action_state.axis(Action::RotateCw, Action::RotateCcw),
+            soft_drop: action_state.value(Action::SoftDrop),
+            hard_drop: action_state.just_pressed(Action::HardDrop),
+            hold: action_state.just_pressed(Action::Hold),
+        }
+    }
+
+    /// Input for the second piece in Twin mode or the second player in Versus mode,
+    /// which can't hold.
+    pub(crate) fn from_second_actions(action_state: &ActionState) -> Self {
+        Self {
+            movement: action_state.axis(Action::SecondMoveLeft, Action::SecondMoveRight),
+            torque: action_state.axis(Action::SecondRotateCw, Action::SecondRotateCcw),
+            soft_drop: action_state.value(Action::SecondSoftDrop),
+            hard_drop: action_state.just_pressed(Action::SecondHardDrop),
+            ..Default::default()
+        }
+    }
+
+    /// Input for each piece live on a board: the first and second piece controls in Twin
+    /// mode, or the controls of the board's player in Versus mode. Online, the local player
+    /// has the first controls whichever board is theirs. The bot only controls the first piece,
+    /// and so does the replay of a match re-simulated by the referee.
+    pub(crate) fn for_board(
+        action_state: &ActionState,
+        coop: Option<&CoopSession>,
+        online: Option<&OnlineSession>,
+        referee: Option<&Referee>,
+        board: &Board,
+        bot: &Bot,
+    ) -> [Self; 2] {
+        if let (Some(referee), Some(player)) = (referee, board.player) {
+            return [referee.piece_input(player), Self::default()];
+        }
+        if board.bot {
+            return [bot.input, Self::default()];
+        }
+
+        let first = match (board.player, coop, online) {
+            (Some(_), _, Some(online)) => online.piece_input(action_state, board),
+            (Some(1), _, None) => Self::from_second_actions(action_state),
+            (Some(_), _, None) | (None, None, _) => Self::from_actions(action_state),
+            (None, Some(coop), _) => coop.piece_input(action_state),
+        };
+
+        [first, Self::from_second_actions(action_state)]
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct GamepadRemapPrompt;
+
+/// Waiting for the key to bind `action` to on the key bindings screen.
+#[derive(Resource)]
+pub(crate) struct KeyCapture {
+    pub(crate) action: Action,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn update_action_state(
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    gamepads: Res<Gamepads>,
+    gamepad_profiles: Res<GamepadProfiles>,
+    buttons: Res<Input<GamepadButton>>,
+    button_axes: Res<Axis<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    touch_actions: Res<TouchActions>,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    settings: Res<Settings>,
+    mut piece_settled_events: EventReader<PieceSettled>,
+    mut action_state: ResMut<ActionState>,
+    mut one_switch: ResMut<OneSwitch>,
+) {
+    action_state.previous_values = action_state.values.clone();
+
+    for action in Action::ALL {
+        let digital: f32 = if input_map.pressed(&input, action) {
+            1.0
+        } else {
+            0.0
+        };
+
+        let gamepad = gamepads
+            .iter()
+            .map(|gamepad| {
+                gamepad_profiles
+                    .get(gamepads.name(gamepad).unwrap_or_default())
+                    .value(action, gamepad, &buttons, &button_axes, &axes)
+            })
+            .fold(0.0, f32::max);
+
+        action_state.values.insert(
+            action,
+            digital.max(gamepad).max(touch_actions.value(action)),
+        );
+    }
+
+    if piece_settled_events.iter().count() > 0 {
+        one_switch.reset();
+    }
+
+    if settings.one_switch_sweep_seconds > 0.0 {
+        one_switch.apply(
+            &mut action_state,
+            &settings,
+            time_scale.delta_seconds(&time),
+            time.delta_seconds(),
+        );
+    }
+}
+
+pub(crate) fn setup_input_map(mut commands: Commands, profile: Res<Profile>) {
+    commands.insert_resource(InputMap::load(profile.control_preset));
+}
+
+pub(crate) fn cycle_control_preset(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    fonts: Res<Fonts>,
+    mut profile: ResMut<Profile>,
+    mut input_map: ResMut<InputMap>,
+) {
+    if !input_map.just_pressed(&input, Action::ControlPreset) {
+        return;
+    }
+
+    profile.control_preset = profile.control_preset.next();
+    profile.save();
+
+    // Choosing a preset replaces any custom bindings
+    *input_map = InputMap::from_preset(profile.control_preset);
+    input_map.save();
+
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("Controls: {}", profile.control_preset.name()),
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gamepad_remap_wizard(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    buttons: Res<Input<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+    fonts: Res<Fonts>,
+    wizard: Option<ResMut<GamepadRemapWizard>>,
+    mut gamepad_profiles: ResMut<GamepadProfiles>,
+    mut time_scale: ResMut<GameTimeScale>,
+    mut prompt_query: Query<(Entity, &mut Text), With<GamepadRemapPrompt>>,
+) {
+    let close = |commands: &mut Commands, time_scale: &mut GameTimeScale| {
+        for (entity, _) in prompt_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        commands.remove_resource::<GamepadRemapWizard>();
+        time_scale.clear(TimeScaleSource::GamepadRemap);
+    };
+
+    let Some(mut wizard) = wizard else {
+        if input_map.just_pressed(&input, Action::GamepadRemap) {
+            let wizard = GamepadRemapWizard {
+                gamepad: None,
+                step: 0,
+                profile: GamepadProfile::default(),
+            };
+
+            commands
+                .spawn(
+                    fonts
+                        .text_bundle(&wizard.prompt(), 32.0, Color::WHITE)
+                        .with_text_alignment(TextAlignment::Center)
+                        .with_style(Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect {
+                                top: Val::Percent(40.0),
+                                left: Val::Percent(20.0),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        }),
+                )
+                .insert(GamepadRemapPrompt);
+
+            commands.insert_resource(wizard);
+            time_scale.set(TimeScaleSource::GamepadRemap, 0.0);
+        }
+        return;
+    };
+
+    if input_map.just_pressed(&input, Action::GamepadRemap) {
+        close(&mut commands, &mut time_scale);
+        return;
+    }
+
+    let Some(pressed) = buttons.get_just_pressed().next().copied() else {
+        return;
+    };
+
+    match (wizard.gamepad, wizard.current_action()) {
+        (None, _) => {
+            // The first button pressed picks the controller to remap
+            wizard.gamepad = Some(pressed.gamepad);
+            let controller_name = gamepads.name(pressed.gamepad).unwrap_or_default();
+            wizard.profile = gamepad_profiles.get(controller_name).clone();
+        }
+        (Some(gamepad), Some(action)) if pressed.gamepad == gamepad => {
+            wizard.profile.buttons.insert(action, pressed.button_type);
+            wizard.step += 1;
+        }
+        _ => {}
+    }
+
+    if let (Some(gamepad), None) = (wizard.gamepad, wizard.current_action()) {
+        let controller_name = gamepads.name(gamepad).unwrap_or_default().to_string();
+        gamepad_profiles
+            .profiles
+            .insert(controller_name, wizard.profile.clone());
+        gamepad_profiles.save();
+
+        close(&mut commands, &mut time_scale);
+        return;
+    }
+
+    for (_, mut text) in prompt_query.iter_mut() {
+        *text = fonts.text(&wizard.prompt(), 32.0, Color::WHITE);
+    }
+}
+
+/// Gameplay input is ignored while a menu, dialog or wizard is open.
+pub(crate) fn gameplay_input_allowed(
+    remap_wizard: Option<Res<GamepadRemapWizard>>,
+    menu_query: Query<(), With<Menu>>,
+) -> bool {
+    remap_wizard.is_none() && menu_query.is_empty()
+}
+
+/// Bind the next key pressed to the action being edited. Escape cancels.
+pub(crate) fn capture_key_binding(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    key_capture: Res<KeyCapture>,
+    mut input_map: ResMut<InputMap>,
+) {
+    let Some(key) = input.get_just_pressed().next() else {
+        return;
+    };
+
+    if *key != KeyCode::Escape {
+        input_map.rebind(key_capture.action, *key);
+        input_map.save();
+    }
+    commands.remove_resource::<KeyCapture>();
+}
+
+/// Pause a run nobody is playing, and after a longer while go back to the main menu.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn idle_detection(
+    mut commands: Commands,
+    time: Res<Time>,
+    input: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    action_state: Res<ActionState>,
+    settings: Res<Settings>,
+    fonts: Res<Fonts>,
+    onboarding: Option<Res<Onboarding>>,
+    demo: Option<Res<DemoMode>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut idle_timer: ResMut<IdleTimer>,
+    mut time_scale: ResMut<GameTimeScale>,
+    menu_query: Query<(), With<Menu>>,
+    screen_query: Query<Entity, With<IdlePauseScreen>>,
+) {
+    let any_input = input.get_pressed().next().is_some()
+        || buttons.get_pressed().next().is_some()
+        || Action::ALL
+            .iter()
+            // The sweep of the one-switch mode isn't input
+            .filter(|action| {
+                settings.one_switch_sweep_seconds <= 0.0 || !OneSwitch::ASSISTED.contains(action)
+            })
+            .any(|action| action_state.value(*action) > 0.0);
+
+    let resume =
+        |commands: &mut Commands, idle_timer: &mut IdleTimer, time_scale: &mut GameTimeScale| {
+            for entity in screen_query.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+            idle_timer.paused = false;
+            time_scale.clear(TimeScaleSource::Idle);
+        };
+
+    // Menus and dialogs wait for input anyway, and the demo plays on its own
+    if any_input || onboarding.is_some() || demo.is_some() || !menu_query.is_empty() {
+        idle_timer.idle = 0.0;
+        if idle_timer.paused {
+            resume(&mut commands, &mut idle_timer, &mut time_scale);
+        }
+        return;
+    }
+
+    idle_timer.idle += time.delta_seconds();
+
+    if settings.idle_menu_seconds > 0.0 && idle_timer.idle > settings.idle_menu_seconds {
+        resume(&mut commands, &mut idle_timer, &mut time_scale);
+        idle_timer.idle = 0.0;
+        next_state.set(AppState::MainMenu);
+        return;
+    }
+
+    if settings.idle_pause_seconds > 0.0
+        && idle_timer.idle > settings.idle_pause_seconds
+        && !idle_timer.paused
+    {
+        idle_timer.paused = true;
+        time_scale.set(TimeScaleSource::Idle, 0.0);
+
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                ..Default::default()
+            })
+            .insert(IdlePauseScreen)
+            .with_children(|parent| {
+                parent.spawn(fonts.text_bundle("Paused, press any key", 40.0, Color::WHITE));
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_follow_their_bindings() {
+        let mut input_map = InputMap::from_preset(ControlPreset::ArrowsAd);
+        let mut input = Input::<KeyCode>::default();
+        input.press(KeyCode::F1);
+        assert!(input_map.just_pressed(&input, Action::AssistMode));
+
+        input_map.rebind(Action::AssistMode, KeyCode::G);
+        assert!(!input_map.just_pressed(&input, Action::AssistMode));
+        input.press(KeyCode::G);
+        assert!(input_map.just_pressed(&input, Action::AssistMode));
+    }
+
+    #[test]
+    fn rebinding_a_key_takes_it_away_from_other_actions() {
+        let mut input_map = InputMap::from_preset(ControlPreset::ArrowsAd);
+        assert_eq!(input_map.keys(Action::SwapInset), &[KeyCode::Tab]);
+        assert_eq!(input_map.keys(Action::UiScaleUp), &[KeyCode::Equals]);
+        assert_eq!(input_map.keys(Action::CleanFeed), &[KeyCode::V]);
+
+        input_map.rebind(Action::Hold, KeyCode::Tab);
+        assert_eq!(input_map.keys(Action::Hold), &[KeyCode::Tab]);
+        assert!(input_map.keys(Action::SwapInset).is_empty());
+        assert_eq!(input_map.describe(Action::SwapInset), "unbound");
+        // Menu keys are not rebindable and keep their keys
+        input_map.rebind(Action::Pause, KeyCode::Escape);
+        assert!(input_map
+            .keys(Action::MenuCancel)
+            .contains(&KeyCode::Escape));
+    }
+
+    #[test]
+    fn one_switch_sweeps_until_a_long_enough_press_commits() {
+        let settings = Settings {
+            one_switch_sweep_seconds: 4.0,
+            one_switch_accept_seconds: 0.1,
+            ..Default::default()
+        };
+        let mut one_switch = OneSwitch::default();
+        let mut action_state = ActionState::default();
+
+        // A quarter sweep in, pushing right as hard as it goes
+        one_switch.apply(&mut action_state, &settings, 1.0, 1.0);
+        assert!((action_state.value(Action::MoveRight) - 1.0).abs() < 1e-6);
+        assert_eq!(action_state.value(Action::MoveLeft), 0.0);
+
+        action_state.values.insert(Action::Switch, 1.0);
+        one_switch.apply(&mut action_state, &settings, 0.05, 0.05);
+        assert!(!one_switch.committed);
+        one_switch.apply(&mut action_state, &settings, 0.06, 0.06);
+        assert!(one_switch.committed);
+        assert_eq!(action_state.value(Action::SoftDrop), 1.0);
+        assert_eq!(action_state.value(Action::MoveRight), 0.0);
+
+        one_switch.reset();
+        action_state.values.insert(Action::Switch, 0.0);
+        one_switch.apply(&mut action_state, &settings, 0.0, 0.0);
+        assert!(!one_switch.committed);
+        assert_eq!(action_state.value(Action::SoftDrop), 0.0);
+    }
+}
@@ -0,0 +1,110 @@
+//! Tetris where the pieces are rigid bodies, pushed around with forces.
+//!
+//! [`GamePlugin`] adds the whole game to an app. The plugins of the individual
+//! parts are exported too, for embedding the game with only some of them.
+
+mod board;
+mod bot;
+mod coop;
+mod custom;
+mod damage;
+mod danger;
+mod dressing;
+mod effects;
+mod hazards;
+pub mod headless;
+mod input;
+mod modes;
+mod online;
+mod physics;
+mod pieces;
+mod scoring;
+mod settings;
+mod skins;
+mod slicing;
+mod sticky;
+mod tetromino;
+mod touch;
+mod tournament;
+mod ui;
+mod versus;
+
+use bevy::prelude::*;
+
+use crate::settings::Settings;
+
+pub use board::BoardPlugin;
+pub use bot::BotPlugin;
+pub use coop::CoopPlugin;
+pub use custom::CustomGamePlugin;
+pub use damage::DamagePlugin;
+pub use danger::DangerPlugin;
+pub use dressing::DressingPlugin;
+pub use effects::EffectsPlugin;
+pub use hazards::HazardsPlugin;
+pub use input::GameInputPlugin;
+pub use modes::GameModePlugin;
+pub use online::OnlinePlugin;
+pub use physics::PhysicsPlugin;
+pub use pieces::PiecesPlugin;
+pub use scoring::ScoringPlugin;
+pub use skins::SkinsPlugin;
+pub use slicing::SlicingPlugin;
+pub use sticky::StickyPlugin;
+pub use tetromino::TetrominoPlugin;
+pub use touch::TouchInputPlugin;
+pub use tournament::TournamentPlugin;
+pub use ui::GameUiPlugin;
+pub use versus::VersusPlugin;
+
+/// The whole game. Add it to an app that already has `DefaultPlugins`, or the
+/// equivalent rendering, windowing and input plugins.
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
+            .insert_resource(Msaa::default())
+            .insert_resource(Settings::load())
+            .add_state::<AppState>()
+            .configure_set(GameplaySet.run_if(in_state(AppState::Playing)))
+            .add_plugin(BoardPlugin)
+            .add_plugin(DressingPlugin)
+            .add_plugin(DangerPlugin)
+            .add_plugin(PiecesPlugin)
+            .add_plugin(TetrominoPlugin)
+            .add_plugin(SkinsPlugin)
+            .add_plugin(PhysicsPlugin)
+            .add_plugin(ScoringPlugin)
+            .add_plugin(EffectsPlugin)
+            .add_plugin(SlicingPlugin)
+            .add_plugin(DamagePlugin)
+            .add_plugin(StickyPlugin)
+            .add_plugin(GameModePlugin)
+            .add_plugin(HazardsPlugin)
+            .add_plugin(VersusPlugin)
+            .add_plugin(CoopPlugin)
+            .add_plugin(OnlinePlugin)
+            .add_plugin(CustomGamePlugin)
+            .add_plugin(BotPlugin)
+            .add_plugin(TournamentPlugin)
+            .add_plugin(GameInputPlugin)
+            .add_plugin(TouchInputPlugin)
+            .add_plugin(GameUiPlugin);
+    }
+}
+
+/// Top-level flow of the game. Gameplay systems only run while `Playing`.
+#[derive(States, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub(crate) enum AppState {
+    #[default]
+    MainMenu,
+    Playing,
+    Paused,
+    /// Every board is over, their runs restart shortly
+    GameOver,
+}
+
+/// Systems simulating and controlling the boards.
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct GameplaySet;
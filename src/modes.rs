@@ -0,0 +1,669 @@
+//! Game modes and their rules: Drill scenarios, Recycle junk and bombs. The hazards of Chaos
+//! mode are scheduled by `hazards`.
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Board, GameRng, SpawnEntry, GAME_OVER_TIME_SCALE};
+use crate::effects::{spawn_sparks, start_clear_flash, CameraShake, EXPLOSION_SHAKE};
+use crate::hazards::{HazardTable, CHAOS_HAZARDS};
+use crate::input::{Action, InputMap};
+use crate::physics::{block_death_detection, GameTimeScale, TimeScaleSource, GRACE_DURATION};
+use crate::scoring::{BoardStats, CascadeChain, GradeThreshold, Profile};
+use crate::settings::Settings;
+use crate::tetromino::{
+    spawn_loose_block, tetromino_sleep_detection, ActivePieces, Block, PieceSettled, TetrominoKind,
+};
+use crate::ui::{spawn_toast, Fonts};
+use crate::GameplaySet;
+
+/// Rules of the Drill and Recycle modes, bombs, and switching between modes.
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(toggle_game_mode)
+            .add_system(
+                drill_progress
+                    .after(tetromino_sleep_detection)
+                    .in_set(GameplaySet),
+            )
+            .add_system(drill_hud.after(drill_progress))
+            .add_system(
+                recycle_lost_blocks
+                    .after(block_death_detection)
+                    .in_set(GameplaySet),
+            )
+            .add_system(bomb_blink)
+            .add_system(
+                detonate_bombs
+                    .after(tetromino_sleep_detection)
+                    .in_set(GameplaySet),
+            );
+    }
+}
+
+// Recycle mode, in scaled seconds:
+pub(crate) const JUNK_RESPAWN_DELAY: f32 = 3.0;
+pub(crate) const JUNK_COLOR: Color = Color::rgb(0.45, 0.45, 0.45);
+
+pub(crate) const DRILL_COMPLETION_POINTS: i32 = 100;
+pub(crate) const DRILL_SPARE_PIECE_POINTS: i32 = 50;
+
+pub(crate) const GRADE_THRESHOLDS: &str = include_str!("../assets/modes/grades.ron");
+
+pub(crate) const BOMB_RADIUS: f32 = 3.0;
+pub(crate) const BOMB_IMPULSE: f32 = 0.08;
+pub(crate) const BOMB_BLINK_INTERVAL: f32 = 0.25;
+// Chance of a piece being a bomb in any mode but Drill, besides the bomb hazard of Chaos mode
+pub(crate) const BOMB_CHANCE: f64 = 0.015;
+// Horizontal speed of the pieces sliding in from the side chute
+pub(crate) const CHAOS_CHUTE_SPEED: f32 = 6.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum GameMode {
+    #[default]
+    Classic,
+    /// Random wind, earthquakes, gravity flips, pistons and bomb pieces, see `CHAOS_HAZARDS`
+    Chaos,
+    /// Short generated scenarios with a given set of pieces and a goal
+    Drill,
+    /// Lost blocks come back as junk, which is only gone for good when cleared
+    Recycle,
+    /// Two pieces live at once, each with its own controls
+    Twin,
+    /// Two players on boards side by side, sending garbage rows to each other, see `versus`
+    Versus,
+    /// Blocks weld to the blocks they come to rest against, see `sticky`
+    Sticky,
+}
+
+impl GameMode {
+    pub(crate) const ALL: [Self; 7] = [
+        Self::Classic,
+        Self::Chaos,
+        Self::Drill,
+        Self::Recycle,
+        Self::Twin,
+        Self::Versus,
+        Self::Sticky,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Classic => "Classic",
+            Self::Chaos => "Chaos",
+            Self::Drill => "Drill",
+            Self::Recycle => "Recycle",
+            Self::Twin => "Twin",
+            Self::Versus => "Versus",
+            Self::Sticky => "Sticky",
+        }
+    }
+
+    pub(crate) fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// How far below the bottom of the view blocks are counted lost.
+    pub(crate) fn loss_margin(&self) -> f32 {
+        match self {
+            // Gravity flips may still bring blocks back
+            Self::Chaos => 4.0,
+            Self::Classic
+            | Self::Drill
+            | Self::Recycle
+            | Self::Twin
+            | Self::Versus
+            | Self::Sticky => 2.0,
+        }
+    }
+
+    /// The hazards striking during a run, `None` when there are none.
+    pub(crate) fn hazards(&self) -> Option<&'static HazardTable> {
+        match self {
+            Self::Chaos => Some(&CHAOS_HAZARDS),
+            Self::Classic
+            | Self::Drill
+            | Self::Recycle
+            | Self::Twin
+            | Self::Versus
+            | Self::Sticky => None,
+        }
+    }
+
+    /// Where new pieces enter the board.
+    pub(crate) fn spawn_entry(&self) -> SpawnEntry {
+        match self {
+            Self::Chaos => SpawnEntry::SideChute {
+                speed: CHAOS_CHUTE_SPEED,
+            },
+            // Spreading the load, as the junk comes back in the middle
+            Self::Recycle => SpawnEntry::AlternatingCorners,
+            Self::Classic | Self::Drill | Self::Twin | Self::Versus | Self::Sticky => {
+                SpawnEntry::TopCenter
+            }
+        }
+    }
+
+    /// Scaled seconds that blocks may teeter in the grace zone below the floor edge and still
+    /// be rescued, `None` when there is no grace zone.
+    pub(crate) fn grace_duration(&self) -> Option<f32> {
+        match self {
+            Self::Classic | Self::Chaos | Self::Twin | Self::Versus | Self::Sticky => {
+                Some(GRACE_DURATION)
+            }
+            // Drills are strict, and Recycle brings lost blocks back anyway
+            Self::Drill | Self::Recycle => None,
+        }
+    }
+
+    /// Grade thresholds from `GRADE_THRESHOLDS`, ordered from best to worst grade.
+    pub(crate) fn grade_thresholds(&self) -> Vec<GradeThreshold> {
+        let mut thresholds: HashMap<Self, Vec<GradeThreshold>> =
+            ron::from_str(GRADE_THRESHOLDS).expect("invalid grade thresholds");
+        thresholds.remove(self).unwrap_or_default()
+    }
+}
+
+/// A kind of practice drill: rows filled up front with gaps, a fixed set of pieces
+/// and a number of rows to clear with them. Each attempt places the gaps at random.
+pub(crate) struct DrillTemplate {
+    pub(crate) name: &'static str,
+    pub(crate) filled_rows: usize,
+    /// Width of the gap in each filled row, in block slots
+    pub(crate) gap_width: usize,
+    /// Whether the gaps of all rows line up, forming a well
+    pub(crate) aligned_gaps: bool,
+    pub(crate) pieces: &'static [TetrominoKind],
+    pub(crate) goal_rows: usize,
+}
+
+pub(crate) const DRILL_TEMPLATES: [DrillTemplate; 4] = [
+    DrillTemplate {
+        name: "Single-column well",
+        filled_rows: 2,
+        gap_width: 1,
+        aligned_gaps: true,
+        pieces: &[TetrominoKind::I, TetrominoKind::I, TetrominoKind::I],
+        goal_rows: 2,
+    },
+    DrillTemplate {
+        name: "Deep well",
+        filled_rows: 4,
+        gap_width: 1,
+        aligned_gaps: true,
+        pieces: &[TetrominoKind::I, TetrominoKind::I],
+        goal_rows: 4,
+    },
+    DrillTemplate {
+        name: "Square pocket",
+        filled_rows: 2,
+        gap_width: 2,
+        aligned_gaps: true,
+        pieces: &[TetrominoKind::O, TetrominoKind::O],
+        goal_rows: 2,
+    },
+    DrillTemplate {
+        name: "Staggered gaps",
+        filled_rows: 2,
+        gap_width: 2,
+        aligned_gaps: false,
+        pieces: &[
+            TetrominoKind::S,
+            TetrominoKind::Z,
+            TetrominoKind::T,
+            TetrominoKind::O,
+        ],
+        goal_rows: 2,
+    },
+];
+
+impl DrillTemplate {
+    pub(crate) fn random(rng: &mut impl Rng) -> &'static Self {
+        &DRILL_TEMPLATES[rng.gen_range(0..DRILL_TEMPLATES.len())]
+    }
+
+    pub(crate) fn description(&self) -> String {
+        format!(
+            "{}: clear {} rows with {} pieces",
+            self.name,
+            self.goal_rows,
+            self.pieces.len()
+        )
+    }
+
+    /// Fill the bottom rows of a board, leaving this drill's gaps.
+    pub(crate) fn spawn_rows(
+        &self,
+        commands: &mut Commands,
+        board_entity: Entity,
+        board: &Board,
+        rng: &mut GameRng,
+    ) {
+        let slots = board.slot_centers();
+        let max_gap_start = slots.len().saturating_sub(self.gap_width);
+        let mut gap_start = rng.gen_range(0..=max_gap_start);
+
+        for row in 0..self.filled_rows {
+            if !self.aligned_gaps {
+                gap_start = rng.gen_range(0..=max_gap_start);
+            }
+
+            for (slot, x) in slots.iter().enumerate() {
+                if slot >= gap_start && slot < gap_start + self.gap_width {
+                    continue;
+                }
+                // Nothing to rest on between floors
+                let Some(floor_y) = board.floor_y_at(*x) else {
+                    continue;
+                };
+
+                let position = Vec2::new(*x, floor_y + row as f32 + 0.5);
+                spawn_loose_block(commands, board_entity, board, position, true, rng);
+            }
+        }
+    }
+}
+
+/// The drill being played on a board in Drill mode.
+#[derive(Component)]
+pub(crate) struct Drill {
+    pub(crate) template: &'static DrillTemplate,
+    /// Whether the goal was reached, once the attempt is over
+    pub(crate) completed: Option<bool>,
+}
+
+/// Drill completion, per drill template.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct DrillRecord {
+    pub(crate) attempts: u32,
+    pub(crate) completions: u32,
+    pub(crate) best_score: i32,
+}
+
+/// Gray block recycled from a lost one in Recycle mode.
+#[derive(Component)]
+pub(crate) struct Junk;
+
+/// Lost blocks of a board waiting to come back as junk in Recycle mode.
+#[derive(Component, Default)]
+pub(crate) struct JunkRecycler {
+    /// Scaled seconds left for each pending junk block
+    pub(crate) pending: Vec<f32>,
+    /// Lost blocks already queued for recycling
+    pub(crate) queued_lost: i32,
+}
+
+/// Block of a bomb piece, exploding when the piece settles.
+#[derive(Component)]
+pub(crate) struct Bomb {
+    pub(crate) color: Color,
+}
+
+/// Goal and progress of the drill on a board.
+#[derive(Component)]
+pub(crate) struct DrillHud {
+    pub(crate) board: Entity,
+}
+
+/// Choose the mode for the next run.
+pub(crate) fn toggle_game_mode(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+    board_query: Query<&Board>,
+) {
+    if !input_map.just_pressed(&input, Action::GameMode) {
+        return;
+    }
+
+    settings.mode = settings.mode.next();
+    settings.save();
+
+    // Switching to or from Versus mode changes the boards, which are set up at launch
+    let versus_boards = board_query.iter().any(|board| board.player.is_some());
+    let when = if versus_boards == (settings.mode == GameMode::Versus) {
+        "Next run"
+    } else {
+        "Next launch"
+    };
+
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("{when}: {} mode", settings.mode.name()),
+    );
+}
+
+/// In Recycle mode, drop each lost block back onto the board as junk after a delay.
+pub(crate) fn recycle_lost_blocks(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut board_query: Query<(
+        Entity,
+        &Board,
+        &mut BoardStats,
+        &mut JunkRecycler,
+        &mut GameRng,
+    )>,
+) {
+    let delta = time_scale.delta_seconds(&time);
+
+    for (board_entity, board, mut stats, mut recycler, mut rng) in board_query.iter_mut() {
+        // A new run starts with nothing to recycle
+        if board.mode != GameMode::Recycle || stats.lost_blocks < recycler.queued_lost {
+            *recycler = JunkRecycler::default();
+        }
+
+        if board.mode != GameMode::Recycle || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        for _ in recycler.queued_lost..stats.lost_blocks {
+            recycler.pending.push(JUNK_RESPAWN_DELAY);
+        }
+        recycler.queued_lost = stats.lost_blocks;
+
+        for remaining in recycler.pending.iter_mut() {
+            *remaining -= delta;
+        }
+
+        let n_ready = recycler
+            .pending
+            .iter()
+            .filter(|remaining| **remaining <= 0.0)
+            .count();
+        recycler.pending.retain(|remaining| *remaining > 0.0);
+
+        let slots = board.slot_centers();
+
+        for _ in 0..n_ready {
+            let x = slots[rng.gen_range(0..slots.len())];
+            let position = Vec2::new(x, board.top_y() - 0.5);
+
+            let junk_entity = spawn_loose_block(
+                &mut commands,
+                board_entity,
+                board,
+                position,
+                false,
+                &mut rng,
+            );
+            commands.entity(junk_entity).insert((
+                Junk,
+                Sprite {
+                    color: JUNK_COLOR,
+                    custom_size: Some(Vec2::ONE),
+                    ..Default::default()
+                },
+            ));
+
+            stats.junk_spawned += 1;
+        }
+    }
+}
+
+/// End a drill attempt once its goal is reached or its pieces have run out.
+pub(crate) fn drill_progress(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    mut profile: ResMut<Profile>,
+    mut time_scale: ResMut<GameTimeScale>,
+    mut board_query: Query<(&ActivePieces, &mut BoardStats, &CascadeChain, &mut Drill)>,
+) {
+    for (active_pieces, mut stats, cascade, mut drill) in board_query.iter_mut() {
+        if drill.completed.is_some() || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        let completed = stats.rows_cleared() >= drill.template.goal_rows;
+        let out_of_pieces = active_pieces.n_live() == 0 && cascade.remaining <= 0.0;
+
+        if !completed && !out_of_pieces {
+            continue;
+        }
+
+        if completed {
+            let spare_pieces =
+                active_pieces.supply.as_ref().map_or(0, VecDeque::len) + active_pieces.n_live();
+            stats.drill_points =
+                DRILL_COMPLETION_POINTS + DRILL_SPARE_PIECE_POINTS * spare_pieces as i32;
+        }
+
+        drill.completed = Some(completed);
+        stats.game_over_duration = Some(0.0);
+        time_scale.set(TimeScaleSource::GameOver, GAME_OVER_TIME_SCALE);
+
+        let score = stats.score();
+        let record = profile
+            .drills
+            .entry(drill.template.name.to_string())
+            .or_default();
+        record.attempts += 1;
+        if completed {
+            record.completions += 1;
+        }
+        record.best_score = record.best_score.max(score);
+        profile.save();
+
+        let message = if completed {
+            "Drill complete!"
+        } else {
+            "Drill failed"
+        };
+        spawn_toast(&mut commands, &fonts, message);
+    }
+}
+
+pub(crate) fn drill_hud(
+    board_query: Query<(&ActivePieces, &BoardStats, Option<&Drill>)>,
+    profile: Res<Profile>,
+    fonts: Res<Fonts>,
+    mut hud_query: Query<(&DrillHud, &mut Text)>,
+) {
+    for (hud, mut text) in hud_query.iter_mut() {
+        let Ok((active_pieces, stats, drill)) = board_query.get(hud.board) else {
+            continue;
+        };
+
+        let value = match drill {
+            Some(drill) => {
+                let record = profile.drills.get(drill.template.name);
+                format!(
+                    "DRILL  {}\nRows {}/{}   Pieces left {}   Completed {}/{}",
+                    drill.template.description(),
+                    stats.rows_cleared(),
+                    drill.template.goal_rows,
+                    active_pieces.supply.as_ref().map_or(0, VecDeque::len),
+                    record.map_or(0, |record| record.completions),
+                    record.map_or(0, |record| record.attempts),
+                )
+            }
+            None => String::new(),
+        };
+
+        *text = fonts.text(&value, 20.0, Color::rgb_u8(0, 244, 243));
+    }
+}
+
+pub(crate) fn bomb_blink(time: Res<Time>, mut bomb_query: Query<(&Bomb, &mut Sprite)>) {
+    let dark = ((time.elapsed_seconds() / BOMB_BLINK_INTERVAL) as u32).is_multiple_of(2);
+
+    for (bomb, mut sprite) in bomb_query.iter_mut() {
+        sprite.color = if dark {
+            Color::rgb(0.15, 0.15, 0.15)
+        } else {
+            bomb.color
+        };
+    }
+}
+
+/// Blow up the bomb pieces that settled: push the blocks around them away, shatter the bomb
+/// blocks and shake the camera.
+pub(crate) fn detonate_bombs(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    mut settled_events: EventReader<PieceSettled>,
+    mut board_query: Query<&mut BoardStats>,
+    bomb_query: Query<(&Bomb, &Transform, &Block)>,
+    mut block_query: Query<(&Block, &Transform, &mut ExternalImpulse), Without<Bomb>>,
+    mut camera_query: Query<&mut CameraShake>,
+) {
+    for event in settled_events.iter() {
+        let bomb_blocks: Vec<(Entity, Vec2, Color, f32)> = event
+            .blocks
+            .iter()
+            .filter_map(|entity| {
+                bomb_query
+                    .get(*entity)
+                    .ok()
+                    .map(|(bomb, transform, block)| {
+                        (
+                            *entity,
+                            transform.translation.truncate(),
+                            bomb.color,
+                            block.size,
+                        )
+                    })
+            })
+            .collect();
+
+        if bomb_blocks.is_empty() {
+            continue;
+        }
+
+        let center = bomb_blocks
+            .iter()
+            .map(|(_, position, _, _)| *position)
+            .sum::<Vec2>()
+            / bomb_blocks.len() as f32;
+
+        let mut in_blast: Vec<Entity> = vec![];
+        rapier_context.intersections_with_shape(
+            center,
+            0.0,
+            &Collider::ball(BOMB_RADIUS),
+            QueryFilter::default(),
+            |entity| {
+                in_blast.push(entity);
+                true
+            },
+        );
+
+        for entity in in_blast {
+            let Ok((block, transform, mut impulse)) = block_query.get_mut(entity) else {
+                continue;
+            };
+            if block.board != event.board {
+                continue;
+            }
+
+            let offset = transform.translation.truncate() - center;
+            let falloff = (1.0 - offset.length() / BOMB_RADIUS).max(0.0);
+            impulse.impulse += offset.normalize_or_zero() * BOMB_IMPULSE * falloff;
+        }
+
+        spawn_sparks(&mut commands, center);
+        for mut shake in camera_query.iter_mut() {
+            shake.add(EXPLOSION_SHAKE);
+        }
+
+        // Exploded blocks are neither cleared nor lost
+        if let Ok(mut stats) = board_query.get_mut(event.board) {
+            stats.generated_blocks -= bomb_blocks.len() as i32;
+        }
+
+        for (entity, _, color, size) in bomb_blocks {
+            start_clear_flash(&mut commands, entity, color, size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::effects::{ClearFlash, Debris};
+    use crate::scoring::Grade;
+    use crate::settings::test_world;
+
+    #[test]
+    fn every_mode_grades_from_best_to_worst() {
+        for mode in GameMode::ALL {
+            let thresholds = mode.grade_thresholds();
+            assert!(!thresholds.is_empty(), "{}", mode.name());
+            assert!(
+                thresholds
+                    .windows(2)
+                    .all(|pair| pair[0].grade < pair[1].grade
+                        && pair[0].min_score >= pair[1].min_score),
+                "{}",
+                mode.name()
+            );
+        }
+
+        let stats = BoardStats {
+            generated_blocks: 20,
+            ..Default::default()
+        };
+        assert_eq!(stats.grade(GameMode::Classic), Grade::D);
+    }
+
+    #[test]
+    fn settled_bombs_shatter_and_shake_the_camera_without_counting_as_lost() {
+        let mut world = test_world();
+        world.init_resource::<RapierContext>();
+        world.init_resource::<Events<PieceSettled>>();
+
+        let board = world
+            .spawn(BoardStats {
+                generated_blocks: 8,
+                ..Default::default()
+            })
+            .id();
+        let camera = world.spawn(CameraShake::default()).id();
+        let bomb_blocks: Vec<Entity> = (0..4)
+            .map(|x| {
+                world
+                    .spawn((
+                        Bomb { color: Color::RED },
+                        Block {
+                            board,
+                            kind: TetrominoKind::O,
+                            size: 1.0,
+                        },
+                        Transform::from_xyz(x as f32, 0.0, 0.0),
+                    ))
+                    .id()
+            })
+            .collect();
+
+        world.send_event(PieceSettled {
+            board,
+            blocks: bomb_blocks.clone(),
+        });
+        let mut schedule = Schedule::new();
+        schedule.add_system(detonate_bombs);
+        schedule.run(&mut world);
+
+        for block in bomb_blocks {
+            assert!(world.get::<ClearFlash>(block).is_some());
+            assert!(world.get::<Block>(block).is_none());
+        }
+        assert_eq!(world.get::<BoardStats>(board).unwrap().generated_blocks, 4);
+        assert_eq!(
+            world.get::<CameraShake>(camera).unwrap().intensity,
+            EXPLOSION_SHAKE
+        );
+        assert!(world.query::<&Debris>().iter(&world).count() > 0);
+    }
+}
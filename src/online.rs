@@ -0,0 +1,2211 @@
+//! Online Versus over UDP. Both boards are simulated by both players: the local board from the
+//! local input, and the opponent's board from the input and pieces the opponent sends, corrected
+//! from snapshots of the opponent's own simulation of it. Runs, garbage and knockouts are up to
+//! the player owning the board.
+//!
+//! Matches are rated by the verdict of a referee, who re-simulates the match from the input and
+//! pieces of both players, see `Referee`.
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{start_run, Board, BoardLayout, GameRng, RowClearing, RunStarted};
+use crate::coop::open_udp_socket;
+use crate::effects::Teardown;
+use crate::input::{ActionState, PieceInput};
+use crate::modes::{GameMode, JUNK_COLOR};
+use crate::physics::{block_death_detection, GameTimeScale, PhysicsTuning};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{BoardStats, Profile};
+use crate::settings::Settings;
+use crate::tetromino::{
+    spawn_loose_block, spawn_tetromino, tetromino_sleep_detection, ActivePieces, Block,
+    MirrorModifier, PieceSet, TetrominoKind,
+};
+use crate::ui::Fonts;
+use crate::versus::{
+    send_garbage, versus_knockout, Garbage, GarbageQueue, GARBAGE_DELAY, GARBAGE_ROWS,
+};
+use crate::{AppState, GameplaySet};
+
+/// Online Versus, active when started with an online Versus address.
+pub struct OnlinePlugin;
+
+impl Plugin for OnlinePlugin {
+    fn build(&self, app: &mut App) {
+        // Before `setup_game`, which sets up the boards for it
+        app.add_startup_system(open_online_session.in_base_set(StartupSet::PreStartup))
+            .add_startup_system(
+                setup_online_status
+                    .in_base_set(StartupSet::PostStartup)
+                    .run_if(resource_exists::<OnlineSession>()),
+            )
+            .add_system(online_receive.run_if(resource_exists::<OnlineSession>()))
+            .add_system(
+                spawn_remote_pieces
+                    .after(online_receive)
+                    .in_set(GameplaySet)
+                    .run_if(resource_exists::<OnlineSession>()),
+            )
+            .add_system(
+                online_send
+                    .after(online_receive)
+                    .after(tetromino_sleep_detection)
+                    .after(send_garbage)
+                    .run_if(resource_exists::<OnlineSession>()),
+            )
+            .add_system(online_rating.run_if(resource_exists::<OnlineSession>()))
+            .add_system(online_status.run_if(resource_exists::<OnlineSession>()))
+            .add_system(
+                referee_clock
+                    .in_base_set(CoreSet::PreUpdate)
+                    .run_if(resource_exists::<Referee>()),
+            )
+            .add_system(
+                referee_judge
+                    .before(versus_knockout)
+                    .after(block_death_detection)
+                    .after(tetromino_sleep_detection)
+                    .in_set(GameplaySet)
+                    .run_if(resource_exists::<Referee>()),
+            );
+    }
+}
+
+// Opponent input is held this long across lost packets, in real seconds
+pub(crate) const ONLINE_INPUT_TIMEOUT: f32 = 0.25;
+// Real seconds without word from the opponent before the status says so
+pub(crate) const ONLINE_TIMEOUT: f32 = 3.0;
+pub(crate) const ONLINE_MAX_DATAGRAM_SIZE: usize = 65507;
+// Bytes sent in a datagram at most, so that it fits the usual MTU without fragmenting
+pub(crate) const ONLINE_MAX_PAYLOAD: usize = 1200;
+// Blocks in each datagram of a snapshot, which fit in `ONLINE_MAX_PAYLOAD`...
+pub(crate) const ONLINE_SNAPSHOT_CHUNK_BLOCKS: usize = 40;
+// ...and in a whole snapshot, the rest being left out
+pub(crate) const ONLINE_SNAPSHOT_MAX_BLOCKS: usize = 1024;
+// Cleared rows a snapshot can bring at most
+pub(crate) const ONLINE_MAX_ROWS_CLEARED: u32 = 100_000;
+// First byte of a datagram: a message in RON, or a chunk of a snapshot in binary
+pub(crate) const DATAGRAM_MESSAGE: u8 = 0;
+pub(crate) const DATAGRAM_SNAPSHOT: u8 = 1;
+// Real seconds between snapshots of the local board
+pub(crate) const ONLINE_SNAPSHOT_INTERVAL: f32 = 0.5;
+// Latest piece kinds repeated in every input message, making up for that many lost messages
+pub(crate) const ONLINE_RECENT_KINDS: usize = 8;
+// Blocks further apart than this aren't the same block in a snapshot
+pub(crate) const ONLINE_MATCH_DISTANCE: f32 = 2.0;
+// Blocks closer than this to where a snapshot has them are left alone
+pub(crate) const ONLINE_CORRECTION_DISTANCE: f32 = 0.2;
+// Game seconds of each tick of input in a replay, see `RunReplay`
+pub(crate) const ONLINE_TICK: f32 = 1.0 / 60.0;
+// Latest ticks of input repeated in every input message, making up for that many lost messages
+pub(crate) const ONLINE_RECENT_TICKS: usize = 20;
+// Ticks of input, or pieces, of a replay received at most, 15 minutes' worth
+pub(crate) const ONLINE_MAX_REPLAY_LENGTH: u32 = 60 * 60 * 15;
+// Ticks of input and pieces in each answer to a replay request, which fit in `ONLINE_MAX_PAYLOAD`
+pub(crate) const ONLINE_REPLAY_CHUNK_TICKS: usize = 40;
+pub(crate) const ONLINE_REPLAY_CHUNK_KINDS: usize = 16;
+// Real seconds between requests for what is missing of the opponent's replay after a match...
+pub(crate) const ONLINE_REPLAY_REQUEST_INTERVAL: f32 = 0.25;
+// ...and before the rest is taken as no input
+pub(crate) const ONLINE_REPLAY_TIMEOUT: f32 = 10.0;
+// Game seconds the referee plays on past the end of both replays before calling a draw
+pub(crate) const REFEREE_OVERTIME: f32 = 60.0;
+// Clears whose garbage one input message can bring at most, making up for lost messages. More
+// garbage than that is taken as the largest possible.
+pub(crate) const ONLINE_MAX_CLEARS_PER_MESSAGE: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum OnlineMessage {
+    /// Guest to host, until welcomed, with the rules the guest plays by and their `player_id`,
+    /// see `Profile::opponent_ratings`
+    Join { rules: OnlineRules, player: u64 },
+    /// Host to guest, answering a join with the rules the host plays by. The two only play when
+    /// their rules are the same.
+    Welcome { rules: OnlineRules, player: u64 },
+    /// Every frame, about the sender's own board. Counts are totals, so that a lost message is
+    /// made up for by the next one.
+    Input {
+        sequence: u32,
+        /// Runs started on the sender's board
+        run: u32,
+        /// Ticks of input this run, the latest of them listed in `inputs`
+        ticks: u32,
+        inputs: Vec<TickInput>,
+        /// The run is over, and its counts final
+        over: bool,
+        /// Pieces drawn this run, the latest of them listed in `kinds`
+        drawn: u32,
+        kinds: Vec<TetrominoKind>,
+        /// Garbage rows sent this run
+        garbage_sent: i32,
+    },
+    /// After a match, asking for what was lost on the way of the opponent's run, from the given
+    /// tick of input and piece on
+    ReplayRequest {
+        run: u32,
+        ticks_from: u32,
+        kinds_from: u32,
+    },
+    /// Answering a `ReplayRequest` with the next of the sender's input and pieces, and their
+    /// counts like in `Input`
+    Replay {
+        run: u32,
+        ticks_from: u32,
+        inputs: Vec<TickInput>,
+        kinds_from: u32,
+        kinds: Vec<TetrominoKind>,
+        ticks: u32,
+        drawn: u32,
+        over: bool,
+    },
+    /// Every `ONLINE_SNAPSHOT_INTERVAL`, the sender's own board as the sender simulates it. Sent in
+    /// binary, in chunks, see `SnapshotChunk`.
+    #[serde(skip)]
+    Snapshot {
+        sequence: u32,
+        run: u32,
+        stats: OnlineStats,
+        blocks: Vec<OnlineBlock>,
+    },
+    /// After a match, the checksum of the physics world as the sender's referee ended it, for
+    /// the match of the receiver's run `run`. Both referees re-simulate the same match, so
+    /// different checksums are a desync between the two machines.
+    RefereeChecksum { run: u32, checksum: u64 },
+}
+
+impl OnlineMessage {
+    pub(crate) fn sequence(&self) -> u32 {
+        match self {
+            Self::Join { .. }
+            | Self::Welcome { .. }
+            | Self::ReplayRequest { .. }
+            | Self::Replay { .. }
+            | Self::RefereeChecksum { .. } => 0,
+            Self::Input { sequence, .. } | Self::Snapshot { sequence, .. } => *sequence,
+        }
+    }
+}
+
+/// Rules of the runs in online Versus. Both players simulate both boards, so both must play by
+/// the same rules for the two simulations to play out alike. Those include the controls that
+/// steer the pieces beyond the input itself.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct OnlineRules {
+    pub(crate) board_layout: String,
+    pub(crate) fuse: bool,
+    pub(crate) mirror: MirrorModifier,
+    pub(crate) stamina: bool,
+    pub(crate) side_walls: bool,
+    pub(crate) physics: PhysicsTuning,
+    pub(crate) row_clearing: RowClearing,
+    pub(crate) slicing: bool,
+    pub(crate) block_damage: bool,
+    pub(crate) breakable_joints: bool,
+    pub(crate) partial_rows: Option<f32>,
+    pub(crate) pieces: PieceSet,
+    pub(crate) rotation_assist: bool,
+    pub(crate) lane_snapping: bool,
+    pub(crate) lane_repeat: Option<(f32, f32)>,
+    pub(crate) assist_mode: bool,
+    pub(crate) piece_definitions: PieceDefinitions,
+}
+
+impl OnlineRules {
+    /// The rules chosen for the next run, which online is in Versus mode, see `start_run`.
+    pub(crate) fn from_settings(
+        settings: &Settings,
+        profile: &Profile,
+        piece_definitions: &PieceDefinitions,
+    ) -> Self {
+        let mode = GameMode::Versus;
+        Self {
+            board_layout: BoardLayout::named(&settings.board_layout).name,
+            fuse: settings.fuse_modifier,
+            mirror: settings.mirror_modifier,
+            stamina: settings.stamina_modifier,
+            side_walls: settings.side_walls.contains(&mode),
+            physics: settings.physics,
+            row_clearing: settings.row_clearing,
+            slicing: settings.slicing,
+            block_damage: settings.block_damage,
+            breakable_joints: settings.breakable_joints,
+            partial_rows: settings.partial_rows.get(&mode).copied(),
+            pieces: settings.piece_set(mode),
+            rotation_assist: settings.rotation_assist,
+            lane_snapping: settings.lane_snapping,
+            lane_repeat: settings.lane_repeat(),
+            assist_mode: profile.assist_mode,
+            piece_definitions: piece_definitions.clone(),
+        }
+    }
+
+    /// The rules of the run on a board, with the controls as they are now.
+    pub(crate) fn from_board(board: &Board, settings: &Settings, profile: &Profile) -> Self {
+        Self {
+            board_layout: board.layout.clone(),
+            fuse: board.fuse,
+            mirror: board.mirror,
+            stamina: board.stamina,
+            side_walls: board.side_walls,
+            physics: board.physics,
+            row_clearing: board.row_clearing,
+            slicing: board.slicing,
+            block_damage: board.block_damage,
+            breakable_joints: board.breakable_joints,
+            partial_rows: board.partial_rows,
+            pieces: board.pieces,
+            piece_definitions: board.piece_definitions.clone(),
+            ..Self::from_settings(settings, profile, &board.piece_definitions)
+        }
+    }
+
+    /// Settings to start the runs of the opponent's board with. The board layout is set up at
+    /// launch, and only checked when joining. Assist Mode is up to the profile.
+    pub(crate) fn settings(&self) -> Settings {
+        let mode = GameMode::Versus;
+        let mut settings = Settings {
+            mode,
+            board_layout: self.board_layout.clone(),
+            fuse_modifier: self.fuse,
+            mirror_modifier: self.mirror,
+            stamina_modifier: self.stamina,
+            physics: self.physics,
+            row_clearing: self.row_clearing,
+            slicing: self.slicing,
+            block_damage: self.block_damage,
+            breakable_joints: self.breakable_joints,
+            rotation_assist: self.rotation_assist,
+            lane_snapping: self.lane_snapping,
+            lane_repeat_delay: self.lane_repeat.map_or(0.0, |(delay, _)| delay),
+            ..Settings::default()
+        };
+        if let Some((_, interval)) = self.lane_repeat {
+            settings.lane_repeat_interval = interval;
+        }
+        if self.side_walls {
+            settings.side_walls.insert(mode);
+        }
+        if let Some(threshold) = self.partial_rows {
+            settings.partial_rows.insert(mode, threshold);
+        }
+        settings.piece_sets.insert(mode, self.pieces);
+        // The rules come from the other player
+        settings.clamped()
+    }
+}
+
+/// Input of a tick, quantized to keep replays small: movement and torque in `-127..=127`, soft
+/// drop in `0..=255`, and a bit each for hard drop and hold pressed during the tick.
+#[derive(Clone, Copy, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct TickInput(i8, i8, u8, u8);
+
+impl TickInput {
+    pub(crate) fn new(input: &PieceInput) -> Self {
+        let axis = |value: f32| (value.clamp(-1.0, 1.0) * 127.0).round() as i8;
+        Self(
+            axis(input.movement),
+            axis(input.torque),
+            (input.soft_drop.clamp(0.0, 1.0) * 255.0).round() as u8,
+            input.hard_drop as u8 | (input.hold as u8) << 1,
+        )
+    }
+
+    pub(crate) fn input(self) -> PieceInput {
+        PieceInput {
+            movement: self.0 as f32 / 127.0,
+            torque: self.1 as f32 / 127.0,
+            soft_drop: self.2 as f32 / 255.0,
+            hard_drop: self.3 & 1 != 0,
+            hold: self.3 & 2 != 0,
+        }
+    }
+}
+
+/// A player's input over a run, a tick every `ONLINE_TICK` of game time, and the pieces they
+/// drew. The referee re-simulates a match from the replays of both players.
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct RunReplay {
+    pub(crate) ticks: Vec<TickInput>,
+    pub(crate) kinds: Vec<TetrominoKind>,
+}
+
+/// The opponent's replay of a run as received so far. Lost messages leave gaps, which are asked
+/// for again after the match.
+#[derive(Default)]
+pub(crate) struct RemoteReplay {
+    pub(crate) ticks: Vec<Option<TickInput>>,
+    pub(crate) kinds: Vec<Option<TetrominoKind>>,
+    /// Ticks and pieces of the whole run, once it is over
+    pub(crate) counts: Option<(u32, u32)>,
+}
+
+impl RemoteReplay {
+    /// Record input and pieces received, starting from the given counts. Those past
+    /// `ONLINE_MAX_REPLAY_LENGTH` are ignored.
+    pub(crate) fn record(
+        &mut self,
+        ticks_from: u32,
+        inputs: &[TickInput],
+        kinds_from: u32,
+        kinds: &[TetrominoKind],
+    ) {
+        record_entries(&mut self.ticks, ticks_from, inputs);
+        record_entries(&mut self.kinds, kinds_from, kinds);
+    }
+
+    /// The first tick of input and piece still missing, `None` once the run is over and all of
+    /// it here.
+    pub(crate) fn missing(&self) -> Option<(u32, u32)> {
+        let (ticks, drawn) = self
+            .counts
+            .unwrap_or((ONLINE_MAX_REPLAY_LENGTH, ONLINE_MAX_REPLAY_LENGTH));
+
+        let missing = (
+            first_missing(&self.ticks, ticks as usize),
+            first_missing(&self.kinds, drawn as usize),
+        );
+        (self.counts != Some(missing)).then_some(missing)
+    }
+
+    /// The replay, with what is still missing taken as no input, and the pieces from the first
+    /// one missing on left to chance.
+    pub(crate) fn replay(&self) -> RunReplay {
+        let (ticks, drawn) = self
+            .counts
+            .unwrap_or((self.ticks.len() as u32, self.kinds.len() as u32));
+
+        RunReplay {
+            ticks: (0..ticks as usize)
+                .map(|index| self.ticks.get(index).copied().flatten().unwrap_or_default())
+                .collect(),
+            kinds: self
+                .kinds
+                .iter()
+                .take(drawn as usize)
+                .map_while(|kind| *kind)
+                .collect(),
+        }
+    }
+}
+
+/// Entries received up to the first gap, and at most `len`.
+fn first_missing<T>(entries: &[Option<T>], len: usize) -> u32 {
+    let gap = entries.iter().position(Option::is_none);
+    gap.unwrap_or(entries.len()).min(len) as u32
+}
+
+fn record_entries<T: Copy>(entries: &mut Vec<Option<T>>, first: u32, received: &[T]) {
+    let end = first as usize + received.len();
+    if end > ONLINE_MAX_REPLAY_LENGTH as usize {
+        return;
+    }
+    if entries.len() < end {
+        entries.resize(end, None);
+    }
+    for (entry, value) in entries[first as usize..end].iter_mut().zip(received) {
+        *entry = Some(*value);
+    }
+}
+
+/// What the referee re-simulates a match from: the agreed rules, and the replays of the host and
+/// the guest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct MatchReplay {
+    pub(crate) rules: OnlineRules,
+    pub(crate) replays: [RunReplay; 2],
+}
+
+/// A match over on the local board, waiting for the rest of the opponent's replay and then for
+/// the referee's verdict.
+pub(crate) struct PendingMatch {
+    /// Runs of the match, the local player's and the opponent's
+    pub(crate) run: u32,
+    pub(crate) opponent_run: u32,
+    /// The opponent's replay, once they have started another run
+    pub(crate) remote_replay: Option<RemoteReplay>,
+    /// Real seconds since the local run was over, and since the last replay request
+    pub(crate) since_over: f32,
+    pub(crate) since_request: f32,
+    /// The referee's process, once started, and the file of the match it re-simulates
+    pub(crate) referee: Option<(Child, PathBuf)>,
+}
+
+/// A block in a snapshot. Its color follows from its kind.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct OnlineBlock {
+    pub(crate) kind: TetrominoKind,
+    /// Part of a piece still in play
+    pub(crate) active: bool,
+    /// Part of a garbage row
+    pub(crate) garbage: bool,
+    pub(crate) position: Vec2,
+    pub(crate) angle: f32,
+    pub(crate) linvel: Vec2,
+    pub(crate) angvel: f32,
+}
+
+/// The scores of a board in a snapshot, see `BoardStats`. What the opponent counts in their
+/// own simulation of the board, such as the blocks of each kind, is left out.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub(crate) struct OnlineStats {
+    pub(crate) generated_blocks: i32,
+    pub(crate) cleared_blocks: i32,
+    pub(crate) lost_blocks: i32,
+    pub(crate) placed_blocks: i32,
+    pub(crate) rows_cleared: u32,
+    pub(crate) fuse_penalties: i32,
+    pub(crate) cascade_points: i32,
+    pub(crate) drill_points: i32,
+    pub(crate) multi_row_points: i32,
+    pub(crate) partial_row_deductions: i32,
+    pub(crate) soft_drop_distance: f32,
+    pub(crate) garbage_sent: i32,
+    pub(crate) garbage_received: i32,
+    pub(crate) elapsed: f32,
+    pub(crate) game_over_duration: Option<f32>,
+}
+
+impl OnlineStats {
+    pub(crate) fn from_stats(stats: &BoardStats) -> Self {
+        Self {
+            generated_blocks: stats.generated_blocks,
+            cleared_blocks: stats.cleared_blocks,
+            lost_blocks: stats.lost_blocks,
+            placed_blocks: stats.placed_blocks,
+            rows_cleared: stats.rows_cleared() as u32,
+            fuse_penalties: stats.fuse_penalties,
+            cascade_points: stats.cascade_points,
+            drill_points: stats.drill_points,
+            multi_row_points: stats.multi_row_points,
+            partial_row_deductions: stats.partial_row_deductions,
+            soft_drop_distance: stats.soft_drop_distance,
+            garbage_sent: stats.garbage_sent,
+            garbage_received: stats.garbage_received,
+            elapsed: stats.elapsed,
+            game_over_duration: stats.game_over_duration,
+        }
+    }
+
+    /// Take the scores over into the stats of the opponent's board. Rows cleared that the
+    /// simulation here missed are taken to be cleared now.
+    pub(crate) fn apply(&self, stats: &mut BoardStats) {
+        stats.generated_blocks = self.generated_blocks;
+        stats.cleared_blocks = self.cleared_blocks;
+        stats.lost_blocks = self.lost_blocks;
+        stats.placed_blocks = self.placed_blocks;
+        stats.fuse_penalties = self.fuse_penalties;
+        stats.cascade_points = self.cascade_points;
+        stats.drill_points = self.drill_points;
+        stats.multi_row_points = self.multi_row_points;
+        stats.partial_row_deductions = self.partial_row_deductions;
+        stats.soft_drop_distance = self.soft_drop_distance;
+        stats.garbage_sent = self.garbage_sent;
+        stats.garbage_received = self.garbage_received;
+        stats.elapsed = self.elapsed;
+        stats.game_over_duration = self.game_over_duration;
+        let rows_cleared = self.rows_cleared.min(ONLINE_MAX_ROWS_CLEARED) as usize;
+        stats.clear_times.resize(rows_cleared, self.elapsed);
+    }
+}
+
+/// A datagram of a snapshot, with the blocks from `ONLINE_SNAPSHOT_CHUNK_BLOCKS * chunk` on.
+/// The snapshot is only applied once all its chunks have arrived.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct SnapshotChunk {
+    pub(crate) sequence: u32,
+    pub(crate) run: u32,
+    pub(crate) chunk: u16,
+    pub(crate) n_chunks: u16,
+    pub(crate) stats: OnlineStats,
+    pub(crate) blocks: Vec<OnlineBlock>,
+}
+
+impl SnapshotChunk {
+    /// A snapshot in chunks, leaving out the blocks past `ONLINE_SNAPSHOT_MAX_BLOCKS`.
+    pub(crate) fn split(message: &OnlineMessage) -> Vec<Self> {
+        let OnlineMessage::Snapshot {
+            sequence,
+            run,
+            stats,
+            blocks,
+        } = message
+        else {
+            return vec![];
+        };
+
+        let blocks = &blocks[..blocks.len().min(ONLINE_SNAPSHOT_MAX_BLOCKS)];
+        // An empty board still takes a chunk
+        let chunks: Vec<&[OnlineBlock]> = if blocks.is_empty() {
+            vec![&[]]
+        } else {
+            blocks.chunks(ONLINE_SNAPSHOT_CHUNK_BLOCKS).collect()
+        };
+        let n_chunks = chunks.len() as u16;
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(chunk, blocks)| Self {
+                sequence: *sequence,
+                run: *run,
+                chunk: chunk as u16,
+                n_chunks,
+                stats: *stats,
+                blocks: blocks.to_vec(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::default();
+        writer.u8(DATAGRAM_SNAPSHOT);
+        writer.u32(self.sequence);
+        writer.u32(self.run);
+        writer.u16(self.chunk);
+        writer.u16(self.n_chunks);
+
+        let stats = &self.stats;
+        for value in [
+            stats.generated_blocks,
+            stats.cleared_blocks,
+            stats.lost_blocks,
+            stats.placed_blocks,
+            stats.fuse_penalties,
+            stats.cascade_points,
+            stats.drill_points,
+            stats.multi_row_points,
+            stats.partial_row_deductions,
+            stats.garbage_sent,
+            stats.garbage_received,
+        ] {
+            writer.i32(value);
+        }
+        writer.u32(stats.rows_cleared);
+        writer.f32(stats.soft_drop_distance);
+        writer.f32(stats.elapsed);
+        writer.option_f32(stats.game_over_duration);
+
+        writer.u16(self.blocks.len() as u16);
+        for block in &self.blocks {
+            writer.u16(kind_code(block.kind));
+            writer.u8(block.active as u8 | (block.garbage as u8) << 1);
+            writer.f32(block.position.x);
+            writer.f32(block.position.y);
+            writer.f32(block.angle);
+            writer.f32(block.linvel.x);
+            writer.f32(block.linvel.y);
+            writer.f32(block.angvel);
+        }
+
+        writer.0
+    }
+
+    /// `None` unless a whole valid chunk, with finite numbers only.
+    pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader(data);
+        if reader.u8()? != DATAGRAM_SNAPSHOT {
+            return None;
+        }
+
+        let sequence = reader.u32()?;
+        let run = reader.u32()?;
+        let chunk = reader.u16()?;
+        let n_chunks = reader.u16()?;
+        let max_chunks = ONLINE_SNAPSHOT_MAX_BLOCKS.div_ceil(ONLINE_SNAPSHOT_CHUNK_BLOCKS);
+        if chunk >= n_chunks || n_chunks as usize > max_chunks {
+            return None;
+        }
+
+        let mut values = [0; 11];
+        for value in values.iter_mut() {
+            *value = reader.i32()?;
+        }
+        let [generated_blocks, cleared_blocks, lost_blocks, placed_blocks, fuse_penalties, cascade_points, drill_points, multi_row_points, partial_row_deductions, garbage_sent, garbage_received] =
+            values;
+        let stats = OnlineStats {
+            generated_blocks,
+            cleared_blocks,
+            lost_blocks,
+            placed_blocks,
+            rows_cleared: reader.u32()?,
+            fuse_penalties,
+            cascade_points,
+            drill_points,
+            multi_row_points,
+            partial_row_deductions,
+            soft_drop_distance: reader.f32()?,
+            garbage_sent,
+            garbage_received,
+            elapsed: reader.f32()?,
+            game_over_duration: reader.option_f32()?,
+        };
+
+        let n_blocks = reader.u16()? as usize;
+        if n_blocks > ONLINE_SNAPSHOT_CHUNK_BLOCKS {
+            return None;
+        }
+        let blocks = (0..n_blocks)
+            .map(|_| {
+                let kind = kind_from_code(reader.u16()?)?;
+                let flags = reader.u8()?;
+                Some(OnlineBlock {
+                    kind,
+                    active: flags & 1 != 0,
+                    garbage: flags & 2 != 0,
+                    position: Vec2::new(reader.f32()?, reader.f32()?),
+                    angle: reader.f32()?,
+                    linvel: Vec2::new(reader.f32()?, reader.f32()?),
+                    angvel: reader.f32()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        reader.0.is_empty().then_some(Self {
+            sequence,
+            run,
+            chunk,
+            n_chunks,
+            stats,
+            blocks,
+        })
+    }
+}
+
+/// The chunks of the newest snapshot that have arrived so far.
+pub(crate) struct SnapshotAssembly {
+    pub(crate) first: SnapshotChunk,
+    pub(crate) chunks: Vec<Option<Vec<OnlineBlock>>>,
+}
+
+impl SnapshotAssembly {
+    /// Add a chunk, starting over for a newer snapshot. The whole snapshot once complete.
+    pub(crate) fn add(assembly: &mut Option<Self>, chunk: SnapshotChunk) -> Option<OnlineMessage> {
+        match assembly {
+            Some(current) if current.first.sequence > chunk.sequence => return None,
+            Some(current)
+                if current.first.sequence == chunk.sequence
+                    && current.chunks.len() == chunk.n_chunks as usize => {}
+            _ => {
+                *assembly = Some(Self {
+                    chunks: vec![None; chunk.n_chunks as usize],
+                    first: SnapshotChunk {
+                        blocks: vec![],
+                        ..chunk.clone()
+                    },
+                })
+            }
+        }
+
+        let current = assembly.as_mut()?;
+        current.chunks[chunk.chunk as usize] = Some(chunk.blocks);
+        if current.chunks.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let current = assembly.take()?;
+        Some(OnlineMessage::Snapshot {
+            sequence: current.first.sequence,
+            run: current.first.run,
+            stats: current.first.stats,
+            blocks: current.chunks.into_iter().flatten().flatten().collect(),
+        })
+    }
+}
+
+/// Kinds in snapshots: the tetrominoes by their place in `TetrominoKind::ALL`, the pieces of
+/// piece sets after them.
+fn kind_code(kind: TetrominoKind) -> u16 {
+    match kind {
+        TetrominoKind::Polyomino(index) => TetrominoKind::ALL.len() as u16 + index,
+        _ => TetrominoKind::ALL
+            .iter()
+            .position(|tetromino| *tetromino == kind)
+            .unwrap_or(0) as u16,
+    }
+}
+
+fn kind_from_code(code: u16) -> Option<TetrominoKind> {
+    let n_tetrominoes = TetrominoKind::ALL.len() as u16;
+    if code < n_tetrominoes {
+        return Some(TetrominoKind::ALL[code as usize]);
+    }
+    // Of the piece sets both players play with, the rules being the same
+    Some(TetrominoKind::Polyomino(code - n_tetrominoes))
+}
+
+/// Little endian numbers, for snapshots.
+#[derive(Default)]
+pub(crate) struct ByteWriter(pub(crate) Vec<u8>);
+
+impl ByteWriter {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    pub(crate) fn i32(&mut self, value: i32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    pub(crate) fn f32(&mut self, value: f32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    pub(crate) fn option_f32(&mut self, value: Option<f32>) {
+        self.u8(value.is_some() as u8);
+        if let Some(value) = value {
+            self.f32(value);
+        }
+    }
+}
+
+/// Reads what `ByteWriter` wrote, `None` past the end. Floats must be finite.
+pub(crate) struct ByteReader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.0.len() < N {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+        bytes.try_into().ok()
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|bytes| bytes[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn i32(&mut self) -> Option<i32> {
+        self.take().map(i32::from_le_bytes)
+    }
+
+    pub(crate) fn f32(&mut self) -> Option<f32> {
+        self.take()
+            .map(f32::from_le_bytes)
+            .filter(|value| value.is_finite())
+    }
+
+    pub(crate) fn option_f32(&mut self) -> Option<Option<f32>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(self.f32()?)),
+            _ => None,
+        }
+    }
+}
+
+/// Online Versus, each player on their own board: the host on the left and the guest on
+/// the right.
+#[derive(Resource)]
+pub(crate) struct OnlineSession {
+    /// Index of the local player's board, 0 for the host and 1 for the guest
+    pub(crate) local_player: usize,
+    pub(crate) socket: UdpSocket,
+    pub(crate) peer: Option<SocketAddr>,
+    pub(crate) connected: bool,
+    /// The rules both players play by, once they found them to be the same
+    pub(crate) rules: Option<OnlineRules>,
+    /// The last player to join or welcome plays by other rules
+    pub(crate) rules_differ: bool,
+    /// Sequence number of the last message sent
+    pub(crate) sent_sequence: u32,
+    /// Sequence number of the newest message received, older ones arrived out of order
+    pub(crate) received_sequence: u32,
+    /// Real seconds since the newest message was received
+    pub(crate) since_received: f32,
+    /// The opponent's `player_id`, once joined
+    pub(crate) remote_player: Option<u64>,
+    /// Latest input from the opponent, applied to the opponent's board
+    pub(crate) remote_input: PieceInput,
+    /// Runs started on the local board
+    pub(crate) run: u32,
+    /// The local player's replay of the run, and whether the run is over
+    pub(crate) replay: RunReplay,
+    pub(crate) replay_over: bool,
+    /// Replay of the run before, still sent when asked for
+    pub(crate) previous_replay: Option<RunReplay>,
+    /// Game seconds of the run recorded in the replay, and presses since the last tick
+    pub(crate) replay_clock: f32,
+    pub(crate) pressed: PieceInput,
+    /// Run on the opponent's board, with its pieces, input and garbage received so far
+    pub(crate) remote_run: u32,
+    pub(crate) remote_drawn: u32,
+    pub(crate) remote_ticks: u32,
+    pub(crate) remote_garbage_sent: i32,
+    pub(crate) remote_replay: RemoteReplay,
+    pub(crate) since_snapshot: f32,
+    /// Chunks of the snapshot being received
+    pub(crate) snapshot_assembly: Option<SnapshotAssembly>,
+    /// The last match, until rated
+    pub(crate) pending_match: Option<PendingMatch>,
+    /// Last local run whose match went to the referee
+    pub(crate) rated_run: Option<u32>,
+    /// Checksums of the last match as the local referee and the opponent's ended it, by the
+    /// local run of the match
+    pub(crate) referee_checksum: Option<(u32, u64)>,
+    pub(crate) remote_referee_checksum: Option<(u32, u64)>,
+}
+
+impl OnlineSession {
+    /// Start a session from the `--versus-host <port>` or `--versus-join <address>` arguments.
+    pub(crate) fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let arg = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|index| args.get(index + 1))
+        };
+
+        let local_player = if arg("--versus-host").is_some() {
+            0
+        } else if arg("--versus-join").is_some() {
+            1
+        } else {
+            return None;
+        };
+        let (socket, peer) = open_udp_socket("online", arg("--versus-host"), arg("--versus-join"))?;
+
+        Some(Self {
+            local_player,
+            socket,
+            peer,
+            connected: false,
+            rules: None,
+            rules_differ: false,
+            sent_sequence: 0,
+            received_sequence: 0,
+            since_received: 0.0,
+            remote_player: None,
+            remote_input: PieceInput::default(),
+            run: 0,
+            replay: RunReplay::default(),
+            replay_over: false,
+            previous_replay: None,
+            replay_clock: 0.0,
+            pressed: PieceInput::default(),
+            remote_run: 0,
+            remote_drawn: 0,
+            remote_ticks: 0,
+            remote_garbage_sent: 0,
+            remote_replay: RemoteReplay::default(),
+            since_snapshot: 0.0,
+            snapshot_assembly: None,
+            pending_match: None,
+            rated_run: None,
+            referee_checksum: None,
+            remote_referee_checksum: None,
+        })
+    }
+
+    /// Warn when the referees of both players re-simulated the last match differently.
+    pub(crate) fn compare_referee_checksums(&self) {
+        if let (Some((run, ours)), Some((remote_run, theirs))) =
+            (self.referee_checksum, self.remote_referee_checksum)
+        {
+            if run == remote_run && ours != theirs {
+                warn!(
+                    "online: desync, the referees re-simulated the match of run {run} \
+                     differently ({ours:016x} here, {theirs:016x} there)"
+                );
+            }
+        }
+    }
+
+    pub(crate) fn send(&mut self, message: &OnlineMessage) {
+        if let Some(peer) = self.peer {
+            self.send_to(message, peer);
+        }
+    }
+
+    pub(crate) fn send_to(&self, message: &OnlineMessage, address: SocketAddr) {
+        // Lost packets are expected, the next frame sends fresh state
+        if let OnlineMessage::Snapshot { .. } = message {
+            for chunk in SnapshotChunk::split(message) {
+                let _ = self.socket.send_to(&chunk.encode(), address);
+            }
+            return;
+        }
+
+        match ron::to_string(message) {
+            Ok(data) if data.len() < ONLINE_MAX_PAYLOAD => {
+                let mut datagram = vec![DATAGRAM_MESSAGE];
+                datagram.extend(data.as_bytes());
+                let _ = self.socket.send_to(&datagram, address);
+            }
+            Ok(_) => warn!("online: message too large"),
+            Err(err) => warn!("online: {err}"),
+        }
+    }
+
+    /// Messages received since the last call. Snapshots are put together from their chunks,
+    /// which are only taken from the opponent.
+    pub(crate) fn receive(&mut self) -> Vec<(SocketAddr, OnlineMessage)> {
+        let mut buffer = vec![0; ONLINE_MAX_DATAGRAM_SIZE];
+        let mut messages = vec![];
+
+        while let Ok((len, from)) = self.socket.recv_from(&mut buffer) {
+            let datagram = &buffer[..len];
+            match datagram.first() {
+                Some(&DATAGRAM_MESSAGE) => match ron::de::from_bytes(&datagram[1..]) {
+                    Ok(message) => messages.push((from, message)),
+                    Err(err) => warn!("online: invalid message from {from}: {err}"),
+                },
+                Some(&DATAGRAM_SNAPSHOT) if self.peer == Some(from) => {
+                    match SnapshotChunk::decode(datagram) {
+                        Some(chunk) => {
+                            if let Some(message) =
+                                SnapshotAssembly::add(&mut self.snapshot_assembly, chunk)
+                            {
+                                messages.push((from, message));
+                            }
+                        }
+                        None => warn!("online: invalid snapshot from {from}"),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        messages
+    }
+
+    /// Input for the piece on a board: the local player's controls on their own board, and the
+    /// opponent's input on the other. Quantized like in the replay, so that the referee plays
+    /// the same input.
+    pub(crate) fn piece_input(&self, action_state: &ActionState, board: &Board) -> PieceInput {
+        if !board.remote {
+            TickInput::new(&PieceInput::from_actions(action_state)).input()
+        } else if self.since_received < ONLINE_INPUT_TIMEOUT {
+            self.remote_input
+        } else {
+            PieceInput::default()
+        }
+    }
+
+    pub(crate) fn status(&self) -> &'static str {
+        if self.rules_differ && !self.connected {
+            "Online: the opponent plays by other rules"
+        } else if !self.connected {
+            match self.local_player {
+                0 => "Online: waiting for opponent",
+                _ => "Online: connecting",
+            }
+        } else if self.since_received > ONLINE_TIMEOUT {
+            "Online: opponent not responding"
+        } else {
+            "Online: connected"
+        }
+    }
+
+    /// The local player's rating, and the opponent's once joined.
+    pub(crate) fn rating_text(&self, profile: &Profile) -> String {
+        let rating = profile.rating;
+        match self.remote_player {
+            Some(player) => format!(
+                "Rating {rating:.0} vs {:.0}",
+                profile.opponent_rating(player)
+            ),
+            None => format!("Rating {rating:.0}"),
+        }
+    }
+
+    pub(crate) fn status_text(&self, profile: &Profile) -> String {
+        format!("{}\n{}", self.status(), self.rating_text(profile))
+    }
+
+    /// The local player's replay of a run, and whether it's over. Kept for the last two runs.
+    pub(crate) fn own_replay(&self, run: u32) -> Option<(&RunReplay, bool)> {
+        if run == self.run {
+            Some((&self.replay, self.replay_over))
+        } else if run + 1 == self.run {
+            self.previous_replay.as_ref().map(|replay| (replay, true))
+        } else {
+            None
+        }
+    }
+
+    /// The opponent's replay of a run, of the current one or of a pending match.
+    pub(crate) fn opponent_replay(&mut self, run: u32) -> Option<&mut RemoteReplay> {
+        if run == self.remote_run {
+            return Some(&mut self.remote_replay);
+        }
+        self.pending_match
+            .as_mut()
+            .filter(|pending| pending.opponent_run == run)
+            .and_then(|pending| pending.remote_replay.as_mut())
+    }
+}
+
+/// Garbage rows to queue from a garbage count received from the opponent, who had sent
+/// `previous` before. The count is the opponent's word, so a count going back or overflowing is
+/// ignored with `None`, and a jump is limited to `ONLINE_MAX_CLEARS_PER_MESSAGE` of the largest
+/// clears.
+pub(crate) fn received_garbage(previous: i32, garbage_sent: i32) -> Option<usize> {
+    let n_garbage = garbage_sent
+        .checked_sub(previous)
+        .filter(|n_garbage| *n_garbage > 0)?;
+    let max_garbage =
+        GARBAGE_ROWS.iter().max().copied().unwrap_or(0) * ONLINE_MAX_CLEARS_PER_MESSAGE;
+    Some((n_garbage as usize).min(max_garbage))
+}
+
+#[derive(Component)]
+pub(crate) struct OnlineStatus;
+
+/// The ratings in the main menu, see `OnlineSession::rating_text`.
+#[derive(Component)]
+pub(crate) struct OnlineRating;
+
+pub(crate) fn open_online_session(mut commands: Commands) {
+    if let Some(session) = OnlineSession::from_args() {
+        commands.insert_resource(session);
+    }
+}
+
+pub(crate) fn setup_online_status(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    session: Res<OnlineSession>,
+) {
+    commands
+        .spawn(
+            fonts
+                .text_bundle(&session.status_text(&profile), 20.0, Color::WHITE)
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(48.0),
+                        left: Val::Px(16.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+        )
+        .insert(OnlineStatus);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn online_receive(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    profile: Res<Profile>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut session: ResMut<OnlineSession>,
+    mut run_started_events: EventWriter<RunStarted>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+        &mut GarbageQueue,
+    )>,
+    mut block_query: Query<(Entity, &Block, &mut Transform, &mut Velocity)>,
+) {
+    session.since_received += time.delta_seconds();
+
+    // Presses apply on a single frame, however long until the next input arrives
+    session.remote_input.hard_drop = false;
+    session.remote_input.hold = false;
+
+    let board = |remote: bool| {
+        board_query
+            .iter()
+            .find(|(_, board, ..)| board.player.is_some() && board.remote == remote)
+            .map(|(entity, ..)| entity)
+    };
+    let (Some(local_board), Some(remote_board)) = (board(false), board(true)) else {
+        return;
+    };
+
+    let rules = OnlineRules::from_settings(&settings, &profile, &piece_definitions);
+
+    for (from, message) in session.receive() {
+        match &message {
+            // The host takes the first guest to join, and nobody after that
+            OnlineMessage::Join {
+                rules: guest_rules,
+                player,
+            } => {
+                if session.local_player != 0 || session.peer.is_some_and(|peer| peer != from) {
+                    continue;
+                }
+
+                // Answered either way, so that a guest with other rules finds out
+                session.send_to(
+                    &OnlineMessage::Welcome {
+                        rules: rules.clone(),
+                        player: profile.player_id,
+                    },
+                    from,
+                );
+                session.rules_differ = *guest_rules != rules;
+                if session.rules_differ {
+                    warn!("online: {from} plays by other rules");
+                    continue;
+                }
+
+                if session.peer.is_none() {
+                    info!("online: {from} joined");
+                    session.peer = Some(from);
+                }
+                session.rules = Some(rules.clone());
+                session.remote_player = Some(*player);
+                session.received_sequence = 0;
+                session.connected = true;
+                session.since_received = 0.0;
+                continue;
+            }
+            OnlineMessage::Welcome {
+                rules: host_rules,
+                player,
+            } => {
+                if session.local_player == 0 || session.peer != Some(from) || session.connected {
+                    continue;
+                }
+
+                session.rules_differ = *host_rules != rules;
+                if session.rules_differ {
+                    warn!("online: the host plays by other rules");
+                    continue;
+                }
+
+                info!("online: joined {from}");
+                session.rules = Some(rules.clone());
+                session.remote_player = Some(*player);
+                session.connected = true;
+                session.since_received = 0.0;
+                continue;
+            }
+            _ => {}
+        }
+
+        if !session.connected || session.peer != Some(from) {
+            continue;
+        }
+
+        // Replays are asked for and sent after a match, whatever else arrives meanwhile
+        match message {
+            OnlineMessage::ReplayRequest {
+                run,
+                ticks_from,
+                kinds_from,
+            } => {
+                let Some((replay, over)) = session.own_replay(run) else {
+                    continue;
+                };
+                let chunk = |len: usize, from: u32, chunk_len: usize| {
+                    let from = (from as usize).min(len);
+                    from..(from + chunk_len).min(len)
+                };
+                let ticks = chunk(replay.ticks.len(), ticks_from, ONLINE_REPLAY_CHUNK_TICKS);
+                let kinds = chunk(replay.kinds.len(), kinds_from, ONLINE_REPLAY_CHUNK_KINDS);
+                let message = OnlineMessage::Replay {
+                    run,
+                    ticks_from: ticks.start as u32,
+                    inputs: replay.ticks[ticks].to_vec(),
+                    kinds_from: kinds.start as u32,
+                    kinds: replay.kinds[kinds].to_vec(),
+                    ticks: replay.ticks.len() as u32,
+                    drawn: replay.kinds.len() as u32,
+                    over,
+                };
+                session.send(&message);
+                continue;
+            }
+            OnlineMessage::Replay {
+                run,
+                ticks_from,
+                inputs,
+                kinds_from,
+                kinds,
+                ticks,
+                drawn,
+                over,
+            } => {
+                if let Some(replay) = session.opponent_replay(run) {
+                    replay.record(ticks_from, &inputs, kinds_from, &kinds);
+                    if over {
+                        replay.counts = Some((ticks, drawn));
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if message.sequence() <= session.received_sequence {
+            continue;
+        }
+        session.received_sequence = message.sequence();
+        session.since_received = 0.0;
+
+        let Ok((_, mut board, mut active_pieces, mut stats, mut rng, _)) =
+            board_query.get_mut(remote_board)
+        else {
+            continue;
+        };
+
+        match message {
+            OnlineMessage::Input {
+                run,
+                ticks,
+                inputs,
+                over,
+                drawn,
+                kinds,
+                garbage_sent,
+                ..
+            } => {
+                // The opponent started a run, so it starts on their board here as well, with
+                // the pieces they draw
+                if run > session.remote_run {
+                    let Some(rules) = session.rules.clone() else {
+                        continue;
+                    };
+                    let rules_settings = rules.settings();
+
+                    // The replay of a match still to be judged is kept for the referee
+                    let remote_replay = std::mem::take(&mut session.remote_replay);
+                    let remote_run = session.remote_run;
+                    if let Some(pending) = session
+                        .pending_match
+                        .as_mut()
+                        .filter(|pending| pending.opponent_run == remote_run)
+                    {
+                        pending.remote_replay = Some(remote_replay);
+                    }
+
+                    session.remote_run = run;
+                    session.remote_drawn = 0;
+                    session.remote_ticks = 0;
+                    session.remote_garbage_sent = 0;
+                    active_pieces.supply = Some(VecDeque::new());
+                    commands.entity(remote_board).remove::<Teardown>();
+
+                    run_started_events.send(start_run(
+                        &mut commands,
+                        remote_board,
+                        &mut board,
+                        &mut active_pieces,
+                        &mut stats,
+                        &rules_settings,
+                        &rules.piece_definitions,
+                        &mut rng,
+                        block_query
+                            .iter()
+                            .filter(|(_, block, _, _)| block.board == remote_board)
+                            .map(|(entity, _, _, _)| entity),
+                    ));
+                }
+                if run != session.remote_run {
+                    continue;
+                }
+
+                // The latest input, pressing what was pressed in any tick not received before
+                let ticks_from = ticks.saturating_sub(inputs.len() as u32);
+                let mut input = PieceInput {
+                    hard_drop: session.remote_input.hard_drop,
+                    hold: session.remote_input.hold,
+                    ..inputs
+                        .last()
+                        .map_or(PieceInput::default(), |tick| tick.input())
+                };
+                for (index, tick) in (ticks_from..ticks).zip(&inputs) {
+                    if index >= session.remote_ticks {
+                        input.hard_drop |= tick.input().hard_drop;
+                        input.hold |= tick.input().hold;
+                    }
+                }
+                session.remote_input = input;
+                session.remote_ticks = session.remote_ticks.max(ticks);
+
+                let kinds_from = drawn.saturating_sub(kinds.len() as u32);
+                session
+                    .remote_replay
+                    .record(ticks_from, &inputs, kinds_from, &kinds);
+                if over {
+                    session.remote_replay.counts = Some((ticks, drawn));
+                }
+
+                // Kinds follow on from those received before, unless too many messages were lost
+                for (index, kind) in (kinds_from..drawn).zip(kinds) {
+                    if index == session.remote_drawn {
+                        active_pieces
+                            .supply
+                            .get_or_insert_with(VecDeque::new)
+                            .push_back(kind);
+                        session.remote_drawn += 1;
+                    }
+                }
+
+                if let Some(n_garbage) = received_garbage(session.remote_garbage_sent, garbage_sent)
+                {
+                    session.remote_garbage_sent = garbage_sent;
+                    if let Ok((_, _, _, _, _, mut queue)) = board_query.get_mut(local_board) {
+                        queue.pending.extend(vec![GARBAGE_DELAY; n_garbage]);
+                    }
+                }
+            }
+            OnlineMessage::Snapshot {
+                run,
+                stats: remote_stats,
+                blocks,
+                ..
+            } => {
+                if run != session.remote_run {
+                    continue;
+                }
+
+                // Scores, and whether the run is over, are the opponent's word
+                remote_stats.apply(&mut stats);
+
+                reconcile_blocks(
+                    &mut commands,
+                    remote_board,
+                    &board,
+                    &active_pieces,
+                    &mut rng,
+                    &blocks,
+                    &mut block_query,
+                );
+            }
+            OnlineMessage::RefereeChecksum { run, checksum } => {
+                session.remote_referee_checksum = Some((run, checksum));
+                session.compare_referee_checksums();
+            }
+            OnlineMessage::Join { .. }
+            | OnlineMessage::Welcome { .. }
+            | OnlineMessage::ReplayRequest { .. }
+            | OnlineMessage::Replay { .. } => {}
+        }
+    }
+}
+
+/// Move the blocks of the opponent's board that drifted away from where the opponent has them,
+/// remove those the opponent doesn't have and add those missing. Blocks are matched by kind and
+/// distance.
+fn reconcile_blocks(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &Board,
+    active_pieces: &ActivePieces,
+    rng: &mut GameRng,
+    snapshot: &[OnlineBlock],
+    block_query: &mut Query<(Entity, &Block, &mut Transform, &mut Velocity)>,
+) {
+    let mut unmatched: Vec<(Entity, TetrominoKind, Vec2)> = block_query
+        .iter()
+        .filter(|(_, block, _, _)| block.board == board_entity)
+        .map(|(entity, block, transform, _)| (entity, block.kind, transform.translation.truncate()))
+        .collect();
+
+    for remote_block in snapshot {
+        let nearest = unmatched
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, kind, _))| *kind == remote_block.kind)
+            .map(|(index, (_, _, position))| (index, position.distance(remote_block.position)))
+            .filter(|(_, distance)| *distance < ONLINE_MATCH_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((index, distance)) = nearest else {
+            // Pieces in play are spawned from their kinds, only settled blocks go missing
+            if !remote_block.active {
+                let block_entity = spawn_loose_block(
+                    commands,
+                    board_entity,
+                    board,
+                    remote_block.position,
+                    false,
+                    rng,
+                );
+                commands.entity(block_entity).insert((
+                    Block {
+                        board: board_entity,
+                        kind: remote_block.kind,
+                        size: 1.0,
+                    },
+                    Sprite {
+                        color: if remote_block.garbage {
+                            JUNK_COLOR
+                        } else {
+                            remote_block.kind.color(&board.piece_definitions)
+                        },
+                        custom_size: Some(Vec2::ONE),
+                        ..Default::default()
+                    },
+                    Transform::from_translation(remote_block.position.extend(0.0))
+                        .with_rotation(Quat::from_rotation_z(remote_block.angle)),
+                ));
+                if remote_block.garbage {
+                    commands.entity(block_entity).insert(Garbage);
+                }
+            }
+            continue;
+        };
+        let (block_entity, _, _) = unmatched.swap_remove(index);
+
+        if distance > ONLINE_CORRECTION_DISTANCE {
+            if let Ok((_, _, mut transform, mut velocity)) = block_query.get_mut(block_entity) {
+                transform.translation = remote_block.position.extend(transform.translation.z);
+                transform.rotation = Quat::from_rotation_z(remote_block.angle);
+                velocity.linvel = remote_block.linvel;
+                velocity.angvel = remote_block.angvel;
+            }
+        }
+    }
+
+    // A piece just spawned here may not be in the opponent's snapshot yet
+    for (block_entity, _, _) in unmatched {
+        if !active_pieces.contains(&block_entity) {
+            commands.entity(block_entity).despawn_recursive();
+        }
+    }
+}
+
+/// Spawn the opponent's pieces once their kinds have arrived, see `ActivePieces::drawn`.
+pub(crate) fn spawn_remote_pieces(
+    mut commands: Commands,
+    mut board_query: Query<(
+        Entity,
+        &Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+) {
+    for (board_entity, board, mut active_pieces, mut stats, mut rng) in board_query.iter_mut() {
+        if !board.remote || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        for index in 0..board.n_pieces() {
+            let empty = active_pieces
+                .pieces
+                .get(index)
+                .is_none_or(|piece| piece.blocks.is_empty());
+            let known = active_pieces
+                .supply
+                .as_ref()
+                .is_some_and(|supply| !supply.is_empty());
+
+            if empty && known {
+                spawn_tetromino(
+                    &mut commands,
+                    board_entity,
+                    board,
+                    &mut active_pieces,
+                    index,
+                    &mut stats,
+                    &mut rng,
+                );
+            }
+        }
+    }
+}
+
+/// Record the local input in the replay, and send it with the runs, pieces and garbage every
+/// frame, and a snapshot of the local board every `ONLINE_SNAPSHOT_INTERVAL`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn online_send(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    state: Res<State<AppState>>,
+    action_state: Res<ActionState>,
+    profile: Res<Profile>,
+    settings: Res<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut session: ResMut<OnlineSession>,
+    mut run_started_events: EventReader<RunStarted>,
+    board_query: Query<(Entity, &Board, &ActivePieces, &BoardStats)>,
+    block_query: Query<(Entity, &Block, &Transform, &Velocity, Option<&Garbage>)>,
+) {
+    let local_player = session.local_player;
+    let Some((board_entity, board, active_pieces, stats)) = board_query
+        .iter()
+        .find(|(_, board, ..)| board.player == Some(local_player))
+    else {
+        return;
+    };
+
+    for event in run_started_events.iter() {
+        if event.board != board_entity {
+            continue;
+        }
+        session.run += 1;
+        let replay = std::mem::take(&mut session.replay);
+        session.previous_replay = Some(replay);
+        session.replay_over = false;
+        session.replay_clock = 0.0;
+        session.pressed = PieceInput::default();
+
+        // Rules changed since joining aren't the opponent's, who would simulate the run by
+        // the agreed ones
+        if session.connected
+            && session.rules != Some(OnlineRules::from_board(board, &settings, &profile))
+        {
+            warn!("online: the run isn't played by the agreed rules");
+            session.connected = false;
+            session.rules_differ = true;
+        }
+    }
+
+    // Menu navigation doesn't move the piece
+    let input = if state.0 == AppState::Playing {
+        session.piece_input(&action_state, board)
+    } else {
+        PieceInput::default()
+    };
+
+    // A tick for every `ONLINE_TICK` of game time played, like the referee plays them
+    session.replay_over = stats.game_over_duration.is_some();
+    if state.0 == AppState::Playing && !session.replay_over {
+        session.replay_clock += time_scale.delta_seconds(&time);
+        session.pressed.hard_drop |= input.hard_drop;
+        session.pressed.hold |= input.hold;
+
+        while (session.replay.ticks.len() as f32) * ONLINE_TICK < session.replay_clock {
+            let tick = TickInput::new(&PieceInput {
+                hard_drop: session.pressed.hard_drop,
+                hold: session.pressed.hold,
+                ..input
+            });
+            session.replay.ticks.push(tick);
+            session.pressed = PieceInput::default();
+        }
+    }
+    let n_kinds = session.replay.kinds.len();
+    if let Some(kinds) = active_pieces.drawn.get(n_kinds..) {
+        session.replay.kinds.extend_from_slice(kinds);
+    }
+
+    if !session.connected {
+        if local_player != 0 && !session.rules_differ {
+            session.send(&OnlineMessage::Join {
+                rules: OnlineRules::from_settings(&settings, &profile, &piece_definitions),
+                player: profile.player_id,
+            });
+        }
+        return;
+    }
+
+    let recent_ticks = session
+        .replay
+        .ticks
+        .len()
+        .saturating_sub(ONLINE_RECENT_TICKS);
+    let recent_kinds = active_pieces
+        .drawn
+        .len()
+        .saturating_sub(ONLINE_RECENT_KINDS);
+    session.sent_sequence += 1;
+    let message = OnlineMessage::Input {
+        sequence: session.sent_sequence,
+        run: session.run,
+        ticks: session.replay.ticks.len() as u32,
+        inputs: session.replay.ticks[recent_ticks..].to_vec(),
+        over: session.replay_over,
+        drawn: active_pieces.drawn.len() as u32,
+        kinds: active_pieces.drawn[recent_kinds..].to_vec(),
+        garbage_sent: stats.garbage_sent,
+    };
+    session.send(&message);
+
+    session.since_snapshot += time.delta_seconds();
+    if session.since_snapshot < ONLINE_SNAPSHOT_INTERVAL {
+        return;
+    }
+    session.since_snapshot = 0.0;
+
+    let blocks = block_query
+        .iter()
+        .filter(|(_, block, ..)| block.board == board_entity)
+        .take(ONLINE_SNAPSHOT_MAX_BLOCKS)
+        .map(
+            |(entity, block, transform, velocity, garbage)| OnlineBlock {
+                kind: block.kind,
+                active: active_pieces.contains(&entity),
+                garbage: garbage.is_some(),
+                position: transform.translation.truncate(),
+                angle: transform.rotation.to_euler(EulerRot::XYZ).2,
+                linvel: velocity.linvel,
+                angvel: velocity.angvel,
+            },
+        )
+        .collect();
+
+    session.sent_sequence += 1;
+    let message = OnlineMessage::Snapshot {
+        sequence: session.sent_sequence,
+        run: session.run,
+        stats: OnlineStats::from_stats(stats),
+        blocks,
+    };
+    session.send(&message);
+}
+
+/// Rate a match once the local run of it is over, by the verdict of the referee re-simulating
+/// it from both players' replays, see `Referee`. What is missing of the opponent's replay is
+/// asked for first, for up to `ONLINE_REPLAY_TIMEOUT`. Only when both players are on the same
+/// run, so that a board left over from the last match doesn't count again.
+///
+/// Neither player's verdict counts, nor the opponent's word on their rating: the ratings of both
+/// are worked out here, see `Profile::rate_online_match`.
+pub(crate) fn online_rating(
+    time: Res<Time>,
+    mut session: ResMut<OnlineSession>,
+    mut profile: ResMut<Profile>,
+    board_query: Query<(&Board, &BoardStats)>,
+) {
+    let session = &mut *session;
+
+    let over = board_query.iter().any(|(board, stats)| {
+        board.mode == GameMode::Versus
+            && board.player == Some(session.local_player)
+            && stats.game_over_duration.is_some()
+    });
+    if over
+        && session.connected
+        && session.pending_match.is_none()
+        && session.run == session.remote_run
+        && session.rated_run != Some(session.run)
+    {
+        session.rated_run = Some(session.run);
+        session.pending_match = Some(PendingMatch {
+            run: session.run,
+            opponent_run: session.remote_run,
+            remote_replay: None,
+            since_over: 0.0,
+            since_request: ONLINE_REPLAY_REQUEST_INTERVAL,
+            referee: None,
+        });
+    }
+
+    let Some(pending) = &mut session.pending_match else {
+        return;
+    };
+    pending.since_over += time.delta_seconds();
+    pending.since_request += time.delta_seconds();
+
+    let Some((referee, path)) = &mut pending.referee else {
+        let (run, opponent_run) = (pending.run, pending.opponent_run);
+        let (timed_out, request) = (
+            pending.since_over > ONLINE_REPLAY_TIMEOUT,
+            pending.since_request >= ONLINE_REPLAY_REQUEST_INTERVAL,
+        );
+        let Some(missing) = session
+            .opponent_replay(opponent_run)
+            .map(|remote_replay| remote_replay.missing())
+        else {
+            session.pending_match = None;
+            return;
+        };
+
+        if let Some((ticks_from, kinds_from)) = missing {
+            if !timed_out {
+                if request {
+                    session.send(&OnlineMessage::ReplayRequest {
+                        run: opponent_run,
+                        ticks_from,
+                        kinds_from,
+                    });
+                    if let Some(pending) = &mut session.pending_match {
+                        pending.since_request = 0.0;
+                    }
+                }
+                return;
+            }
+            warn!("online: the opponent's replay is incomplete, the rest taken as no input");
+        }
+
+        let remote_replay = session
+            .opponent_replay(opponent_run)
+            .map_or_else(RunReplay::default, |remote_replay| remote_replay.replay());
+        let (Some(rules), Some((replay, _))) = (&session.rules, session.own_replay(run)) else {
+            session.pending_match = None;
+            return;
+        };
+        let mut replays = [replay.clone(), remote_replay];
+        if session.local_player != 0 {
+            replays.reverse();
+        }
+        let match_replay = MatchReplay {
+            rules: rules.clone(),
+            replays,
+        };
+
+        match start_referee(&match_replay, run) {
+            Ok(referee) => {
+                if let Some(pending) = &mut session.pending_match {
+                    pending.referee = Some(referee);
+                }
+            }
+            Err(err) => {
+                warn!("online: no referee for the match: {err}");
+                session.pending_match = None;
+            }
+        }
+        return;
+    };
+
+    let verdict = match referee.try_wait() {
+        Ok(None) => return,
+        Ok(Some(status)) if status.success() => {
+            let mut output = String::new();
+            referee
+                .stdout
+                .take()
+                .and_then(|mut stdout| stdout.read_to_string(&mut output).ok());
+            let mut words = output.split_whitespace();
+            let score = words.next().and_then(|word| word.parse::<f32>().ok());
+            let checksum = words.next().and_then(|word| word.parse::<u64>().ok());
+            score.zip(checksum)
+        }
+        Ok(Some(_)) => None,
+        Err(err) => {
+            warn!("online: {err}");
+            None
+        }
+    };
+    let _ = std::fs::remove_file(path);
+
+    if let (Some((_, checksum)), Some(pending)) = (verdict, &session.pending_match) {
+        let (run, opponent_run) = (pending.run, pending.opponent_run);
+        session.referee_checksum = Some((run, checksum));
+        session.send(&OnlineMessage::RefereeChecksum {
+            run: opponent_run,
+            checksum,
+        });
+        session.compare_referee_checksums();
+    }
+
+    match (verdict, session.remote_player) {
+        (Some((host_score, _)), Some(opponent)) => {
+            let score = match session.local_player {
+                0 => host_score,
+                _ => 1.0 - host_score,
+            };
+            profile.rate_online_match(opponent, score);
+            profile.save();
+        }
+        _ => warn!("online: the referee gave no verdict, the match isn't rated"),
+    }
+    session.pending_match = None;
+}
+
+/// Have a match re-simulated by the referee, in a process of its own as it runs a whole game,
+/// see `headless::run_referee`. The match is handed over in a file.
+fn start_referee(match_replay: &MatchReplay, run: u32) -> std::io::Result<(Child, PathBuf)> {
+    let path = std::env::temp_dir().join(format!(
+        "newtonian-tetris-match-{}-{run}.ron",
+        std::process::id()
+    ));
+    let data = ron::to_string(match_replay).map_err(std::io::Error::other)?;
+    std::fs::write(&path, data)?;
+
+    let referee = Command::new(std::env::current_exe()?)
+        .arg("--referee")
+        .arg(&path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok((referee, path))
+}
+
+pub(crate) fn online_status(
+    session: Res<OnlineSession>,
+    profile: Res<Profile>,
+    fonts: Res<Fonts>,
+    mut status_query: Query<&mut Text, (With<OnlineStatus>, Without<OnlineRating>)>,
+    mut rating_query: Query<&mut Text, With<OnlineRating>>,
+    mut shown: Local<String>,
+) {
+    // The main menu is spawned with the ratings up to date, see `spawn_main_menu`
+    let status = session.status_text(&profile);
+    if *shown == status {
+        return;
+    }
+
+    for mut text in status_query.iter_mut() {
+        *text = fonts.text(&status, 20.0, Color::WHITE);
+    }
+    for mut text in rating_query.iter_mut() {
+        *text = fonts.text(&session.rating_text(&profile), 24.0, Color::GOLD);
+    }
+    *shown = status;
+}
+
+/// Re-simulating an online Versus match from the replays of both players, to judge it without
+/// taking either player's word for it. Run by `headless::run_referee`.
+#[derive(Resource)]
+pub(crate) struct Referee {
+    /// The host's replay and the guest's
+    pub(crate) replays: [RunReplay; 2],
+    /// Game seconds played, the tick of input played now, and whether it started this frame
+    pub(crate) clock: f32,
+    pub(crate) tick: usize,
+    pub(crate) new_tick: bool,
+    /// The host's score once a run is over, 1 for a win, 0.5 for a draw and 0 for a loss
+    pub(crate) verdict: Option<f32>,
+}
+
+impl Referee {
+    pub(crate) fn new(replays: [RunReplay; 2]) -> Self {
+        Self {
+            replays,
+            clock: 0.0,
+            tick: 0,
+            new_tick: true,
+            verdict: None,
+        }
+    }
+
+    /// Input of the current tick on a player's board. Presses apply on its first frame.
+    pub(crate) fn piece_input(&self, player: usize) -> PieceInput {
+        let tick = self
+            .replays
+            .get(player)
+            .and_then(|replay| replay.ticks.get(self.tick))
+            .copied()
+            .unwrap_or_default();
+
+        let input = tick.input();
+        if self.new_tick {
+            input
+        } else {
+            PieceInput {
+                hard_drop: false,
+                hold: false,
+                ..input
+            }
+        }
+    }
+
+    /// Game seconds of the longer replay.
+    pub(crate) fn duration(&self) -> f32 {
+        let ticks = self.replays.iter().map(|replay| replay.ticks.len()).max();
+        ticks.unwrap_or(0) as f32 * ONLINE_TICK
+    }
+}
+
+/// Move on to the tick of input for the game time played, like `online_send` records them.
+pub(crate) fn referee_clock(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut referee: ResMut<Referee>,
+    mut active_pieces_query: Query<&mut ActivePieces>,
+) {
+    let tick = (referee.clock / ONLINE_TICK) as usize;
+    referee.new_tick = tick != referee.tick || referee.clock == 0.0;
+    referee.tick = tick;
+    referee.clock += time_scale.delta_seconds(&time);
+
+    // Pieces past those of the replay are left to chance, like those of a run played on
+    for mut active_pieces in active_pieces_query.iter_mut() {
+        if active_pieces
+            .supply
+            .as_ref()
+            .is_some_and(VecDeque::is_empty)
+        {
+            active_pieces.supply = None;
+        }
+    }
+}
+
+/// Judge the match once a run is over on either board, before `versus_knockout` ends the other:
+/// a win for the board still playing, a draw when both ended at once.
+pub(crate) fn referee_judge(
+    mut referee: ResMut<Referee>,
+    board_query: Query<(&Board, &BoardStats)>,
+) {
+    if referee.verdict.is_some() {
+        return;
+    }
+
+    let over = |player: usize| {
+        board_query.iter().any(|(board, stats)| {
+            board.player == Some(player) && stats.game_over_duration.is_some()
+        })
+    };
+    referee.verdict = match (over(0), over(1)) {
+        (false, false) => None,
+        (true, true) => Some(0.5),
+        (true, false) => Some(0.0),
+        (false, true) => Some(1.0),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_counts_queue_the_rows_sent_since_the_last_count() {
+        assert_eq!(received_garbage(0, 2), Some(2));
+        assert_eq!(received_garbage(2, 3), Some(1));
+        assert_eq!(received_garbage(3, 3), None);
+    }
+
+    #[test]
+    fn garbage_counts_going_back_or_overflowing_are_ignored_and_jumps_limited() {
+        let max_garbage =
+            GARBAGE_ROWS.iter().max().copied().unwrap() * ONLINE_MAX_CLEARS_PER_MESSAGE;
+
+        assert_eq!(received_garbage(5, 2), None);
+        assert_eq!(received_garbage(-5, i32::MAX), None);
+        assert_eq!(received_garbage(0, i32::MAX), Some(max_garbage));
+    }
+
+    fn snapshot(n_blocks: usize) -> OnlineMessage {
+        let block = |index: usize| OnlineBlock {
+            kind: TetrominoKind::ALL[index % TetrominoKind::ALL.len()],
+            active: index == 0,
+            garbage: index % 2 == 1,
+            position: Vec2::new(index as f32, -0.5),
+            angle: 0.25,
+            linvel: Vec2::new(0.0, -1.0),
+            angvel: 0.1,
+        };
+
+        OnlineMessage::Snapshot {
+            sequence: 7,
+            run: 2,
+            stats: OnlineStats {
+                cleared_blocks: 20,
+                rows_cleared: 2,
+                game_over_duration: Some(0.5),
+                ..Default::default()
+            },
+            blocks: (0..n_blocks).map(block).collect(),
+        }
+    }
+
+    #[test]
+    fn snapshots_fit_the_mtu_and_are_put_together_from_their_chunks_in_any_order() {
+        let message = snapshot(ONLINE_SNAPSHOT_CHUNK_BLOCKS * 2 + 3);
+        let mut chunks = SnapshotChunk::split(&message);
+        assert_eq!(chunks.len(), 3);
+
+        let mut assembly = None;
+        chunks.reverse();
+        let mut assembled = None;
+        for chunk in chunks {
+            let datagram = chunk.encode();
+            assert!(datagram.len() <= ONLINE_MAX_PAYLOAD, "{}", datagram.len());
+
+            let decoded = SnapshotChunk::decode(&datagram).expect("invalid chunk");
+            assert_eq!(decoded, chunk);
+            assert!(assembled.is_none());
+            assembled = SnapshotAssembly::add(&mut assembly, decoded);
+        }
+
+        let (
+            Some(OnlineMessage::Snapshot { blocks, stats, .. }),
+            OnlineMessage::Snapshot {
+                blocks: sent_blocks,
+                stats: sent_stats,
+                ..
+            },
+        ) = (assembled, message)
+        else {
+            panic!("no snapshot");
+        };
+        assert_eq!(blocks, sent_blocks);
+        assert_eq!(stats, sent_stats);
+    }
+
+    #[test]
+    fn snapshots_missing_a_chunk_are_dropped_for_newer_ones() {
+        let mut assembly = None;
+        let old = SnapshotChunk::split(&snapshot(ONLINE_SNAPSHOT_CHUNK_BLOCKS + 1));
+        assert!(SnapshotAssembly::add(&mut assembly, old[0].clone()).is_none());
+
+        let new: Vec<SnapshotChunk> = SnapshotChunk::split(&snapshot(1))
+            .into_iter()
+            .map(|chunk| SnapshotChunk {
+                sequence: 8,
+                ..chunk
+            })
+            .collect();
+        assert!(SnapshotAssembly::add(&mut assembly, new[0].clone()).is_some());
+        assert!(SnapshotAssembly::add(&mut assembly, old[1].clone()).is_none());
+    }
+
+    #[test]
+    fn snapshots_are_capped_and_invalid_chunks_rejected() {
+        let chunks = SnapshotChunk::split(&snapshot(ONLINE_SNAPSHOT_MAX_BLOCKS + 100));
+        let n_blocks: usize = chunks.iter().map(|chunk| chunk.blocks.len()).sum();
+        assert_eq!(n_blocks, ONLINE_SNAPSHOT_MAX_BLOCKS);
+
+        let datagram = chunks[0].encode();
+        assert!(SnapshotChunk::decode(&datagram[..datagram.len() - 1]).is_none());
+        assert!(SnapshotChunk::decode(&[datagram.as_slice(), &[0]].concat()).is_none());
+
+        let mut nan = chunks[0].clone();
+        nan.blocks[0].position.x = f32::NAN;
+        assert!(SnapshotChunk::decode(&nan.encode()).is_none());
+    }
+
+    #[test]
+    fn the_opponents_board_is_started_by_the_agreed_rules() {
+        let rules = OnlineRules {
+            fuse: true,
+            side_walls: true,
+            slicing: true,
+            partial_rows: Some(0.9),
+            pieces: PieceSet::ALL[PieceSet::ALL.len() - 1],
+            lane_snapping: true,
+            lane_repeat: Some((0.2, 0.05)),
+            ..OnlineRules::from_settings(
+                &Settings::default(),
+                &Profile::default(),
+                &PieceDefinitions::built_in(),
+            )
+        };
+        let profile = Profile::default();
+
+        assert_eq!(
+            OnlineRules::from_settings(&rules.settings(), &profile, &rules.piece_definitions),
+            rules
+        );
+        assert_ne!(
+            OnlineRules::from_settings(
+                &Settings::default(),
+                &profile,
+                &PieceDefinitions::built_in()
+            ),
+            rules
+        );
+
+        let mut piece_definitions = PieceDefinitions::built_in();
+        piece_definitions.pieces[0].weight = 2.0;
+        assert_ne!(
+            OnlineRules::from_settings(&rules.settings(), &profile, &piece_definitions),
+            rules
+        );
+    }
+
+    #[test]
+    fn tick_input_is_quantized_and_keeps_its_presses() {
+        let input = PieceInput {
+            movement: -0.5,
+            torque: 2.0,
+            soft_drop: 1.0,
+            hard_drop: true,
+            hold: false,
+        };
+        let tick = TickInput::new(&input);
+        assert_eq!(TickInput::new(&tick.input()), tick);
+
+        let quantized = tick.input();
+        assert!((quantized.movement + 0.5).abs() < 0.01);
+        assert_eq!(quantized.torque, 1.0);
+        assert_eq!(quantized.soft_drop, 1.0);
+        assert!(quantized.hard_drop && !quantized.hold);
+        assert_eq!(TickInput::new(&PieceInput::default()), TickInput::default());
+    }
+
+    #[test]
+    fn gaps_in_the_opponents_replay_are_asked_for_until_the_run_is_over_and_all_there() {
+        let tick = |movement: i8| TickInput(movement, 0, 0, 0);
+        let mut replay = RemoteReplay::default();
+        replay.record(0, &[tick(1), tick(2)], 0, &[TetrominoKind::I]);
+        replay.record(4, &[tick(5)], 2, &[TetrominoKind::O]);
+        assert_eq!(replay.missing(), Some((2, 1)));
+
+        replay.record(2, &[tick(3), tick(4)], 1, &[TetrominoKind::T]);
+        assert_eq!(replay.missing(), Some((5, 3)));
+
+        replay.counts = Some((6, 3));
+        assert_eq!(replay.missing(), Some((5, 3)));
+        replay.record(5, &[tick(6)], 3, &[]);
+        assert_eq!(replay.missing(), None);
+
+        // Nothing past the longest replay is taken
+        replay.record(ONLINE_MAX_REPLAY_LENGTH, &[tick(7)], 0, &[]);
+        assert_eq!(replay.ticks.len(), 6);
+    }
+
+    #[test]
+    fn what_is_missing_of_a_replay_is_no_input_and_pieces_left_to_chance() {
+        let tick = TickInput(10, 0, 0, 0);
+        let mut replay = RemoteReplay::default();
+        replay.record(
+            0,
+            &[tick],
+            0,
+            &[TetrominoKind::I, TetrominoKind::O, TetrominoKind::T],
+        );
+        replay.record(2, &[tick], 4, &[TetrominoKind::S]);
+        replay.kinds[1] = None;
+        replay.counts = Some((4, 5));
+
+        assert_eq!(
+            replay.replay(),
+            RunReplay {
+                ticks: vec![tick, TickInput::default(), tick, TickInput::default()],
+                kinds: vec![TetrominoKind::I],
+            }
+        );
+    }
+
+    #[test]
+    fn the_referee_judges_the_match_from_the_replays() {
+        // The host pushes every piece off the outer side of their board, the guest lets theirs
+        // fall
+        let push = TickInput::new(&PieceInput {
+            movement: -1.0,
+            ..Default::default()
+        });
+        let match_replay = MatchReplay {
+            rules: OnlineRules::from_settings(
+                &Settings::default(),
+                &Profile::default(),
+                &PieceDefinitions::built_in(),
+            ),
+            replays: [
+                RunReplay {
+                    ticks: vec![push; 60 * 20],
+                    kinds: vec![TetrominoKind::O; 4],
+                },
+                RunReplay {
+                    ticks: vec![TickInput::default(); 60 * 20],
+                    kinds: vec![TetrominoKind::I; 4],
+                },
+            ],
+        };
+
+        let (score, checksum) = crate::headless::referee(&match_replay);
+        assert_eq!(score, 0.0);
+        // As the opponent's referee has it
+        assert_eq!(crate::headless::referee(&match_replay).1, checksum);
+    }
+}
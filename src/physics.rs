@@ -0,0 +1,1658 @@
+//! Controlling pieces with forces, time scaling, and losing blocks off the board.
+
+use std::collections::{BTreeMap, HashMap};
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Write;
+
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use bevy_rapier2d::prelude::*;
+use fxhash::FxHasher64;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{
+    start_run, Board, FixedSeed, GameRng, InsetCamera, RunStarted, GAME_OVER_DURATION,
+    GAME_OVER_TIME_SCALE,
+};
+use crate::bot::Bot;
+use crate::coop::{coop_receive, CoopSession};
+use crate::effects::{start_teardown, Teardown};
+use crate::hazards::hazard_forces;
+use crate::input::{
+    gameplay_input_allowed, idle_detection, update_action_state, Action, ActionState, InputMap,
+    PieceInput, ANALOG_DEAD_ZONE,
+};
+use crate::modes::GameMode;
+use crate::online::{online_receive, OnlineSession, Referee};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{BoardStats, Finale, Profile, RestartRequested};
+use crate::settings::Settings;
+use crate::slicing::Fragment;
+use crate::tetromino::{
+    ActivePiece, ActivePieces, Balloon, Block, LooseBlock, MovementPress, RotationPress,
+    BALLOON_BUOYANCY,
+};
+use crate::ui::{spawn_toast, Fonts};
+use crate::{AppState, GameplaySet};
+
+/// Forces from player input, time scaling and Rapier itself.
+pub struct PhysicsPlugin;
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(snapshot_debug) = SnapshotDebug::from_args() {
+            app.insert_resource(snapshot_debug);
+        }
+
+        app.insert_resource(PhysicsDebugStep::default())
+            .insert_resource(GameTimeScale::default())
+            .add_system(configure_determinism.before(apply_time_scale))
+            .add_system(apply_state_time_scale.before(apply_time_scale))
+            .add_system(reset_block_forces.in_set(GameplaySet))
+            .add_system(
+                balloon_buoyancy
+                    .after(reset_block_forces)
+                    .in_set(GameplaySet),
+            )
+            .add_system(
+                tetromino_movement
+                    .after(update_action_state)
+                    .after(coop_receive)
+                    .after(online_receive)
+                    .after(reset_block_forces)
+                    .in_set(GameplaySet)
+                    .run_if(gameplay_input_allowed),
+            )
+            .add_system(toggle_trajectory)
+            .add_system(toggle_stamina_modifier)
+            .add_system(update_stamina_bar.after(tetromino_movement))
+            .add_system(measure_stability.in_set(GameplaySet))
+            .add_system(update_stability_gauge.after(measure_stability))
+            .add_system(
+                trajectory_line
+                    .after(tetromino_movement)
+                    .after(hazard_forces)
+                    .after(balloon_buoyancy),
+            )
+            .add_system(toggle_assist_mode)
+            .add_system(
+                block_death_detection
+                    .run_if(in_state(AppState::Playing).or_else(in_state(AppState::GameOver))),
+            )
+            .add_system(physics_debug_step)
+            .add_system(
+                world_snapshots
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(PhysicsSet::Writeback)
+                    .run_if(resource_exists::<SnapshotDebug>()),
+            )
+            .add_system(
+                apply_time_scale
+                    .after(physics_debug_step)
+                    .after(idle_detection)
+                    .after(block_death_detection),
+            )
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
+    }
+}
+
+// Defaults of the tunable physics, see `PhysicsTuning`
+pub(crate) const MOVEMENT_FORCE: f32 = 80.0;
+pub(crate) const TORQUE: f32 = 110.0;
+// Game gets more difficult when this is lower:
+pub(crate) const BLOCK_LINEAR_DAMPING: f32 = 8.5;
+// ...so every level above the first multiplies it by this, up to the max level
+pub(crate) const LEVEL_DAMPING_FACTOR: f32 = 0.92;
+pub(crate) const LEVEL_DAMPING_MAX_LEVEL: usize = 10;
+pub(crate) const SOFT_DROP_FORCE: f32 = 60.0;
+/// Downward speed a hard dropped piece is launched with
+pub(crate) const HARD_DROP_SPEED: f32 = 20.0;
+// Assist Mode pulls a piece without horizontal input towards the block slots, this much per unit of offset
+pub(crate) const LANE_GUIDE_STIFFNESS: f32 = 40.0;
+// Rotation assist: rotation held shorter than this is a tap, turning the piece to a quarter turn
+pub(crate) const ROTATION_TAP_SECONDS: f32 = 0.2;
+// ...with torque input per radian off the quarter turn, less per radian per second of spin...
+pub(crate) const ROTATION_ASSIST_GAIN: f32 = 3.0;
+pub(crate) const ROTATION_ASSIST_DAMPING: f32 = 0.6;
+// ...until this close and this still
+pub(crate) const ROTATION_ASSIST_SETTLE_ANGLE: f32 = 0.03;
+pub(crate) const ROTATION_ASSIST_SETTLE_SPIN: f32 = 0.2;
+// Lane snapping: a tap of left or right targets the next block slot over, or the one the piece is
+// headed to when still at least this far from it
+pub(crate) const LANE_SNAP_AHEAD: f32 = 0.25;
+// Push input per block off the target, less per block per second of speed...
+pub(crate) const LANE_SNAP_GAIN: f32 = 2.0;
+pub(crate) const LANE_SNAP_DAMPING: f32 = 0.5;
+// ...until this close and this slow
+pub(crate) const LANE_SNAP_SETTLE_DISTANCE: f32 = 0.05;
+pub(crate) const LANE_SNAP_SETTLE_SPEED: f32 = 0.2;
+
+// Trajectory line, predicting the piece over this many scaled seconds:
+pub(crate) const TRAJECTORY_DURATION: f32 = 0.5;
+pub(crate) const TRAJECTORY_DOTS: usize = 10;
+pub(crate) const TRAJECTORY_SUBSTEPS: usize = 4;
+pub(crate) const TRAJECTORY_DOT_SIZE: f32 = 0.12;
+
+// Stamina modifier, in full meters per scaled second: drained pushing or rotating at full input,
+// regenerated while neither
+pub(crate) const STAMINA_DRAIN: f32 = 0.35;
+pub(crate) const STAMINA_REGEN: f32 = 0.5;
+pub(crate) const STAMINA_BAR_HEIGHT: f32 = 0.2;
+
+// Stack stability, from the contact impulses between settled blocks, updated every this many
+// scaled seconds
+pub(crate) const STABILITY_INTERVAL: f32 = 1.0;
+// Below this the stack counts as precarious, and the board gets a breather
+pub(crate) const STABILITY_BREATHER_THRESHOLD: f32 = 0.6;
+// Scaled seconds of new pieces falling as on the first level...
+pub(crate) const STABILITY_BREATHER_DURATION: f32 = 10.0;
+// ...and at least this long after one before the next
+pub(crate) const STABILITY_BREATHER_COOLDOWN: f32 = 30.0;
+pub(crate) const STABILITY_GAUGE_HEIGHT: f32 = 3.0;
+pub(crate) const STABILITY_GAUGE_WIDTH: f32 = 0.25;
+
+// Physics step when running with a fixed seed
+pub(crate) const DETERMINISTIC_TIMESTEP: f32 = 1.0 / 60.0;
+// Physics ticks between world snapshots, when logging or comparing them
+pub(crate) const SNAPSHOT_INTERVAL: u64 = 30;
+// Mismatching bodies reported when a world snapshot differs from the reference
+pub(crate) const SNAPSHOT_DIFF_BODIES: usize = 5;
+
+// Blocks hanging below the floor edge, by up to this much, are lost unless back above it within
+// the grace duration
+pub(crate) const GRACE_ZONE_DEPTH: f32 = 1.5;
+pub(crate) const GRACE_DURATION: f32 = 1.0;
+
+// Ranges of the physics settings, as (min, max)
+pub(crate) const LINEAR_DAMPING_RANGE: (f32, f32) = (2.5, 16.5);
+pub(crate) const MOVEMENT_FORCE_RANGE: (f32, f32) = (40.0, 160.0);
+pub(crate) const TORQUE_RANGE: (f32, f32) = (50.0, 200.0);
+
+/// Physics constants that players may tune to their liking.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PhysicsTuning {
+    pub(crate) linear_damping: f32,
+    pub(crate) movement_force: f32,
+    pub(crate) torque: f32,
+}
+
+impl Default for PhysicsTuning {
+    fn default() -> Self {
+        Self {
+            linear_damping: BLOCK_LINEAR_DAMPING,
+            movement_force: MOVEMENT_FORCE,
+            torque: TORQUE,
+        }
+    }
+}
+
+impl PhysicsTuning {
+    /// The named profile with these values, if any.
+    pub(crate) fn profile(&self) -> Option<PhysicsProfile> {
+        PhysicsProfile::ALL
+            .into_iter()
+            .find(|profile| profile.tuning() == *self)
+    }
+
+    /// Within the ranges of the settings. `None` if a value isn't a number at all.
+    pub(crate) fn clamped(&self) -> Option<Self> {
+        let clamp =
+            |value: f32, (min, max): (f32, f32)| value.is_finite().then(|| value.clamp(min, max));
+        Some(Self {
+            linear_damping: clamp(self.linear_damping, LINEAR_DAMPING_RANGE)?,
+            movement_force: clamp(self.movement_force, MOVEMENT_FORCE_RANGE)?,
+            torque: clamp(self.torque, TORQUE_RANGE)?,
+        })
+    }
+
+    /// Linear damping of pieces spawned at the given level, lower the higher the level.
+    pub(crate) fn linear_damping_at(&self, level: usize) -> f32 {
+        let steps = level.clamp(1, LEVEL_DAMPING_MAX_LEVEL) - 1;
+        self.linear_damping * LEVEL_DAMPING_FACTOR.powi(steps as i32)
+    }
+}
+
+/// Named sets of physics values to start from when building a custom game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PhysicsProfile {
+    Standard,
+    /// Little damping, so that pieces drift and bounce
+    Floaty,
+    /// Much damping and strong controls
+    Heavy,
+}
+
+impl PhysicsProfile {
+    pub(crate) const ALL: [Self; 3] = [Self::Standard, Self::Floaty, Self::Heavy];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Standard => "Standard",
+            Self::Floaty => "Floaty",
+            Self::Heavy => "Heavy",
+        }
+    }
+
+    pub(crate) fn tuning(&self) -> PhysicsTuning {
+        match self {
+            Self::Standard => PhysicsTuning::default(),
+            Self::Floaty => PhysicsTuning {
+                linear_damping: 4.5,
+                movement_force: 60.0,
+                torque: 80.0,
+            },
+            Self::Heavy => PhysicsTuning {
+                linear_damping: 12.5,
+                movement_force: 120.0,
+                torque: 150.0,
+            },
+        }
+    }
+}
+
+/// Debug mode where the physics world only advances on keypress.
+#[derive(Resource, Default)]
+pub(crate) struct PhysicsDebugStep {
+    pub(crate) enabled: bool,
+    pub(crate) pending_steps: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub(crate) enum TimeScaleSource {
+    Onboarding,
+    GamepadRemap,
+    QuitDialog,
+    History,
+    /// Menus and pause, see `AppState`
+    AppState,
+    Idle,
+    GameOver,
+    DebugStep,
+}
+
+/// Time scale shared by physics and all time-based game logic.
+///
+/// Each effect registers its own factor, and the effective scale is their product,
+/// so that e.g. game over slow-motion and debug stepping compose. Multiplied in a fixed order,
+/// as floats multiplied in another order may round differently and desync the physics.
+#[derive(Resource, Default)]
+pub(crate) struct GameTimeScale {
+    pub(crate) factors: BTreeMap<TimeScaleSource, f32>,
+}
+
+impl GameTimeScale {
+    pub(crate) fn set(&mut self, source: TimeScaleSource, factor: f32) {
+        self.factors.insert(source, factor);
+    }
+
+    pub(crate) fn clear(&mut self, source: TimeScaleSource) {
+        self.factors.remove(&source);
+    }
+
+    pub(crate) fn value(&self) -> f32 {
+        self.factors.values().product()
+    }
+
+    pub(crate) fn delta_seconds(&self, time: &Time) -> f32 {
+        time.delta_seconds() * self.value()
+    }
+}
+
+/// Block in the grace zone below the floor edge, lost when the timer runs out.
+#[derive(Component)]
+pub(crate) struct Teetering {
+    pub(crate) timer: Timer,
+}
+
+/// One dot of the predicted path of a piece, `step` dots ahead of it.
+#[derive(Component)]
+pub(crate) struct TrajectoryDot {
+    pub(crate) board: Entity,
+    pub(crate) piece: usize,
+    pub(crate) step: usize,
+}
+
+/// Lift balloon blocks against gravity, so that they sink slowly and are easily pushed up.
+pub(crate) fn balloon_buoyancy(
+    rapier_config: Res<RapierConfiguration>,
+    board_query: Query<&Board>,
+    mut block_query: Query<
+        (
+            &Block,
+            &Transform,
+            &ReadMassProperties,
+            &GravityScale,
+            &mut ExternalForce,
+        ),
+        With<Balloon>,
+    >,
+) {
+    for (block, transform, mass_properties, gravity_scale, mut forces) in block_query.iter_mut() {
+        let Ok(board) = board_query.get(block.board) else {
+            continue;
+        };
+
+        // Unlike other pieces a balloon can rise, but not above the spawn rows and off the board
+        if transform.translation.y > board.top_y() {
+            continue;
+        }
+
+        forces.force -=
+            rapier_config.gravity * gravity_scale.0 * mass_properties.0.mass * BALLOON_BUOYANCY;
+    }
+}
+
+/// Forces are accumulated from several systems each frame, starting from zero.
+pub(crate) fn reset_block_forces(mut external_force: Query<&mut ExternalForce, With<Block>>) {
+    for mut forces in external_force.iter_mut() {
+        *forces = ExternalForce::default();
+    }
+}
+
+/// Stamina of a board under the stamina modifier, from 0 to 1. Pushes and rotations
+/// are only as strong as the stamina left.
+#[derive(Component)]
+pub(crate) struct Stamina {
+    pub(crate) value: f32,
+}
+
+impl Default for Stamina {
+    fn default() -> Self {
+        Self { value: 1.0 }
+    }
+}
+
+impl Stamina {
+    /// Drain by the combined push and rotation input of the board's pieces, or regenerate
+    /// while there is none.
+    pub(crate) fn update(&mut self, effort: f32, delta: f32) {
+        let change = if effort > ANALOG_DEAD_ZONE {
+            -STAMINA_DRAIN * effort.min(1.0)
+        } else {
+            STAMINA_REGEN
+        };
+        self.value = (self.value + change * delta).clamp(0.0, 1.0);
+    }
+}
+
+/// Meter below the health bar, shown under the stamina modifier.
+#[derive(Component)]
+pub(crate) struct StaminaBar {
+    pub(crate) board: Entity,
+}
+
+/// How steady the settled stack of a board is, from 0.0 with all the load between blocks carried
+/// sideways (leaning and wedged blocks) to 1.0 with blocks resting squarely on each other.
+#[derive(Component)]
+pub(crate) struct Stability {
+    pub(crate) value: f32,
+    /// Contact impulses since the last update, in total and across
+    pub(crate) load: f32,
+    pub(crate) shear: f32,
+    pub(crate) since_update: f32,
+    /// Scaled seconds left of the current breather
+    pub(crate) breather: f32,
+    /// Scaled seconds until another breather may start
+    pub(crate) breather_cooldown: f32,
+}
+
+impl Default for Stability {
+    fn default() -> Self {
+        Self {
+            value: 1.0,
+            load: 0.0,
+            shear: 0.0,
+            since_update: 0.0,
+            breather: 0.0,
+            breather_cooldown: 0.0,
+        }
+    }
+}
+
+impl Stability {
+    /// Add the contact impulses of a frame, and update the value once per interval.
+    /// Returns whether a breather starts, which it may only while `can_breathe`.
+    pub(crate) fn update(&mut self, load: f32, shear: f32, delta: f32, can_breathe: bool) -> bool {
+        self.load += load;
+        self.shear += shear;
+        self.since_update += delta;
+        self.breather = (self.breather - delta).max(0.0);
+        self.breather_cooldown = (self.breather_cooldown - delta).max(0.0);
+
+        if self.since_update < STABILITY_INTERVAL {
+            return false;
+        }
+
+        self.value = if self.load > 0.0 {
+            1.0 - self.shear / self.load
+        } else {
+            1.0
+        };
+        self.load = 0.0;
+        self.shear = 0.0;
+        self.since_update = 0.0;
+
+        if can_breathe && self.value < STABILITY_BREATHER_THRESHOLD && self.breather_cooldown <= 0.0
+        {
+            self.breather = STABILITY_BREATHER_DURATION;
+            self.breather_cooldown = STABILITY_BREATHER_DURATION + STABILITY_BREATHER_COOLDOWN;
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Vertical gauge to the right of a board, showing its stack stability.
+#[derive(Component)]
+pub(crate) struct StabilityGauge {
+    pub(crate) board: Entity,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn tetromino_movement(
+    action_state: Res<ActionState>,
+    coop: Option<Res<CoopSession>>,
+    online: Option<Res<OnlineSession>>,
+    referee: Option<Res<Referee>>,
+    profile: Res<Profile>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut piece_query: Query<(
+        &Board,
+        &Bot,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut Stamina,
+    )>,
+    mut block_query: Query<(&mut ExternalForce, &Transform, &mut Velocity)>,
+) {
+    let delta = time_scale.delta_seconds(&time);
+
+    for (board, bot, mut active_pieces, mut stats, mut stamina) in piece_query.iter_mut() {
+        let inputs = PieceInput::for_board(
+            &action_state,
+            coop.as_deref(),
+            online.as_deref(),
+            referee.as_deref(),
+            board,
+            bot,
+        );
+        let strength = if board.stamina { stamina.value } else { 1.0 };
+        let mut effort = 0.0;
+
+        for (active_piece, mut input) in active_pieces.pieces.iter_mut().zip(inputs) {
+            // The bot turns its pieces precisely on its own
+            if settings.rotation_assist && !board.bot {
+                let (orientation, spin, n_blocks) = active_piece
+                    .blocks
+                    .iter()
+                    .filter_map(|block_entity| block_query.get(*block_entity).ok())
+                    .fold(
+                        (Vec2::ZERO, 0.0, 0),
+                        |(orientation, spin, n_blocks), (_, transform, velocity)| {
+                            let (_, _, angle) = transform.rotation.to_euler(EulerRot::XYZ);
+                            (
+                                orientation + Vec2::from_angle(angle),
+                                spin + velocity.angvel,
+                                n_blocks + 1,
+                            )
+                        },
+                    );
+                if n_blocks > 0 {
+                    input.torque = assisted_torque(
+                        active_piece,
+                        input.torque,
+                        orientation.y.atan2(orientation.x),
+                        spin / n_blocks as f32,
+                        time.delta_seconds(),
+                    );
+                }
+            }
+
+            // Likewise for its pushes
+            if settings.lane_snapping && !board.bot {
+                let (x, slot_offset, speed, n_blocks) = active_piece
+                    .blocks
+                    .iter()
+                    .filter_map(|block_entity| block_query.get(*block_entity).ok())
+                    .fold(
+                        (0.0, 0.0, 0.0, 0),
+                        |(x, slot_offset, speed, n_blocks), (_, transform, velocity)| {
+                            let block_x = transform.translation.x;
+                            (
+                                x + block_x,
+                                slot_offset + board.slot_offset(block_x).unwrap_or(0.0),
+                                speed + velocity.linvel.x,
+                                n_blocks + 1,
+                            )
+                        },
+                    );
+                if n_blocks > 0 {
+                    let n_blocks = n_blocks as f32;
+                    input.movement = assisted_movement(
+                        active_piece,
+                        input.movement,
+                        x / n_blocks,
+                        (x - slot_offset) / n_blocks,
+                        speed / n_blocks,
+                        settings.lane_repeat(),
+                        time.delta_seconds(),
+                    );
+                }
+            }
+
+            // Without horizontal input, Assist Mode nudges the piece towards the nearest block slots
+            let guide_force = if profile.assist_mode && input.movement.abs() < ANALOG_DEAD_ZONE {
+                let offsets: Vec<f32> = active_piece
+                    .blocks
+                    .iter()
+                    .filter_map(|block_entity| block_query.get(*block_entity).ok())
+                    .filter_map(|(_, transform, _)| board.slot_offset(transform.translation.x))
+                    .collect();
+
+                if offsets.is_empty() {
+                    0.0
+                } else {
+                    -LANE_GUIDE_STIFFNESS * offsets.iter().sum::<f32>() / offsets.len() as f32
+                }
+            } else {
+                0.0
+            };
+
+            let mut falling_speed = 0.0;
+
+            for block_entity in &active_piece.blocks {
+                if let Ok((mut forces, _, mut velocity)) = block_query.get_mut(*block_entity) {
+                    forces.force += Vec2::new(
+                        input.movement * board.physics.movement_force * strength + guide_force,
+                        -input.soft_drop * SOFT_DROP_FORCE,
+                    );
+                    forces.torque += input.torque * board.physics.torque * strength;
+                    if input.hard_drop {
+                        velocity.linvel.y = velocity.linvel.y.min(-HARD_DROP_SPEED);
+                    }
+                    falling_speed += (-velocity.linvel.y).max(0.0);
+                }
+            }
+
+            // Soft dropping earns points for the distance fallen
+            if input.soft_drop > ANALOG_DEAD_ZONE && !active_piece.blocks.is_empty() {
+                stats.soft_drop_distance +=
+                    falling_speed / active_piece.blocks.len() as f32 * delta;
+            }
+
+            if !active_piece.blocks.is_empty() {
+                effort += input.movement.abs() + input.torque.abs();
+            }
+        }
+
+        if board.stamina {
+            stamina.update(effort, delta);
+        }
+    }
+}
+
+/// Torque input with the rotation assist: a tap of rotation turns the piece on to the next quarter
+/// turn, and a PD controller holds it there until it settles. Holding rotation spins freely.
+/// Takes the average angle and spin of the piece, and the unscaled seconds of the frame.
+pub(crate) fn assisted_torque(
+    piece: &mut ActivePiece,
+    torque: f32,
+    angle: f32,
+    spin: f32,
+    delta: f32,
+) -> f32 {
+    let nearest = (angle / FRAC_PI_2).round() * FRAC_PI_2;
+
+    if torque.abs() > ANALOG_DEAD_ZONE {
+        let press = piece.rotation_press.get_or_insert(RotationPress {
+            held: 0.0,
+            direction: torque.signum(),
+            from: nearest,
+        });
+        press.held += delta;
+        piece.snap_angle = None;
+        return torque;
+    }
+
+    if let Some(press) = piece.rotation_press.take() {
+        if press.held < ROTATION_TAP_SECONDS {
+            // However short the tap, the piece turns on from where it was
+            let turned = ((nearest - press.from + PI).rem_euclid(TAU) - PI).abs() > 0.1;
+            piece.snap_angle = Some(if turned {
+                nearest
+            } else {
+                nearest + press.direction * FRAC_PI_2
+            });
+        }
+    }
+
+    let Some(target) = piece.snap_angle else {
+        return torque;
+    };
+
+    let error = (target - angle + PI).rem_euclid(TAU) - PI;
+    if error.abs() < ROTATION_ASSIST_SETTLE_ANGLE && spin.abs() < ROTATION_ASSIST_SETTLE_SPIN {
+        piece.snap_angle = None;
+        return torque;
+    }
+
+    (error * ROTATION_ASSIST_GAIN - spin * ROTATION_ASSIST_DAMPING).clamp(-1.0, 1.0)
+}
+
+/// Movement input with lane snapping: a tap of left or right targets the next block slot over, and
+/// holding steps the target on like a repeating key, after the delay and at the interval of
+/// `repeat` (DAS and ARR). A proportional controller pushes the piece to the target, instead of
+/// the raw push. Without `repeat`, holding pushes as usual, and the piece snaps to the slot it's
+/// headed to once released. Takes the center of the piece, the center it has with its blocks in
+/// their nearest slots, its horizontal speed, and the unscaled seconds of the frame.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn assisted_movement(
+    piece: &mut ActivePiece,
+    movement: f32,
+    x: f32,
+    slotted_x: f32,
+    speed: f32,
+    repeat: Option<(f32, f32)>,
+    delta: f32,
+) -> f32 {
+    let pressed = movement.abs() > ANALOG_DEAD_ZONE;
+
+    if pressed {
+        let direction = movement.signum();
+        let new_press = piece
+            .movement_press
+            .is_none_or(|press| press.direction != direction);
+
+        let Some((repeat_delay, repeat_interval)) = repeat else {
+            if new_press {
+                piece.movement_press = Some(MovementPress {
+                    held: 0.0,
+                    direction,
+                });
+            }
+            piece.lane_target = None;
+            return movement;
+        };
+
+        if new_press {
+            let headed = (slotted_x - x) * direction > LANE_SNAP_AHEAD;
+            piece.lane_target = Some(if headed {
+                slotted_x
+            } else {
+                slotted_x + direction
+            });
+            piece.movement_press = Some(MovementPress {
+                held: 0.0,
+                direction,
+            });
+        }
+
+        if let Some(press) = &mut piece.movement_press {
+            let repeats = |held: f32| {
+                if held < repeat_delay {
+                    0.0
+                } else {
+                    ((held - repeat_delay) / repeat_interval).floor() + 1.0
+                }
+            };
+            let steps = repeats(press.held + delta) - repeats(press.held);
+            press.held += delta;
+
+            if let Some(target) = &mut piece.lane_target {
+                *target += steps * direction;
+            }
+        }
+    } else if let Some(press) = piece.movement_press.take() {
+        // Released after pushing freely: on to the next slot it's headed to
+        if repeat.is_none() {
+            let passed = (slotted_x - x) * press.direction < 0.0;
+            piece.lane_target = Some(if passed {
+                slotted_x + press.direction
+            } else {
+                slotted_x
+            });
+        }
+    }
+
+    let Some(target) = piece.lane_target else {
+        return movement;
+    };
+
+    let error = target - x;
+    if !pressed && error.abs() < LANE_SNAP_SETTLE_DISTANCE && speed.abs() < LANE_SNAP_SETTLE_SPEED {
+        piece.lane_target = None;
+        return movement;
+    }
+
+    (error * LANE_SNAP_GAIN - speed * LANE_SNAP_DAMPING).clamp(-1.0, 1.0)
+}
+
+pub(crate) fn update_stamina_bar(
+    board_query: Query<(&Board, &Stamina)>,
+    mut stamina_bar_query: Query<(&StaminaBar, &mut Transform, &mut Visibility)>,
+) {
+    for (stamina_bar, mut transform, mut visibility) in stamina_bar_query.iter_mut() {
+        let Ok((board, stamina)) = board_query.get(stamina_bar.board) else {
+            continue;
+        };
+
+        if !board.stamina {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        }
+
+        *visibility = Visibility::Inherited;
+        transform.scale.x = stamina.value;
+    }
+}
+
+/// Aggregate the contact impulses within the settled stack of each board into its stability.
+/// A precarious stack gives the board a breather, with new pieces falling as on the first level.
+pub(crate) fn measure_stability(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    rapier_context: Res<RapierContext>,
+    mut board_query: Query<(Entity, &BoardStats, &mut ActivePieces, &mut Stability)>,
+    block_query: Query<&Block>,
+) {
+    let delta = time_scale.delta_seconds(&time);
+
+    // Load and shear per board
+    let mut impulses: HashMap<Entity, (f32, f32)> = HashMap::new();
+
+    for contact_pair in rapier_context.contact_pairs() {
+        let (entity1, entity2) = (contact_pair.collider1(), contact_pair.collider2());
+        let (Ok(block1), Ok(block2)) = (block_query.get(entity1), block_query.get(entity2)) else {
+            continue;
+        };
+        if block1.board != block2.board {
+            continue;
+        }
+
+        // Only the stack, not the pieces landing on it
+        let Ok((_, _, active_pieces, _)) = board_query.get(block1.board) else {
+            continue;
+        };
+        if active_pieces.contains(&entity1) || active_pieces.contains(&entity2) {
+            continue;
+        }
+
+        let (load, shear) = impulses.entry(block1.board).or_default();
+        for manifold in contact_pair.manifolds() {
+            let across = manifold.normal().x.abs();
+            for contact in manifold.points() {
+                *load += contact.impulse();
+                *shear += contact.impulse() * across;
+            }
+        }
+    }
+
+    for (board_entity, stats, mut active_pieces, mut stability) in board_query.iter_mut() {
+        let (load, shear) = impulses.get(&board_entity).copied().unwrap_or_default();
+        let can_breathe = stats.game_over_duration.is_none();
+        let breather_started = stability.update(load, shear, delta, can_breathe);
+        active_pieces.breather = stability.breather > 0.0;
+
+        if breather_started {
+            spawn_toast(
+                &mut commands,
+                &fonts,
+                "Wobbly stack! Pieces fall slower for a moment",
+            );
+        }
+    }
+}
+
+/// Fill the gauge to the stability, from red when precarious to green when steady.
+pub(crate) fn update_stability_gauge(
+    board_query: Query<&Stability>,
+    mut gauge_query: Query<(&StabilityGauge, &mut Transform, &mut Sprite)>,
+) {
+    for (gauge, mut transform, mut sprite) in gauge_query.iter_mut() {
+        let Ok(stability) = board_query.get(gauge.board) else {
+            continue;
+        };
+
+        transform.scale.y = stability.value;
+        sprite.color = Color::rgb(1.0 - stability.value, stability.value, 0.2);
+    }
+}
+
+pub(crate) fn toggle_stamina_modifier(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::StaminaModifier) {
+        return;
+    }
+
+    settings.stamina_modifier = !settings.stamina_modifier;
+    settings.save();
+
+    let state = if settings.stamina_modifier {
+        "on"
+    } else {
+        "off"
+    };
+    spawn_toast(&mut commands, &fonts, &format!("Next run: stamina {state}"));
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn block_death_detection(
+    mut commands: Commands,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    projection_query: Query<&OrthographicProjection, Without<InsetCamera>>,
+    mut block_query: Query<(Entity, &Transform, &Block, Option<&mut Teetering>)>,
+    time: Res<Time>,
+    mut time_scale: ResMut<GameTimeScale>,
+    settings: Res<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut run_started_events: EventWriter<RunStarted>,
+    finale_query: Query<&Finale>,
+    loose_query: Query<(), With<LooseBlock>>,
+    fragment_query: Query<(), With<Fragment>>,
+    restart_query: Query<(), With<RestartRequested>>,
+    teardown_query: Query<&Teardown>,
+) {
+    let delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+
+    for projection in projection_query.iter() {
+        for (block_entity, transform, block, teetering) in block_query.iter_mut() {
+            let (mode, floor_edge) = board_query.get(block.board).map_or(
+                (GameMode::default(), f32::NEG_INFINITY),
+                |(_, board, _, _, _)| (board.mode, board.floor_edge_y()),
+            );
+            let outside_limit = projection.area.min.y - mode.loss_margin();
+            let y = transform.translation.y;
+            let grace_zone = floor_edge - GRACE_ZONE_DEPTH..floor_edge;
+
+            match mode.grace_duration() {
+                _ if y < outside_limit => {}
+                Some(grace_duration) if grace_zone.contains(&y) => match teetering {
+                    Some(mut teetering) => {
+                        if !teetering.timer.tick(delta).finished() {
+                            continue;
+                        }
+                    }
+                    None => {
+                        commands.entity(block_entity).insert(Teetering {
+                            timer: Timer::from_seconds(grace_duration, TimerMode::Once),
+                        });
+                        continue;
+                    }
+                },
+                // Rescued, or falling on past the grace zone towards the loss boundary
+                _ => {
+                    if teetering.is_some() {
+                        commands.entity(block_entity).remove::<Teetering>();
+                    }
+                    continue;
+                }
+            }
+
+            if let Ok((_, board, active_pieces, mut stats, _)) = board_query.get_mut(block.board) {
+                // The opponent's run online only ends by the opponent's word, see `online`
+                if active_pieces.contains(&block_entity)
+                    && !board.remote
+                    && stats.game_over_duration.is_none()
+                {
+                    stats.game_over_duration = Some(0.0);
+                    time_scale.set(TimeScaleSource::GameOver, GAME_OVER_TIME_SCALE);
+                }
+
+                // Fragments of sliced blocks were counted as cleared already
+                if !fragment_query.contains(block_entity) {
+                    stats.lost_blocks += 1;
+                    if !loose_query.contains(block_entity) {
+                        *stats.lost_by_kind.entry(block.kind).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            commands.entity(block_entity).despawn_recursive();
+        }
+    }
+
+    for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in board_query.iter_mut() {
+        let Some(game_over_duration) = stats.game_over_duration.as_mut() else {
+            continue;
+        };
+
+        *game_over_duration += time_scale.delta_seconds(&time);
+
+        // Start a new game once the results are dismissed, leaving time for the finale
+        let finale_duration = finale_query
+            .get(board_entity)
+            .map_or(0.0, |finale| finale.timer.duration().as_secs_f32());
+
+        if *game_over_duration > GAME_OVER_DURATION + finale_duration
+            && restart_query.contains(board_entity)
+            && !board.remote
+        {
+            // ...and for the board to be torn down
+            match teardown_query.get(board_entity) {
+                Err(_) => {
+                    time_scale.clear(TimeScaleSource::GameOver);
+                    start_teardown(
+                        &mut commands,
+                        board_entity,
+                        board.center,
+                        block_query
+                            .iter()
+                            .filter(|(_, _, block, _)| block.board == board_entity)
+                            .map(|(entity, transform, _, _)| {
+                                (entity, transform.translation.truncate())
+                            }),
+                    );
+                    continue;
+                }
+                Ok(teardown) if !teardown.timer.finished() => continue,
+                Ok(_) => {}
+            }
+
+            time_scale.clear(TimeScaleSource::GameOver);
+            commands.entity(board_entity).remove::<Teardown>();
+
+            run_started_events.send(start_run(
+                &mut commands,
+                board_entity,
+                &mut board,
+                &mut active_pieces,
+                &mut stats,
+                &settings,
+                &piece_definitions,
+                &mut rng,
+                block_query
+                    .iter()
+                    .filter(|(_, _, block, _)| block.board == board_entity)
+                    .map(|(entity, _, _, _)| entity),
+            ));
+        }
+    }
+}
+
+/// While debug stepping is enabled, keep game time frozen except for
+/// exactly one frame per step keypress. Rendering and other systems keep running.
+pub(crate) fn physics_debug_step(
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    mut debug_step: ResMut<PhysicsDebugStep>,
+    mut time_scale: ResMut<GameTimeScale>,
+) {
+    if input_map.just_pressed(&input, Action::DebugStepToggle) {
+        debug_step.enabled = !debug_step.enabled;
+        debug_step.pending_steps = 0;
+        info!("physics debug stepping: {}", debug_step.enabled);
+    }
+
+    if debug_step.enabled && input_map.just_pressed(&input, Action::DebugStep) {
+        debug_step.pending_steps += 1;
+    }
+
+    if !debug_step.enabled {
+        time_scale.clear(TimeScaleSource::DebugStep);
+    } else if debug_step.pending_steps > 0 {
+        // With the default variable timestep, one active frame is one physics tick
+        debug_step.pending_steps -= 1;
+        time_scale.clear(TimeScaleSource::DebugStep);
+    } else {
+        time_scale.set(TimeScaleSource::DebugStep, 0.0);
+    }
+}
+
+/// Physics state of a rigid body, as stepped by Rapier.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct BodyState {
+    /// Order the body was spawned in, to tell the bodies apart in a diff, see
+    /// `SnapshotDebug::ordinals`. Left out of the checksum.
+    pub(crate) ordinal: u64,
+    pub(crate) translation: Vec2,
+    pub(crate) angle: f32,
+    pub(crate) linvel: Vec2,
+    pub(crate) angvel: f32,
+}
+
+impl BodyState {
+    fn bits(&self) -> [u32; 6] {
+        [
+            self.translation.x,
+            self.translation.y,
+            self.angle,
+            self.linvel.x,
+            self.linvel.y,
+            self.angvel,
+        ]
+        .map(f32::to_bits)
+    }
+}
+
+/// Every rigid body of the physics world at one tick, sorted by spawn ordinal.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct WorldSnapshot {
+    pub(crate) tick: u64,
+    pub(crate) checksum: u64,
+    pub(crate) bodies: Vec<BodyState>,
+}
+
+impl WorldSnapshot {
+    /// The bodies of `context`, by their ordinals in `ordinals`. Bodies without one come last.
+    pub(crate) fn capture(
+        tick: u64,
+        context: &RapierContext,
+        ordinals: &HashMap<Entity, u64>,
+    ) -> Self {
+        let mut bodies: Vec<BodyState> = context
+            .bodies
+            .iter()
+            .filter_map(|(handle, body)| {
+                let entity = context.rigid_body_entity(handle)?;
+                let translation = body.translation();
+                let linvel = body.linvel();
+
+                Some(BodyState {
+                    ordinal: ordinals.get(&entity).copied().unwrap_or(u64::MAX),
+                    translation: Vec2::new(translation.x, translation.y),
+                    angle: body.rotation().angle(),
+                    linvel: Vec2::new(linvel.x, linvel.y),
+                    angvel: body.angvel(),
+                })
+            })
+            .collect();
+        bodies.sort_by_key(|body| body.ordinal);
+
+        Self {
+            tick,
+            checksum: checksum(&bodies),
+            bodies,
+        }
+    }
+
+    /// Descriptions of the first bodies that differ from `reference`, including missing and extra bodies.
+    pub(crate) fn diff(&self, reference: &Self, limit: usize) -> Vec<String> {
+        let mut lines = vec![];
+        let mut ours = self.bodies.iter().peekable();
+        let mut theirs = reference.bodies.iter().peekable();
+
+        while lines.len() < limit {
+            let line = match (ours.peek().copied(), theirs.peek().copied()) {
+                (None, None) => break,
+                (Some(body), Some(expected)) if body.ordinal == expected.ordinal => {
+                    ours.next();
+                    theirs.next();
+                    if body == expected {
+                        continue;
+                    }
+                    format!("{:?}\n  expected {:?}", body, expected)
+                }
+                (Some(body), Some(expected)) if body.ordinal < expected.ordinal => {
+                    ours.next();
+                    format!("extra {body:?}")
+                }
+                (Some(_), Some(expected)) | (None, Some(expected)) => {
+                    theirs.next();
+                    format!("missing {expected:?}")
+                }
+                (Some(body), None) => {
+                    ours.next();
+                    format!("extra {body:?}")
+                }
+            };
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+/// Hash of the bodies bit for bit, as a difference in the last bit of a float is a desync.
+/// Bodies are hashed in the order of their state rather than their spawn order. The hash is
+/// the same on every platform and Rust version, for comparing checksums between machines.
+fn checksum(bodies: &[BodyState]) -> u64 {
+    let mut states: Vec<[u32; 6]> = bodies.iter().map(BodyState::bits).collect();
+    states.sort_unstable();
+
+    let mut hasher = FxHasher64::default();
+    hasher.write_u64(states.len() as u64);
+    for word in states.iter().flatten() {
+        hasher.write_u32(*word);
+    }
+    hasher.finish()
+}
+
+/// Checksum of the positions and velocities of every rigid body, see `checksum`.
+pub(crate) fn world_checksum(context: &RapierContext) -> u64 {
+    WorldSnapshot::capture(0, context, &HashMap::new()).checksum
+}
+
+/// Debugging desyncs: snapshots of the physics world every `SNAPSHOT_INTERVAL` ticks,
+/// written to a log with `--snapshot-log <path>`, and compared to the log of an earlier run
+/// with `--snapshot-compare <path>`. Runs only reproduce each other with the same `--seed` and input.
+#[derive(Resource)]
+pub(crate) struct SnapshotDebug {
+    /// Physics ticks so far
+    pub(crate) tick: u64,
+    pub(crate) log: Option<File>,
+    /// Snapshots of the earlier run, by tick
+    pub(crate) reference: HashMap<u64, WorldSnapshot>,
+    /// Only the first divergence is reported, everything after it differs as well
+    pub(crate) diverged: bool,
+    /// The order of the rigid bodies spawned so far, which is the same between runs that play
+    /// out alike, unlike how their entities are numbered
+    pub(crate) ordinals: HashMap<Entity, u64>,
+    pub(crate) next_ordinal: u64,
+}
+
+impl SnapshotDebug {
+    pub(crate) fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let arg = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|index| args.get(index + 1))
+        };
+
+        let log_path = arg("--snapshot-log");
+        let compare_path = arg("--snapshot-compare");
+
+        if log_path.is_none() && compare_path.is_none() {
+            return None;
+        }
+
+        let log = log_path.and_then(|path| match File::create(path) {
+            Ok(file) => Some(file),
+            Err(err) => {
+                warn!("snapshots: could not create {path}: {err}");
+                None
+            }
+        });
+
+        // One snapshot per line
+        let reference = compare_path
+            .and_then(|path| match std::fs::read_to_string(path) {
+                Ok(contents) => Some(contents),
+                Err(err) => {
+                    warn!("snapshots: could not read {path}: {err}");
+                    None
+                }
+            })
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter_map(|line| ron::from_str::<WorldSnapshot>(line).ok())
+                    .map(|snapshot| (snapshot.tick, snapshot))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            tick: 0,
+            log,
+            reference,
+            diverged: false,
+            ordinals: HashMap::new(),
+            next_ordinal: 0,
+        })
+    }
+}
+
+pub(crate) fn world_snapshots(
+    rapier_config: Res<RapierConfiguration>,
+    context: Res<RapierContext>,
+    mut snapshot_debug: ResMut<SnapshotDebug>,
+    added_query: Query<(Entity, &Transform), Added<RigidBody>>,
+    mut removed_bodies: RemovedComponents<RigidBody>,
+) {
+    let snapshot_debug = &mut *snapshot_debug;
+
+    // Bodies spawned on the same frame are put in order by where they are, as their entities
+    // may be numbered differently between runs
+    let mut added: Vec<(Entity, [u32; 3])> = added_query
+        .iter()
+        .map(|(entity, transform)| {
+            let position = transform.translation.truncate();
+            let angle = transform.rotation.to_euler(EulerRot::XYZ).2;
+            (entity, [position.x, position.y, angle].map(f32::to_bits))
+        })
+        .collect();
+    added.sort_by_key(|(entity, bits)| (*bits, *entity));
+    for (entity, _) in added {
+        snapshot_debug
+            .ordinals
+            .insert(entity, snapshot_debug.next_ordinal);
+        snapshot_debug.next_ordinal += 1;
+    }
+    for entity in removed_bodies.iter() {
+        snapshot_debug.ordinals.remove(&entity);
+    }
+
+    if !rapier_config.physics_pipeline_active {
+        return;
+    }
+
+    snapshot_debug.tick += 1;
+    let tick = snapshot_debug.tick;
+
+    if !tick.is_multiple_of(SNAPSHOT_INTERVAL) {
+        return;
+    }
+
+    let snapshot = WorldSnapshot::capture(tick, &context, &snapshot_debug.ordinals);
+
+    if let Some(log) = snapshot_debug.log.as_mut() {
+        if let Ok(line) = ron::to_string(&snapshot) {
+            if let Err(err) = writeln!(log, "{line}") {
+                warn!("snapshots: could not write the log: {err}");
+                snapshot_debug.log = None;
+            }
+        }
+    }
+
+    if snapshot_debug.diverged {
+        return;
+    }
+
+    let Some(reference) = snapshot_debug.reference.get(&tick) else {
+        return;
+    };
+
+    if snapshot.checksum != reference.checksum {
+        let diff = snapshot.diff(reference, SNAPSHOT_DIFF_BODIES);
+        warn!(
+            "snapshots: desync at tick {tick}, first differing bodies:\n{}",
+            diff.join("\n")
+        );
+        snapshot_debug.diverged = true;
+    }
+}
+
+pub(crate) fn apply_time_scale(
+    time_scale: Res<GameTimeScale>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    let scale = time_scale.value();
+
+    rapier_config.physics_pipeline_active = scale > 0.0;
+
+    match &mut rapier_config.timestep_mode {
+        TimestepMode::Variable {
+            time_scale: rapier_time_scale,
+            ..
+        }
+        | TimestepMode::Interpolated {
+            time_scale: rapier_time_scale,
+            ..
+        } => *rapier_time_scale = scale,
+        // Deterministic mode: one step per update, of scaled length
+        TimestepMode::Fixed { dt, .. } => *dt = DETERMINISTIC_TIMESTEP * scale,
+    }
+}
+
+/// With a fixed seed, step the physics once per update by a fixed timestep,
+/// so that runs don't depend on the frame rate and their ticks line up.
+/// Also when a seed is fixed later, by replaying a run from the history screen.
+pub(crate) fn configure_determinism(
+    fixed_seed: Res<FixedSeed>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    // Only switched once, as the time scale is kept in the timestep mode
+    if fixed_seed.0.is_some()
+        && matches!(rapier_config.timestep_mode, TimestepMode::Variable { .. })
+    {
+        rapier_config.timestep_mode = TimestepMode::Fixed {
+            dt: DETERMINISTIC_TIMESTEP,
+            substeps: 1,
+        };
+    }
+}
+
+pub(crate) fn toggle_trajectory(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::Trajectory) {
+        return;
+    }
+
+    settings.show_trajectory = !settings.show_trajectory;
+    settings.save();
+
+    let state = if settings.show_trajectory {
+        "on"
+    } else {
+        "off"
+    };
+    spawn_toast(&mut commands, &fonts, &format!("Trajectory line {state}"));
+}
+
+/// Predict where the centroid of each piece is headed from its velocity, gravity and the forces
+/// applied this frame, damping the velocity like the physics does, and place the dots along it.
+#[allow(clippy::type_complexity)]
+pub(crate) fn trajectory_line(
+    settings: Res<Settings>,
+    rapier_config: Res<RapierConfiguration>,
+    board_query: Query<(Entity, &ActivePieces)>,
+    block_query: Query<(
+        &Transform,
+        &Velocity,
+        &ExternalForce,
+        &ReadMassProperties,
+        &Damping,
+        Option<&GravityScale>,
+    )>,
+    mut dot_query: Query<(&TrajectoryDot, &mut Transform, &mut Visibility), Without<Velocity>>,
+) {
+    // Dot positions per board and piece
+    let mut paths: HashMap<(Entity, usize), Vec<Vec2>> = HashMap::new();
+
+    if settings.show_trajectory && !settings.clean_feed {
+        for (board_entity, active_pieces) in board_query.iter() {
+            for (index, active_piece) in active_pieces.pieces.iter().enumerate() {
+                let blocks: Vec<_> = active_piece
+                    .blocks
+                    .iter()
+                    .filter_map(|block_entity| block_query.get(*block_entity).ok())
+                    .collect();
+
+                let mass: f32 = blocks
+                    .iter()
+                    .map(|(_, _, _, mass_properties, _, _)| mass_properties.0.mass)
+                    .sum();
+
+                if blocks.is_empty() || mass <= 0.0 {
+                    continue;
+                }
+
+                let n_blocks = blocks.len() as f32;
+                let mut position = blocks
+                    .iter()
+                    .map(|(transform, ..)| transform.translation.truncate())
+                    .sum::<Vec2>()
+                    / n_blocks;
+                let mut velocity = blocks
+                    .iter()
+                    .map(|(_, velocity, ..)| velocity.linvel)
+                    .sum::<Vec2>()
+                    / n_blocks;
+                let force: Vec2 = blocks
+                    .iter()
+                    .map(
+                        |(_, _, external_force, mass_properties, _, gravity_scale)| {
+                            let gravity_scale = gravity_scale.map_or(1.0, |scale| scale.0);
+                            external_force.force
+                                + rapier_config.gravity * gravity_scale * mass_properties.0.mass
+                        },
+                    )
+                    .sum();
+                let acceleration = force / mass;
+                let damping = blocks[0].4.linear_damping;
+
+                let dt = TRAJECTORY_DURATION / (TRAJECTORY_DOTS * TRAJECTORY_SUBSTEPS) as f32;
+                let mut path = Vec::with_capacity(TRAJECTORY_DOTS);
+
+                for _ in 0..TRAJECTORY_DOTS {
+                    for _ in 0..TRAJECTORY_SUBSTEPS {
+                        velocity = (velocity + acceleration * dt) / (1.0 + dt * damping);
+                        position += velocity * dt;
+                    }
+                    path.push(position);
+                }
+
+                paths.insert((board_entity, index), path);
+            }
+        }
+    }
+
+    for (dot, mut transform, mut visibility) in dot_query.iter_mut() {
+        let Some(position) = paths
+            .get(&(dot.board, dot.piece))
+            .and_then(|path| path.get(dot.step - 1))
+        else {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        };
+
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        *visibility = Visibility::Inherited;
+    }
+}
+
+/// Freeze the boards while in the main menu or paused.
+pub(crate) fn apply_state_time_scale(
+    state: Res<State<AppState>>,
+    mut time_scale: ResMut<GameTimeScale>,
+) {
+    if !state.is_changed() {
+        return;
+    }
+
+    match state.0 {
+        AppState::MainMenu | AppState::Paused => time_scale.set(TimeScaleSource::AppState, 0.0),
+        AppState::Playing | AppState::GameOver => time_scale.clear(TimeScaleSource::AppState),
+    }
+}
+
+pub(crate) fn toggle_assist_mode(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut profile: ResMut<Profile>,
+) {
+    if !input_map.just_pressed(&input, Action::AssistMode) {
+        return;
+    }
+
+    profile.assist_mode = !profile.assist_mode;
+    profile.save();
+
+    let state = if profile.assist_mode { "on" } else { "off" };
+    spawn_toast(&mut commands, &fonts, &format!("Assist Mode {state}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(ordinal: u64, x: f32) -> BodyState {
+        BodyState {
+            ordinal,
+            translation: Vec2::new(x, 0.0),
+            angle: 0.0,
+            linvel: Vec2::ZERO,
+            angvel: 0.0,
+        }
+    }
+
+    fn snapshot(bodies: Vec<BodyState>) -> WorldSnapshot {
+        WorldSnapshot {
+            tick: 30,
+            checksum: 0,
+            bodies,
+        }
+    }
+
+    #[test]
+    fn stamina_drains_with_effort_and_regenerates_without() {
+        let mut stamina = Stamina::default();
+
+        stamina.update(1.0, 1.0);
+        assert!((stamina.value - (1.0 - STAMINA_DRAIN)).abs() < 1e-6);
+
+        // Pushing and rotating at once drains no faster than either at full input
+        stamina.update(2.0, 10.0);
+        assert_eq!(stamina.value, 0.0);
+
+        stamina.update(0.0, 1.0);
+        assert!((stamina.value - STAMINA_REGEN).abs() < 1e-6);
+        stamina.update(0.0, 10.0);
+        assert_eq!(stamina.value, 1.0);
+    }
+
+    #[test]
+    fn sideways_load_gives_a_breather_then_a_cooldown() {
+        let mut stability = Stability::default();
+
+        // Measured once per interval
+        assert!(!stability.update(10.0, 8.0, 0.5, true));
+        assert_eq!(stability.value, 1.0);
+        assert!(stability.update(0.0, 0.0, STABILITY_INTERVAL, true));
+        assert!((stability.value - 0.2).abs() < 1e-6);
+        assert_eq!(stability.breather, STABILITY_BREATHER_DURATION);
+
+        // No new breather until the cooldown is over
+        assert!(!stability.update(10.0, 8.0, STABILITY_INTERVAL, true));
+        stability.breather_cooldown = 0.0;
+        assert!(!stability.update(10.0, 8.0, STABILITY_INTERVAL, false));
+        assert!(stability.update(10.0, 8.0, STABILITY_INTERVAL, true));
+
+        // A steady stack
+        stability.update(10.0, 1.0, STABILITY_INTERVAL, true);
+        assert!((stability.value - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn new_pieces_are_damped_less_up_to_the_max_level() {
+        let physics = PhysicsTuning::default();
+
+        assert_eq!(physics.linear_damping_at(0), physics.linear_damping);
+        assert_eq!(physics.linear_damping_at(1), physics.linear_damping);
+        assert!(physics.linear_damping_at(2) < physics.linear_damping_at(1));
+        assert_eq!(
+            physics.linear_damping_at(LEVEL_DAMPING_MAX_LEVEL + 5),
+            physics.linear_damping_at(LEVEL_DAMPING_MAX_LEVEL)
+        );
+    }
+
+    #[test]
+    fn snapshot_diff_reports_moved_missing_and_extra_bodies() {
+        let reference = snapshot(vec![body(1, 0.0), body(2, 1.0), body(3, 2.0)]);
+
+        assert!(reference.diff(&reference, SNAPSHOT_DIFF_BODIES).is_empty());
+
+        let moved = snapshot(vec![body(1, 0.0), body(2, 1.5), body(3, 2.0)]);
+        let lines = moved.diff(&reference, SNAPSHOT_DIFF_BODIES);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("BodyState { ordinal: 2"), "{lines:?}");
+
+        let shuffled = snapshot(vec![body(1, 0.0), body(3, 2.0), body(4, 3.0)]);
+        let lines = shuffled.diff(&reference, SNAPSHOT_DIFF_BODIES);
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].starts_with("missing BodyState { ordinal: 2"),
+            "{lines:?}"
+        );
+        assert!(
+            lines[1].starts_with("extra BodyState { ordinal: 4"),
+            "{lines:?}"
+        );
+    }
+
+    #[test]
+    fn checksum_ignores_the_spawn_order_and_is_stable() {
+        let reference = checksum(&[body(1, 0.0), body(2, 1.0)]);
+
+        assert_eq!(checksum(&[body(3, 1.0), body(5, 0.0)]), reference);
+        assert_ne!(checksum(&[body(1, 0.0), body(2, 1.5)]), reference);
+        // Compared between machines, so never to change
+        assert_eq!(reference, 1836583856856208815);
+    }
+
+    #[test]
+    fn tapped_rotation_turns_the_piece_on_to_the_next_quarter_turn() {
+        let mut piece = ActivePiece::default();
+
+        assert_eq!(assisted_torque(&mut piece, 1.0, 0.0, 0.0, 0.05), 1.0);
+        let torque = assisted_torque(&mut piece, 0.0, 0.1, 0.5, 0.05);
+        assert_eq!(piece.snap_angle, Some(FRAC_PI_2));
+        assert!(torque > 0.0);
+
+        // Once there and still, the piece is let go
+        assert_eq!(assisted_torque(&mut piece, 0.0, FRAC_PI_2, 0.0, 0.05), 0.0);
+        assert_eq!(piece.snap_angle, None);
+    }
+
+    #[test]
+    fn held_rotation_spins_freely() {
+        let mut piece = ActivePiece::default();
+
+        for _ in 0..10 {
+            assert_eq!(assisted_torque(&mut piece, -1.0, 0.0, -2.0, 0.05), -1.0);
+        }
+        assert_eq!(assisted_torque(&mut piece, 0.0, -2.0, -2.0, 0.05), 0.0);
+        assert_eq!(piece.snap_angle, None);
+    }
+
+    // Steps on after a quarter of a second held, and then every tenth
+    const REPEAT: Option<(f32, f32)> = Some((0.25, 0.1));
+
+    #[test]
+    fn tapped_movement_targets_the_next_block_slot_over() {
+        let mut piece = ActivePiece::default();
+
+        assert!(assisted_movement(&mut piece, 1.0, 0.5, 0.5, 0.0, REPEAT, 0.05) > 0.0);
+        assert_eq!(piece.lane_target, Some(1.5));
+        assert!(assisted_movement(&mut piece, 0.0, 1.2, 1.5, 0.5, REPEAT, 0.05) > 0.0);
+        assert_eq!(piece.lane_target, Some(1.5));
+
+        // Once there and still, the piece is let go
+        assert_eq!(
+            assisted_movement(&mut piece, 0.0, 1.5, 1.5, 0.0, REPEAT, 0.05),
+            0.0
+        );
+        assert_eq!(piece.lane_target, None);
+    }
+
+    #[test]
+    fn held_movement_steps_the_target_on_after_the_repeat_delay() {
+        let mut piece = ActivePiece::default();
+
+        assisted_movement(&mut piece, -1.0, 0.5, 0.5, 0.0, REPEAT, 0.0);
+        assert_eq!(piece.lane_target, Some(-0.5));
+        assisted_movement(&mut piece, -1.0, 0.5, 0.5, 0.0, REPEAT, 0.125);
+        assert_eq!(piece.lane_target, Some(-0.5));
+        assisted_movement(&mut piece, -1.0, 0.5, 0.5, 0.0, REPEAT, 0.125 + 0.15);
+        assert_eq!(piece.lane_target, Some(-2.5));
+    }
+
+    #[test]
+    fn without_repeat_held_movement_pushes_freely_and_snaps_on_release() {
+        let mut piece = ActivePiece::default();
+
+        for _ in 0..10 {
+            assert_eq!(
+                assisted_movement(&mut piece, 1.0, 0.7, 0.5, 3.0, None, 0.05),
+                1.0
+            );
+        }
+        assert_eq!(piece.lane_target, None);
+
+        // Past the middle of its slot, it's headed to the next one
+        assisted_movement(&mut piece, 0.0, 0.7, 0.5, 3.0, None, 0.05);
+        assert_eq!(piece.lane_target, Some(1.5));
+    }
+}
@@ -0,0 +1,417 @@
+//! Shapes, joints, colors and spawn weights of the tetrominoes, defined in
+//! `assets/pieces/tetrominoes.pieces.ron` and loaded as an asset, so that they can be changed
+//! without recompiling. Until the file is loaded, or when it's invalid, the definitions built in
+//! from the same file are used. Piece sets of polyominoes are loaded the same way from
+//! `assets/pieces/*.set.ron`, and left out until they are loaded. A run keeps the definitions it
+//! started with, see `Board::piece_definitions`.
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::tetromino::{spanning_joints, TetrominoKind};
+
+/// Piece definitions, loaded from the assets.
+pub struct PiecesPlugin;
+
+impl Plugin for PiecesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<PieceDefinitions>()
+            .add_asset::<PieceSetDefinition>()
+            .insert_resource(PieceDefinitions::built_in())
+            .init_asset_loader::<PieceDefinitionsLoader>()
+            .init_asset_loader::<PieceSetLoader>()
+            .add_startup_system(load_piece_definitions)
+            .add_system(install_piece_definitions)
+            .add_system(install_piece_sets);
+    }
+}
+
+pub(crate) const PIECE_DEFINITIONS_PATH: &str = "pieces/tetrominoes.pieces.ron";
+pub(crate) const BUILT_IN_PIECE_DEFINITIONS: &str =
+    include_str!("../assets/pieces/tetrominoes.pieces.ron");
+// Piece sets, selectable after the built-in ones in this order
+pub(crate) const PIECE_SET_PATHS: [&str; 1] = ["pieces/pentominoes.set.ron"];
+
+/// How each kind of tetromino looks and is dealt. As a resource, the definitions runs are
+/// started with.
+#[derive(Resource, Clone, PartialEq, Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "6f1c3a52-8d0e-4b7a-9f61-2c4e5d7a8b90"]
+pub(crate) struct PieceDefinitions {
+    pub(crate) pieces: Vec<PieceDefinition>,
+    /// The piece sets of `PIECE_SET_PATHS` in the same order, `None` until loaded or when
+    /// invalid. Not part of the file.
+    #[serde(default)]
+    pub(crate) sets: Vec<Option<PieceSetDefinition>>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct PieceDefinition {
+    pub(crate) kind: TetrominoKind,
+    pub(crate) color: (u8, u8, u8),
+    /// Column and row of each block, rows going down from the top row of the board at 0
+    pub(crate) coords: Vec<(i32, i32)>,
+    /// Blocks joined together, by index in `coords`. Each block is joined to a neighbour when
+    /// left out, see `spanning_joints`.
+    #[serde(default)]
+    pub(crate) joints: Option<Vec<(usize, usize)>>,
+    /// Relative chance of being dealt
+    #[serde(default = "default_weight")]
+    pub(crate) weight: f32,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+/// A piece set file in `assets/pieces/`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "2d8b6e41-97c3-4f0a-b5e2-6a1d3c9f7e18"]
+pub(crate) struct PieceSetDefinition {
+    pub(crate) name: String,
+    pub(crate) pieces: Vec<PolyominoDefinition>,
+}
+
+/// A piece of any number of blocks, defined in a piece set file.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct PolyominoDefinition {
+    /// Glyph identifying the piece independently of color
+    pub(crate) letter: String,
+    pub(crate) color: (u8, u8, u8),
+    /// Column and row of each block, as in `TetrominoKind::layout`
+    pub(crate) coords: Vec<(i32, i32)>,
+}
+
+impl PieceSetDefinition {
+    /// Pieces with a letter and blocks, each block next to another, so that they hold together.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.pieces.is_empty() {
+            return Err(format!("{} has no pieces", self.name));
+        }
+        for piece in &self.pieces {
+            if piece.letter.is_empty() {
+                return Err(format!("a piece of {} has no letter", self.name));
+            }
+            if piece.coords.is_empty() {
+                return Err(format!("{} has no blocks", piece.letter));
+            }
+            if spanning_joints(&piece.coords).len() + 1 != piece.coords.len() {
+                return Err(format!(
+                    "the blocks of {} don't hold together",
+                    piece.letter
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PieceDefinitions {
+    pub(crate) fn built_in() -> Self {
+        ron::from_str(BUILT_IN_PIECE_DEFINITIONS).expect("invalid built-in piece definitions")
+    }
+
+    pub(crate) fn get(&self, kind: TetrominoKind) -> Option<&PieceDefinition> {
+        self.pieces.iter().find(|piece| piece.kind == kind)
+    }
+
+    /// A piece of the piece sets, by its index over all of them.
+    pub(crate) fn polyomino(&self, index: u16) -> Option<&PolyominoDefinition> {
+        self.sets
+            .iter()
+            .flatten()
+            .flat_map(|set| &set.pieces)
+            .nth(index as usize)
+    }
+
+    /// The kinds of the pieces of a piece set.
+    pub(crate) fn set_kinds(&self, index: usize) -> Option<Vec<TetrominoKind>> {
+        let set = self.sets.get(index)?.as_ref()?;
+        let first: usize = self.sets[..index]
+            .iter()
+            .flatten()
+            .map(|set| set.pieces.len())
+            .sum();
+        Some(
+            (first..first + set.pieces.len())
+                .map(|index| TetrominoKind::Polyomino(index as u16))
+                .collect(),
+        )
+    }
+
+    /// Every tetromino defined, with blocks, joints between its own blocks, and a weight.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        for kind in TetrominoKind::ALL {
+            let Some(piece) = self.get(kind) else {
+                return Err(format!("{} is not defined", kind.letter(self)));
+            };
+            if piece.coords.is_empty() {
+                return Err(format!("{} has no blocks", kind.letter(self)));
+            }
+            let n_blocks = piece.coords.len();
+            let joints_valid = piece
+                .joints
+                .iter()
+                .flatten()
+                .all(|(i, j)| *i < n_blocks && *j < n_blocks && i != j);
+            if !joints_valid {
+                return Err(format!(
+                    "{} joins blocks it doesn't have",
+                    kind.letter(self)
+                ));
+            }
+            if piece.weight.is_nan() || piece.weight < 0.0 {
+                return Err(format!("{} has a negative weight", kind.letter(self)));
+            }
+        }
+
+        if self.pieces.iter().map(|piece| piece.weight).sum::<f32>() <= 0.0 {
+            return Err("no piece has any weight".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Whether all tetrominoes are dealt alike.
+    pub(crate) fn uniform(&self) -> bool {
+        TetrominoKind::ALL
+            .iter()
+            .all(|kind| self.weight(*kind) == self.weight(TetrominoKind::ALL[0]))
+    }
+
+    /// Weight of a kind, 1.0 for polyominoes, which aren't defined here.
+    pub(crate) fn weight(&self, kind: TetrominoKind) -> f32 {
+        self.get(kind).map_or(1.0, |piece| piece.weight)
+    }
+
+    /// One of `kinds`, by their weights.
+    pub(crate) fn weighted(&self, kinds: &[TetrominoKind], rng: &mut impl Rng) -> TetrominoKind {
+        let total: f32 = kinds.iter().map(|kind| self.weight(*kind)).sum();
+        let mut draw = rng.gen::<f32>() * total;
+
+        for kind in kinds {
+            draw -= self.weight(*kind);
+            if draw < 0.0 {
+                return *kind;
+            }
+        }
+
+        kinds[kinds.len() - 1]
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PieceDefinitionsLoader;
+
+impl AssetLoader for PieceDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let definitions: PieceDefinitions = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["pieces.ron"]
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PieceSetLoader;
+
+impl AssetLoader for PieceSetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let set: PieceSetDefinition = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(set));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["set.ron"]
+    }
+}
+
+/// Kept to keep the definitions loaded, and reloaded when changed.
+#[derive(Resource)]
+pub(crate) struct PieceDefinitionsHandle(pub(crate) Handle<PieceDefinitions>);
+
+/// The piece sets of `PIECE_SET_PATHS`, in the same order.
+#[derive(Resource)]
+pub(crate) struct PieceSetHandles(pub(crate) Vec<Handle<PieceSetDefinition>>);
+
+pub(crate) fn load_piece_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(PieceDefinitionsHandle(
+        asset_server.load(PIECE_DEFINITIONS_PATH),
+    ));
+    commands.insert_resource(PieceSetHandles(
+        PIECE_SET_PATHS
+            .iter()
+            .map(|path| asset_server.load(*path))
+            .collect(),
+    ));
+}
+
+/// Use the piece definitions once loaded, and again whenever they change, for the runs started
+/// from then on.
+pub(crate) fn install_piece_definitions(
+    mut asset_events: EventReader<AssetEvent<PieceDefinitions>>,
+    assets: Res<Assets<PieceDefinitions>>,
+    definitions_handle: Option<Res<PieceDefinitionsHandle>>,
+    mut piece_definitions: ResMut<PieceDefinitions>,
+) {
+    for event in asset_events.iter() {
+        let (AssetEvent::Created { handle } | AssetEvent::Modified { handle }) = event else {
+            continue;
+        };
+        if !matches!(&definitions_handle, Some(loaded) if loaded.0 == *handle) {
+            continue;
+        }
+        let Some(definitions) = assets.get(handle) else {
+            continue;
+        };
+
+        match definitions.validate() {
+            Ok(()) => {
+                piece_definitions.pieces = definitions.pieces.clone();
+            }
+            Err(err) => warn!("ignoring {PIECE_DEFINITIONS_PATH}: {err}"),
+        }
+    }
+}
+
+/// Offer the piece sets that are loaded and valid, again whenever one changes, for the runs
+/// started from then on.
+pub(crate) fn install_piece_sets(
+    mut asset_events: EventReader<AssetEvent<PieceSetDefinition>>,
+    assets: Res<Assets<PieceSetDefinition>>,
+    set_handles: Option<Res<PieceSetHandles>>,
+    mut piece_definitions: ResMut<PieceDefinitions>,
+) {
+    if asset_events.iter().count() == 0 {
+        return;
+    }
+    let Some(set_handles) = set_handles else {
+        return;
+    };
+
+    piece_definitions.sets = set_handles
+        .0
+        .iter()
+        .zip(PIECE_SET_PATHS)
+        .map(|(handle, path)| {
+            let set = assets.get(handle)?;
+            match set.validate() {
+                Ok(()) => Some(set.clone()),
+                Err(err) => {
+                    warn!("ignoring {path}: {err}");
+                    None
+                }
+            }
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::GameRng;
+
+    #[test]
+    fn built_in_definitions_are_valid_and_deal_alike() {
+        let definitions = PieceDefinitions::built_in();
+
+        assert_eq!(definitions.validate(), Ok(()));
+        assert!(definitions.uniform());
+        for kind in TetrominoKind::ALL {
+            let piece = definitions.get(kind).unwrap();
+            assert_eq!(
+                Color::rgb_u8(piece.color.0, piece.color.1, piece.color.2),
+                kind.color(&definitions)
+            );
+        }
+    }
+
+    #[test]
+    fn definitions_missing_a_kind_or_joining_blocks_they_lack_are_invalid() {
+        let mut missing = PieceDefinitions::built_in();
+        missing
+            .pieces
+            .retain(|piece| piece.kind != TetrominoKind::T);
+        assert_eq!(missing.validate(), Err("T is not defined".to_string()));
+
+        let mut bad_joints = PieceDefinitions::built_in();
+        bad_joints.pieces[0].joints = Some(vec![(0, 4)]);
+        assert!(bad_joints.validate().is_err());
+
+        let mut negative = PieceDefinitions::built_in();
+        negative.pieces[0].weight = -1.0;
+        assert!(negative.validate().is_err());
+
+        let mut weightless = PieceDefinitions::built_in();
+        for piece in &mut weightless.pieces {
+            piece.weight = 0.0;
+        }
+        assert!(weightless.validate().is_err());
+    }
+
+    #[test]
+    fn piece_sets_with_pieces_that_fall_apart_are_invalid() {
+        let piece = |coords: Vec<(i32, i32)>| PolyominoDefinition {
+            letter: "X".to_string(),
+            color: (255, 255, 255),
+            coords,
+        };
+        let mut set = PieceSetDefinition {
+            name: "Test".to_string(),
+            pieces: vec![piece(vec![(0, 0), (1, 0), (1, -1)])],
+        };
+        assert_eq!(set.validate(), Ok(()));
+
+        set.pieces.push(piece(vec![(0, 0), (2, 0)]));
+        assert!(set.validate().is_err());
+        set.pieces = vec![];
+        assert!(set.validate().is_err());
+    }
+
+    #[test]
+    fn kinds_are_dealt_by_their_weights() {
+        let mut definitions = PieceDefinitions::built_in();
+        for piece in &mut definitions.pieces {
+            piece.weight = match piece.kind {
+                TetrominoKind::I => 3.0,
+                TetrominoKind::O => 1.0,
+                _ => 0.0,
+            };
+        }
+        let mut rng = GameRng::new(Some(0));
+
+        let kinds: Vec<TetrominoKind> = (0..1000)
+            .map(|_| definitions.weighted(&TetrominoKind::ALL, &mut rng))
+            .collect();
+        let lines = kinds
+            .iter()
+            .filter(|kind| **kind == TetrominoKind::I)
+            .count();
+
+        assert!(!definitions.uniform());
+        assert!(kinds
+            .iter()
+            .all(|kind| matches!(kind, TetrominoKind::I | TetrominoKind::O)));
+        assert!((700..800).contains(&lines), "{lines}");
+    }
+}
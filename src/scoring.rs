@@ -0,0 +1,3190 @@
+//! Points, health, grades, results and the records of past runs.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::f32::consts::{SQRT_2, TAU};
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::tasks::{IoTaskPool, Task};
+use bevy::window::PrimaryWindow;
+use bevy_rapier2d::prelude::*;
+use futures_lite::future;
+use image::{Rgba, RgbaImage};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{start_run, Board, FixedSeed, GameRng, InsetCamera, RowClearing, RunStarted};
+use crate::custom::CustomGame;
+use crate::effects::start_clear_flash;
+use crate::input::{update_action_state, Action, ActionState, ControlPreset, InputMap};
+use crate::modes::{DrillRecord, GameMode, Junk, JUNK_COLOR};
+use crate::physics::{tetromino_movement, GameTimeScale, TimeScaleSource};
+use crate::pieces::PieceDefinitions;
+use crate::settings::{load_ron, project_dirs, save_ron, Friend, Settings};
+use crate::slicing::{slice_block, spawn_fragment, Fragment};
+use crate::tetromino::{
+    tetromino_sleep_detection, ActivePieces, Block, TetrominoKind, FUSE_PENALTY_POINTS,
+};
+use crate::ui::{
+    menu_navigation, spawn_sized_toast, spawn_toast, Fonts, Language, Menu, MenuCancelled,
+    MenuConfirmed, MenuItem, CLEAN_FEED_SCORE_FONT_SIZE, CLEAN_FEED_TOAST_FONT_SIZE,
+    WORLD_TEXT_FONT_SIZE,
+};
+use crate::versus::GarbageQueue;
+use crate::{AppState, GameplaySet};
+
+/// Scoring HUD, health, results, highlights and the run history.
+pub struct ScoringPlugin;
+
+impl Plugin for ScoringPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Profile::load_or_first_run())
+            .add_event::<RowCleared>()
+            .add_system(announce_cascades.after(tetromino_sleep_detection))
+            .add_system(toggle_history.after(results_screen))
+            .add_system(history_input.after(menu_navigation))
+            .add_system(
+                update_history_list
+                    .after(toggle_history)
+                    .after(history_input),
+            )
+            .add_system(
+                score_hud
+                    .after(tetromino_movement)
+                    .after(tetromino_sleep_detection),
+            )
+            .add_system(update_health_bar)
+            .add_system(health_warning)
+            .add_system(track_run_time.in_set(GameplaySet))
+            .add_system(update_pace_indicator.after(track_run_time))
+            .add_system(count_attempts)
+            .add_system(results_screen)
+            .add_system(
+                results_restart_prompt
+                    .after(results_screen)
+                    .after(update_action_state),
+            )
+            .add_system(
+                high_score_initials_entry
+                    .after(results_restart_prompt)
+                    .after(update_action_state),
+            )
+            .add_system(export_share_card.after(results_screen))
+            .add_system(grade_stamp_animation)
+            .add_system(finale_playback.before(results_screen))
+            .add_system(record_highlights.in_set(GameplaySet))
+            .add_system(highlight_replay.after(results_screen))
+            .add_system(highlight_timeline_click.before(highlight_replay))
+            .add_system(friends_comparison.after(results_screen));
+    }
+}
+
+// In terms of block size:
+pub(crate) const HEALTH_BAR_HEIGHT: f32 = 0.5;
+
+pub(crate) const HEALTH_BAR_EASING: f32 = 6.0;
+
+// Health warnings, shown as a pulse around the edges of the screen:
+pub(crate) const HEALTH_LOW_THRESHOLD: f32 = 0.5;
+pub(crate) const HEALTH_CRITICAL_THRESHOLD: f32 = 0.25;
+// Health has to climb this much above a threshold to count as recovered, so warnings don't flicker
+pub(crate) const HEALTH_RECOVERY_MARGIN: f32 = 0.05;
+pub(crate) const HEALTH_EDGE_WIDTH: f32 = 24.0;
+pub(crate) const HEALTH_LOW_PULSE_PERIOD: f32 = 1.5;
+pub(crate) const HEALTH_HEARTBEAT_PERIOD: f32 = 0.8;
+// Share of the heartbeat period at which the first beat peaks, and the heartbeat sounds
+pub(crate) const HEALTH_HEARTBEAT_PHASE: f32 = 0.1;
+pub(crate) const HEALTH_HEARTBEAT_SOUND_PATH: &str = "sounds/heartbeat.wav";
+pub(crate) const HEALTH_RECOVERED_FLASH_DURATION: f32 = 0.6;
+
+// Finale celebrating a run that ended with this grade or better, before the results
+pub(crate) const FINALE_MIN_GRADE: Grade = Grade::A;
+// In scaled seconds:
+pub(crate) const FINALE_DURATION: f32 = 2.0;
+pub(crate) const FINALE_ZOOM: f32 = 0.6;
+pub(crate) const CONFETTI_COUNT: usize = 120;
+pub(crate) const CONFETTI_GRAVITY: f32 = 12.0;
+pub(crate) const CONFETTI_DRAG: f32 = 1.5;
+// In seconds:
+pub(crate) const CONFETTI_LIFETIME: f32 = 3.0;
+pub(crate) const FINALE_SOUND_PATH: &str = "sounds/fireworks.wav";
+
+pub(crate) const POINTS_PER_CLEARED_BLOCK: i32 = 10;
+// Points for cleared rows are multiplied by this, by the number of rows cleared together (capped)
+pub(crate) const MULTI_ROW_MULTIPLIERS: [f32; 4] = [1.0, 1.5, 2.0, 3.0];
+// Cleared blocks of partial rows are worth this share of the points, bonuses included
+pub(crate) const PARTIAL_ROW_POINTS_FACTOR: f32 = 0.5;
+// For each row of distance a piece falls while soft dropping
+pub(crate) const SOFT_DROP_POINTS_PER_ROW: f32 = 1.0;
+pub(crate) const ROWS_PER_LEVEL: usize = 10;
+
+// Elo rating of a new player, and the range ratings are kept in, received ones clamped to
+pub(crate) const RATING_INITIAL: f32 = 1200.0;
+pub(crate) const RATING_MIN: f32 = 0.0;
+pub(crate) const RATING_MAX: f32 = 4000.0;
+// The most a single match can move a rating
+pub(crate) const RATING_K_FACTOR: f32 = 32.0;
+
+// Rows completed this long after a clear continue its chain, in scaled seconds
+pub(crate) const CASCADE_WINDOW: f32 = 3.0;
+
+pub(crate) const GRADE_STAMP_DURATION: f32 = 0.35;
+// Real seconds the results screen waits before starting the next run by itself
+pub(crate) const RESTART_COUNTDOWN: f32 = 15.0;
+// Real seconds before the results screen takes input, so that a last hard drop doesn't skip it
+pub(crate) const RESTART_INPUT_DELAY: f32 = 1.0;
+pub(crate) const GRADE_STAMP_START_SCALE: f32 = 3.0;
+
+// Share card layout, in pixels
+pub(crate) const SHARE_CARD_BLOCK_PX: f32 = 20.0;
+pub(crate) const SHARE_CARD_MARGIN: u32 = 16;
+pub(crate) const SHARE_CARD_HEADER: u32 = 140;
+
+pub(crate) const HIGHLIGHT_SAMPLE_INTERVAL: f32 = 0.05;
+pub(crate) const HIGHLIGHT_DURATION: f32 = 15.0;
+pub(crate) const HIGHLIGHT_INSET_SCALE: f32 = 0.3;
+// Timeline below the highlight inset, in board units before the inset is scaled down
+pub(crate) const HIGHLIGHT_TIMELINE_GAP: f32 = 1.0;
+pub(crate) const HIGHLIGHT_TIMELINE_HEIGHT: f32 = 0.3;
+pub(crate) const HIGHLIGHT_MARKER_SIZE: Vec2 = Vec2::new(0.4, 1.5);
+// Clicks this close to a timeline marker (in world units) jump to it
+pub(crate) const HIGHLIGHT_MARKER_CLICK_RADIUS: f32 = 0.4;
+
+// Oldest runs are dropped from the history beyond this
+pub(crate) const HISTORY_MAX_RUNS: usize = 500;
+pub(crate) const HISTORY_VISIBLE_RUNS: usize = 15;
+// Blocks of a kind played over the history before it can be the nemesis
+pub(crate) const NEMESIS_MIN_BLOCKS: i32 = 80;
+pub(crate) const HIGH_SCORE_COUNT: usize = 10;
+pub(crate) const INITIALS_LENGTH: usize = 3;
+// Cycled through with up and down when entering initials
+pub(crate) const INITIALS_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Statistics of the current run on a board.
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct BoardStats {
+    pub(crate) generated_blocks: i32,
+    pub(crate) cleared_blocks: i32,
+    pub(crate) lost_blocks: i32,
+    pub(crate) placed_blocks: i32,
+    pub(crate) placement_precision_sum: f32,
+    /// Scaled seconds since the run started
+    pub(crate) elapsed: f32,
+    /// Time of each cleared row, in the order they were cleared
+    pub(crate) clear_times: Vec<f32>,
+    /// Pieces force-settled by the fuse modifier
+    pub(crate) fuse_penalties: i32,
+    /// Bonus on top of the cleared blocks, for rows completed in cascades
+    pub(crate) cascade_points: i32,
+    /// Bonus for completing a drill, more the fewer pieces were needed
+    pub(crate) drill_points: i32,
+    /// Bonus on top of the cleared blocks, for rows cleared together
+    pub(crate) multi_row_points: i32,
+    /// Taken off the points of the cleared blocks, for rows that were cleared as partial rows
+    pub(crate) partial_row_deductions: i32,
+    /// Distance pieces have fallen while soft dropping
+    pub(crate) soft_drop_distance: f32,
+    /// Junk blocks dropped onto the board in Recycle mode
+    pub(crate) junk_spawned: i32,
+    /// Junk blocks gone for good, by being part of a cleared row
+    pub(crate) junk_cleared: i32,
+    /// Garbage rows sent to the opponent in Versus mode, not counting those cancelled out
+    pub(crate) garbage_sent: i32,
+    /// Garbage rows dropped onto the board by the opponent in Versus mode
+    pub(crate) garbage_received: i32,
+    pub(crate) generated_by_kind: HashMap<TetrominoKind, i32>,
+    /// Blocks of pieces lost, not counting junk and drill rows
+    pub(crate) lost_by_kind: HashMap<TetrominoKind, i32>,
+    /// Seed of the run, to reproduce it with `--seed`
+    pub(crate) seed: u64,
+    pub(crate) game_over_duration: Option<f32>,
+}
+
+impl BoardStats {
+    pub(crate) fn score(&self) -> i32 {
+        self.cleared_blocks * POINTS_PER_CLEARED_BLOCK
+            + self.cascade_points
+            + self.drill_points
+            + self.multi_row_points
+            - self.partial_row_deductions
+            + (self.soft_drop_distance * SOFT_DROP_POINTS_PER_ROW) as i32
+            - self.fuse_penalties * FUSE_PENALTY_POINTS
+    }
+
+    pub(crate) fn rows_cleared(&self) -> usize {
+        self.clear_times.len()
+    }
+
+    /// Goes up every `ROWS_PER_LEVEL` cleared rows, starting from 1.
+    pub(crate) fn level(&self) -> usize {
+        1 + self.rows_cleared() / ROWS_PER_LEVEL
+    }
+
+    pub(crate) fn lost_ratio(&self) -> f32 {
+        if self.generated_blocks == 0 {
+            0.0
+        } else {
+            self.lost_blocks as f32 / self.generated_blocks as f32
+        }
+    }
+
+    /// Record how precisely a block came to rest, in the range `0.0..=1.0`.
+    pub(crate) fn record_placement(&mut self, precision: f32) {
+        self.placed_blocks += 1;
+        self.placement_precision_sum += precision;
+    }
+
+    /// Average placement precision, 1.0 being perfectly lane-aligned and upright blocks.
+    pub(crate) fn placement_precision(&self) -> f32 {
+        if self.placed_blocks == 0 {
+            0.0
+        } else {
+            self.placement_precision_sum / self.placed_blocks as f32
+        }
+    }
+
+    pub(crate) fn grade(&self, mode: GameMode) -> Grade {
+        mode.grade_thresholds()
+            .iter()
+            .find(|threshold| {
+                self.score() >= threshold.min_score
+                    && self.lost_ratio() <= threshold.max_lost_ratio
+                    && self.placement_precision() >= threshold.min_precision
+            })
+            .map(|threshold| threshold.grade)
+            .unwrap_or(Grade::D)
+    }
+
+    pub(crate) fn health(&self) -> f32 {
+        if self.game_over_duration.is_some() {
+            0.0
+        } else if self.cleared_blocks == 0 {
+            if self.lost_blocks > 0 {
+                0.0
+            } else {
+                1.0
+            }
+        } else {
+            let lost_ratio = self.lost_blocks as f32 / self.cleared_blocks as f32;
+
+            1.0 - lost_ratio
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub(crate) enum Grade {
+    S,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Grade {
+    pub(crate) fn letter(&self) -> &'static str {
+        match self {
+            Self::S => "S",
+            Self::A => "A",
+            Self::B => "B",
+            Self::C => "C",
+            Self::D => "D",
+        }
+    }
+
+    pub(crate) fn color(&self) -> Color {
+        match self {
+            Self::S => Color::rgb_u8(255, 215, 0),
+            Self::A => Color::rgb_u8(0, 247, 0),
+            Self::B => Color::rgb_u8(0, 244, 243),
+            Self::C => Color::rgb_u8(252, 157, 0),
+            Self::D => Color::rgb_u8(255, 0, 0),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GradeThreshold {
+    pub(crate) grade: Grade,
+    pub(crate) min_score: i32,
+    pub(crate) max_lost_ratio: f32,
+    pub(crate) min_precision: f32,
+}
+
+/// Block transform, as recorded for highlights and sent in co-op snapshots.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct HighlightBlock {
+    pub(crate) position: Vec2,
+    pub(crate) angle: f32,
+    pub(crate) size: f32,
+    pub(crate) color: Color,
+}
+
+/// Something that happened during a highlight frame, marked on the replay timeline.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum HighlightMarker {
+    Spawn,
+    Clear,
+    Loss,
+}
+
+impl HighlightMarker {
+    pub(crate) fn color(&self) -> Color {
+        match self {
+            Self::Spawn => Color::rgb_u8(0, 244, 243),
+            Self::Clear => Color::rgb_u8(0, 247, 0),
+            Self::Loss => Color::rgb_u8(247, 0, 0),
+        }
+    }
+}
+
+/// Rolling buffer of the last seconds of block transforms on a board, replayed at game over.
+#[derive(Component, Default)]
+pub(crate) struct HighlightBuffer {
+    pub(crate) frames: VecDeque<Vec<HighlightBlock>>,
+    /// Markers of each frame, in step with `frames`
+    pub(crate) markers: VecDeque<Vec<HighlightMarker>>,
+    pub(crate) since_last_sample: f32,
+    /// Generated blocks, cleared rows and lost blocks at the last sample
+    pub(crate) last_counts: (i32, usize, i32),
+}
+
+impl HighlightBuffer {
+    pub(crate) fn max_frames() -> usize {
+        (HIGHLIGHT_DURATION / HIGHLIGHT_SAMPLE_INTERVAL) as usize
+    }
+
+    pub(crate) fn push(&mut self, frame: Vec<HighlightBlock>, markers: Vec<HighlightMarker>) {
+        if self.frames.len() >= Self::max_frames() {
+            self.frames.pop_front();
+            self.markers.pop_front();
+        }
+        self.frames.push_back(frame);
+        self.markers.push_back(markers);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+        self.markers.clear();
+        self.since_last_sample = 0.0;
+        self.last_counts = (0, 0, 0);
+    }
+}
+
+/// Persistent player profile, stored in the platform data directory.
+#[derive(Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Profile {
+    pub(crate) language: Language,
+    pub(crate) control_preset: ControlPreset,
+    pub(crate) assist_mode: bool,
+    pub(crate) show_onboarding: bool,
+    pub(crate) attempts: HashMap<GameMode, u32>,
+    pub(crate) best_runs: HashMap<GameMode, BestRun>,
+    /// Every completed run, oldest first
+    pub(crate) history: Vec<RunRecord>,
+    /// By drill template name
+    pub(crate) drills: HashMap<String, DrillRecord>,
+    /// Across all modes, highest first
+    pub(crate) high_scores: Vec<HighScore>,
+    /// Last entered for a high score, to start from next time
+    pub(crate) initials: String,
+    /// Saved from the custom game menu, in the order first saved
+    pub(crate) custom_games: Vec<CustomGame>,
+    /// Elo rating on the online ladder, kept locally
+    pub(crate) rating: f32,
+    /// Tells the player apart from others online, see `opponent_ratings`
+    pub(crate) player_id: u64,
+    /// Ratings of the opponents played online, by their `player_id`. Worked out here from the
+    /// matches played against them, as their own word on it can't be trusted.
+    pub(crate) opponent_ratings: HashMap<u64, f32>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct BestRun {
+    pub(crate) score: i32,
+    pub(crate) attempt: u32,
+    #[serde(default)]
+    pub(crate) clear_times: Vec<f32>,
+}
+
+impl BestRun {
+    /// Rows this run had cleared at the given time.
+    pub(crate) fn rows_cleared_at(&self, elapsed: f32) -> usize {
+        self.clear_times.partition_point(|time| *time <= elapsed)
+    }
+}
+
+/// Entry of the high score table.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct HighScore {
+    pub(crate) score: i32,
+    pub(crate) rows: usize,
+    /// Seconds since the Unix epoch
+    pub(crate) date: u64,
+    pub(crate) initials: String,
+    /// Unique in the table, unlike the date of runs ending in the same second. Not recorded by
+    /// older versions.
+    #[serde(default)]
+    pub(crate) id: u64,
+}
+
+/// Summary of a completed run, for the history screen.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RunRecord {
+    pub(crate) mode: GameMode,
+    pub(crate) attempt: u32,
+    pub(crate) score: i32,
+    /// Scaled seconds
+    pub(crate) duration: f32,
+    pub(crate) grade: Grade,
+    /// Seconds since the Unix epoch
+    pub(crate) date: u64,
+    /// Practiced kind, for a run of a nemesis practice session
+    #[serde(default)]
+    pub(crate) practice: Option<TetrominoKind>,
+    #[serde(default)]
+    pub(crate) generated_by_kind: HashMap<TetrominoKind, i32>,
+    #[serde(default)]
+    pub(crate) lost_by_kind: HashMap<TetrominoKind, i32>,
+    /// Seed of the run, to replay it from the history screen. Not recorded by older versions.
+    #[serde(default)]
+    pub(crate) seed: Option<u64>,
+    /// The piece definitions of the run, to replay it with, when not the built-in ones. With
+    /// the piece sets only when the run dealt their pieces.
+    #[serde(default)]
+    pub(crate) piece_definitions: Option<PieceDefinitions>,
+}
+
+impl RunRecord {
+    pub(crate) fn new(board: &Board, attempt: u32, stats: &BoardStats) -> Self {
+        let mode = board.mode;
+        let mut piece_definitions = board.piece_definitions.clone();
+        let polyominoes = stats
+            .generated_by_kind
+            .keys()
+            .any(|kind| matches!(kind, TetrominoKind::Polyomino(_)));
+        if !polyominoes {
+            piece_definitions.sets.clear();
+        }
+
+        Self {
+            mode,
+            attempt,
+            score: stats.score(),
+            duration: stats.elapsed,
+            grade: stats.grade(mode),
+            date: unix_now(),
+            practice: board.practice,
+            generated_by_kind: stats.generated_by_kind.clone(),
+            lost_by_kind: stats.lost_by_kind.clone(),
+            seed: Some(stats.seed),
+            piece_definitions: (piece_definitions != PieceDefinitions::built_in())
+                .then_some(piece_definitions),
+        }
+    }
+
+    /// The piece definitions the run was played with.
+    pub(crate) fn piece_definitions(&self) -> PieceDefinitions {
+        self.piece_definitions
+            .clone()
+            .unwrap_or_else(PieceDefinitions::built_in)
+    }
+
+    pub(crate) fn mode_name(&self) -> String {
+        match self.practice {
+            Some(kind) => format!("{} practice", kind.letter(&self.piece_definitions())),
+            None => self.mode.name().to_string(),
+        }
+    }
+
+    /// How long ago the run was played, e.g. "3d ago".
+    pub(crate) fn age(&self, now: u64) -> String {
+        age(self.date, now)
+    }
+}
+
+/// Seconds since the Unix epoch.
+#[cfg(not(feature = "wasm"))]
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+/// Seconds since the Unix epoch. There is no system clock in the browser.
+#[cfg(feature = "wasm")]
+pub(crate) fn unix_now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// How long ago `date` was, in seconds since the Unix epoch, e.g. "3d ago".
+pub(crate) fn age(date: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(date);
+
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+/// A rating after a match against an opponent of the given rating, see `Profile::rate_match`.
+pub(crate) fn elo_rating(rating: f32, opponent_rating: f32, result: f32) -> f32 {
+    let expected = 1.0 / (1.0 + 10f32.powf((opponent_rating - rating) / 400.0));
+    (rating + RATING_K_FACTOR * (result - expected)).clamp(RATING_MIN, RATING_MAX)
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            language: Language::default(),
+            control_preset: ControlPreset::default(),
+            assist_mode: false,
+            show_onboarding: true,
+            attempts: HashMap::new(),
+            best_runs: HashMap::new(),
+            history: vec![],
+            drills: HashMap::new(),
+            high_scores: vec![],
+            initials: "AAA".to_string(),
+            custom_games: vec![],
+            rating: RATING_INITIAL,
+            player_id: rand::random(),
+            opponent_ratings: HashMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    pub(crate) fn path() -> Option<PathBuf> {
+        project_dirs().map(|dirs| dirs.data_dir().join("profile.ron"))
+    }
+
+    /// Load the profile. On first launch (no profile file), new players start in Assist Mode.
+    pub(crate) fn load_or_first_run() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        load_ron(&path).unwrap_or_else(|| Self {
+            assist_mode: true,
+            ..Self::default()
+        })
+    }
+
+    pub(crate) fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_ron(&path, self);
+        }
+    }
+
+    /// Update the Elo rating from a match against an opponent of the given rating. `result` is 1
+    /// for a win, 0.5 for a draw and 0 for a loss.
+    pub(crate) fn rate_match(&mut self, opponent_rating: f32, result: f32) {
+        self.rating = elo_rating(self.rating, opponent_rating, result);
+    }
+
+    /// The rating of an opponent met online, starting out like any new player's.
+    pub(crate) fn opponent_rating(&self, player_id: u64) -> f32 {
+        self.opponent_ratings
+            .get(&player_id)
+            .copied()
+            .unwrap_or(RATING_INITIAL)
+    }
+
+    /// Rate an online match for both players, see `rate_match`.
+    pub(crate) fn rate_online_match(&mut self, opponent_id: u64, result: f32) {
+        let rating = self.rating;
+        let opponent_rating = self.opponent_rating(opponent_id);
+        self.rate_match(opponent_rating, result);
+        self.opponent_ratings.insert(
+            opponent_id,
+            elo_rating(opponent_rating, rating, 1.0 - result),
+        );
+    }
+
+    /// The kind of piece the player loses the largest share of the blocks of, over the history,
+    /// with that share. Only kinds with enough blocks played are considered.
+    pub(crate) fn nemesis(&self) -> Option<(TetrominoKind, f32)> {
+        TetrominoKind::ALL
+            .into_iter()
+            .filter_map(|kind| {
+                let generated: i32 = self
+                    .history
+                    .iter()
+                    .filter_map(|run| run.generated_by_kind.get(&kind))
+                    .sum();
+                let lost: i32 = self
+                    .history
+                    .iter()
+                    .filter_map(|run| run.lost_by_kind.get(&kind))
+                    .sum();
+
+                (generated >= NEMESIS_MIN_BLOCKS && lost > 0)
+                    .then(|| (kind, lost as f32 / generated as f32))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    pub(crate) fn record_run(&mut self, record: RunRecord) {
+        self.history.push(record);
+
+        let excess = self.history.len().saturating_sub(HISTORY_MAX_RUNS);
+        self.history.drain(..excess);
+    }
+
+    /// Add a score to the high score table if it makes it in, returning its rank from 0.
+    /// It is given an id no other entry has.
+    pub(crate) fn record_high_score(&mut self, mut high_score: HighScore) -> Option<usize> {
+        let rank = self
+            .high_scores
+            .partition_point(|entry| entry.score >= high_score.score);
+        if high_score.score <= 0 || rank >= HIGH_SCORE_COUNT {
+            return None;
+        }
+
+        high_score.id = self
+            .high_scores
+            .iter()
+            .map(|entry| entry.id + 1)
+            .max()
+            .unwrap_or(0);
+        self.high_scores.insert(rank, high_score);
+        self.high_scores.truncate(HIGH_SCORE_COUNT);
+        Some(rank)
+    }
+
+    /// The high score table, one line per entry.
+    pub(crate) fn high_score_lines(&self) -> Vec<String> {
+        let now = unix_now();
+
+        self.high_scores
+            .iter()
+            .enumerate()
+            .map(|(rank, entry)| {
+                format!(
+                    "{:>2}. {}   {} points   {} rows   {}",
+                    rank + 1,
+                    entry.initials,
+                    entry.score,
+                    entry.rows,
+                    age(entry.date, now)
+                )
+            })
+            .collect()
+    }
+}
+
+/// Sent for each cleared row. `chain` is 1 for rows cleared when a piece settles,
+/// and counts up for rows completed by blocks sliding or falling in shortly after.
+pub(crate) struct RowCleared {
+    pub(crate) board: Entity,
+    pub(crate) blocks: usize,
+    pub(crate) chain: u32,
+}
+
+/// How worrying the health of a board is, from best to worst.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub(crate) enum HealthLevel {
+    #[default]
+    Healthy,
+    Low,
+    Critical,
+}
+
+impl HealthLevel {
+    pub(crate) fn at(health: f32, low_threshold: f32, critical_threshold: f32) -> Self {
+        if health <= critical_threshold {
+            Self::Critical
+        } else if health <= low_threshold {
+            Self::Low
+        } else {
+            Self::Healthy
+        }
+    }
+
+    /// The level following `self` for the given health. Getting worse is immediate,
+    /// getting better needs a margin above the threshold.
+    pub(crate) fn next(&self, health: f32) -> Self {
+        let level = Self::at(health, HEALTH_LOW_THRESHOLD, HEALTH_CRITICAL_THRESHOLD);
+
+        if level >= *self {
+            level
+        } else {
+            Self::at(
+                health,
+                HEALTH_LOW_THRESHOLD + HEALTH_RECOVERY_MARGIN,
+                HEALTH_CRITICAL_THRESHOLD + HEALTH_RECOVERY_MARGIN,
+            )
+            .min(*self)
+        }
+    }
+}
+
+/// Health warning state of a board.
+#[derive(Component, Default)]
+pub(crate) struct HealthWarning {
+    pub(crate) level: HealthLevel,
+    /// Seconds left of the flash shown when health recovers
+    pub(crate) recovered_flash: f32,
+    /// Whether the heartbeat has sounded in the current heartbeat period
+    pub(crate) heartbeat_sounded: bool,
+}
+
+impl HealthWarning {
+    /// Whether the heartbeat sounds now, once per heartbeat period while health is critical.
+    /// `phase` is how far into the heartbeat period the time is, `0.0..1.0`.
+    pub(crate) fn heartbeat(&mut self, phase: f32) -> bool {
+        if self.level != HealthLevel::Critical || phase < HEALTH_HEARTBEAT_PHASE {
+            self.heartbeat_sounded = false;
+            false
+        } else if self.heartbeat_sounded {
+            false
+        } else {
+            self.heartbeat_sounded = true;
+            true
+        }
+    }
+}
+
+/// Chain of row clears triggering further row clears on a board.
+#[derive(Component, Default)]
+pub(crate) struct CascadeChain {
+    pub(crate) chain: u32,
+    /// Scaled seconds left for the next link of the chain
+    pub(crate) remaining: f32,
+}
+
+#[derive(Component)]
+pub(crate) struct HealthBar {
+    pub(crate) board: Entity,
+    pub(crate) value: f32,
+}
+
+/// Ahead/behind delta against the personal best run, next to the health bar.
+#[derive(Component)]
+pub(crate) struct PaceIndicator {
+    pub(crate) board: Entity,
+}
+
+/// Score, level and cleared rows of a board.
+#[derive(Component)]
+pub(crate) struct ScoreHud {
+    pub(crate) board: Entity,
+}
+
+/// One of the screen edges pulsing when the health of a board is low.
+#[derive(Component)]
+pub(crate) struct HealthEdge {
+    pub(crate) board: Entity,
+}
+
+#[derive(Component)]
+pub(crate) struct ResultsScreen {
+    pub(crate) board: Entity,
+    /// Until the next run starts without input
+    pub(crate) countdown: Timer,
+}
+
+/// Line of the results screen prompting to play again, counting down.
+#[derive(Component)]
+pub(crate) struct RestartPrompt {
+    pub(crate) board: Entity,
+}
+
+/// Line of the results screen entering initials for a new high score. Confirming moves on to
+/// the next letter, and saves them from the last.
+#[derive(Component)]
+pub(crate) struct InitialsEntry {
+    pub(crate) board: Entity,
+    pub(crate) rank: usize,
+    /// Of the high score being named, which moves down the table as other boards' scores
+    /// make it in
+    pub(crate) id: u64,
+    pub(crate) initials: [char; INITIALS_LENGTH],
+    pub(crate) cursor: usize,
+}
+
+impl InitialsEntry {
+    pub(crate) fn text(&self) -> String {
+        let letters: String = self
+            .initials
+            .iter()
+            .enumerate()
+            .map(|(index, letter)| {
+                if index == self.cursor {
+                    format!("[{letter}]")
+                } else {
+                    format!(" {letter} ")
+                }
+            })
+            .collect();
+
+        format!("New high score, #{}! Initials: {letters}", self.rank + 1)
+    }
+}
+
+/// On a board whose results have been dismissed, starting its next run.
+#[derive(Component)]
+pub(crate) struct RestartRequested;
+
+/// Results screen line ranking the run against the friends' best runs in the mode,
+/// filled in once their profiles have been read.
+#[derive(Component)]
+pub(crate) struct FriendsComparison {
+    pub(crate) score: i32,
+    /// Name and best score of each friend with a run in the mode
+    pub(crate) task: Task<Vec<(String, i32)>>,
+}
+
+#[derive(Component)]
+pub(crate) struct GradeStamp {
+    pub(crate) timer: Timer,
+}
+
+/// Celebration of a run that ended well: the camera pans slowly up the final stack
+/// while confetti flies, then the results screen is shown.
+#[derive(Component)]
+pub(crate) struct Finale {
+    pub(crate) timer: Timer,
+    pub(crate) bottom_y: f32,
+    pub(crate) top_y: f32,
+}
+
+impl Finale {
+    pub(crate) fn is_playing(&self) -> bool {
+        !self.timer.finished()
+    }
+
+    /// Height the camera is panned to, easing in and out.
+    pub(crate) fn pan_y(&self) -> f32 {
+        let t = self.timer.percent();
+        self.bottom_y + (self.top_y - self.bottom_y) * t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct Confetti {
+    pub(crate) velocity: Vec2,
+    pub(crate) spin: f32,
+    pub(crate) timer: Timer,
+}
+
+#[derive(Component)]
+pub(crate) struct HistoryScreen;
+
+/// The list of runs on the history screen.
+#[derive(Component)]
+pub(crate) struct HistoryList;
+
+pub(crate) const HISTORY_LAYER: u32 = 5;
+pub(crate) const HISTORY_SORT_ITEM: usize = 0;
+pub(crate) const HISTORY_FILTER_ITEM: usize = 1;
+pub(crate) const HISTORY_PRACTICE_ITEM: usize = 2;
+pub(crate) const HISTORY_REPLAY_ITEM: usize = 3;
+pub(crate) const HISTORY_CLOSE_ITEM: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum HistorySort {
+    #[default]
+    Date,
+    Score,
+    Duration,
+}
+
+impl HistorySort {
+    pub(crate) const ALL: [Self; 3] = [Self::Date, Self::Score, Self::Duration];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Date => "date",
+            Self::Score => "score",
+            Self::Duration => "duration",
+        }
+    }
+
+    pub(crate) fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|sort| sort == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Sorting and filtering of the open history screen.
+#[derive(Resource, Default)]
+pub(crate) struct HistoryView {
+    pub(crate) sort: HistorySort,
+    /// Only runs of this mode are listed, all runs when `None`
+    pub(crate) mode: Option<GameMode>,
+}
+
+impl HistoryView {
+    pub(crate) fn sort_text(&self) -> String {
+        format!("Sort by: {}", self.sort.name())
+    }
+
+    pub(crate) fn filter_text(&self) -> String {
+        match self.mode {
+            Some(mode) => format!("Mode: {}", mode.name()),
+            None => "Mode: all".to_string(),
+        }
+    }
+
+    pub(crate) fn next_mode(&self) -> Option<GameMode> {
+        match self.mode {
+            None => Some(GameMode::ALL[0]),
+            Some(mode) if mode.next() == GameMode::ALL[0] => None,
+            Some(mode) => Some(mode.next()),
+        }
+    }
+
+    /// The listed runs, best first (newest first when sorting by date).
+    pub(crate) fn runs<'a>(&self, history: &'a [RunRecord]) -> Vec<&'a RunRecord> {
+        let mut runs: Vec<&RunRecord> = history
+            .iter()
+            .filter(|run| self.mode.is_none_or(|mode| run.mode == mode))
+            .collect();
+
+        match self.sort {
+            HistorySort::Date => runs.sort_by_key(|run| Reverse(run.date)),
+            HistorySort::Score => runs.sort_by_key(|run| Reverse(run.score)),
+            HistorySort::Duration => runs.sort_by(|a, b| b.duration.total_cmp(&a.duration)),
+        }
+
+        runs
+    }
+
+    /// The first listed run that can be replayed from its seed.
+    pub(crate) fn replay_run<'a>(&self, history: &'a [RunRecord]) -> Option<&'a RunRecord> {
+        self.runs(history)
+            .into_iter()
+            .find(|run| run.seed.is_some())
+    }
+
+    pub(crate) fn replay_text(&self, history: &[RunRecord]) -> String {
+        match self.replay_run(history) {
+            Some(run) => format!(
+                "Replay {} #{} (seed {})",
+                run.mode_name(),
+                run.attempt,
+                run.seed.unwrap_or_default()
+            ),
+            None => "Replay: no run with a seed yet".to_string(),
+        }
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct HighlightInset {
+    pub(crate) board: Entity,
+    pub(crate) frame: usize,
+    pub(crate) since_last_frame: f32,
+}
+
+#[derive(Component)]
+pub(crate) struct HighlightSprite {
+    pub(crate) inset: Entity,
+}
+
+/// Marker on the timeline of a highlight inset. Clicking it jumps to its frame.
+#[derive(Component)]
+pub(crate) struct HighlightTimelineMarker {
+    pub(crate) inset: Entity,
+    pub(crate) frame: usize,
+}
+
+/// Current position on the timeline of a highlight inset.
+#[derive(Component)]
+pub(crate) struct HighlightPlayhead {
+    pub(crate) inset: Entity,
+}
+
+/// Points for the blocks of a cleared row, before the cascade and multi-row bonuses.
+pub(crate) fn cleared_row_points(n_blocks: i32, partial: bool) -> f32 {
+    let points = (n_blocks * POINTS_PER_CLEARED_BLOCK) as f32;
+    if partial {
+        points * PARTIAL_ROW_POINTS_FACTOR
+    } else {
+        points
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn clear_filled_rows(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &Board,
+    stats: &mut BoardStats,
+    cascade: &mut CascadeChain,
+    block_query: &Query<(
+        Entity,
+        &Block,
+        &Transform,
+        &mut Sleeping,
+        &RapierRigidBodyHandle,
+    )>,
+    junk_query: &Query<(), With<Junk>>,
+    fragment_query: &Query<&Fragment>,
+    row_cleared_events: &mut EventWriter<RowCleared>,
+) -> BTreeSet<Entity> {
+    let rows = board.rows();
+    let mut blocks_per_row: Vec<(f32, BTreeSet<Entity>)> =
+        rows.iter().map(|_| (0.0, BTreeSet::new())).collect();
+
+    for (block_entity, block, transform, sleep, _) in block_query.iter() {
+        // Only sleeping blocks count.. So disregard blocks "falling off"
+        // that are in the row
+        if block.board != board_entity || !sleep.sleeping {
+            continue;
+        }
+
+        for (cell_center, area) in block.cells(transform) {
+            if let Some(row) = board.row_at(cell_center) {
+                blocks_per_row[row].0 += area;
+                blocks_per_row[row].1.insert(block_entity);
+            }
+        }
+    }
+
+    // Measured for clearing by coverage, and for partial rows
+    let covered_areas: Option<Vec<f32>> =
+        (matches!(board.row_clearing, RowClearing::Coverage { .. })
+            || board.partial_rows.is_some())
+        .then(|| {
+            let blocks: Vec<(Vec2, Quat, f32)> = block_query
+                .iter()
+                .filter(|(_, block, _, sleep, _)| block.board == board_entity && sleep.sleeping)
+                .map(|(_, block, transform, _, _)| {
+                    (
+                        transform.translation.truncate(),
+                        transform.rotation,
+                        block.size,
+                    )
+                })
+                .collect();
+            board.covered_row_areas(&rows, &blocks)
+        });
+
+    // Scoring goes by the cells in a row either way
+    let full_rows: Vec<(Rect, f32, BTreeSet<Entity>, bool)> = blocks_per_row
+        .into_iter()
+        .zip(rows.iter())
+        .enumerate()
+        .filter_map(|(index, ((area, row_blocks), row))| {
+            let covered_area = covered_areas.as_ref().map_or(0.0, |areas| areas[index]);
+            let filled_area = match board.row_clearing {
+                RowClearing::Cells => area,
+                RowClearing::Coverage { .. } => covered_area,
+            };
+
+            if board.row_is_full(row, filled_area) {
+                Some((*row, area, row_blocks, false))
+            } else if board.row_is_partial(row, covered_area) {
+                Some((*row, area, row_blocks, true))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if full_rows.is_empty() {
+        return BTreeSet::new();
+    }
+
+    // Rows cleared together are one link of the chain
+    cascade.chain = if cascade.remaining > 0.0 {
+        cascade.chain + 1
+    } else {
+        1
+    };
+    cascade.remaining = CASCADE_WINDOW;
+
+    let multiplier = MULTI_ROW_MULTIPLIERS[full_rows.len().min(MULTI_ROW_MULTIPLIERS.len()) - 1];
+
+    // A giant block may be part of several cleared rows. Ordered, so that the fragments of sliced
+    // blocks are spawned in the same order every time
+    let mut cleared_rows_per_block: BTreeMap<Entity, Vec<Rect>> = BTreeMap::new();
+
+    for (row, area, row_blocks, partial) in full_rows {
+        // Blocks are counted in unit areas, so a giant block counts as four
+        let n_blocks = area.round() as i32;
+
+        stats.cleared_blocks += n_blocks;
+        let points = cleared_row_points(n_blocks, partial);
+        if partial {
+            stats.partial_row_deductions +=
+                ((n_blocks * POINTS_PER_CLEARED_BLOCK) as f32 - points).round() as i32;
+        }
+        // The bonuses go by the points of the row, so partial rows earn less of them as well
+        stats.cascade_points += (points * (cascade.chain - 1) as f32).round() as i32;
+        stats.multi_row_points += (points * (multiplier - 1.0)).round() as i32;
+        let elapsed = stats.elapsed;
+        stats.clear_times.push(elapsed);
+
+        row_cleared_events.send(RowCleared {
+            board: board_entity,
+            blocks: row_blocks.len(),
+            chain: cascade.chain,
+        });
+
+        for block_entity in row_blocks {
+            cleared_rows_per_block
+                .entry(block_entity)
+                .or_default()
+                .push(row);
+        }
+    }
+
+    for (&block_entity, cleared_rows) in &cleared_rows_per_block {
+        let junk = junk_query.contains(block_entity);
+        if junk {
+            stats.junk_cleared += 1;
+        }
+
+        if let Ok((_, block, transform, _, _)) = block_query.get(block_entity) {
+            let color = if junk {
+                JUNK_COLOR
+            } else {
+                block.kind.color(&board.piece_definitions)
+            };
+
+            if board.slicing {
+                let fragment = fragment_query.get(block_entity).ok();
+                for polygon in slice_block(block, fragment, transform, cleared_rows) {
+                    spawn_fragment(commands, board_entity, board, block.kind, color, polygon);
+                }
+            }
+
+            start_clear_flash(commands, block_entity, color, block.size);
+        }
+    }
+
+    cleared_rows_per_block.into_keys().collect()
+}
+
+pub(crate) fn announce_cascades(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+    mut row_cleared_events: EventReader<RowCleared>,
+) {
+    let mut chain = 0;
+    let mut blocks = 0;
+
+    for event in row_cleared_events.iter() {
+        chain = chain.max(event.chain);
+        blocks += event.blocks;
+    }
+
+    if chain > 1 {
+        let font_size = if settings.clean_feed {
+            CLEAN_FEED_TOAST_FONT_SIZE
+        } else {
+            24.0
+        };
+        spawn_sized_toast(
+            &mut commands,
+            &fonts,
+            &format!("Cascade ×{chain}! {blocks} blocks"),
+            font_size,
+        );
+    }
+}
+
+/// The bar is a child of its board and anchored at its left end, so only its length changes here.
+pub(crate) fn update_health_bar(
+    board_query: Query<&BoardStats>,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut health_bar_query: Query<(&mut HealthBar, &mut Transform)>,
+) {
+    for (mut healthbar, mut transform) in health_bar_query.iter_mut() {
+        let Ok(stats) = board_query.get(healthbar.board) else {
+            continue;
+        };
+
+        let health = stats.health();
+
+        let delta = health - healthbar.value;
+        healthbar.value += delta * (HEALTH_BAR_EASING * time_scale.delta_seconds(&time)).min(1.0);
+
+        transform.scale.x = healthbar.value;
+    }
+}
+
+/// Escalating warnings as the health of a board gets low: a slow red pulse around the
+/// screen edges, then a heartbeat, heard as well as seen. Climbing back out flashes the edges
+/// green.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn health_warning(
+    mut commands: Commands,
+    time: Res<Time>,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    // Missing without audio, such as in simulations
+    audio: Option<Res<Audio>>,
+    mut board_query: Query<(&BoardStats, &mut HealthWarning)>,
+    mut edge_query: Query<(&HealthEdge, &mut BackgroundColor)>,
+) {
+    for (stats, mut warning) in board_query.iter_mut() {
+        let running = stats.game_over_duration.is_none() && stats.generated_blocks > 0;
+
+        // A new run starts out healthy, without celebrating it
+        if !running {
+            *warning = HealthWarning::default();
+            continue;
+        }
+
+        let level = warning.level.next(stats.health());
+        if level < warning.level {
+            warning.recovered_flash = HEALTH_RECOVERED_FLASH_DURATION;
+            spawn_toast(&mut commands, &fonts, "Recovered!");
+        }
+        warning.level = level;
+        warning.recovered_flash = (warning.recovered_flash - time.delta_seconds()).max(0.0);
+
+        // In step with the edges beating, see below
+        let phase = (time.elapsed_seconds() / HEALTH_HEARTBEAT_PERIOD).fract();
+        if warning.heartbeat(phase) {
+            if let Some(audio) = &audio {
+                audio.play_with_settings(
+                    asset_server.load(HEALTH_HEARTBEAT_SOUND_PATH),
+                    PlaybackSettings::ONCE.with_volume(settings.volume),
+                );
+            }
+        }
+    }
+
+    let elapsed = time.elapsed_seconds();
+
+    for (edge, mut background) in edge_query.iter_mut() {
+        let Ok((_, warning)) = board_query.get(edge.board) else {
+            continue;
+        };
+
+        let color = if warning.recovered_flash > 0.0 {
+            Color::rgba(
+                0.0,
+                0.97,
+                0.0,
+                0.5 * warning.recovered_flash / HEALTH_RECOVERED_FLASH_DURATION,
+            )
+        } else {
+            match warning.level {
+                HealthLevel::Healthy => Color::NONE,
+                HealthLevel::Low => {
+                    let pulse = 0.5
+                        - 0.5 * (elapsed * std::f32::consts::TAU / HEALTH_LOW_PULSE_PERIOD).cos();
+                    Color::rgba(0.97, 0.0, 0.0, 0.25 * pulse)
+                }
+                HealthLevel::Critical => {
+                    // Two beats, lub-dub
+                    let phase = (elapsed / HEALTH_HEARTBEAT_PERIOD).fract();
+                    let beat = |offset: f32| (1.0 - ((phase - offset) / 0.1).abs()).max(0.0);
+                    Color::rgba(
+                        0.97,
+                        0.0,
+                        0.0,
+                        0.6 * beat(HEALTH_HEARTBEAT_PHASE).max(0.7 * beat(0.35)),
+                    )
+                }
+            }
+        };
+
+        if background.0 != color {
+            background.0 = color;
+        }
+    }
+}
+
+/// 3x5 pixel glyphs for the share card, a byte per row with the leftmost pixel in bit 2.
+pub(crate) const SHARE_CARD_GLYPHS: [(char, [u8; 5]); 41] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+];
+
+/// Image of a finished run to share: mode, score, grade, and the challenge code and seed to play
+/// it again, above a snapshot of the board.
+/// Drawn on the CPU from the block positions, so it doesn't depend on what the camera shows.
+#[derive(Component)]
+pub(crate) struct ShareCard {
+    pub(crate) image: RgbaImage,
+    /// Seconds since the Unix epoch, naming the file
+    pub(crate) date: u64,
+}
+
+impl ShareCard {
+    /// `blocks` are center, angle, size and color of each block on the board.
+    pub(crate) fn new(
+        board: &Board,
+        record: &RunRecord,
+        blocks: impl Iterator<Item = (Vec2, f32, f32, Color)>,
+    ) -> Self {
+        let width = (board.width() * SHARE_CARD_BLOCK_PX) as u32 + 2 * SHARE_CARD_MARGIN;
+        let height = SHARE_CARD_HEADER
+            + (board.n_rows as f32 * SHARE_CARD_BLOCK_PX) as u32
+            + SHARE_CARD_MARGIN;
+        let mut card = Self {
+            image: RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])),
+            date: record.date,
+        };
+
+        let gray = Color::rgb(0.6, 0.6, 0.6);
+        let seconds = record.duration as u32;
+        card.text(SHARE_CARD_MARGIN, 16, 2, "Newtonian Tetris", gray);
+        card.text(SHARE_CARD_MARGIN, 34, 3, record.mode.name(), Color::WHITE);
+        card.text(
+            SHARE_CARD_MARGIN,
+            57,
+            3,
+            &format!("Score {}", record.score),
+            Color::WHITE,
+        );
+        card.text(
+            SHARE_CARD_MARGIN,
+            80,
+            2,
+            &format!("#{}  {}:{:02}", record.attempt, seconds / 60, seconds % 60),
+            gray,
+        );
+        card.text(
+            SHARE_CARD_MARGIN,
+            98,
+            2,
+            &CustomGame::from_board(board).challenge_code(),
+            gray,
+        );
+        if let Some(seed) = record.seed {
+            card.text(SHARE_CARD_MARGIN, 116, 2, &format!("Seed {seed}"), gray);
+        }
+        card.text(
+            width - SHARE_CARD_MARGIN - 3 * 8,
+            16,
+            8,
+            record.grade.letter(),
+            record.grade.color(),
+        );
+
+        for y in SHARE_CARD_HEADER..height - SHARE_CARD_MARGIN {
+            for x in SHARE_CARD_MARGIN..width - SHARE_CARD_MARGIN {
+                card.blend(x, y, Color::rgb(0.12, 0.12, 0.12));
+            }
+        }
+
+        // Pixels are tested against each block rotated back upright, in board units
+        let top_left = Vec2::new(board.left_wall_x(), board.top_y());
+        for (center, angle, size, color) in blocks {
+            let center_px = Vec2::new(
+                SHARE_CARD_MARGIN as f32 + (center.x - top_left.x) * SHARE_CARD_BLOCK_PX,
+                SHARE_CARD_HEADER as f32 + (top_left.y - center.y) * SHARE_CARD_BLOCK_PX,
+            );
+            let reach = size * 0.5 * SQRT_2 * SHARE_CARD_BLOCK_PX;
+            let unrotate = Mat2::from_angle(-angle);
+
+            let min = (center_px - reach).max(Vec2::new(0.0, SHARE_CARD_HEADER as f32));
+            let max = (center_px + reach).min(Vec2::new(width as f32, height as f32));
+
+            for y in min.y as u32..max.y.ceil() as u32 {
+                for x in min.x as u32..max.x.ceil() as u32 {
+                    let offset =
+                        Vec2::new(x as f32 + 0.5 - center_px.x, center_px.y - (y as f32 + 0.5))
+                            / SHARE_CARD_BLOCK_PX;
+                    let upright = unrotate * offset;
+
+                    if upright.abs().max_element() <= size * 0.5 {
+                        card.blend(x, y, color);
+                    }
+                }
+            }
+        }
+
+        card
+    }
+
+    pub(crate) fn blend(&mut self, x: u32, y: u32, color: Color) {
+        let Some(pixel) = self.image.get_pixel_mut_checked(x, y) else {
+            return;
+        };
+        let [r, g, b, alpha] = color.as_rgba_f32();
+
+        for (channel, value) in pixel.0.iter_mut().zip([r, g, b]) {
+            let value = (value * 255.0) as u8;
+            *channel = (value as f32 * alpha + *channel as f32 * (1.0 - alpha)).round() as u8;
+        }
+    }
+
+    /// Upper case text with the pixel font, each font pixel `scale` pixels wide.
+    pub(crate) fn text(&mut self, x: u32, y: u32, scale: u32, text: &str, color: Color) {
+        for (index, character) in text.chars().enumerate() {
+            let rows = SHARE_CARD_GLYPHS
+                .iter()
+                .find(|(glyph, _)| *glyph == character.to_ascii_uppercase())
+                .map_or([0; 5], |(_, rows)| *rows);
+            let glyph_x = x + index as u32 * 4 * scale;
+
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            self.blend(
+                                glyph_x + column * scale + dx,
+                                y + row as u32 * scale + dy,
+                                color,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn path(&self) -> Option<PathBuf> {
+        project_dirs().map(|dirs| {
+            dirs.data_dir()
+                .join("share")
+                .join(format!("run-{}.png", self.date))
+        })
+    }
+
+    pub(crate) fn save(&self) -> Option<PathBuf> {
+        let path = self.path()?;
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(|err| err.to_string())
+            .and_then(|_| self.image.save(&path).map_err(|err| err.to_string()));
+
+        match result {
+            Ok(()) => Some(path),
+            Err(err) => {
+                warn!("could not save {path:?}: {err}");
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "clipboard")]
+    pub(crate) fn copy_to_clipboard(&self) -> bool {
+        let result = arboard::Clipboard::new().and_then(|mut clipboard| {
+            clipboard.set_image(arboard::ImageData {
+                width: self.image.width() as usize,
+                height: self.image.height() as usize,
+                bytes: std::borrow::Cow::Borrowed(self.image.as_raw()),
+            })
+        });
+
+        if let Err(err) = &result {
+            warn!("could not copy the share card: {err}");
+        }
+        result.is_ok()
+    }
+
+    /// Only with the `clipboard` feature.
+    #[cfg(not(feature = "clipboard"))]
+    pub(crate) fn copy_to_clipboard(&self) -> bool {
+        false
+    }
+}
+
+/// Show the results screen while a board's game is over, and remove it when a new game starts.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn results_screen(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+    input_map: Res<InputMap>,
+    asset_server: Res<AssetServer>,
+    // Missing without audio, such as in simulations
+    audio: Option<Res<Audio>>,
+    mut profile: ResMut<Profile>,
+    board_query: Query<(Entity, &Board, &BoardStats, Option<&Finale>)>,
+    block_query: Query<(&Block, &Transform, &Sprite)>,
+    results_query: Query<(Entity, &ResultsScreen)>,
+) {
+    for (board_entity, board, stats, finale) in board_query.iter() {
+        // Runs of the bot aren't the player's, see `bot_restart`
+        if board.bot {
+            continue;
+        }
+
+        let mut board_results = results_query
+            .iter()
+            .filter(|(_, results)| results.board == board_entity);
+
+        if stats.game_over_duration.is_none() {
+            for (entity, _) in board_results {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+
+        if board_results.next().is_some() {
+            continue;
+        }
+
+        let grade = stats.grade(board.mode);
+
+        // A run that ended well is celebrated before its results
+        match finale {
+            None if grade <= FINALE_MIN_GRADE => {
+                let top_y = block_query
+                    .iter()
+                    .filter(|(block, _, _)| block.board == board_entity)
+                    .map(|(_, transform, _)| transform.translation.y)
+                    .fold(board.floor_y(), f32::max);
+
+                commands.entity(board_entity).insert(Finale {
+                    timer: Timer::from_seconds(FINALE_DURATION, TimerMode::Once),
+                    bottom_y: board.floor_y(),
+                    top_y,
+                });
+                spawn_confetti(
+                    &mut commands,
+                    &board.piece_definitions,
+                    Vec2::new(board.center.x, board.floor_y()),
+                );
+                if let Some(audio) = &audio {
+                    audio.play_with_settings(
+                        asset_server.load(FINALE_SOUND_PATH),
+                        PlaybackSettings::ONCE.with_volume(settings.volume),
+                    );
+                }
+                continue;
+            }
+            Some(finale) if finale.is_playing() => continue,
+            _ => {}
+        }
+        let score = stats.score();
+        let attempt = profile.attempts.get(&board.mode).copied().unwrap_or(1);
+
+        let best_text = match profile.best_runs.get(&board.mode) {
+            _ if board.practice.is_some() => {
+                "Practice run, not counted towards best runs".to_string()
+            }
+            Some(best) if score <= best.score => format!(
+                "Attempt #{attempt}: {} behind best (attempt #{})",
+                best.score - score,
+                best.attempt
+            ),
+            Some(best) => {
+                format!("Attempt #{attempt}: new best, {} ahead", score - best.score)
+            }
+            None => format!("Attempt #{attempt}: first best"),
+        };
+
+        let is_new_best = board.practice.is_none()
+            && profile
+                .best_runs
+                .get(&board.mode)
+                .is_none_or(|best| score > best.score);
+
+        if is_new_best {
+            profile.best_runs.insert(
+                board.mode,
+                BestRun {
+                    score,
+                    attempt,
+                    clear_times: stats.clear_times.clone(),
+                },
+            );
+        }
+
+        let record = RunRecord::new(board, attempt, stats);
+        let high_score_date = record.date;
+        // Snapshot of the board now, before the next run clears it
+        let share_card = ShareCard::new(
+            board,
+            &record,
+            block_query
+                .iter()
+                .filter(|(block, _, _)| block.board == board_entity)
+                .map(|(block, transform, sprite)| {
+                    let (_, _, angle) = transform.rotation.to_euler(EulerRot::XYZ);
+                    (
+                        transform.translation.truncate(),
+                        angle,
+                        block.size,
+                        sprite.color,
+                    )
+                }),
+        );
+
+        profile.record_run(record);
+        let high_score_rank = if board.practice.is_none() {
+            let initials = profile.initials.clone();
+            profile.record_high_score(HighScore {
+                score,
+                rows: stats.rows_cleared(),
+                date: high_score_date,
+                initials,
+                id: 0,
+            })
+        } else {
+            None
+        };
+        profile.save();
+
+        let high_score_text = profile.high_score_lines().join("\n");
+        let mut initials = ['A'; INITIALS_LENGTH];
+        for (slot, letter) in initials.iter_mut().zip(profile.initials.chars()) {
+            *slot = letter;
+        }
+
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(ResultsScreen {
+                board: board_entity,
+                countdown: Timer::from_seconds(RESTART_COUNTDOWN, TimerMode::Once),
+            })
+            .insert(share_card)
+            .with_children(|parent| {
+                parent.spawn(fonts.text_bundle("GAME OVER", 40.0, Color::WHITE));
+                parent
+                    .spawn(fonts.text_bundle(grade.letter(), 160.0, grade.color()))
+                    .insert(GradeStamp {
+                        timer: Timer::from_seconds(GRADE_STAMP_DURATION, TimerMode::Once),
+                    });
+                parent.spawn(fonts.text_bundle(
+                    &format!(
+                        "Score {}   Lost {:.0}%   Precision {:.0}%",
+                        stats.score(),
+                        stats.lost_ratio() * 100.0,
+                        stats.placement_precision() * 100.0
+                    ),
+                    24.0,
+                    Color::WHITE,
+                ));
+                let seconds = stats.elapsed as u32;
+                parent.spawn(fonts.text_bundle(
+                    &format!(
+                        "Cleared {}   Lost {}   Level {}   Time {}:{:02}",
+                        stats.cleared_blocks,
+                        stats.lost_blocks,
+                        stats.level(),
+                        seconds / 60,
+                        seconds % 60
+                    ),
+                    20.0,
+                    Color::WHITE,
+                ));
+                parent.spawn(fonts.text_bundle(&best_text, 20.0, Color::WHITE));
+                if let Some(rank) = high_score_rank {
+                    let entry = InitialsEntry {
+                        board: board_entity,
+                        rank,
+                        id: profile.high_scores[rank].id,
+                        initials,
+                        cursor: 0,
+                    };
+                    parent
+                        .spawn(fonts.text_bundle(&entry.text(), 24.0, Color::GOLD))
+                        .insert(entry);
+                }
+                parent.spawn(fonts.text_bundle(&high_score_text, 16.0, Color::WHITE));
+                parent.spawn(fonts.text_bundle(&format!("Seed {}", stats.seed), 16.0, Color::GRAY));
+                parent.spawn(fonts.text_bundle(
+                    &format!(
+                        "Press {} to save a share card",
+                        input_map.describe(Action::ShareCard)
+                    ),
+                    16.0,
+                    Color::WHITE,
+                ));
+                parent
+                    .spawn(fonts.text_bundle(
+                        &restart_prompt_text(RESTART_COUNTDOWN),
+                        24.0,
+                        Color::WHITE,
+                    ))
+                    .insert(RestartPrompt {
+                        board: board_entity,
+                    });
+
+                if !settings.friends.is_empty() {
+                    parent
+                        .spawn(fonts.text_bundle("Comparing with friends...", 20.0, Color::GRAY))
+                        .insert(FriendsComparison {
+                            score,
+                            task: fetch_friends_best_scores(settings.friends.clone(), board.mode),
+                        });
+                }
+            });
+    }
+}
+
+fn restart_prompt_text(remaining_seconds: f32) -> String {
+    format!(
+        "Press Enter or A to play again ({})",
+        remaining_seconds.ceil() as u32
+    )
+}
+
+/// Dismiss the results screen on confirm, or when its countdown runs out, to start the next run.
+/// The countdown holds while a menu or dialog is open on top.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn results_restart_prompt(
+    mut commands: Commands,
+    time: Res<Time>,
+    fonts: Res<Fonts>,
+    action_state: Res<ActionState>,
+    menu_query: Query<(), With<Menu>>,
+    entry_query: Query<&InitialsEntry>,
+    mut results_query: Query<&mut ResultsScreen>,
+    mut prompt_query: Query<(&RestartPrompt, &mut Text)>,
+) {
+    if !menu_query.is_empty() {
+        return;
+    }
+
+    let confirmed = action_state.just_pressed(Action::MenuConfirm);
+
+    for mut results in results_query.iter_mut() {
+        // Waiting for the initials of a new high score
+        if entry_query.iter().any(|entry| entry.board == results.board) {
+            continue;
+        }
+
+        results.countdown.tick(time.delta());
+
+        let accepts_input = results.countdown.elapsed_secs() >= RESTART_INPUT_DELAY;
+        if results.countdown.just_finished() || (confirmed && accepts_input) {
+            commands.entity(results.board).insert(RestartRequested);
+        }
+    }
+
+    for (prompt, mut text) in prompt_query.iter_mut() {
+        let Some(results) = results_query
+            .iter()
+            .find(|results| results.board == prompt.board)
+        else {
+            continue;
+        };
+
+        *text = fonts.text(
+            &restart_prompt_text(results.countdown.remaining_secs()),
+            24.0,
+            Color::WHITE,
+        );
+    }
+}
+
+fn cycle_letter(letter: char, step: isize) -> char {
+    let letters: Vec<char> = INITIALS_ALPHABET.chars().collect();
+    let index = letters.iter().position(|c| *c == letter).unwrap_or(0) as isize;
+
+    letters[(index + step).rem_euclid(letters.len() as isize) as usize]
+}
+
+/// Enter the initials of a new high score: up and down change the letter, left and right move
+/// between the letters.
+pub(crate) fn high_score_initials_entry(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    action_state: Res<ActionState>,
+    mut profile: ResMut<Profile>,
+    menu_query: Query<(), With<Menu>>,
+    mut entry_query: Query<(Entity, &mut InitialsEntry, &mut Text)>,
+) {
+    if !menu_query.is_empty() {
+        return;
+    }
+
+    for (entity, mut entry, mut text) in entry_query.iter_mut() {
+        let cursor = entry.cursor;
+
+        if action_state.just_pressed(Action::MenuUp) {
+            entry.initials[cursor] = cycle_letter(entry.initials[cursor], 1);
+        }
+        if action_state.just_pressed(Action::MenuDown) {
+            entry.initials[cursor] = cycle_letter(entry.initials[cursor], -1);
+        }
+        if action_state.just_pressed(Action::MoveLeft) {
+            entry.cursor = cursor.saturating_sub(1);
+        }
+        if action_state.just_pressed(Action::MoveRight) {
+            entry.cursor = (cursor + 1).min(INITIALS_LENGTH - 1);
+        }
+
+        if action_state.just_pressed(Action::MenuConfirm) {
+            if cursor + 1 < INITIALS_LENGTH {
+                entry.cursor += 1;
+            } else {
+                let initials: String = entry.initials.iter().collect();
+                if let Some(high_score) = profile
+                    .high_scores
+                    .iter_mut()
+                    .find(|high_score| high_score.id == entry.id)
+                {
+                    high_score.initials = initials.clone();
+                }
+                profile.initials = initials.clone();
+                profile.save();
+
+                commands.entity(entity).remove::<InitialsEntry>();
+                *text = fonts.text(
+                    &format!("High score #{} saved as {initials}", entry.rank + 1),
+                    24.0,
+                    Color::GOLD,
+                );
+                continue;
+            }
+        }
+
+        if entry.is_changed() {
+            *text = fonts.text(&entry.text(), 24.0, Color::GOLD);
+        }
+    }
+}
+
+/// Save the share card of a finished run, and copy it to the clipboard where supported.
+pub(crate) fn export_share_card(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    card_query: Query<&ShareCard>,
+) {
+    if !input_map.just_pressed(&input, Action::ShareCard) {
+        return;
+    }
+
+    for card in card_query.iter() {
+        let message = match card.save() {
+            Some(path) if card.copy_to_clipboard() => {
+                format!("Share card saved to {} and copied", path.display())
+            }
+            Some(path) => format!("Share card saved to {}", path.display()),
+            None => "Could not save the share card".to_string(),
+        };
+        spawn_toast(&mut commands, &fonts, &message);
+    }
+}
+
+pub(crate) fn spawn_confetti(
+    commands: &mut Commands,
+    piece_definitions: &PieceDefinitions,
+    origin: Vec2,
+) {
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..CONFETTI_COUNT {
+        // Bursting upwards, fanning out to the sides
+        let angle = rng.gen_range(-1.0..1.0_f32);
+        let speed: f32 = rng.gen_range(10.0..22.0);
+
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_translation(origin.extend(5.0))
+                    .with_rotation(Quat::from_rotation_z(rng.gen_range(0.0..TAU))),
+                sprite: Sprite {
+                    color: TetrominoKind::random(piece_definitions, &mut rng)
+                        .color(piece_definitions),
+                    custom_size: Some(Vec2::new(0.3, 0.15)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Confetti {
+                velocity: Vec2::new(angle.sin(), angle.cos()) * speed,
+                spin: rng.gen_range(-10.0..10.0),
+                timer: Timer::from_seconds(CONFETTI_LIFETIME, TimerMode::Once),
+            });
+    }
+}
+
+/// Play the finale, and let the confetti flutter down and fade out in real time.
+pub(crate) fn finale_playback(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut finale_query: Query<&mut Finale>,
+    mut confetti_query: Query<(Entity, &mut Confetti, &mut Transform, &mut Sprite)>,
+) {
+    let scaled_delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+    for mut finale in finale_query.iter_mut() {
+        finale.timer.tick(scaled_delta);
+    }
+
+    let delta = time.delta_seconds();
+    for (entity, mut confetti, mut transform, mut sprite) in confetti_query.iter_mut() {
+        confetti.timer.tick(time.delta());
+        if confetti.timer.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let drag = 1.0 / (1.0 + CONFETTI_DRAG * delta);
+        confetti.velocity = (confetti.velocity - Vec2::Y * CONFETTI_GRAVITY * delta) * drag;
+        transform.translation += (confetti.velocity * delta).extend(0.0);
+        transform.rotate_z(confetti.spin * delta);
+        sprite.color.set_a(1.0 - confetti.timer.percent());
+    }
+}
+
+/// Read the best score in the mode of each friend, off the main thread.
+/// Friends whose profile can't be read, or who haven't played the mode, are left out.
+pub(crate) fn fetch_friends_best_scores(
+    friends: Vec<Friend>,
+    mode: GameMode,
+) -> Task<Vec<(String, i32)>> {
+    IoTaskPool::get().spawn(async move {
+        friends
+            .into_iter()
+            .filter_map(|friend| {
+                let profile: Profile = load_ron(&friend.profile)?;
+                let best = profile.best_runs.get(&mode)?;
+                Some((friend.name, best.score))
+            })
+            .collect()
+    })
+}
+
+/// Rank a score among the friends' best scores, calling out the best friend it beat,
+/// or the friend just ahead of it when it beat nobody.
+pub(crate) fn friends_callout(score: i32, friends: &[(String, i32)]) -> String {
+    if friends.is_empty() {
+        return "No friends' runs in this mode yet".to_string();
+    }
+
+    let rank = 1 + friends.iter().filter(|(_, best)| *best > score).count();
+    let ranking = format!("#{rank} of {} among friends", friends.len() + 1);
+
+    let beaten = friends
+        .iter()
+        .filter(|(_, best)| *best < score)
+        .max_by_key(|(_, best)| *best);
+    let ahead = friends
+        .iter()
+        .filter(|(_, best)| *best >= score)
+        .min_by_key(|(_, best)| *best);
+
+    match (beaten, ahead) {
+        (Some((name, best)), _) => format!("{ranking}: beat {name} by {} points", score - best),
+        (None, Some((name, best))) if *best == score => format!("{ranking}: tied with {name}"),
+        (None, Some((name, best))) => {
+            format!("{ranking}: {name} is ahead by {} points", best - score)
+        }
+        (None, None) => ranking,
+    }
+}
+
+pub(crate) fn friends_comparison(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    mut comparison_query: Query<(Entity, &mut FriendsComparison, &mut Text)>,
+) {
+    for (entity, mut comparison, mut text) in comparison_query.iter_mut() {
+        let Some(friends) = future::block_on(future::poll_once(&mut comparison.task)) else {
+            continue;
+        };
+
+        *text = fonts.text(
+            &friends_callout(comparison.score, &friends),
+            20.0,
+            Color::WHITE,
+        );
+        commands.entity(entity).remove::<FriendsComparison>();
+    }
+}
+
+pub(crate) fn count_attempts(
+    mut commands: Commands,
+    mut run_started_events: EventReader<RunStarted>,
+    board_query: Query<&Board>,
+    fonts: Res<Fonts>,
+    mut profile: ResMut<Profile>,
+) {
+    for event in run_started_events.iter() {
+        let Ok(board) = board_query.get(event.board) else {
+            continue;
+        };
+        if board.bot {
+            continue;
+        }
+
+        if let Some(kind) = board.practice {
+            spawn_toast(
+                &mut commands,
+                &fonts,
+                &format!(
+                    "Practice: mostly {} pieces",
+                    kind.letter(&board.piece_definitions)
+                ),
+            );
+            continue;
+        }
+
+        let attempts = profile.attempts.entry(board.mode).or_insert(0);
+        *attempts += 1;
+        let attempt = *attempts;
+        profile.save();
+
+        spawn_toast(&mut commands, &fonts, &format!("Attempt #{attempt}"));
+    }
+}
+
+/// Slam the grade letter down onto the results screen like a rubber stamp.
+pub(crate) fn grade_stamp_animation(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut stamp_query: Query<(&mut GradeStamp, &mut Transform)>,
+) {
+    let delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+
+    for (mut stamp, mut transform) in stamp_query.iter_mut() {
+        stamp.timer.tick(delta);
+
+        // Ease out: fast start, gentle landing
+        let t = 1.0 - (1.0 - stamp.timer.percent()).powi(3);
+        let scale = GRADE_STAMP_START_SCALE + (1.0 - GRADE_STAMP_START_SCALE) * t;
+
+        transform.scale = Vec3::new(scale, scale, 1.0);
+    }
+}
+
+pub(crate) fn record_highlights(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut run_started_events: EventReader<RunStarted>,
+    mut board_query: Query<(Entity, &BoardStats, &mut HighlightBuffer)>,
+    block_query: Query<(&Block, &Transform, &Sprite)>,
+) {
+    // A new game starts with an empty buffer
+    for event in run_started_events.iter() {
+        if let Ok((_, _, mut buffer)) = board_query.get_mut(event.board) {
+            buffer.clear();
+        }
+    }
+
+    let delta = time_scale.delta_seconds(&time);
+
+    for (board_entity, stats, mut buffer) in board_query.iter_mut() {
+        // Freeze the buffer at game over, the final collapse is what gets replayed
+        if stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        buffer.since_last_sample += delta;
+        if buffer.since_last_sample < HIGHLIGHT_SAMPLE_INTERVAL {
+            continue;
+        }
+        buffer.since_last_sample -= HIGHLIGHT_SAMPLE_INTERVAL;
+
+        let frame = block_query
+            .iter()
+            .filter(|(block, _, _)| block.board == board_entity)
+            .map(|(block, transform, sprite)| HighlightBlock {
+                position: transform.translation.truncate(),
+                angle: transform.rotation.to_euler(EulerRot::XYZ).2,
+                size: block.size,
+                color: sprite.color,
+            })
+            .collect();
+
+        // Compare with the last sample to find what happened in between
+        let counts = (
+            stats.generated_blocks,
+            stats.rows_cleared(),
+            stats.lost_blocks,
+        );
+        let (generated, cleared, lost) = buffer.last_counts;
+        let markers = [
+            (counts.0 > generated, HighlightMarker::Spawn),
+            (counts.1 > cleared, HighlightMarker::Clear),
+            (counts.2 > lost, HighlightMarker::Loss),
+        ]
+        .into_iter()
+        .filter(|(happened, _)| *happened)
+        .map(|(_, marker)| marker)
+        .collect();
+        buffer.last_counts = counts;
+
+        buffer.push(frame, markers);
+    }
+}
+
+/// Loop the buffered highlights in a small inset in the corner of the board
+/// while its results screen is shown.
+pub(crate) fn highlight_replay(
+    mut commands: Commands,
+    time: Res<Time>,
+    board_query: Query<(Entity, &Board, &HighlightBuffer)>,
+    results_query: Query<&ResultsScreen>,
+    mut inset_query: Query<(Entity, &mut HighlightInset)>,
+    sprite_query: Query<(Entity, &HighlightSprite)>,
+    mut playhead_query: Query<(&HighlightPlayhead, &mut Transform)>,
+) {
+    for (board_entity, board, buffer) in board_query.iter() {
+        let showing_results = results_query
+            .iter()
+            .any(|results| results.board == board_entity);
+
+        let board_inset = inset_query
+            .iter_mut()
+            .find(|(_, inset)| inset.board == board_entity);
+
+        if !showing_results || buffer.frames.is_empty() {
+            if let Some((entity, _)) = board_inset {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+
+        let board_size = Vec2::new(board.width(), board.n_rows as f32);
+        let inset_size = board_size * HIGHLIGHT_INSET_SCALE;
+
+        let Some((inset_entity, mut inset)) = board_inset else {
+            let inset_position = Vec2::new(
+                board.left_wall_x() + board_size.x - inset_size.x * 0.5,
+                board.floor_y() + board_size.y - inset_size.y * 0.5,
+            );
+
+            let inset_entity = commands
+                .spawn(SpatialBundle {
+                    transform: Transform::from_translation(inset_position.extend(5.0))
+                        .with_scale(Vec3::new(HIGHLIGHT_INSET_SCALE, HIGHLIGHT_INSET_SCALE, 1.0)),
+                    ..Default::default()
+                })
+                .insert(HighlightInset {
+                    board: board_entity,
+                    frame: 0,
+                    since_last_frame: 0.0,
+                })
+                .id();
+
+            let timeline_y = -board_size.y * 0.5 - HIGHLIGHT_TIMELINE_GAP;
+
+            commands.entity(inset_entity).with_children(|parent| {
+                parent.spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(0.1, 0.1, 0.1, 0.9),
+                        custom_size: Some(board_size),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+
+                parent.spawn(SpriteBundle {
+                    transform: Transform::from_xyz(0.0, timeline_y, 0.0),
+                    sprite: Sprite {
+                        color: Color::rgba(1.0, 1.0, 1.0, 0.4),
+                        custom_size: Some(Vec2::new(board_size.x, HIGHLIGHT_TIMELINE_HEIGHT)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+
+                // The buffer is frozen at game over, so the markers don't change while shown
+                for (frame, markers) in buffer.markers.iter().enumerate() {
+                    for marker in markers {
+                        parent
+                            .spawn(SpriteBundle {
+                                transform: Transform::from_xyz(
+                                    timeline_x(frame, buffer.frames.len(), board_size.x),
+                                    timeline_y,
+                                    1.0,
+                                ),
+                                sprite: Sprite {
+                                    color: marker.color(),
+                                    custom_size: Some(HIGHLIGHT_MARKER_SIZE),
+                                    ..Default::default()
+                                },
+                                ..Default::default()
+                            })
+                            .insert(HighlightTimelineMarker {
+                                inset: inset_entity,
+                                frame,
+                            });
+                    }
+                }
+
+                parent
+                    .spawn(SpriteBundle {
+                        transform: Transform::from_xyz(
+                            timeline_x(0, buffer.frames.len(), board_size.x),
+                            timeline_y,
+                            2.0,
+                        ),
+                        sprite: Sprite {
+                            color: Color::WHITE,
+                            custom_size: Some(Vec2::new(
+                                HIGHLIGHT_MARKER_SIZE.x * 0.5,
+                                HIGHLIGHT_MARKER_SIZE.y * 1.5,
+                            )),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(HighlightPlayhead {
+                        inset: inset_entity,
+                    });
+            });
+            continue;
+        };
+
+        // Replay runs in real time, unaffected by game over slow-motion
+        inset.since_last_frame += time.delta_seconds();
+        while inset.since_last_frame >= HIGHLIGHT_SAMPLE_INTERVAL {
+            inset.since_last_frame -= HIGHLIGHT_SAMPLE_INTERVAL;
+            inset.frame = (inset.frame + 1) % buffer.frames.len();
+        }
+
+        for (entity, sprite) in sprite_query.iter() {
+            if sprite.inset == inset_entity {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        for (playhead, mut transform) in playhead_query.iter_mut() {
+            if playhead.inset == inset_entity {
+                transform.translation.x =
+                    timeline_x(inset.frame, buffer.frames.len(), board_size.x);
+            }
+        }
+
+        let frame = &buffer.frames[inset.frame.min(buffer.frames.len() - 1)];
+
+        commands.entity(inset_entity).with_children(|parent| {
+            for block in frame {
+                let local = block.position - board.center;
+
+                // Blocks falling out of the board are not part of the inset
+                if local.x.abs() > board_size.x * 0.5 || local.y.abs() > board_size.y * 0.5 {
+                    continue;
+                }
+
+                parent
+                    .spawn(SpriteBundle {
+                        transform: Transform::from_translation(local.extend(1.0))
+                            .with_rotation(Quat::from_rotation_z(block.angle)),
+                        sprite: Sprite {
+                            color: block.color,
+                            custom_size: Some(Vec2::splat(block.size)),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(HighlightSprite {
+                        inset: inset_entity,
+                    });
+            }
+        });
+    }
+}
+
+/// Position of a frame along a highlight timeline of the given width, centered on 0.
+pub(crate) fn timeline_x(frame: usize, n_frames: usize, width: f32) -> f32 {
+    let progress = if n_frames > 1 {
+        frame as f32 / (n_frames - 1) as f32
+    } else {
+        0.0
+    };
+
+    (progress - 0.5) * width
+}
+
+/// Jump the highlight replay to the timeline marker that was clicked.
+pub(crate) fn highlight_timeline_click(
+    mouse_buttons: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), Without<InsetCamera>>,
+    marker_query: Query<(&HighlightTimelineMarker, &GlobalTransform)>,
+    mut inset_query: Query<&mut HighlightInset>,
+) {
+    if !mouse_buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let Some(cursor) = window_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+    else {
+        return;
+    };
+
+    let Some(cursor) = camera_query
+        .iter()
+        .find_map(|(camera, transform)| camera.viewport_to_world(transform, cursor))
+        .map(|ray| ray.origin.truncate())
+    else {
+        return;
+    };
+
+    let clicked = marker_query
+        .iter()
+        .map(|(marker, transform)| (marker, transform.translation().truncate().distance(cursor)))
+        .filter(|(_, distance)| *distance < HIGHLIGHT_MARKER_CLICK_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    if let Some((marker, _)) = clicked {
+        if let Ok(mut inset) = inset_query.get_mut(marker.inset) {
+            inset.frame = marker.frame;
+            inset.since_last_frame = 0.0;
+        }
+    }
+}
+
+pub(crate) fn track_run_time(
+    mut board_query: Query<&mut BoardStats>,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+) {
+    for mut stats in board_query.iter_mut() {
+        if stats.game_over_duration.is_none() {
+            stats.elapsed += time_scale.delta_seconds(&time);
+        }
+    }
+}
+
+/// Compare rows cleared so far with what the best run had cleared at the same time.
+pub(crate) fn update_pace_indicator(
+    board_query: Query<(&Board, &BoardStats)>,
+    profile: Res<Profile>,
+    fonts: Res<Fonts>,
+    mut indicator_query: Query<(&PaceIndicator, &mut Text)>,
+) {
+    for (indicator, mut text) in indicator_query.iter_mut() {
+        let Ok((board, stats)) = board_query.get(indicator.board) else {
+            continue;
+        };
+
+        let (value, color) = match profile.best_runs.get(&board.mode) {
+            Some(best) if stats.game_over_duration.is_none() => {
+                let delta =
+                    stats.rows_cleared() as i64 - best.rows_cleared_at(stats.elapsed) as i64;
+
+                match delta {
+                    0 => ("±0".to_string(), Color::WHITE),
+                    delta if delta > 0 => (format!("+{delta}"), Color::rgb_u8(0, 247, 0)),
+                    delta => (format!("{delta}"), Color::rgb_u8(255, 0, 0)),
+                }
+            }
+            _ => (String::new(), Color::WHITE),
+        };
+
+        if text
+            .sections
+            .iter()
+            .map(|section| section.value.as_str())
+            .collect::<String>()
+            != value
+        {
+            *text = fonts
+                .text(&value, WORLD_TEXT_FONT_SIZE, color)
+                .with_alignment(TextAlignment::Center);
+        }
+    }
+}
+
+/// Open the run history screen, or close it when open.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn toggle_history(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut time_scale: ResMut<GameTimeScale>,
+    screen_query: Query<Entity, With<HistoryScreen>>,
+    menu_query: Query<(), With<Menu>>,
+) {
+    if !input_map.just_pressed(&input, Action::History) {
+        return;
+    }
+
+    if !screen_query.is_empty() {
+        close_history(&mut commands, &screen_query, &mut time_scale);
+        return;
+    }
+
+    // Not on top of other menus and dialogs
+    if !menu_query.is_empty() {
+        return;
+    }
+
+    let view = HistoryView::default();
+    time_scale.set(TimeScaleSource::History, 0.0);
+
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+            ..Default::default()
+        })
+        .insert(HistoryScreen)
+        .insert(Menu {
+            focused: HISTORY_CLOSE_ITEM,
+            layer: HISTORY_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("History", 40.0, Color::WHITE));
+        parent
+            .spawn(fonts.text_bundle(&view.sort_text(), 24.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: HISTORY_SORT_ITEM,
+            });
+        parent
+            .spawn(fonts.text_bundle(&view.filter_text(), 24.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: HISTORY_FILTER_ITEM,
+            });
+        parent
+            .spawn(fonts.text_bundle("", 18.0, Color::WHITE).with_style(Style {
+                margin: UiRect::all(Val::Px(16.0)),
+                ..Default::default()
+            }))
+            .insert(HistoryList);
+
+        let (nemesis_text, practice_text) = match profile.nemesis() {
+            Some((kind, lost_share)) => (
+                format!(
+                    "Nemesis: {} pieces, {:.0}% of their blocks lost",
+                    kind.letter(&piece_definitions),
+                    lost_share * 100.0
+                ),
+                format!("Practice {} pieces", kind.letter(&piece_definitions)),
+            ),
+            None => (
+                "Nemesis: not enough runs yet".to_string(),
+                "Practice: no nemesis yet".to_string(),
+            ),
+        };
+        parent.spawn(fonts.text_bundle(&nemesis_text, 20.0, Color::WHITE));
+        parent
+            .spawn(fonts.text_bundle(&practice_text, 24.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: HISTORY_PRACTICE_ITEM,
+            });
+        parent
+            .spawn(fonts.text_bundle(&view.replay_text(&profile.history), 24.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: HISTORY_REPLAY_ITEM,
+            });
+        parent
+            .spawn(fonts.text_bundle("Close", 28.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: HISTORY_CLOSE_ITEM,
+            });
+    });
+
+    commands.insert_resource(view);
+}
+
+pub(crate) fn close_history(
+    commands: &mut Commands,
+    screen_query: &Query<Entity, With<HistoryScreen>>,
+    time_scale: &mut GameTimeScale,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<HistoryView>();
+    time_scale.clear(TimeScaleSource::History);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn history_input(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    mut time_scale: ResMut<GameTimeScale>,
+    view: Option<ResMut<HistoryView>>,
+    screen_query: Query<Entity, With<HistoryScreen>>,
+    profile: Res<Profile>,
+    mut settings: ResMut<Settings>,
+    mut piece_definitions: ResMut<PieceDefinitions>,
+    mut fixed_seed: ResMut<FixedSeed>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut run_started_events: EventWriter<RunStarted>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    block_query: Query<(Entity, &Block)>,
+) {
+    let Some(mut view) = view else {
+        return;
+    };
+
+    let mut close = cancelled_events
+        .iter()
+        .any(|event| screen_query.contains(event.menu));
+    let mut restart = false;
+
+    for event in confirmed_events.iter() {
+        if !screen_query.contains(event.menu) {
+            continue;
+        }
+
+        match event.item {
+            HISTORY_SORT_ITEM => view.sort = view.sort.next(),
+            HISTORY_FILTER_ITEM => view.mode = view.next_mode(),
+            HISTORY_PRACTICE_ITEM => {
+                let Some((kind, _)) = profile.nemesis() else {
+                    continue;
+                };
+
+                // A practice session on all boards, right away
+                settings.practice = Some(kind);
+                restart = true;
+            }
+            HISTORY_REPLAY_ITEM => {
+                let Some(run) = view.replay_run(&profile.history) else {
+                    continue;
+                };
+
+                // The same pieces, drills and chaos, on all boards, right away. Like `--seed`,
+                // later runs keep the seed, and the piece definitions until they are reloaded.
+                settings.practice = run.practice;
+                if run.practice.is_none() {
+                    settings.mode = run.mode;
+                }
+                let run_definitions = run.piece_definitions();
+                piece_definitions.pieces = run_definitions.pieces;
+                if !run_definitions.sets.is_empty() {
+                    piece_definitions.sets = run_definitions.sets;
+                }
+                fixed_seed.0 = run.seed;
+                for (.., mut rng) in board_query.iter_mut() {
+                    rng.fixed_seed = run.seed;
+                }
+                spawn_toast(
+                    &mut commands,
+                    &fonts,
+                    &format!("Replaying seed {}", run.seed.unwrap_or_default()),
+                );
+                restart = true;
+            }
+            _ => close = true,
+        }
+    }
+
+    if restart {
+        for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in
+            board_query.iter_mut()
+        {
+            // Started by the opponent online
+            if board.remote {
+                continue;
+            }
+            run_started_events.send(start_run(
+                &mut commands,
+                board_entity,
+                &mut board,
+                &mut active_pieces,
+                &mut stats,
+                &settings,
+                &piece_definitions,
+                &mut rng,
+                block_query
+                    .iter()
+                    .filter(|(_, block)| block.board == board_entity)
+                    .map(|(entity, _)| entity),
+            ));
+        }
+        next_state.set(AppState::Playing);
+        close = true;
+    }
+
+    if close {
+        close_history(&mut commands, &screen_query, &mut time_scale);
+    }
+}
+
+/// Refill the history list when the screen opens or its sorting or filter changes.
+pub(crate) fn update_history_list(
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    view: Option<Res<HistoryView>>,
+    mut list_query: Query<&mut Text, With<HistoryList>>,
+    mut item_query: Query<(&MenuItem, &mut Text), Without<HistoryList>>,
+    screen_query: Query<(), With<HistoryScreen>>,
+) {
+    let Some(view) = view else {
+        return;
+    };
+
+    // The view is inserted together with the list, so this also fills a newly opened screen
+    if !view.is_changed() {
+        return;
+    }
+
+    let now = unix_now();
+
+    let runs = view.runs(&profile.history);
+    let lines: Vec<String> = runs
+        .iter()
+        .take(HISTORY_VISIBLE_RUNS)
+        .map(|run| {
+            let seconds = run.duration as u32;
+            format!(
+                "{}   {} #{}   {}   {} points   {}:{:02}",
+                run.age(now),
+                run.mode_name(),
+                run.attempt,
+                run.grade.letter(),
+                run.score,
+                seconds / 60,
+                seconds % 60
+            )
+        })
+        .collect();
+
+    let value = if lines.is_empty() {
+        "No completed runs yet".to_string()
+    } else if runs.len() > lines.len() {
+        format!(
+            "{}\n… and {} more",
+            lines.join("\n"),
+            runs.len() - lines.len()
+        )
+    } else {
+        lines.join("\n")
+    };
+
+    for mut text in list_query.iter_mut() {
+        *text = fonts.text(&value, 18.0, Color::WHITE);
+    }
+
+    for (item, mut text) in item_query.iter_mut() {
+        if !screen_query.contains(item.menu) {
+            continue;
+        }
+
+        match item.index {
+            HISTORY_SORT_ITEM => *text = fonts.text(&view.sort_text(), 24.0, Color::WHITE),
+            HISTORY_FILTER_ITEM => *text = fonts.text(&view.filter_text(), 24.0, Color::WHITE),
+            HISTORY_REPLAY_ITEM => {
+                *text = fonts.text(&view.replay_text(&profile.history), 24.0, Color::WHITE)
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn score_hud(
+    board_query: Query<(&Board, &BoardStats, &ActivePieces, &GarbageQueue)>,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+    mut hud_query: Query<(&ScoreHud, &mut Text)>,
+) {
+    for (hud, mut text) in hud_query.iter_mut() {
+        let Ok((board, stats, active_pieces, garbage)) = board_query.get(hud.board) else {
+            continue;
+        };
+
+        let mut value = format!(
+            "Score {}\nLevel {}\nRows {}",
+            stats.score(),
+            stats.level(),
+            stats.rows_cleared()
+        );
+
+        if stats.junk_spawned > 0 {
+            value += &format!("\nJunk {}/{}", stats.junk_cleared, stats.junk_spawned);
+        }
+
+        if let Some(held) = active_pieces.held {
+            value += &format!("\nHold {}", held.letter(&board.piece_definitions));
+        }
+
+        if board.mode == GameMode::Versus {
+            value += &format!(
+                "\nSent {}\nIncoming {}",
+                stats.garbage_sent,
+                garbage.pending.len()
+            );
+        }
+
+        let font_size = if settings.clean_feed {
+            CLEAN_FEED_SCORE_FONT_SIZE
+        } else {
+            24.0
+        };
+        *text = fonts.text(&value, font_size, Color::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::test_world;
+
+    fn rated(rating: f32, opponent_rating: f32, result: f32) -> f32 {
+        let mut profile = Profile {
+            rating,
+            ..Profile::default()
+        };
+        profile.rate_match(opponent_rating, result);
+        profile.rating
+    }
+
+    #[test]
+    fn matches_against_an_equal_opponent_move_the_rating_by_half_the_k_factor() {
+        assert_eq!(rated(1200.0, 1200.0, 1.0), 1200.0 + RATING_K_FACTOR / 2.0);
+        assert_eq!(rated(1200.0, 1200.0, 0.5), 1200.0);
+        assert_eq!(rated(1200.0, 1200.0, 0.0), 1200.0 - RATING_K_FACTOR / 2.0);
+    }
+
+    #[test]
+    fn upsets_move_the_rating_more_than_expected_results() {
+        // 400 points ahead, the stronger player is expected to score 10 out of 11
+        let expected = 1.0 / 11.0;
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+
+        assert!(close(
+            rated(1200.0, 1600.0, 1.0),
+            1200.0 + RATING_K_FACTOR * (1.0 - expected)
+        ));
+        assert!(close(
+            rated(1200.0, 1600.0, 0.5),
+            1200.0 + RATING_K_FACTOR * (0.5 - expected)
+        ));
+        assert!(close(
+            rated(1200.0, 1600.0, 0.0),
+            1200.0 - RATING_K_FACTOR * expected
+        ));
+        assert!(close(
+            rated(1600.0, 1200.0, 1.0),
+            1600.0 + RATING_K_FACTOR * expected
+        ));
+        assert!(close(
+            rated(1600.0, 1200.0, 0.0),
+            1600.0 - RATING_K_FACTOR * (1.0 - expected)
+        ));
+    }
+
+    #[test]
+    fn ratings_stay_in_range() {
+        assert_eq!(rated(RATING_MIN, RATING_MIN + 100.0, 0.0), RATING_MIN);
+        assert_eq!(rated(RATING_MAX, RATING_MAX - 100.0, 1.0), RATING_MAX);
+    }
+
+    #[test]
+    fn online_matches_rate_the_opponent_here_as_well() {
+        let mut profile = Profile {
+            rating: 1400.0,
+            ..Profile::default()
+        };
+        assert_eq!(profile.opponent_rating(7), RATING_INITIAL);
+
+        profile.rate_online_match(7, 0.0);
+        assert_eq!(profile.rating, rated(1400.0, RATING_INITIAL, 0.0));
+        assert_eq!(
+            profile.opponent_rating(7),
+            rated(RATING_INITIAL, 1400.0, 1.0)
+        );
+        assert_eq!(profile.opponent_rating(8), RATING_INITIAL);
+    }
+
+    #[test]
+    fn friends_callout_names_the_best_friend_beaten_or_the_next_one_ahead() {
+        let friends = [
+            ("Kari".to_string(), 300),
+            ("Ola".to_string(), 120),
+            ("Per".to_string(), 80),
+        ];
+
+        assert_eq!(
+            friends_callout(150, &friends),
+            "#2 of 4 among friends: beat Ola by 30 points"
+        );
+        assert_eq!(
+            friends_callout(50, &friends),
+            "#4 of 4 among friends: Per is ahead by 30 points"
+        );
+        assert_eq!(
+            friends_callout(80, &friends),
+            "#3 of 4 among friends: tied with Per"
+        );
+        assert_eq!(
+            friends_callout(400, &friends),
+            "#1 of 4 among friends: beat Kari by 100 points"
+        );
+        assert_eq!(
+            friends_callout(400, &[]),
+            "No friends' runs in this mode yet"
+        );
+    }
+
+    #[test]
+    fn heartbeat_sounds_once_per_period_while_health_is_critical() {
+        let beats = |warning: &mut HealthWarning| -> usize {
+            (0..40)
+                .map(|step| (step as f32 / 20.0).fract())
+                .filter(|phase| warning.heartbeat(*phase))
+                .count()
+        };
+
+        let mut warning = HealthWarning {
+            level: HealthLevel::Critical,
+            ..Default::default()
+        };
+        assert_eq!(beats(&mut warning), 2);
+
+        warning.level = HealthLevel::Low;
+        assert_eq!(beats(&mut warning), 0);
+    }
+
+    fn run_record(mode: GameMode, score: i32, duration: f32, date: u64) -> RunRecord {
+        RunRecord {
+            mode,
+            attempt: 1,
+            score,
+            duration,
+            grade: Grade::C,
+            date,
+            practice: None,
+            generated_by_kind: HashMap::new(),
+            lost_by_kind: HashMap::new(),
+            seed: None,
+            piece_definitions: None,
+        }
+    }
+
+    #[test]
+    fn history_lists_runs_of_the_filtered_mode_in_sort_order() {
+        let history = [
+            run_record(GameMode::Classic, 100, 30.0, 1),
+            run_record(GameMode::Chaos, 300, 10.0, 2),
+            run_record(GameMode::Classic, 200, 20.0, 3),
+        ];
+        let scores = |view: &HistoryView| -> Vec<i32> {
+            view.runs(&history).iter().map(|run| run.score).collect()
+        };
+
+        let mut view = HistoryView::default();
+        assert_eq!(scores(&view), [200, 300, 100]);
+
+        view.sort = HistorySort::Score;
+        assert_eq!(scores(&view), [300, 200, 100]);
+
+        view.sort = HistorySort::Duration;
+        assert_eq!(scores(&view), [100, 200, 300]);
+
+        view.mode = Some(GameMode::Classic);
+        assert_eq!(scores(&view), [100, 200]);
+    }
+
+    #[test]
+    fn history_survives_saving_and_loading_the_profile() {
+        let mut profile = Profile::default();
+        profile
+            .history
+            .push(run_record(GameMode::Chaos, 250, 42.5, 1_700_000_000));
+
+        let saved = ron::to_string(&profile).unwrap();
+        let loaded: Profile = ron::from_str(&saved).unwrap();
+
+        assert_eq!(loaded.history.len(), 1);
+        assert_eq!(loaded.history[0].mode, GameMode::Chaos);
+        assert_eq!(loaded.history[0].score, 250);
+        assert_eq!(loaded.history[0].date, 1_700_000_000);
+        assert_eq!(loaded.history[0].seed, None);
+    }
+
+    #[test]
+    fn runs_from_older_versions_load_without_a_seed() {
+        let loaded: RunRecord = ron::from_str(
+            "(mode: Classic, attempt: 3, score: 120, duration: 60.0, grade: B, date: 5)",
+        )
+        .unwrap();
+
+        assert_eq!(loaded.seed, None);
+        assert_eq!(loaded.attempt, 3);
+    }
+
+    #[test]
+    fn nemesis_is_the_kind_with_the_largest_lost_share_once_enough_are_played() {
+        let mut profile = Profile::default();
+        assert!(profile.nemesis().is_none());
+
+        let mut run = run_record(GameMode::Classic, 100, 30.0, 1);
+        run.generated_by_kind = HashMap::from([(TetrominoKind::S, 40), (TetrominoKind::I, 100)]);
+        run.lost_by_kind = HashMap::from([(TetrominoKind::S, 20), (TetrominoKind::I, 10)]);
+        profile.history.push(run.clone());
+
+        // Half of the S blocks are lost, but too few have been played yet
+        assert_eq!(profile.nemesis(), Some((TetrominoKind::I, 0.1)));
+
+        profile.history.push(run);
+        assert_eq!(profile.nemesis(), Some((TetrominoKind::S, 0.5)));
+    }
+
+    #[test]
+    fn replay_picks_the_first_listed_run_with_a_seed() {
+        let mut history = [
+            run_record(GameMode::Classic, 100, 30.0, 1),
+            run_record(GameMode::Chaos, 300, 10.0, 2),
+            run_record(GameMode::Classic, 200, 20.0, 3),
+        ];
+        let mut view = HistoryView::default();
+        assert!(view.replay_run(&history).is_none());
+        assert_eq!(view.replay_text(&history), "Replay: no run with a seed yet");
+
+        history[0].seed = Some(7);
+        history[1].seed = Some(42);
+        assert_eq!(view.replay_run(&history).unwrap().seed, Some(42));
+
+        view.mode = Some(GameMode::Classic);
+        assert_eq!(view.replay_run(&history).unwrap().seed, Some(7));
+        assert_eq!(view.replay_text(&history), "Replay Classic #1 (seed 7)");
+
+        history[0].practice = Some(TetrominoKind::S);
+        assert_eq!(view.replay_text(&history), "Replay S practice #1 (seed 7)");
+    }
+
+    #[test]
+    fn share_card_blends_colors_by_their_alpha() {
+        let mut card = ShareCard {
+            image: RgbaImage::new(2, 1),
+            date: 0,
+        };
+
+        card.blend(0, 0, Color::rgb(1.0, 0.0, 0.0));
+        card.blend(1, 0, Color::rgba(1.0, 1.0, 1.0, 0.5));
+        // Outside the card
+        card.blend(5, 5, Color::WHITE);
+
+        assert_eq!(card.image.get_pixel(0, 0).0[..3], [255, 0, 0]);
+        assert_eq!(card.image.get_pixel(1, 0).0[..3], [128, 128, 128]);
+    }
+
+    #[test]
+    fn finale_pans_from_the_floor_to_the_top_of_the_stack() {
+        let mut finale = Finale {
+            timer: Timer::from_seconds(FINALE_DURATION, TimerMode::Once),
+            bottom_y: -10.0,
+            top_y: 6.0,
+        };
+        assert!(finale.is_playing());
+        assert_eq!(finale.pan_y(), -10.0);
+
+        finale
+            .timer
+            .tick(std::time::Duration::from_secs_f32(FINALE_DURATION / 2.0));
+        assert!((finale.pan_y() + 2.0).abs() < 1e-4);
+
+        finale
+            .timer
+            .tick(std::time::Duration::from_secs_f32(FINALE_DURATION));
+        assert!(!finale.is_playing());
+        assert_eq!(finale.pan_y(), 6.0);
+    }
+
+    #[test]
+    fn confirming_the_results_only_restarts_once_they_take_input() {
+        let mut world = test_world();
+        world.insert_resource(Time::default());
+        world.insert_resource(Fonts {
+            latin: Handle::default(),
+            cjk: Handle::default(),
+        });
+        let mut action_state = ActionState::default();
+        action_state.values.insert(Action::MenuConfirm, 1.0);
+        world.insert_resource(action_state);
+
+        let board = world.spawn_empty().id();
+        let results = world
+            .spawn(ResultsScreen {
+                board,
+                countdown: Timer::from_seconds(RESTART_COUNTDOWN, TimerMode::Once),
+            })
+            .id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(results_restart_prompt);
+        schedule.run(&mut world);
+        assert!(!world.entity(board).contains::<RestartRequested>());
+
+        world
+            .get_mut::<ResultsScreen>(results)
+            .unwrap()
+            .countdown
+            .set_elapsed(std::time::Duration::from_secs_f32(RESTART_INPUT_DELAY));
+        schedule.run(&mut world);
+        assert!(world.entity(board).contains::<RestartRequested>());
+    }
+
+    #[test]
+    fn high_scores_keep_the_ten_best_in_order() {
+        let high_score = |score| HighScore {
+            score,
+            rows: 0,
+            date: 0,
+            initials: "AAA".to_string(),
+            id: 0,
+        };
+
+        let mut profile = Profile::default();
+        for score in 1..=HIGH_SCORE_COUNT as i32 {
+            assert_eq!(profile.record_high_score(high_score(score * 10)), Some(0));
+        }
+        assert_eq!(profile.record_high_score(high_score(5)), None);
+        assert_eq!(profile.record_high_score(high_score(0)), None);
+        assert_eq!(profile.record_high_score(high_score(55)), Some(5));
+
+        let scores: Vec<i32> = profile
+            .high_scores
+            .iter()
+            .map(|entry| entry.score)
+            .collect();
+        assert_eq!(scores, [100, 90, 80, 70, 60, 55, 50, 40, 30, 20]);
+    }
+
+    #[test]
+    fn initials_name_their_own_high_score_among_those_of_the_same_second() {
+        let high_score = |score| HighScore {
+            score,
+            rows: 0,
+            date: 1_700_000_000,
+            initials: "AAA".to_string(),
+            id: 0,
+        };
+        let mut profile = Profile::default();
+        assert_eq!(profile.record_high_score(high_score(50)), Some(0));
+        // The other board's run, ending in the same second, ranks above it
+        assert_eq!(profile.record_high_score(high_score(80)), Some(0));
+        let id = profile.high_scores[1].id;
+        assert_ne!(profile.high_scores[0].id, id);
+
+        let mut world = test_world();
+        world.insert_resource(Fonts {
+            latin: Handle::default(),
+            cjk: Handle::default(),
+        });
+        world.insert_resource(ActionState::default());
+        world.insert_resource(profile);
+        let board = world.spawn_empty().id();
+        let entry = world
+            .spawn((
+                InitialsEntry {
+                    board,
+                    rank: 0,
+                    id,
+                    initials: ['A'; INITIALS_LENGTH],
+                    cursor: 0,
+                },
+                Text::default(),
+            ))
+            .id();
+        let mut schedule = Schedule::new();
+        schedule.add_system(high_score_initials_entry);
+        let mut press = |world: &mut World, action: Action| {
+            let mut action_state = world.resource_mut::<ActionState>();
+            action_state.previous_values.clear();
+            action_state.values.clear();
+            action_state.values.insert(action, 1.0);
+            schedule.run(world);
+        };
+
+        press(&mut world, Action::MenuUp);
+        for _ in 0..INITIALS_LENGTH {
+            press(&mut world, Action::MenuConfirm);
+        }
+
+        assert!(!world.entity(entry).contains::<InitialsEntry>());
+        let profile = world.resource::<Profile>();
+        let initials: Vec<&str> = profile
+            .high_scores
+            .iter()
+            .map(|entry| entry.initials.as_str())
+            .collect();
+        assert_eq!(initials, ["AAA", "BAA"]);
+        assert_eq!(profile.initials, "BAA");
+    }
+
+    #[test]
+    fn partial_rows_are_worth_a_share_of_the_points() {
+        assert_eq!(cleared_row_points(10, false), 100.0);
+        assert_eq!(
+            cleared_row_points(9, true),
+            90.0 * PARTIAL_ROW_POINTS_FACTOR
+        );
+    }
+
+    #[test]
+    fn initials_letters_wrap_around_the_alphabet() {
+        let first = INITIALS_ALPHABET.chars().next().unwrap();
+        let last = INITIALS_ALPHABET.chars().last().unwrap();
+
+        assert_eq!(cycle_letter(first, -1), last);
+        assert_eq!(cycle_letter(last, 1), first);
+        assert_eq!(cycle_letter('A', 1), 'B');
+    }
+}
@@ -0,0 +1,700 @@
+//! Persistent settings and the files they are stored in.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bevy::prelude::*;
+use directories::ProjectDirs;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::board::{BoardLayout, CameraMode, RowClearing, PARTIAL_ROW_THRESHOLDS};
+use crate::dressing::BoardBackground;
+use crate::modes::GameMode;
+use crate::physics::{PhysicsTuning, LINEAR_DAMPING_RANGE, MOVEMENT_FORCE_RANGE, TORQUE_RANGE};
+use crate::pieces::PieceDefinitions;
+use crate::skins::Skin;
+use crate::tetromino::{MirrorModifier, PieceSet, TetrominoKind};
+use crate::ui::{UI_SCALE_MAX, UI_SCALE_MIN, UI_SCALE_STEP};
+
+// Ranges of the settings that are numbers, as stepped through on the settings screen
+pub(crate) const VOLUME_RANGE: (f32, f32) = (0.0, 1.0);
+pub(crate) const LANE_REPEAT_DELAY_RANGE: (f32, f32) = (0.0, 0.5);
+pub(crate) const LANE_REPEAT_INTERVAL_RANGE: (f32, f32) = (0.02, 0.3);
+
+static PERSISTENCE_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Stop loading and saving files, so that simulations don't touch the player's data.
+pub(crate) fn disable_persistence() {
+    PERSISTENCE_DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// A world for tests that run systems, with persistence disabled so that systems saving the
+/// profile, settings or a tournament don't write into the developer's data.
+#[cfg(test)]
+pub(crate) fn test_world() -> World {
+    disable_persistence();
+    World::new()
+}
+
+/// An app for tests, like `test_world`.
+#[cfg(test)]
+pub(crate) fn test_app() -> App {
+    disable_persistence();
+    App::new()
+}
+
+pub(crate) fn project_dirs() -> Option<ProjectDirs> {
+    if PERSISTENCE_DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    ProjectDirs::from("", "", "newtonian-tetris")
+}
+
+/// Persistent settings, stored in the platform config directory.
+#[derive(Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    /// Scale of all UI and HUD text, `UI_SCALE_MIN..=UI_SCALE_MAX`
+    pub(crate) ui_scale: f32,
+    /// Volume of all sounds, from 0 (muted) to 1
+    pub(crate) volume: f32,
+    /// Mode for the next run
+    pub(crate) mode: GameMode,
+    /// Show mass, force and spin of the controlled piece
+    pub(crate) show_telemetry: bool,
+    /// Show the predicted path of the controlled piece
+    pub(crate) show_trajectory: bool,
+    /// Presentation mode for footage and events: no debug or assist visuals, larger readouts,
+    /// a vignette and a corner logo
+    pub(crate) clean_feed: bool,
+    /// Fuse modifier for the next run
+    pub(crate) fuse_modifier: bool,
+    /// Mirror modifier for the next run
+    pub(crate) mirror_modifier: MirrorModifier,
+    /// Stamina modifier for the next run
+    pub(crate) stamina_modifier: bool,
+    /// Modes played with walls on the sides of the board, instead of blocks falling off
+    pub(crate) side_walls: HashSet<GameMode>,
+    pub(crate) camera_mode: CameraMode,
+    /// Name of the board layout, applied on launch
+    pub(crate) board_layout: String,
+    /// Accessibility: show the kind of each block as a letter
+    pub(crate) kind_glyphs: bool,
+    pub(crate) skin: Skin,
+    pub(crate) board_background: BoardBackground,
+    /// Lines between the block slots and rows of the boards
+    pub(crate) board_grid: bool,
+    /// Seconds without input before a run is paused, 0 to never pause
+    pub(crate) idle_pause_seconds: f32,
+    /// Seconds without input before returning to the title menu, 0 to never return
+    pub(crate) idle_menu_seconds: f32,
+    /// Applied from the next run
+    pub(crate) physics: PhysicsTuning,
+    /// Applied from the next run
+    pub(crate) row_clearing: RowClearing,
+    /// Applied from the next run
+    pub(crate) slicing: bool,
+    /// Hard knocks crack blocks and finally shatter them, from the next run
+    pub(crate) block_damage: bool,
+    /// Pieces snap apart when slammed hard enough, from the next run
+    pub(crate) breakable_joints: bool,
+    /// Modes where rows covered this much clear as partial rows, for fewer points. Applied from
+    /// the next run.
+    pub(crate) partial_rows: HashMap<GameMode, f32>,
+    /// Accessibility: seconds of each sweep of the one-switch mode, 0 to play with all the
+    /// controls instead, see `OneSwitch`
+    pub(crate) one_switch_sweep_seconds: f32,
+    /// Seconds the switch must be held for a press to count
+    pub(crate) one_switch_accept_seconds: f32,
+    /// On-screen buttons during a run instead of touch gestures
+    pub(crate) touch_buttons: bool,
+    /// Kinds of pieces dealt per mode, from the next run. All pieces in modes left out.
+    pub(crate) piece_sets: HashMap<GameMode, PieceSet>,
+    /// The one piece set for every mode of older versions, moved to `piece_sets` on load
+    #[serde(
+        rename = "piece_set",
+        skip_serializing,
+        deserialize_with = "deserialize_some"
+    )]
+    pub(crate) legacy_piece_set: Option<PieceSet>,
+    /// Versus mode against the bot instead of a second player, from the next run
+    pub(crate) versus_bot: bool,
+    /// A tap of rotation turns the piece a quarter turn, see `assisted_torque`
+    pub(crate) rotation_assist: bool,
+    /// A tap of left or right moves the piece a block slot over, see `assisted_movement`. Off is
+    /// the hardcore raw push.
+    pub(crate) lane_snapping: bool,
+    /// Seconds left or right is held before lane snapping steps on by itself (DAS). Zero pushes
+    /// freely while held instead.
+    pub(crate) lane_repeat_delay: f32,
+    /// Seconds between the steps after that (ARR)
+    pub(crate) lane_repeat_interval: f32,
+    /// Compared against on the results screen
+    pub(crate) friends: Vec<Friend>,
+    /// Nemesis practice session, until a new game is started from the main menu
+    #[serde(skip)]
+    pub(crate) practice: Option<TetrominoKind>,
+}
+
+/// A friend whose best runs are compared against on the results screen, read from their
+/// profile file, e.g. in a shared or synced folder.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Friend {
+    pub(crate) name: String,
+    pub(crate) profile: PathBuf,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ui_scale: 1.0,
+            volume: 1.0,
+            mode: GameMode::default(),
+            show_telemetry: false,
+            show_trajectory: false,
+            clean_feed: false,
+            fuse_modifier: false,
+            mirror_modifier: MirrorModifier::default(),
+            stamina_modifier: false,
+            side_walls: HashSet::new(),
+            camera_mode: CameraMode::default(),
+            board_layout: String::new(),
+            kind_glyphs: false,
+            skin: Skin::default(),
+            board_background: BoardBackground::default(),
+            board_grid: true,
+            idle_pause_seconds: 30.0,
+            idle_menu_seconds: 120.0,
+            physics: PhysicsTuning::default(),
+            row_clearing: RowClearing::default(),
+            slicing: false,
+            block_damage: false,
+            breakable_joints: false,
+            partial_rows: HashMap::new(),
+            one_switch_sweep_seconds: 0.0,
+            one_switch_accept_seconds: 0.1,
+            touch_buttons: false,
+            piece_sets: HashMap::new(),
+            legacy_piece_set: None,
+            versus_bot: false,
+            rotation_assist: false,
+            lane_snapping: false,
+            lane_repeat_delay: 0.25,
+            lane_repeat_interval: 0.1,
+            friends: vec![],
+            practice: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Delay and interval of lane snapping steps while held, `None` when held keys push freely.
+    pub(crate) fn lane_repeat(&self) -> Option<(f32, f32)> {
+        (self.lane_repeat_delay > 0.0)
+            .then_some((self.lane_repeat_delay, self.lane_repeat_interval))
+    }
+
+    /// Switch to a later or earlier board layout, applied on next launch.
+    pub(crate) fn cycle_board_layout(&mut self, direction: i32) {
+        let layouts = BoardLayout::all();
+        let index = layouts
+            .iter()
+            .position(|layout| layout.name == self.board_layout)
+            .unwrap_or(0);
+        let index = (index as i32 + direction).rem_euclid(layouts.len() as i32) as usize;
+        self.board_layout = layouts[index].name.clone();
+    }
+
+    /// Switch partial rows off, or to a higher or lower threshold, for the mode of the next run.
+    pub(crate) fn cycle_partial_rows(&mut self, direction: i32) {
+        // Off is the choice after the highest threshold
+        let n_choices = PARTIAL_ROW_THRESHOLDS.len() as i32 + 1;
+        let index = self
+            .partial_rows
+            .get(&self.mode)
+            .and_then(|threshold| {
+                PARTIAL_ROW_THRESHOLDS
+                    .iter()
+                    .position(|choice| choice == threshold)
+            })
+            .unwrap_or(PARTIAL_ROW_THRESHOLDS.len()) as i32;
+
+        match PARTIAL_ROW_THRESHOLDS.get((index + direction).rem_euclid(n_choices) as usize) {
+            Some(threshold) => self.partial_rows.insert(self.mode, *threshold),
+            None => self.partial_rows.remove(&self.mode),
+        };
+    }
+
+    pub(crate) fn piece_set(&self, mode: GameMode) -> PieceSet {
+        self.piece_sets.get(&mode).copied().unwrap_or_default()
+    }
+
+    /// Switch to the next or previous piece set for the mode of the next run.
+    pub(crate) fn cycle_piece_set(&mut self, piece_definitions: &PieceDefinitions, direction: i32) {
+        let sets = PieceSet::all(piece_definitions);
+        let index = sets
+            .iter()
+            .position(|set| *set == self.piece_set(self.mode))
+            .unwrap_or(0) as i32;
+        let set = sets[(index + direction).rem_euclid(sets.len() as i32) as usize];
+
+        if set == PieceSet::default() {
+            self.piece_sets.remove(&self.mode);
+        } else {
+            self.piece_sets.insert(self.mode, set);
+        }
+    }
+
+    pub(crate) fn path() -> Option<PathBuf> {
+        project_dirs().map(|dirs| dirs.config_dir().join("settings.ron"))
+    }
+
+    pub(crate) fn load() -> Self {
+        Self::path()
+            .and_then(|path| load_ron::<Self>(&path))
+            .unwrap_or_default()
+            .migrated()
+            .clamped()
+    }
+
+    /// Settings of older versions in their current place.
+    pub(crate) fn migrated(mut self) -> Self {
+        if let Some(set) = self.legacy_piece_set.take() {
+            if set != PieceSet::default() {
+                for mode in GameMode::ALL {
+                    self.piece_sets.entry(mode).or_insert(set);
+                }
+            }
+        }
+        self
+    }
+
+    /// The settings with the numbers edited out of range brought back within range, and those
+    /// that aren't numbers at all back to their defaults.
+    pub(crate) fn clamped(mut self) -> Self {
+        let default = Self::default();
+        let clamp = |value: f32, default: f32, (min, max): (f32, f32)| {
+            if value.is_finite() {
+                value.clamp(min, max)
+            } else {
+                default
+            }
+        };
+
+        self.ui_scale = clamp(
+            self.ui_scale,
+            default.ui_scale,
+            (UI_SCALE_MIN, UI_SCALE_MAX),
+        );
+        self.volume = clamp(self.volume, default.volume, VOLUME_RANGE);
+        self.lane_repeat_delay = clamp(
+            self.lane_repeat_delay,
+            default.lane_repeat_delay,
+            LANE_REPEAT_DELAY_RANGE,
+        );
+        self.lane_repeat_interval = clamp(
+            self.lane_repeat_interval,
+            default.lane_repeat_interval,
+            LANE_REPEAT_INTERVAL_RANGE,
+        );
+        self.physics = self.physics.clamped().unwrap_or(default.physics);
+        self
+    }
+
+    pub(crate) fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_ron(&path, self);
+        }
+    }
+}
+
+/// A field that was no `Option` when it was saved.
+fn deserialize_some<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Read a RON file. `None` if there is no such file, the default value if the file is invalid.
+pub(crate) fn load_ron<T: DeserializeOwned + Default>(path: &Path) -> Option<T> {
+    let contents = std::fs::read_to_string(path).ok()?;
+
+    Some(ron::from_str(&contents).unwrap_or_else(|err| {
+        warn!("invalid {path:?}: {err}");
+        T::default()
+    }))
+}
+
+pub(crate) fn save_ron<T: Serialize>(path: &Path, value: &T) {
+    let result = ron::ser::to_string_pretty(value, Default::default())
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            std::fs::write(path, contents).map_err(|err| err.to_string())
+        });
+
+    if let Err(err) = result {
+        warn!("could not save {path:?}: {err}");
+    }
+}
+
+/// Entries of the settings screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum SettingsItem {
+    BoardLayout,
+    UiScale,
+    Skin,
+    Background,
+    Grid,
+    Volume,
+    Damping,
+    MovementForce,
+    Torque,
+    RowClearing,
+    Slicing,
+    BlockDamage,
+    BreakableJoints,
+    PartialRows,
+    Pieces,
+    OneSwitchSweep,
+    OneSwitchAccept,
+    TouchControls,
+    VersusOpponent,
+    RotationAssist,
+    Movement,
+    LaneRepeatDelay,
+    LaneRepeatInterval,
+    KeyBindings,
+    Back,
+}
+
+impl SettingsItem {
+    pub(crate) const ALL: [Self; 25] = [
+        Self::BoardLayout,
+        Self::UiScale,
+        Self::Skin,
+        Self::Background,
+        Self::Grid,
+        Self::Volume,
+        Self::Damping,
+        Self::MovementForce,
+        Self::Torque,
+        Self::RowClearing,
+        Self::Slicing,
+        Self::BlockDamage,
+        Self::BreakableJoints,
+        Self::PartialRows,
+        Self::Pieces,
+        Self::OneSwitchSweep,
+        Self::OneSwitchAccept,
+        Self::TouchControls,
+        Self::VersusOpponent,
+        Self::RotationAssist,
+        Self::Movement,
+        Self::LaneRepeatDelay,
+        Self::LaneRepeatInterval,
+        Self::KeyBindings,
+        Self::Back,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::BoardLayout => "Board (next launch)",
+            Self::UiScale => "UI scale",
+            Self::Skin => "Block skin",
+            Self::Background => "Board background",
+            Self::Grid => "Board grid",
+            Self::Volume => "Volume",
+            Self::Damping => "Damping (next run)",
+            Self::MovementForce => "Push force (next run)",
+            Self::Torque => "Rotation torque (next run)",
+            Self::RowClearing => "Rows clear by (next run)",
+            Self::Slicing => "Slice tilted blocks (next run)",
+            Self::BlockDamage => "Block damage (next run)",
+            Self::BreakableJoints => "Breakable pieces (next run)",
+            Self::PartialRows => "Partial rows, mode of next run",
+            Self::Pieces => "Pieces, mode of next run",
+            Self::OneSwitchSweep => "One-switch sweep",
+            Self::OneSwitchAccept => "One-switch press time",
+            Self::TouchControls => "Touch controls",
+            Self::VersusOpponent => "Versus opponent (next run)",
+            Self::RotationAssist => "Rotation assist",
+            Self::Movement => "Movement",
+            Self::LaneRepeatDelay => "Lane step delay (DAS)",
+            Self::LaneRepeatInterval => "Lane step repeat (ARR)",
+            Self::KeyBindings => "Key bindings",
+            Self::Back => "Back",
+        }
+    }
+
+    /// The current value, `None` for entries that aren't values.
+    pub(crate) fn value(
+        &self,
+        settings: &Settings,
+        piece_definitions: &PieceDefinitions,
+    ) -> Option<String> {
+        match self {
+            Self::BoardLayout => Some(BoardLayout::named(&settings.board_layout).name),
+            Self::UiScale => Some(format!("{:.0}%", settings.ui_scale * 100.0)),
+            Self::Skin => Some(settings.skin.name().to_string()),
+            Self::Background => Some(settings.board_background.name().to_string()),
+            Self::Grid => Some(if settings.board_grid { "On" } else { "Off" }.to_string()),
+            Self::Volume => Some(format!("{:.0}%", settings.volume * 100.0)),
+            Self::Damping => Some(format!("{:.1}", settings.physics.linear_damping)),
+            Self::MovementForce => Some(format!("{:.0}", settings.physics.movement_force)),
+            Self::Torque => Some(format!("{:.0}", settings.physics.torque)),
+            Self::RowClearing => Some(settings.row_clearing.name()),
+            Self::Slicing => Some(if settings.slicing { "On" } else { "Off" }.to_string()),
+            Self::BlockDamage => Some(if settings.block_damage { "On" } else { "Off" }.to_string()),
+            Self::BreakableJoints => Some(
+                if settings.breakable_joints {
+                    "On"
+                } else {
+                    "Off"
+                }
+                .to_string(),
+            ),
+            Self::PartialRows => Some(match settings.partial_rows.get(&settings.mode) {
+                Some(threshold) => format!("{}: {:.0}%", settings.mode.name(), threshold * 100.0),
+                None => format!("{}: Off", settings.mode.name()),
+            }),
+            Self::Pieces => Some(format!(
+                "{}: {}",
+                settings.mode.name(),
+                settings.piece_set(settings.mode).name(piece_definitions)
+            )),
+            Self::OneSwitchSweep => Some(if settings.one_switch_sweep_seconds > 0.0 {
+                format!("{:.0} s", settings.one_switch_sweep_seconds)
+            } else {
+                "Off".to_string()
+            }),
+            Self::OneSwitchAccept => Some(format!("{:.2} s", settings.one_switch_accept_seconds)),
+            Self::TouchControls => Some(
+                if settings.touch_buttons {
+                    "Buttons"
+                } else {
+                    "Gestures"
+                }
+                .to_string(),
+            ),
+            Self::VersusOpponent => {
+                Some(if settings.versus_bot { "Bot" } else { "Player" }.to_string())
+            }
+            Self::RotationAssist => Some(
+                if settings.rotation_assist {
+                    "Snap to quarter turns"
+                } else {
+                    "Off"
+                }
+                .to_string(),
+            ),
+            Self::Movement => Some(
+                if settings.lane_snapping {
+                    "Lane snapping"
+                } else {
+                    "Hardcore"
+                }
+                .to_string(),
+            ),
+            Self::LaneRepeatDelay => Some(if settings.lane_repeat_delay > 0.0 {
+                format!("{:.2} s", settings.lane_repeat_delay)
+            } else {
+                "Off, hold pushes".to_string()
+            }),
+            Self::LaneRepeatInterval => Some(format!("{:.2} s", settings.lane_repeat_interval)),
+            Self::KeyBindings | Self::Back => None,
+        }
+    }
+
+    /// Step the value up or down, wrapping around at the ends.
+    pub(crate) fn adjust(
+        &self,
+        settings: &mut Settings,
+        piece_definitions: &PieceDefinitions,
+        direction: i32,
+    ) {
+        let physics = &mut settings.physics;
+        match self {
+            Self::BoardLayout => settings.cycle_board_layout(direction),
+            Self::UiScale => {
+                settings.ui_scale = step_wrapping(
+                    settings.ui_scale,
+                    UI_SCALE_STEP,
+                    UI_SCALE_MIN,
+                    UI_SCALE_MAX,
+                    direction,
+                )
+            }
+            Self::Skin => settings.skin = settings.skin.cycle(direction),
+            Self::Background => {
+                settings.board_background = settings.board_background.cycle(direction)
+            }
+            Self::Grid => settings.board_grid = !settings.board_grid,
+            Self::Volume => {
+                let (min, max) = VOLUME_RANGE;
+                settings.volume = step_wrapping(settings.volume, 0.1, min, max, direction)
+            }
+            Self::Damping => {
+                let (min, max) = LINEAR_DAMPING_RANGE;
+                physics.linear_damping =
+                    step_wrapping(physics.linear_damping, 1.0, min, max, direction)
+            }
+            Self::MovementForce => {
+                let (min, max) = MOVEMENT_FORCE_RANGE;
+                physics.movement_force =
+                    step_wrapping(physics.movement_force, 10.0, min, max, direction)
+            }
+            Self::Torque => {
+                let (min, max) = TORQUE_RANGE;
+                physics.torque = step_wrapping(physics.torque, 10.0, min, max, direction)
+            }
+            Self::RowClearing => settings.row_clearing = settings.row_clearing.cycle(direction),
+            Self::Slicing => settings.slicing = !settings.slicing,
+            Self::BlockDamage => settings.block_damage = !settings.block_damage,
+            Self::BreakableJoints => settings.breakable_joints = !settings.breakable_joints,
+            Self::PartialRows => settings.cycle_partial_rows(direction),
+            Self::Pieces => settings.cycle_piece_set(piece_definitions, direction),
+            Self::OneSwitchSweep => {
+                settings.one_switch_sweep_seconds =
+                    step_wrapping(settings.one_switch_sweep_seconds, 1.0, 0.0, 6.0, direction)
+            }
+            Self::OneSwitchAccept => {
+                settings.one_switch_accept_seconds = step_wrapping(
+                    settings.one_switch_accept_seconds,
+                    0.05,
+                    0.0,
+                    0.5,
+                    direction,
+                )
+            }
+            Self::TouchControls => settings.touch_buttons = !settings.touch_buttons,
+            Self::VersusOpponent => settings.versus_bot = !settings.versus_bot,
+            Self::RotationAssist => settings.rotation_assist = !settings.rotation_assist,
+            Self::Movement => settings.lane_snapping = !settings.lane_snapping,
+            Self::LaneRepeatDelay => {
+                let (min, max) = LANE_REPEAT_DELAY_RANGE;
+                settings.lane_repeat_delay =
+                    step_wrapping(settings.lane_repeat_delay, 0.05, min, max, direction)
+            }
+            Self::LaneRepeatInterval => {
+                let (min, max) = LANE_REPEAT_INTERVAL_RANGE;
+                settings.lane_repeat_interval =
+                    step_wrapping(settings.lane_repeat_interval, 0.02, min, max, direction)
+            }
+            Self::KeyBindings | Self::Back => {}
+        }
+    }
+}
+
+pub(crate) fn step_wrapping(value: f32, step: f32, min: f32, max: f32, direction: i32) -> f32 {
+    let value = value + step * direction as f32;
+    // Half a step of slack for rounding errors
+    if value > max + step * 0.5 {
+        min
+    } else if value < min - step * 0.5 {
+        max
+    } else {
+        value.clamp(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_steps_in_tenths_and_wraps_around() {
+        let piece_definitions = PieceDefinitions::built_in();
+        let mut settings = Settings::default();
+        assert_eq!(settings.volume, 1.0);
+
+        SettingsItem::Volume.adjust(&mut settings, &piece_definitions, -1);
+        assert!((settings.volume - 0.9).abs() < 1e-4);
+
+        settings.volume = 1.0;
+        SettingsItem::Volume.adjust(&mut settings, &piece_definitions, 1);
+        assert_eq!(settings.volume, 0.0);
+        SettingsItem::Volume.adjust(&mut settings, &piece_definitions, -1);
+        assert_eq!(settings.volume, 1.0);
+    }
+
+    #[test]
+    fn settings_files_without_a_volume_play_at_full_volume() {
+        let settings: Settings = ron::from_str("(ui_scale: 1.5)").unwrap();
+        assert_eq!(settings.ui_scale, 1.5);
+        assert_eq!(settings.volume, 1.0);
+
+        let settings = Settings {
+            volume: 0.3,
+            ..Default::default()
+        };
+        let saved = ron::to_string(&settings).unwrap();
+        let loaded: Settings = ron::from_str(&saved).unwrap();
+        assert_eq!(loaded.volume, 0.3);
+    }
+
+    #[test]
+    fn settings_edited_out_of_range_are_brought_back_within_range() {
+        let settings = ron::from_str::<Settings>(
+            "(ui_scale: 100.0, volume: -1.0, lane_repeat_interval: 0.0, \
+             physics: (linear_damping: 1000.0, movement_force: 0.0, torque: 100.0))",
+        )
+        .unwrap()
+        .clamped();
+        assert_eq!(settings.ui_scale, UI_SCALE_MAX);
+        assert_eq!(settings.volume, VOLUME_RANGE.0);
+        assert_eq!(settings.lane_repeat_interval, LANE_REPEAT_INTERVAL_RANGE.0);
+        assert_eq!(settings.physics.linear_damping, LINEAR_DAMPING_RANGE.1);
+        assert_eq!(settings.physics.movement_force, MOVEMENT_FORCE_RANGE.0);
+        assert_eq!(settings.physics.torque, 100.0);
+
+        let settings = Settings {
+            volume: f32::NAN,
+            lane_repeat_interval: f32::INFINITY,
+            ..Settings::default()
+        }
+        .clamped();
+        assert_eq!(settings.volume, Settings::default().volume);
+        assert_eq!(
+            settings.lane_repeat_interval,
+            Settings::default().lane_repeat_interval
+        );
+    }
+
+    #[test]
+    fn the_piece_set_of_older_versions_applies_to_every_mode() {
+        let settings = ron::from_str::<Settings>("(piece_set: Simple)")
+            .unwrap()
+            .migrated();
+        assert_eq!(settings.legacy_piece_set, None);
+        for mode in GameMode::ALL {
+            assert_eq!(settings.piece_set(mode), PieceSet::Simple);
+        }
+
+        let saved = ron::to_string(&settings).unwrap();
+        assert!(!saved.contains("piece_set:"), "{saved}");
+    }
+
+    #[test]
+    fn partial_rows_cycle_through_the_thresholds_for_the_mode_of_the_next_run() {
+        let piece_definitions = PieceDefinitions::built_in();
+        let mut settings = Settings::default();
+
+        SettingsItem::PartialRows.adjust(&mut settings, &piece_definitions, 1);
+        assert_eq!(settings.partial_rows.get(&GameMode::Classic), Some(&0.85));
+        SettingsItem::PartialRows.adjust(&mut settings, &piece_definitions, 1);
+        assert_eq!(settings.partial_rows.get(&GameMode::Classic), Some(&0.9));
+
+        settings.mode = GameMode::Chaos;
+        SettingsItem::PartialRows.adjust(&mut settings, &piece_definitions, -1);
+        assert_eq!(settings.partial_rows.get(&GameMode::Chaos), Some(&0.95));
+        SettingsItem::PartialRows.adjust(&mut settings, &piece_definitions, 1);
+        assert_eq!(settings.partial_rows.get(&GameMode::Chaos), None);
+        assert_eq!(settings.partial_rows.get(&GameMode::Classic), Some(&0.9));
+    }
+}
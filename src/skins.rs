@@ -0,0 +1,194 @@
+//! Block skins: textures for the blocks instead of flat colors, cut from a texture atlas
+//! described by `assets/skins/blocks.skins.ron`. The tiles are tinted with the colors of the
+//! blocks, so that weathering, balloons and flashes look the same with any skin.
+
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::math::Rect;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::texture::DEFAULT_IMAGE_HANDLE;
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+use crate::tetromino::{Block, TetrominoKind};
+
+/// Textures of the blocks, by the skin chosen in the settings.
+pub struct SkinsPlugin;
+
+impl Plugin for SkinsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<SkinAtlas>()
+            .init_asset_loader::<SkinAtlasLoader>()
+            .add_startup_system(load_skin_atlas)
+            .add_system(apply_skin);
+    }
+}
+
+pub(crate) const SKIN_ATLAS_PATH: &str = "skins/blocks.skins.ron";
+
+/// Look of the blocks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum Skin {
+    /// Plain colored squares
+    #[default]
+    Flat,
+    Beveled,
+    Glossy,
+    Retro,
+}
+
+impl Skin {
+    pub(crate) const ALL: [Self; 4] = [Self::Flat, Self::Beveled, Self::Glossy, Self::Retro];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Flat => "Flat",
+            Self::Beveled => "Beveled",
+            Self::Glossy => "Glossy",
+            Self::Retro => "Retro",
+        }
+    }
+
+    pub(crate) fn cycle(&self, direction: i32) -> Self {
+        let index = Self::ALL.iter().position(|skin| skin == self).unwrap_or(0);
+        Self::ALL[(index as i32 + direction).rem_euclid(Self::ALL.len() as i32) as usize]
+    }
+}
+
+/// Where the tiles of each skin are in the atlas image.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "b3e7d0a4-5c21-4f8e-a6d9-71f02c8e4b35"]
+pub(crate) struct SkinAtlas {
+    /// Path of the image in the assets
+    pub(crate) image: String,
+    /// Side length of a tile, in pixels
+    pub(crate) tile_size: f32,
+    /// Skins by row of tiles
+    pub(crate) skins: Vec<Skin>,
+    /// Kinds by column of tiles. Polyominoes use the column after these.
+    pub(crate) kinds: Vec<TetrominoKind>,
+}
+
+impl SkinAtlas {
+    /// Area of the image with the tile of a kind in a skin, `None` for skins not in the atlas.
+    pub(crate) fn tile(&self, skin: Skin, kind: TetrominoKind) -> Option<Rect> {
+        let row = self.skins.iter().position(|other| *other == skin)?;
+        let column = self
+            .kinds
+            .iter()
+            .position(|other| *other == kind)
+            .unwrap_or(self.kinds.len());
+        let min = Vec2::new(column as f32, row as f32) * self.tile_size;
+
+        Some(Rect::from_corners(min, min + Vec2::splat(self.tile_size)))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SkinAtlasLoader;
+
+impl AssetLoader for SkinAtlasLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let atlas: SkinAtlas = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(atlas));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["skins.ron"]
+    }
+}
+
+/// The skin atlas, and its image once the atlas is loaded.
+#[derive(Resource)]
+pub(crate) struct SkinTextures {
+    pub(crate) atlas: Handle<SkinAtlas>,
+    pub(crate) image: Handle<Image>,
+}
+
+pub(crate) fn load_skin_atlas(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SkinTextures {
+        atlas: asset_server.load(SKIN_ATLAS_PATH),
+        image: Handle::default(),
+    });
+}
+
+/// Texture new blocks with the chosen skin, and all blocks when the skin or the atlas changes.
+/// Blocks stay flat until the atlas is loaded. Changes to the image itself show by themselves.
+pub(crate) fn apply_skin(
+    settings: Res<Settings>,
+    asset_server: Res<AssetServer>,
+    mut textures: ResMut<SkinTextures>,
+    mut atlas_events: EventReader<AssetEvent<SkinAtlas>>,
+    atlases: Res<Assets<SkinAtlas>>,
+    mut block_query: Query<(Ref<Block>, &mut Sprite, &mut Handle<Image>)>,
+) {
+    let reloaded = atlas_events.iter().any(|event| {
+        matches!(
+            event,
+            AssetEvent::Created { .. } | AssetEvent::Modified { .. }
+        )
+    });
+    let atlas = atlases.get(&textures.atlas);
+    if reloaded {
+        if let Some(atlas) = atlas {
+            textures.image = asset_server.load(atlas.image.as_str());
+        }
+    }
+    let refresh = reloaded || settings.is_changed();
+
+    for (block, mut sprite, mut texture) in block_query.iter_mut() {
+        if !refresh && !block.is_added() {
+            continue;
+        }
+
+        match atlas.and_then(|atlas| atlas.tile(settings.skin, block.kind)) {
+            Some(tile) => {
+                *texture = textures.image.clone();
+                sprite.rect = Some(tile);
+            }
+            None => {
+                *texture = DEFAULT_IMAGE_HANDLE.typed();
+                sprite.rect = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atlas() -> SkinAtlas {
+        ron::from_str(include_str!("../assets/skins/blocks.skins.ron")).expect("invalid skin atlas")
+    }
+
+    #[test]
+    fn tiles_are_found_by_skin_row_and_kind_column() {
+        let atlas = atlas();
+
+        assert_eq!(
+            atlas.tile(Skin::Glossy, TetrominoKind::T),
+            Some(Rect::new(64.0, 32.0, 96.0, 64.0))
+        );
+        assert_eq!(
+            atlas.tile(Skin::Retro, TetrominoKind::Polyomino(3)),
+            Some(Rect::new(224.0, 64.0, 256.0, 96.0))
+        );
+        assert_eq!(atlas.tile(Skin::Flat, TetrominoKind::I), None);
+    }
+
+    #[test]
+    fn skins_cycle_around_both_ways() {
+        assert_eq!(Skin::Flat.cycle(1), Skin::Beveled);
+        assert_eq!(Skin::Flat.cycle(-1), Skin::Retro);
+        assert_eq!(Skin::Retro.cycle(1), Skin::Flat);
+    }
+}
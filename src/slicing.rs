@@ -0,0 +1,289 @@
+//! Slicing tilted blocks on a clear: the parts of a block outside the cleared rows live on as
+//! polygon fragments with colliders of their own.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::sprite::Mesh2dHandle;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::Board;
+use crate::tetromino::{Block, TetrominoKind};
+
+/// Meshes of the fragments of sliced blocks.
+pub struct SlicingPlugin;
+
+impl Plugin for SlicingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(attach_fragment_meshes);
+    }
+}
+
+// Parts of a sliced block smaller than this area are cleared with the rest of it
+pub(crate) const FRAGMENT_MIN_AREA: f32 = 0.1;
+
+/// Block cut from a sliced block, shaped by its convex polygon instead of a square. Its `Block`
+/// size is that of a square of the same area, so it counts towards rows by its area.
+#[derive(Component)]
+pub(crate) struct Fragment {
+    /// Counter-clockwise, relative to the center of mass
+    pub(crate) points: Vec<Vec2>,
+    pub(crate) color: Color,
+}
+
+impl Fragment {
+    pub(crate) fn mesh(&self) -> Mesh {
+        let n_points = self.points.len();
+        let positions: Vec<[f32; 3]> = self
+            .points
+            .iter()
+            .map(|point| [point.x, point.y, 0.0])
+            .collect();
+        // The polygon is convex, so a fan of triangles covers it
+        let indices: Vec<u32> = (1..n_points.saturating_sub(1) as u32)
+            .flat_map(|i| [0, i, i + 1])
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; n_points]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; n_points]);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+/// Corners of a block, counter-clockwise, in world space.
+pub(crate) fn block_polygon(block: &Block, transform: &Transform) -> Vec<Vec2> {
+    let half_size = block.size * 0.5;
+
+    [
+        Vec2::new(-half_size, -half_size),
+        Vec2::new(half_size, -half_size),
+        Vec2::new(half_size, half_size),
+        Vec2::new(-half_size, half_size),
+    ]
+    .into_iter()
+    .map(|corner| {
+        transform.translation.truncate() + (transform.rotation * corner.extend(0.0)).truncate()
+    })
+    .collect()
+}
+
+/// The part of a convex polygon above or below the line at `y`.
+pub(crate) fn clip_polygon(points: &[Vec2], y: f32, keep_above: bool) -> Vec<Vec2> {
+    let inside = |point: Vec2| {
+        if keep_above {
+            point.y >= y
+        } else {
+            point.y <= y
+        }
+    };
+    let mut clipped = vec![];
+
+    for (index, current) in points.iter().enumerate() {
+        let next = points[(index + 1) % points.len()];
+
+        if inside(*current) {
+            clipped.push(*current);
+        }
+        if inside(*current) != inside(next) {
+            let t = (y - current.y) / (next.y - current.y);
+            clipped.push(current.lerp(next, t));
+        }
+    }
+
+    clipped
+}
+
+/// Area and center of mass of a polygon.
+pub(crate) fn polygon_area_centroid(points: &[Vec2]) -> (f32, Vec2) {
+    let mut area = 0.0;
+    let mut centroid = Vec2::ZERO;
+
+    for (index, current) in points.iter().enumerate() {
+        let next = points[(index + 1) % points.len()];
+        let cross = current.perp_dot(next);
+        area += cross;
+        centroid += (*current + next) * cross;
+    }
+
+    area *= 0.5;
+    if area.abs() < f32::EPSILON {
+        return (0.0, Vec2::ZERO);
+    }
+
+    (area.abs(), centroid / (6.0 * area))
+}
+
+/// Corners of a fragment, counter-clockwise, in world space.
+pub(crate) fn fragment_polygon(fragment: &Fragment, transform: &Transform) -> Vec<Vec2> {
+    fragment
+        .points
+        .iter()
+        .map(|point| transform.transform_point(point.extend(0.0)).truncate())
+        .collect()
+}
+
+/// The parts of a block outside the cleared rows, big enough to keep. A fragment is cut by its
+/// own polygon, so that it keeps its shape and area.
+pub(crate) fn slice_block(
+    block: &Block,
+    fragment: Option<&Fragment>,
+    transform: &Transform,
+    cleared_rows: &[Rect],
+) -> Vec<Vec<Vec2>> {
+    let mut parts = vec![match fragment {
+        Some(fragment) => fragment_polygon(fragment, transform),
+        None => block_polygon(block, transform),
+    }];
+
+    for row in cleared_rows {
+        parts = parts
+            .iter()
+            .flat_map(|part| {
+                [
+                    clip_polygon(part, row.max.y, true),
+                    clip_polygon(part, row.min.y, false),
+                ]
+            })
+            .filter(|part| part.len() >= 3)
+            .collect();
+    }
+
+    parts.retain(|part| polygon_area_centroid(part).0 >= FRAGMENT_MIN_AREA);
+    parts
+}
+
+/// Spawn a fragment of a block from its polygon, in world space. Its mesh is attached by
+/// `attach_fragment_meshes`.
+pub(crate) fn spawn_fragment(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &Board,
+    kind: TetrominoKind,
+    color: Color,
+    polygon: Vec<Vec2>,
+) {
+    let (area, centroid) = polygon_area_centroid(&polygon);
+    let points: Vec<Vec2> = polygon.iter().map(|point| *point - centroid).collect();
+    let collider =
+        Collider::convex_hull(&points).unwrap_or_else(|| Collider::ball(area.sqrt() * 0.5));
+
+    commands
+        .spawn(SpatialBundle::from_transform(Transform::from_translation(
+            centroid.extend(0.0),
+        )))
+        .insert(RigidBody::Dynamic)
+        .insert(collider)
+        .insert(Damping {
+            linear_damping: board.physics.linear_damping,
+            angular_damping: 0.0,
+        })
+        .insert(Sleeping {
+            linear_threshold: 10.0,
+            angular_threshold: 10.0,
+            sleeping: false,
+        })
+        .insert(ExternalForce::default())
+        .insert(ExternalImpulse::default())
+        .insert(GravityScale::default())
+        .insert(Velocity::default())
+        .insert(ReadMassProperties::default())
+        .insert(Block {
+            board: board_entity,
+            kind,
+            size: area.sqrt(),
+        })
+        .insert(Fragment { points, color });
+}
+
+pub(crate) fn attach_fragment_meshes(
+    mut commands: Commands,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
+    fragment_query: Query<(Entity, &Fragment), Added<Fragment>>,
+) {
+    // Not rendered when running headless
+    let (Some(mut meshes), Some(mut materials)) = (meshes, materials) else {
+        return;
+    };
+
+    for (entity, fragment) in fragment_query.iter() {
+        commands.entity(entity).insert((
+            Mesh2dHandle(meshes.add(fragment.mesh())),
+            materials.add(ColorMaterial::from(fragment.color)),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+    use super::*;
+
+    fn block(size: f32) -> Block {
+        Block {
+            board: Entity::from_raw(0),
+            kind: TetrominoKind::T,
+            size,
+        }
+    }
+
+    #[test]
+    fn a_block_squarely_in_a_cleared_row_leaves_nothing() {
+        let row = Rect::new(-5.0, -0.5, 5.0, 0.5);
+
+        assert!(slice_block(&block(1.0), None, &Transform::IDENTITY, &[row]).is_empty());
+    }
+
+    #[test]
+    fn a_tilted_block_keeps_its_corners_outside_the_cleared_row() {
+        let row = Rect::new(-5.0, -0.5, 5.0, 0.5);
+        let transform = Transform::from_rotation(Quat::from_rotation_z(FRAC_PI_4));
+
+        let parts = slice_block(&block(2.0), None, &transform, &[row]);
+        assert_eq!(parts.len(), 2);
+
+        // Each corner sticking out is a right triangle as high as half its base
+        let height = SQRT_2 - 0.5;
+        for part in &parts {
+            let (area, centroid) = polygon_area_centroid(part);
+            assert!((area - height * height).abs() < 1e-4);
+            assert!(centroid.y.abs() > 0.5);
+        }
+
+        // Small corners are dropped
+        assert!(slice_block(&block(1.0), None, &transform, &[row]).is_empty());
+    }
+
+    #[test]
+    fn a_fragment_is_cut_again_by_its_own_shape() {
+        // A triangle, whose square of the same area would stick out of the row below
+        let (area, centroid) = polygon_area_centroid(&[
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 2.0),
+        ]);
+        let fragment = Fragment {
+            points: [
+                Vec2::new(-1.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(0.0, 2.0),
+            ]
+            .map(|point| point - centroid)
+            .to_vec(),
+            color: Color::WHITE,
+        };
+        let transform = Transform::from_translation(centroid.extend(0.0));
+        let row = Rect::new(-5.0, 1.0, 5.0, 3.0);
+
+        // Only the part below the row is left, a trapezoid of three quarters of the area
+        let parts = slice_block(&block(area.sqrt()), Some(&fragment), &transform, &[row]);
+        assert_eq!(parts.len(), 1);
+        let (part_area, part_centroid) = polygon_area_centroid(&parts[0]);
+        assert!((part_area - area * 0.75).abs() < 1e-4);
+        assert!(part_centroid.y < 1.0);
+        assert!(parts[0].iter().all(|point| point.x.abs() <= 1.0 + 1e-4));
+    }
+}
@@ -0,0 +1,299 @@
+//! Sticky mode: blocks coming to rest weld to the blocks they touch, growing a welded structure.
+//! The contacts between blocks are tracked in a graph from the collision events of Rapier, and
+//! welds go with the blocks they hold, such as when a row is cleared through them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::board::Board;
+use crate::modes::GameMode;
+use crate::tetromino::{tetromino_sleep_detection, Block, PieceSettled};
+use crate::GameplaySet;
+
+/// The contact graph of the blocks, and the welds of Sticky mode.
+pub struct StickyPlugin;
+
+impl Plugin for StickyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ContactGraph>()
+            .add_system(update_contact_graph)
+            .add_system(
+                weld_settled_blocks
+                    .after(update_contact_graph)
+                    .after(tetromino_sleep_detection)
+                    .in_set(GameplaySet),
+            )
+            .add_system(break_welds.before(weld_settled_blocks));
+    }
+}
+
+/// Blocks touching each other, on boards that track contacts, and the welds between them.
+/// Ordered, so that blocks are welded in the same order every time.
+#[derive(Resource, Default)]
+pub(crate) struct ContactGraph {
+    pub(crate) contacts: BTreeMap<Entity, BTreeSet<Entity>>,
+    /// Welded pairs of blocks, lowest entity first
+    pub(crate) welds: BTreeSet<(Entity, Entity)>,
+}
+
+impl ContactGraph {
+    pub(crate) fn touching(&self, block_entity: Entity) -> impl Iterator<Item = &Entity> {
+        self.contacts.get(&block_entity).into_iter().flatten()
+    }
+
+    /// Forget a block that is gone, with its contacts and welds.
+    pub(crate) fn remove(&mut self, block_entity: Entity) {
+        if let Some(others) = self.contacts.remove(&block_entity) {
+            for other in others {
+                if let Some(contacts) = self.contacts.get_mut(&other) {
+                    contacts.remove(&block_entity);
+                }
+            }
+        }
+        self.welds
+            .retain(|(a, b)| *a != block_entity && *b != block_entity);
+    }
+}
+
+fn pair(a: Entity, b: Entity) -> (Entity, Entity) {
+    (a.min(b), a.max(b))
+}
+
+/// Joint welding two blocks together, a child of the second one.
+#[derive(Component)]
+pub(crate) struct Weld {
+    pub(crate) blocks: (Entity, Entity),
+}
+
+pub(crate) fn update_contact_graph(
+    mut graph: ResMut<ContactGraph>,
+    mut collision_events: EventReader<CollisionEvent>,
+    block_query: Query<&Block>,
+) {
+    for event in collision_events.iter() {
+        match event {
+            CollisionEvent::Started(a, b, _) => {
+                // Blocks of the same board only, not the floor or the walls
+                let (Ok(block_a), Ok(block_b)) = (block_query.get(*a), block_query.get(*b)) else {
+                    continue;
+                };
+                if block_a.board != block_b.board {
+                    continue;
+                }
+
+                graph.contacts.entry(*a).or_default().insert(*b);
+                graph.contacts.entry(*b).or_default().insert(*a);
+            }
+            CollisionEvent::Stopped(a, b, _) => {
+                if let Some(contacts) = graph.contacts.get_mut(a) {
+                    contacts.remove(b);
+                }
+                if let Some(contacts) = graph.contacts.get_mut(b) {
+                    contacts.remove(a);
+                }
+            }
+        }
+    }
+}
+
+/// Weld the blocks of each piece settling on a Sticky board to the settled blocks they touch,
+/// and to each other, keeping them as they lie.
+pub(crate) fn weld_settled_blocks(
+    mut commands: Commands,
+    mut graph: ResMut<ContactGraph>,
+    mut settled_events: EventReader<PieceSettled>,
+    board_query: Query<&Board>,
+    block_query: Query<(&Transform, &Sleeping), With<Block>>,
+) {
+    for event in settled_events.iter() {
+        let Ok(board) = board_query.get(event.board) else {
+            continue;
+        };
+        if board.mode != GameMode::Sticky {
+            continue;
+        }
+
+        for block_entity in &event.blocks {
+            let touching: Vec<Entity> = graph.touching(*block_entity).copied().collect();
+
+            for other in touching {
+                let blocks = pair(*block_entity, other);
+                if graph.welds.contains(&blocks) {
+                    continue;
+                }
+
+                // Only to blocks at rest, or of the same piece
+                let resting = block_query
+                    .get(other)
+                    .is_ok_and(|(_, sleeping)| sleeping.sleeping);
+                if !resting && !event.blocks.contains(&other) {
+                    continue;
+                }
+
+                let (Ok((transform1, _)), Ok((transform2, _))) =
+                    (block_query.get(blocks.0), block_query.get(blocks.1))
+                else {
+                    continue;
+                };
+
+                let (_, _, angle1) = transform1.rotation.to_euler(EulerRot::XYZ);
+                let (_, _, angle2) = transform2.rotation.to_euler(EulerRot::XYZ);
+                let position1 = transform1.translation.truncate();
+                let position2 = transform2.translation.truncate();
+                let middle = (position1 + position2) * 0.5;
+
+                // Both frames at the middle, turned alike
+                let joint = FixedJointBuilder::new()
+                    .local_anchor1(Vec2::from_angle(-angle1).rotate(middle - position1))
+                    .local_anchor2(Vec2::from_angle(-angle2).rotate(middle - position2))
+                    .local_basis2(angle1 - angle2);
+
+                commands.entity(blocks.1).with_children(|parent| {
+                    parent
+                        .spawn(ImpulseJoint::new(blocks.0, joint))
+                        .insert(Weld { blocks });
+                });
+                graph.welds.insert(blocks);
+            }
+        }
+    }
+}
+
+/// Break the welds of blocks that are gone from the game, such as cleared or sliced along a
+/// row, and forget the blocks.
+pub(crate) fn break_welds(
+    mut commands: Commands,
+    mut graph: ResMut<ContactGraph>,
+    block_query: Query<(), With<Block>>,
+    weld_query: Query<(Entity, &Weld)>,
+) {
+    for (weld_entity, weld) in weld_query.iter() {
+        let (a, b) = weld.blocks;
+        if !block_query.contains(a) || !block_query.contains(b) {
+            commands.entity(weld_entity).despawn();
+        }
+    }
+
+    let gone: Vec<Entity> = graph
+        .contacts
+        .keys()
+        .copied()
+        .filter(|block_entity| !block_query.contains(*block_entity))
+        .collect();
+    for block_entity in gone {
+        graph.remove(block_entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier2d::rapier::geometry::CollisionEventFlags;
+
+    use super::*;
+    use crate::board::BoardLayout;
+    use crate::settings::test_world;
+    use crate::tetromino::TetrominoKind;
+
+    fn spawn_block(world: &mut World, board: Entity, x: f32, sleeping: bool) -> Entity {
+        world
+            .spawn((
+                Block {
+                    board,
+                    kind: TetrominoKind::O,
+                    size: 1.0,
+                },
+                Transform::from_xyz(x, 0.0, 0.0),
+                Sleeping {
+                    sleeping,
+                    ..Default::default()
+                },
+            ))
+            .id()
+    }
+
+    fn world() -> (World, Entity) {
+        let mut world = test_world();
+        world.init_resource::<ContactGraph>();
+        world.init_resource::<Events<CollisionEvent>>();
+        world.init_resource::<Events<PieceSettled>>();
+        let board = world
+            .spawn(Board::new(
+                BoardLayout::named("Standard"),
+                GameMode::Sticky,
+                Vec2::ZERO,
+            ))
+            .id();
+        (world, board)
+    }
+
+    fn run(world: &mut World) {
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_contact_graph);
+        schedule.add_system(weld_settled_blocks.after(update_contact_graph));
+        schedule.add_system(break_welds.before(weld_settled_blocks));
+        schedule.run(world);
+        world.resource_mut::<Events<CollisionEvent>>().update();
+        world.resource_mut::<Events<PieceSettled>>().update();
+    }
+
+    fn touch(world: &mut World, a: Entity, b: Entity) {
+        world.send_event(CollisionEvent::Started(a, b, CollisionEventFlags::empty()));
+    }
+
+    #[test]
+    fn contacts_are_tracked_between_blocks_of_the_same_board() {
+        let (mut world, board) = world();
+        let other_board = world.spawn_empty().id();
+        let a = spawn_block(&mut world, board, 0.0, true);
+        let b = spawn_block(&mut world, board, 1.0, true);
+        let c = spawn_block(&mut world, other_board, 1.0, true);
+
+        touch(&mut world, a, b);
+        touch(&mut world, a, c);
+        run(&mut world);
+        let graph = world.resource::<ContactGraph>();
+        assert_eq!(graph.touching(a).collect::<Vec<_>>(), [&b]);
+        assert_eq!(graph.touching(b).collect::<Vec<_>>(), [&a]);
+
+        world.send_event(CollisionEvent::Stopped(a, b, CollisionEventFlags::empty()));
+        run(&mut world);
+        assert_eq!(world.resource::<ContactGraph>().touching(a).count(), 0);
+    }
+
+    #[test]
+    fn settled_pieces_weld_to_resting_blocks_until_one_is_gone() {
+        let (mut world, board) = world();
+        let resting = spawn_block(&mut world, board, 0.0, true);
+        let moving = spawn_block(&mut world, board, 2.0, false);
+        let settled = spawn_block(&mut world, board, 1.0, true);
+
+        touch(&mut world, settled, resting);
+        touch(&mut world, settled, moving);
+        world.send_event(PieceSettled {
+            board,
+            blocks: vec![settled],
+        });
+        run(&mut world);
+
+        let welds: Vec<(Entity, Entity)> = world
+            .query::<&Weld>()
+            .iter(&world)
+            .map(|weld| weld.blocks)
+            .collect();
+        assert_eq!(welds, [pair(settled, resting)]);
+        assert!(world
+            .resource::<ContactGraph>()
+            .welds
+            .contains(&pair(resting, settled)));
+
+        // Cleared
+        world.entity_mut(resting).remove::<Block>();
+        run(&mut world);
+        assert_eq!(world.query::<&Weld>().iter(&world).count(), 0);
+        let graph = world.resource::<ContactGraph>();
+        assert!(graph.welds.is_empty());
+        assert_eq!(graph.touching(settled).collect::<Vec<_>>(), [&moving]);
+    }
+}
@@ -0,0 +1,2001 @@
+//! Pieces and their blocks, from spawning until they settle.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Board, GameRng, GAME_OVER_TIME_SCALE, SPAWN_AREA_MARGIN};
+use crate::bot::Bot;
+use crate::coop::{coop_receive, CoopSession};
+use crate::effects::spawn_sparks;
+use crate::input::{
+    gameplay_input_allowed, update_action_state, Action, ActionState, InputMap, PieceInput,
+    ANALOG_DEAD_ZONE,
+};
+use crate::modes::{Bomb, GameMode, Junk, BOMB_CHANCE};
+use crate::online::{online_receive, OnlineSession, Referee};
+use crate::physics::{tetromino_movement, GameTimeScale, TimeScaleSource};
+use crate::pieces::{PieceDefinitions, PIECE_SET_PATHS};
+use crate::scoring::{clear_filled_rows, BoardStats, CascadeChain, RowCleared};
+use crate::settings::Settings;
+use crate::slicing::Fragment;
+use crate::ui::{spawn_toast, Fonts, WORLD_TEXT_FONT_SIZE, WORLD_TEXT_SCALE};
+use crate::versus::Garbage;
+use crate::GameplaySet;
+
+/// Spawning, holding and settling pieces, and the modifiers changing them.
+pub struct TetrominoPlugin;
+
+impl Plugin for TetrominoPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PieceSettled>()
+            .add_system(
+                pre_rotate_growing_pieces
+                    .after(update_action_state)
+                    .after(coop_receive)
+                    .after(online_receive)
+                    .before(piece_spawn_growth)
+                    .in_set(GameplaySet)
+                    .run_if(gameplay_input_allowed),
+            )
+            .add_system(piece_spawn_growth.in_set(GameplaySet))
+            .add_system(
+                piece_fuse
+                    .before(tetromino_sleep_detection)
+                    .in_set(GameplaySet),
+            )
+            .add_system(update_fuse_bar.after(piece_fuse))
+            .add_system(toggle_fuse_modifier)
+            .add_system(cycle_mirror_modifier)
+            .add_system(attach_kind_glyphs)
+            .add_system(toggle_kind_glyphs)
+            .add_system(
+                hold_piece
+                    .after(update_action_state)
+                    .after(coop_receive)
+                    .after(online_receive)
+                    .before(tetromino_movement)
+                    .in_set(GameplaySet)
+                    .run_if(gameplay_input_allowed),
+            )
+            .add_system(block_weathering)
+            .add_system(break_joints.in_set(GameplaySet))
+            .add_system(
+                settle_still_pieces
+                    .after(piece_fuse)
+                    .before(tetromino_sleep_detection)
+                    .in_set(GameplaySet),
+            )
+            .add_system(tetromino_sleep_detection.in_set(GameplaySet));
+    }
+}
+
+// Fuse modifier, in scaled seconds:
+pub(crate) const FUSE_DURATION: f32 = 12.0;
+pub(crate) const FUSE_PENALTY_POINTS: i32 = 30;
+pub(crate) const FUSE_BAR_HEIGHT: f32 = 0.2;
+
+pub(crate) const BALLOON_CHANCE: f64 = 0.04;
+// Share of the pieces that are the practiced kind in a nemesis practice session
+pub(crate) const PRACTICE_PIECE_SHARE: f64 = 0.7;
+// Fraction of their weight that balloon blocks are lifted by
+pub(crate) const BALLOON_BUOYANCY: f32 = 0.85;
+pub(crate) const BALLOON_ALPHA: f32 = 0.6;
+// Chance of a piece being of a special material, either ice or rubber
+pub(crate) const SPECIAL_MATERIAL_CHANCE: f64 = 0.06;
+// Side of the overlay marking the material of a block, relative to the block
+pub(crate) const MATERIAL_OVERLAY_SIZE: f32 = 0.6;
+// Breakable joints snap under a larger impulse than this in a physics step, from a slam or a hard
+// drop landing, but not from pushing and turning
+pub(crate) const JOINT_BREAK_IMPULSE: f32 = 6.0;
+
+// Settled blocks fade towards dust over this many scaled seconds
+pub(crate) const WEATHERING_DURATION: f32 = 90.0;
+pub(crate) const WEATHERING_COLOR: Color = Color::rgb(0.45, 0.42, 0.38);
+// How far the color fades at most
+pub(crate) const WEATHERING_MAX_AMOUNT: f32 = 0.45;
+
+// A piece resting on something, with every block moving and turning slower than this in blocks
+// and radians per second...
+pub(crate) const STILL_LINEAR_SPEED: f32 = 0.2;
+pub(crate) const STILL_ANGULAR_SPEED: f32 = 0.2;
+// ...for this many scaled seconds has settled, even when Rapier hasn't put it to sleep
+pub(crate) const STILL_SETTLE_DURATION: f32 = 1.0;
+
+// In scaled seconds:
+pub(crate) const SPAWN_GROWTH_DURATION: f32 = 0.3;
+// Rapier can't handle zero-sized colliders
+pub(crate) const SPAWN_GROWTH_MIN_SCALE: f32 = 0.01;
+
+/// Modifier flipping piece layouts horizontally.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum MirrorModifier {
+    #[default]
+    Off,
+    /// Every piece is mirrored
+    Always,
+    /// Each piece has an even chance of being mirrored
+    Random,
+}
+
+impl MirrorModifier {
+    pub(crate) const ALL: [Self; 3] = [Self::Off, Self::Always, Self::Random];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Always => "always",
+            Self::Random => "random",
+        }
+    }
+
+    pub(crate) fn next(&self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|mirror| mirror == self)
+            .unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub(crate) fn mirror_next_piece(&self, rng: &mut impl Rng) -> bool {
+        match self {
+            Self::Off => false,
+            Self::Always => true,
+            Self::Random => rng.gen_bool(0.5),
+        }
+    }
+}
+
+/// Kinds of pieces dealt in a run, chosen for a custom game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum PieceSet {
+    #[default]
+    All,
+    /// Without the S and Z pieces, which leave the most gaps
+    NoSnakes,
+    /// Only the I, O and T pieces
+    Simple,
+    /// Only the S and Z pieces
+    Snakes,
+    /// A set defined in `assets/pieces/`, by its index in `PIECE_SET_PATHS`
+    Custom(u8),
+}
+
+impl PieceSet {
+    pub(crate) const ALL: [Self; 4] = [Self::All, Self::NoSnakes, Self::Simple, Self::Snakes];
+
+    /// The built-in sets, then those defined in `assets/pieces/`.
+    pub(crate) fn all(definitions: &PieceDefinitions) -> Vec<Self> {
+        let loaded = definitions
+            .sets
+            .iter()
+            .enumerate()
+            .filter(|(_, set)| set.is_some())
+            .map(|(index, _)| Self::Custom(index as u8));
+        Self::ALL.into_iter().chain(loaded).collect()
+    }
+
+    /// Name in challenge codes, the same whichever sets are loaded.
+    pub(crate) fn code_name(&self) -> String {
+        match self {
+            // The name of the file, without the path and extension
+            Self::Custom(index) => PIECE_SET_PATHS
+                .get(*index as usize)
+                .and_then(|path| path.rsplit('/').next())
+                .and_then(|file| file.split('.').next())
+                .unwrap_or_default()
+                .to_string(),
+            _ => format!("{self:?}"),
+        }
+    }
+
+    pub(crate) fn from_code_name(name: &str) -> Option<Self> {
+        let custom = (0..PIECE_SET_PATHS.len()).map(|index| Self::Custom(index as u8));
+        Self::ALL
+            .into_iter()
+            .chain(custom)
+            .find(|set| set.code_name() == name)
+    }
+
+    pub(crate) fn name<'a>(&self, definitions: &'a PieceDefinitions) -> &'a str {
+        match self {
+            Self::All => "All",
+            Self::NoSnakes => "No S/Z",
+            Self::Simple => "I, O and T",
+            Self::Snakes => "Only S/Z",
+            Self::Custom(index) => definitions
+                .sets
+                .get(*index as usize)
+                .and_then(Option::as_ref)
+                .map_or("Custom", |set| set.name.as_str()),
+        }
+    }
+
+    /// The kinds dealt. A set that isn't loaded deals all the tetrominoes.
+    pub(crate) fn kinds(&self, definitions: &PieceDefinitions) -> Vec<TetrominoKind> {
+        match self {
+            Self::All => TetrominoKind::ALL.to_vec(),
+            Self::NoSnakes => vec![
+                TetrominoKind::I,
+                TetrominoKind::O,
+                TetrominoKind::T,
+                TetrominoKind::J,
+                TetrominoKind::L,
+            ],
+            Self::Simple => vec![TetrominoKind::I, TetrominoKind::O, TetrominoKind::T],
+            Self::Snakes => vec![TetrominoKind::S, TetrominoKind::Z],
+            Self::Custom(index) => definitions
+                .set_kinds(*index as usize)
+                .unwrap_or_else(|| TetrominoKind::ALL.to_vec()),
+        }
+    }
+
+    pub(crate) fn random(
+        &self,
+        definitions: &PieceDefinitions,
+        rng: &mut impl Rng,
+    ) -> TetrominoKind {
+        match self {
+            // The same draws as before there were piece sets, so that seeds still reproduce runs
+            Self::All => TetrominoKind::random(definitions, rng),
+            _ => {
+                let kinds = self.kinds(definitions);
+                if definitions.uniform() {
+                    kinds[rng.gen_range(0..kinds.len())]
+                } else {
+                    definitions.weighted(&kinds, rng)
+                }
+            }
+        }
+    }
+}
+
+/// Sent when the current tetromino of a board has come to rest.
+pub(crate) struct PieceSettled {
+    pub(crate) board: Entity,
+    pub(crate) blocks: Vec<Entity>,
+}
+
+/// One tetromino being controlled on a board.
+#[derive(Default)]
+pub(crate) struct ActivePiece {
+    /// Ordered, so that runs with the same input visit the blocks in the same order
+    pub(crate) blocks: BTreeSet<Entity>,
+    pub(crate) joints: Vec<Entity>,
+    /// Joints to attach once the blocks have grown: parent block, child block, direction to the child
+    pub(crate) pending_joints: Vec<(Entity, Entity, Vec2)>,
+    pub(crate) kind: Option<TetrominoKind>,
+    /// Hold can only be used once per piece
+    pub(crate) hold_used: bool,
+    /// Only burns when the fuse modifier is on
+    pub(crate) fuse: Timer,
+    /// Direction of the rotation input on the previous frame while growing, -1.0, 0.0 or 1.0
+    pub(crate) pre_rotation_input: f32,
+    /// Given to the blocks once they have grown, see `SpawnEntry`
+    pub(crate) entry_velocity: Vec2,
+    /// Rotation input being held, for the rotation assist to tell a tap from a spin
+    pub(crate) rotation_press: Option<RotationPress>,
+    /// Quarter turn the rotation assist is turning the piece to, after a tap
+    pub(crate) snap_angle: Option<f32>,
+    /// Left or right being held, for lane snapping to step the target on
+    pub(crate) movement_press: Option<MovementPress>,
+    /// Center x lane snapping is pushing the piece to
+    pub(crate) lane_target: Option<f32>,
+}
+
+/// Rotation input held on a piece, see `assisted_torque`.
+#[derive(Clone, Copy)]
+pub(crate) struct RotationPress {
+    /// Seconds held so far
+    pub(crate) held: f32,
+    /// -1.0 or 1.0, counterclockwise being positive
+    pub(crate) direction: f32,
+    /// Quarter turn nearest to the piece when pressed
+    pub(crate) from: f32,
+}
+
+/// Left or right held on a piece, see `assisted_movement`.
+#[derive(Clone, Copy)]
+pub(crate) struct MovementPress {
+    /// Seconds held so far
+    pub(crate) held: f32,
+    /// -1.0 or 1.0, right being positive
+    pub(crate) direction: f32,
+}
+
+/// The tetrominoes currently controlled on a board, and what comes next.
+/// There is one piece, or two in Twin mode, each settling on its own.
+#[derive(Component, Default)]
+pub(crate) struct ActivePieces {
+    pub(crate) pieces: Vec<ActivePiece>,
+    pub(crate) next_is_bomb: bool,
+    /// Block size of the next piece, when a chaos event changes it
+    pub(crate) next_scale: Option<f32>,
+    /// The only pieces left to play, e.g. in a drill. Pieces are random when `None`.
+    pub(crate) supply: Option<VecDeque<TetrominoKind>>,
+    /// The piece put aside with the hold action
+    pub(crate) held: Option<TetrominoKind>,
+    /// A held piece coming back into play, taking precedence over the supply
+    pub(crate) unheld: Option<TetrominoKind>,
+    /// New pieces fall as on the first level while the stack is precarious, see `Stability`
+    pub(crate) breather: bool,
+    /// Pieces spawned this run, for spawn entries taking turns
+    pub(crate) spawned: usize,
+    /// Kinds of the new pieces drawn this run, not counting held pieces coming back. Sent to the
+    /// opponent in online Versus.
+    pub(crate) drawn: Vec<TetrominoKind>,
+}
+
+impl ActivePieces {
+    pub(crate) fn has_next(&self) -> bool {
+        self.supply.as_ref().is_none_or(|supply| !supply.is_empty())
+    }
+
+    pub(crate) fn next_kind(
+        &mut self,
+        practice: Option<TetrominoKind>,
+        pieces: PieceSet,
+        definitions: &PieceDefinitions,
+        rng: &mut impl Rng,
+    ) -> TetrominoKind {
+        if let Some(kind) = self.unheld.take() {
+            return kind;
+        }
+
+        let kind = self
+            .supply
+            .as_mut()
+            .and_then(VecDeque::pop_front)
+            .unwrap_or_else(|| match practice {
+                Some(kind) if rng.gen_bool(PRACTICE_PIECE_SHARE) => kind,
+                _ => pieces.random(definitions, rng),
+            });
+        self.drawn.push(kind);
+        kind
+    }
+
+    /// The blocks of every live piece.
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = &Entity> {
+        self.pieces.iter().flat_map(|piece| piece.blocks.iter())
+    }
+
+    pub(crate) fn contains(&self, block_entity: &Entity) -> bool {
+        self.pieces
+            .iter()
+            .any(|piece| piece.blocks.contains(block_entity))
+    }
+
+    /// Pieces that are in play, i.e. not gone for lack of supply.
+    pub(crate) fn n_live(&self) -> usize {
+        self.pieces
+            .iter()
+            .filter(|piece| !piece.blocks.is_empty())
+            .count()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub(crate) enum TetrominoKind {
+    I,
+    O,
+    T,
+    J,
+    L,
+    S,
+    Z,
+    /// A piece of a set defined in `assets/pieces/`, see `PieceDefinitions::polyomino`
+    Polyomino(u16),
+}
+
+impl TetrominoKind {
+    pub(crate) const ALL: [Self; 7] = [
+        Self::I,
+        Self::O,
+        Self::T,
+        Self::J,
+        Self::L,
+        Self::S,
+        Self::Z,
+    ];
+
+    pub(crate) fn random(definitions: &PieceDefinitions, rng: &mut impl Rng) -> Self {
+        if !definitions.uniform() {
+            return definitions.weighted(&Self::ALL, rng);
+        }
+
+        match rng.gen_range(0..7) {
+            0 => Self::I,
+            1 => Self::O,
+            2 => Self::T,
+            3 => Self::J,
+            4 => Self::L,
+            5 => Self::S,
+            _ => Self::Z,
+        }
+    }
+
+    /// Blocks and joints of the kind, as defined in `pieces` or in a piece set file.
+    pub(crate) fn layout(&self, definitions: &PieceDefinitions) -> TetrominoLayout {
+        let coords_and_joints =
+            |coords: &Vec<(i32, i32)>, joints: Option<&Vec<(usize, usize)>>| TetrominoLayout {
+                joints: joints.cloned().unwrap_or_else(|| spanning_joints(coords)),
+                coords: coords.clone(),
+            };
+
+        if let Self::Polyomino(index) = self {
+            // Of a set that is no longer loaded, a single block
+            let coords = definitions
+                .polyomino(*index)
+                .map_or(vec![(0, 0)], |piece| piece.coords.clone());
+            return coords_and_joints(&coords, None);
+        }
+
+        let piece = definitions
+            .get(*self)
+            .expect("every tetromino is defined, see PieceDefinitions::validate");
+        coords_and_joints(&piece.coords, piece.joints.as_ref())
+    }
+
+    pub(crate) fn color(&self, definitions: &PieceDefinitions) -> Color {
+        let (r, g, b) = match self {
+            Self::Polyomino(index) => definitions
+                .polyomino(*index)
+                .map_or((255, 255, 255), |piece| piece.color),
+            _ => definitions
+                .get(*self)
+                .map_or((255, 255, 255), |piece| piece.color),
+        };
+        Color::rgb_u8(r, g, b)
+    }
+
+    /// Glyph identifying the kind independently of color.
+    pub(crate) fn letter<'a>(&self, definitions: &'a PieceDefinitions) -> &'a str {
+        match self {
+            Self::I => "I",
+            Self::O => "O",
+            Self::T => "T",
+            Self::J => "J",
+            Self::L => "L",
+            Self::S => "S",
+            Self::Z => "Z",
+            Self::Polyomino(index) => definitions
+                .polyomino(*index)
+                .map_or("?", |piece| piece.letter.as_str()),
+        }
+    }
+}
+
+/// Blocks of a piece, of any number, and the joints between them.
+pub(crate) struct TetrominoLayout {
+    pub(crate) coords: Vec<(i32, i32)>,
+    pub(crate) joints: Vec<(usize, usize)>,
+}
+
+impl TetrominoLayout {
+    /// Flip the layout horizontally, within the same columns.
+    pub(crate) fn mirrored(self) -> Self {
+        let max_x = self.coords.iter().map(|(x, _)| *x).max().unwrap_or(0);
+        let min_x = self.coords.iter().map(|(x, _)| *x).min().unwrap_or(0);
+
+        Self {
+            coords: self
+                .coords
+                .iter()
+                .map(|(x, y)| (max_x + min_x - x, *y))
+                .collect(),
+            // Joints connect the same blocks, their anchors follow from the mirrored coords
+            joints: self.joints,
+        }
+    }
+}
+
+/// Joints holding a polyomino together: from the first block outwards, each block is joined to an
+/// adjacent block that is already held.
+pub(crate) fn spanning_joints(coords: &[(i32, i32)]) -> Vec<(usize, usize)> {
+    if coords.is_empty() {
+        return vec![];
+    }
+
+    let mut held = vec![0];
+    let mut joints = vec![];
+    let mut next = 0;
+
+    while next < held.len() {
+        let parent = held[next];
+        let (parent_x, parent_y) = coords[parent];
+
+        for (child, (x, y)) in coords.iter().enumerate() {
+            if !held.contains(&child) && (x - parent_x).abs() + (y - parent_y).abs() == 1 {
+                held.push(child);
+                joints.push((parent, child));
+            }
+        }
+        next += 1;
+    }
+
+    joints
+}
+
+#[derive(Component)]
+pub(crate) struct Block {
+    pub(crate) board: Entity,
+    pub(crate) kind: TetrominoKind,
+    /// Side length, 1.0 except for giant and tiny pieces
+    pub(crate) size: f32,
+}
+
+/// Visual aging of a block, so that old foundations read differently from fresh placements.
+#[derive(Component, Default)]
+pub(crate) struct Weathering {
+    /// Scaled seconds spent settled
+    pub(crate) age: f32,
+}
+
+impl Weathering {
+    pub(crate) fn color(&self, fresh: Color) -> Color {
+        let amount = WEATHERING_MAX_AMOUNT * (self.age / WEATHERING_DURATION).min(1.0);
+        let [r, g, b, a] = fresh.as_rgba_f32();
+        let [dust_r, dust_g, dust_b, _] = WEATHERING_COLOR.as_rgba_f32();
+
+        Color::rgba(
+            r + (dust_r - r) * amount,
+            g + (dust_g - g) * amount,
+            b + (dust_b - b) * amount,
+            a,
+        )
+    }
+}
+
+impl Block {
+    /// The cells of the block, with their centers and areas. A block is split into unit cells
+    /// (or a single smaller cell), so that a giant block counts towards every row it spans.
+    pub(crate) fn cells(&self, transform: &Transform) -> Vec<(Vec2, f32)> {
+        let n_cells = self.size.round().max(1.0) as i32;
+        let cell_size = self.size / n_cells as f32;
+        let center = transform.translation.truncate();
+
+        (0..n_cells)
+            .flat_map(|i| (0..n_cells).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let local = Vec3::new(
+                    (i as f32 + 0.5) * cell_size - self.size * 0.5,
+                    (j as f32 + 0.5) * cell_size - self.size * 0.5,
+                    0.0,
+                );
+                (
+                    center + (transform.rotation * local).truncate(),
+                    cell_size * cell_size,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Letter on a block telling its kind, for palettes where colors are hard to tell apart.
+#[derive(Component)]
+pub(crate) struct KindGlyph;
+
+/// Scaled seconds a block has been all but still, see `settle_still_pieces`.
+#[derive(Component, Default)]
+pub(crate) struct Stillness {
+    pub(crate) seconds: f32,
+}
+
+/// Block of a newly spawned piece, scaling up from nothing.
+#[derive(Component)]
+pub(crate) struct SpawnGrowth {
+    pub(crate) timer: Timer,
+}
+
+/// Block of a rare, slowly falling piece, lifted by buoyancy.
+#[derive(Component)]
+pub(crate) struct Balloon;
+
+/// What a block of a special piece is made of, setting its friction and bounce. Blocks without
+/// it are of the standard material.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum BlockMaterial {
+    /// Slides on anything
+    Ice,
+    /// Grips anything, and bounces a little
+    Rubber,
+}
+
+impl BlockMaterial {
+    pub(crate) fn friction(&self) -> Friction {
+        match self {
+            // The least friction of the two wins, so that ice slides on any block
+            Self::Ice => Friction {
+                coefficient: 0.0,
+                combine_rule: CoefficientCombineRule::Min,
+            },
+            Self::Rubber => Friction {
+                coefficient: 1.5,
+                combine_rule: CoefficientCombineRule::Max,
+            },
+        }
+    }
+
+    pub(crate) fn restitution(&self) -> Restitution {
+        match self {
+            Self::Ice => Restitution::coefficient(0.0),
+            Self::Rubber => Restitution {
+                coefficient: 0.3,
+                combine_rule: CoefficientCombineRule::Max,
+            },
+        }
+    }
+
+    /// Color of the overlay marking the material on a block.
+    pub(crate) fn overlay_color(&self) -> Color {
+        match self {
+            Self::Ice => Color::rgba(0.85, 0.95, 1.0, 0.6),
+            Self::Rubber => Color::rgba(0.1, 0.1, 0.1, 0.6),
+        }
+    }
+}
+
+/// Remaining fuse of the current piece, shrinking towards the center.
+#[derive(Component)]
+pub(crate) struct FuseBar {
+    pub(crate) board: Entity,
+}
+
+pub(crate) fn spawn_tetromino(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &Board,
+    active_pieces: &mut ActivePieces,
+    index: usize,
+    stats: &mut BoardStats,
+    rng: &mut GameRng,
+) {
+    let definitions = &board.piece_definitions;
+    let kind = active_pieces.next_kind(board.practice, board.pieces, definitions, rng);
+    let layout = if board.mirror.mirror_next_piece(rng) {
+        kind.layout(definitions).mirrored()
+    } else {
+        kind.layout(definitions)
+    };
+    let TetrominoLayout { coords, joints } = layout;
+    let scale = active_pieces.next_scale.take().unwrap_or(1.0);
+    let n_columns = coords
+        .iter()
+        .map(|(column, _)| column + 1)
+        .max()
+        .unwrap_or(1);
+    let (spawn_x, entry_velocity) =
+        board.spawn_entry(index, active_pieces.spawned, n_columns as f32 * scale);
+    active_pieces.spawned += 1;
+
+    // Columns are block-sized, counted from the spawn lane, so that the blocks of a piece
+    // are adjacent whatever the lane widths. Row 0 is the top row of the board,
+    // and the rows of a piece go downwards from there.
+    let block_entities: Vec<Entity> = coords
+        .iter()
+        .map(|(column, row)| {
+            let position = Vec2::new(
+                spawn_x + (*column as f32 + 0.5) * scale,
+                board.top_y() + (*row as f32 - 0.5) * scale,
+            );
+            spawn_block(commands, board_entity, board, kind, position, scale)
+        })
+        .collect();
+
+    // Falling faster as the level goes up. Blocks already on the board keep their damping.
+    let level = if active_pieces.breather {
+        0
+    } else {
+        stats.level()
+    };
+    let linear_damping = board.physics.linear_damping_at(level);
+    for block_entity in &block_entities {
+        commands.entity(*block_entity).insert(Damping {
+            linear_damping,
+            angular_damping: 0.0,
+        });
+    }
+
+    // Joints are attached by `piece_spawn_growth` when the blocks are fully grown
+    let pending_joints = joints
+        .iter()
+        .map(|(i, j)| {
+            let x_dir = coords[*j].0 as f32 - coords[*i].0 as f32;
+            let y_dir = coords[*j].1 as f32 - coords[*i].1 as f32;
+
+            (
+                block_entities[*i],
+                block_entities[*j],
+                Vec2::new(x_dir, y_dir) * scale,
+            )
+        })
+        .collect();
+
+    stats.generated_blocks += block_entities.len() as i32;
+    *stats.generated_by_kind.entry(kind).or_insert(0) += block_entities.len() as i32;
+
+    let bomb = std::mem::take(&mut active_pieces.next_is_bomb)
+        || (board.mode != GameMode::Drill && rng.gen_bool(BOMB_CHANCE));
+    if bomb {
+        for block_entity in &block_entities {
+            commands.entity(*block_entity).insert(Bomb {
+                color: kind.color(definitions),
+            });
+        }
+    } else if rng.gen_bool(BALLOON_CHANCE) {
+        for block_entity in &block_entities {
+            commands.entity(*block_entity).insert((
+                Balloon,
+                Sprite {
+                    color: kind.color(definitions).with_a(BALLOON_ALPHA),
+                    custom_size: Some(Vec2::splat(scale)),
+                    ..Default::default()
+                },
+            ));
+        }
+    } else if rng.gen_bool(SPECIAL_MATERIAL_CHANCE) {
+        let material = if rng.gen_bool(0.5) {
+            BlockMaterial::Ice
+        } else {
+            BlockMaterial::Rubber
+        };
+
+        for block_entity in &block_entities {
+            commands
+                .entity(*block_entity)
+                .insert((material, material.friction(), material.restitution()))
+                .with_children(|parent| {
+                    parent.spawn(SpriteBundle {
+                        transform: Transform::from_xyz(0.0, 0.0, 0.05),
+                        sprite: Sprite {
+                            color: material.overlay_color(),
+                            custom_size: Some(Vec2::splat(MATERIAL_OVERLAY_SIZE * scale)),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    });
+                });
+        }
+    }
+
+    if active_pieces.pieces.len() <= index {
+        active_pieces
+            .pieces
+            .resize_with(index + 1, ActivePiece::default);
+    }
+
+    active_pieces.pieces[index] = ActivePiece {
+        blocks: block_entities.into_iter().collect(),
+        joints: vec![],
+        pending_joints,
+        kind: Some(kind),
+        hold_used: false,
+        fuse: Timer::from_seconds(FUSE_DURATION, TimerMode::Once),
+        pre_rotation_input: 0.0,
+        entry_velocity,
+        rotation_press: None,
+        snap_angle: None,
+        movement_press: None,
+        lane_target: None,
+    };
+}
+
+pub(crate) fn spawn_block(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &Board,
+    kind: TetrominoKind,
+    position: Vec2,
+    scale: f32,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            transform: Transform::from_translation(position.extend(0.0)).with_scale(Vec3::new(
+                SPAWN_GROWTH_MIN_SCALE,
+                SPAWN_GROWTH_MIN_SCALE,
+                1.0,
+            )),
+            sprite: Sprite {
+                color: kind.color(&board.piece_definitions),
+                custom_size: Some(Vec2::splat(scale)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        // Kinematic while growing, so it gently pushes away anything in the way
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(SpawnGrowth {
+            timer: Timer::from_seconds(SPAWN_GROWTH_DURATION, TimerMode::Once),
+        })
+        .insert(AdditionalMassProperties::Mass(0.01))
+        .insert(Damping {
+            linear_damping: board.physics.linear_damping,
+            angular_damping: 0.0,
+        })
+        .insert(Collider::cuboid(0.5 * scale, 0.5 * scale))
+        .insert(Sleeping {
+            linear_threshold: 10.0,
+            angular_threshold: 10.0,
+            sleeping: false,
+        })
+        // Rapier doesn't always put jittering blocks to sleep, see `settle_still_pieces`
+        .insert(Stillness::default())
+        .insert(ExternalForce::default())
+        .insert(ExternalImpulse::default())
+        .insert(GravityScale::default())
+        .insert(Velocity::default())
+        .insert(ReadMassProperties::default())
+        .insert(Block {
+            board: board_entity,
+            kind,
+            size: scale,
+        })
+        .insert(Weathering::default())
+        .id()
+}
+
+/// A block that was never part of a piece, such as drill rows and junk.
+#[derive(Component)]
+pub(crate) struct LooseBlock;
+
+/// Spawn a block that isn't part of a piece, such as the prefilled rows of a drill.
+pub(crate) fn spawn_loose_block(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board: &Board,
+    position: Vec2,
+    resting: bool,
+    rng: &mut GameRng,
+) -> Entity {
+    let block_entity = spawn_block(
+        commands,
+        board_entity,
+        board,
+        TetrominoKind::random(&board.piece_definitions, rng),
+        position,
+        1.0,
+    );
+
+    commands
+        .entity(block_entity)
+        .remove::<SpawnGrowth>()
+        .insert((
+            LooseBlock,
+            RigidBody::Dynamic,
+            Transform::from_translation(position.extend(0.0)),
+            Sleeping {
+                linear_threshold: 10.0,
+                angular_threshold: 10.0,
+                sleeping: resting,
+            },
+        ));
+
+    block_entity
+}
+
+/// Lower left and upper right corners of the smallest rectangle containing all the points.
+fn bounds(points: &[Vec2]) -> (Vec2, Vec2) {
+    points.iter().fold(
+        (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+        |(min, max), point| (min.min(*point), max.max(*point)),
+    )
+}
+
+/// Turn a growing piece a quarter turn for each press of a rotate control, so that it starts out
+/// the right way round instead of being turned by torque once it falls. A control already held
+/// when the piece appears counts as a press.
+pub(crate) fn pre_rotate_growing_pieces(
+    action_state: Res<ActionState>,
+    coop: Option<Res<CoopSession>>,
+    online: Option<Res<OnlineSession>>,
+    referee: Option<Res<Referee>>,
+    mut piece_query: Query<(&Board, &Bot, &mut ActivePieces)>,
+    mut block_query: Query<(&Block, &mut Transform), With<SpawnGrowth>>,
+) {
+    for (board, bot, mut active_pieces) in piece_query.iter_mut() {
+        let inputs = PieceInput::for_board(
+            &action_state,
+            coop.as_deref(),
+            online.as_deref(),
+            referee.as_deref(),
+            board,
+            bot,
+        );
+        for (active_piece, input) in active_pieces.pieces.iter_mut().zip(inputs) {
+            let growing = active_piece
+                .blocks
+                .iter()
+                .any(|block_entity| block_query.contains(*block_entity));
+            if !growing {
+                continue;
+            }
+
+            let direction = if input.torque.abs() < ANALOG_DEAD_ZONE {
+                0.0
+            } else {
+                input.torque.signum()
+            };
+            let pressed = direction != 0.0 && direction != active_piece.pre_rotation_input;
+            active_piece.pre_rotation_input = direction;
+            if !pressed {
+                continue;
+            }
+
+            // Positive torque turns counterclockwise
+            let turn = |offset: Vec2| Vec2::new(-offset.y, offset.x) * direction;
+
+            let mut block_entities = vec![];
+            let mut positions = vec![];
+            let mut half_size: f32 = 0.0;
+            for block_entity in &active_piece.blocks {
+                if let Ok((block, transform)) = block_query.get(*block_entity) {
+                    block_entities.push(*block_entity);
+                    positions.push(transform.translation.truncate());
+                    half_size = half_size.max(block.size * 0.5);
+                }
+            }
+
+            let center = positions.iter().sum::<Vec2>() / positions.len() as f32;
+            let turned: Vec<Vec2> = positions
+                .iter()
+                .map(|position| center + turn(*position - center))
+                .collect();
+
+            // Keeping the top left corner where it was keeps the blocks lined up with the lanes
+            let (min, max) = bounds(&positions);
+            let (turned_min, turned_max) = bounds(&turned);
+            let mut shift = Vec2::new(min.x - turned_min.x, max.y - turned_max.y);
+            let right_wall_x = board.left_wall_x() + board.width();
+            shift.x += (right_wall_x - (turned_max.x + shift.x + half_size)).min(0.0);
+
+            for (block_entity, position) in block_entities.into_iter().zip(turned) {
+                if let Ok((_, mut transform)) = block_query.get_mut(block_entity) {
+                    transform.translation = (position + shift).extend(transform.translation.z);
+                }
+            }
+
+            for (_, _, direction_to_child) in active_piece.pending_joints.iter_mut() {
+                *direction_to_child = turn(*direction_to_child);
+            }
+        }
+    }
+}
+
+/// Grow newly spawned pieces, then hand them over to the physics with their joints attached.
+pub(crate) fn piece_spawn_growth(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut piece_query: Query<&mut ActivePieces>,
+    mut block_query: Query<(&mut SpawnGrowth, &mut Transform)>,
+) {
+    let delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+
+    for mut active_pieces in piece_query.iter_mut() {
+        for active_piece in active_pieces.pieces.iter_mut() {
+            let mut growing = false;
+            let mut grown = true;
+
+            for block_entity in &active_piece.blocks {
+                let Ok((mut growth, mut transform)) = block_query.get_mut(*block_entity) else {
+                    continue;
+                };
+
+                growing = true;
+                growth.timer.tick(delta);
+                grown &= growth.timer.finished();
+
+                // Ease out, like the grade stamp
+                let t = 1.0 - (1.0 - growth.timer.percent()).powi(3);
+                let scale = t.max(SPAWN_GROWTH_MIN_SCALE);
+                transform.scale = Vec3::new(scale, scale, 1.0);
+            }
+
+            if !growing || !grown {
+                continue;
+            }
+
+            for block_entity in &active_piece.blocks {
+                commands
+                    .entity(*block_entity)
+                    .remove::<SpawnGrowth>()
+                    .insert((
+                        RigidBody::Dynamic,
+                        Velocity::linear(active_piece.entry_velocity),
+                    ));
+            }
+
+            let mut joint_entities: Vec<Entity> = vec![];
+
+            for (parent, child, direction) in std::mem::take(&mut active_piece.pending_joints) {
+                let joint = FixedJointBuilder::new()
+                    .local_anchor1(direction * 0.5)
+                    .local_anchor2(direction * -0.5);
+
+                commands.entity(child).with_children(|cmd| {
+                    joint_entities.push(cmd.spawn(ImpulseJoint::new(parent, joint)).id());
+                });
+            }
+
+            active_piece.joints = joint_entities;
+        }
+    }
+}
+
+/// Snap the joints of pieces on boards with breakable joints that are pulled harder than
+/// `JOINT_BREAK_IMPULSE`, leaving the blocks of the piece loose but still in play.
+pub(crate) fn break_joints(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    mut board_query: Query<(&Board, &mut ActivePieces)>,
+    joint_query: Query<(&ImpulseJoint, &Parent, &RapierImpulseJointHandle)>,
+    transform_query: Query<&Transform, With<Block>>,
+) {
+    for (board, mut active_pieces) in board_query.iter_mut() {
+        if !board.breakable_joints {
+            continue;
+        }
+
+        for active_piece in active_pieces.pieces.iter_mut() {
+            active_piece.joints.retain(|joint_entity| {
+                let Ok((joint, child, handle)) = joint_query.get(*joint_entity) else {
+                    return true;
+                };
+                let Some(rapier_joint) = rapier_context.impulse_joints.get(handle.0) else {
+                    return true;
+                };
+
+                let impulse = Vec2::new(rapier_joint.impulses.x, rapier_joint.impulses.y);
+                if impulse.length() <= JOINT_BREAK_IMPULSE {
+                    return true;
+                }
+
+                // Between the two blocks
+                if let (Ok(parent), Ok(child)) = (
+                    transform_query.get(joint.parent),
+                    transform_query.get(child.get()),
+                ) {
+                    let position = (parent.translation + child.translation).truncate() * 0.5;
+                    spawn_sparks(&mut commands, position);
+                }
+
+                commands.entity(*joint_entity).despawn();
+                false
+            });
+        }
+    }
+}
+
+/// Burn the fuse of each piece, and settle it in place when the fuse runs out.
+pub(crate) fn piece_fuse(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut board_query: Query<(&Board, &mut ActivePieces, &mut BoardStats)>,
+    growth_query: Query<(), With<SpawnGrowth>>,
+    mut block_query: Query<(&mut Velocity, &mut Sleeping)>,
+) {
+    let delta = std::time::Duration::from_secs_f32(time_scale.delta_seconds(&time));
+
+    for (board, mut active_pieces, mut stats) in board_query.iter_mut() {
+        if !board.fuse || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        for active_piece in active_pieces.pieces.iter_mut() {
+            let growing = active_piece
+                .blocks
+                .iter()
+                .any(|block_entity| growth_query.contains(*block_entity));
+
+            if growing {
+                continue;
+            }
+
+            active_piece.fuse.tick(delta);
+            if !active_piece.fuse.just_finished() {
+                continue;
+            }
+
+            // Settled right here, `tetromino_sleep_detection` takes it from there
+            for block_entity in &active_piece.blocks {
+                if let Ok((mut velocity, mut sleeping)) = block_query.get_mut(*block_entity) {
+                    *velocity = Velocity::zero();
+                    sleeping.sleeping = true;
+                }
+            }
+
+            stats.fuse_penalties += 1;
+        }
+    }
+}
+
+/// Settle pieces that have kept still on something for `STILL_SETTLE_DURATION`, but jitter
+/// enough that Rapier never puts them to sleep, which would otherwise stall the run. The still
+/// blocks of the stack they rest on settle along with them, so that the rows they fill count.
+pub(crate) fn settle_still_pieces(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    rapier_context: Res<RapierContext>,
+    board_query: Query<(Entity, &ActivePieces, &BoardStats)>,
+    growth_query: Query<(), With<SpawnGrowth>>,
+    mut block_query: Query<(Entity, &Block, &mut Velocity, &mut Sleeping, &mut Stillness)>,
+) {
+    let delta = time_scale.delta_seconds(&time);
+    let still = |velocity: &Velocity| {
+        velocity.linvel.length() < STILL_LINEAR_SPEED && velocity.angvel.abs() < STILL_ANGULAR_SPEED
+    };
+
+    for (board_entity, active_pieces, stats) in board_query.iter() {
+        if stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        for active_piece in &active_pieces.pieces {
+            let growing = active_piece
+                .blocks
+                .iter()
+                .any(|block_entity| growth_query.contains(*block_entity));
+            if growing || active_piece.blocks.is_empty() {
+                continue;
+            }
+
+            // Still only counts while the piece lies on the stack, the floor or a wall, not just
+            // on its own blocks
+            let resting = active_piece.blocks.iter().any(|block_entity| {
+                rapier_context
+                    .contacts_with(*block_entity)
+                    .any(|contact_pair| {
+                        let other = if contact_pair.collider1() == *block_entity {
+                            contact_pair.collider2()
+                        } else {
+                            contact_pair.collider1()
+                        };
+                        contact_pair.has_any_active_contacts()
+                            && !active_piece.blocks.contains(&other)
+                    })
+            });
+
+            let mut all_still = true;
+            for block_entity in &active_piece.blocks {
+                let Ok((_, _, velocity, _, mut stillness)) = block_query.get_mut(*block_entity)
+                else {
+                    continue;
+                };
+
+                stillness.seconds = if resting && still(&velocity) {
+                    stillness.seconds + delta
+                } else {
+                    0.0
+                };
+                all_still &= stillness.seconds >= STILL_SETTLE_DURATION;
+            }
+
+            if !all_still {
+                continue;
+            }
+
+            // Settled right here, `tetromino_sleep_detection` takes it from there
+            for (block_entity, block, mut velocity, mut sleeping, _) in block_query.iter_mut() {
+                if block.board != board_entity {
+                    continue;
+                }
+
+                let settling = active_piece.blocks.contains(&block_entity)
+                    || (!active_pieces.contains(&block_entity) && still(&velocity));
+                if settling {
+                    *velocity = Velocity::zero();
+                    sleeping.sleeping = true;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn update_fuse_bar(
+    board_query: Query<(&Board, &ActivePieces)>,
+    mut fuse_bar_query: Query<(&FuseBar, &mut Transform, &mut Sprite, &mut Visibility)>,
+) {
+    for (fuse_bar, mut transform, mut sprite, mut visibility) in fuse_bar_query.iter_mut() {
+        let Ok((board, active_pieces)) = board_query.get(fuse_bar.board) else {
+            continue;
+        };
+
+        if !board.fuse {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        }
+
+        // The piece closest to burning out
+        let remaining = active_pieces
+            .pieces
+            .iter()
+            .map(|active_piece| 1.0 - active_piece.fuse.percent())
+            .fold(1.0, f32::min);
+
+        *visibility = Visibility::Inherited;
+        transform.scale.x = remaining;
+        // Orange to red as it burns down
+        sprite.color = Color::rgb(1.0, 0.6 * remaining, 0.0);
+    }
+}
+
+/// Put the active piece aside, bringing back the previously held piece if there is one.
+pub(crate) fn hold_piece(
+    mut commands: Commands,
+    action_state: Res<ActionState>,
+    coop: Option<Res<CoopSession>>,
+    online: Option<Res<OnlineSession>>,
+    referee: Option<Res<Referee>>,
+    mut board_query: Query<(
+        Entity,
+        &Board,
+        &Bot,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+) {
+    for (board_entity, board, bot, mut active_pieces, mut stats, mut rng) in board_query.iter_mut()
+    {
+        let [input, _] = PieceInput::for_board(
+            &action_state,
+            coop.as_deref(),
+            online.as_deref(),
+            referee.as_deref(),
+            board,
+            bot,
+        );
+        if !input.hold {
+            continue;
+        }
+
+        // Hold is one of the first piece's controls
+        let Some(active_piece) = active_pieces.pieces.first_mut() else {
+            continue;
+        };
+        if active_piece.hold_used || active_piece.blocks.is_empty() {
+            continue;
+        }
+        let Some(kind) = active_piece.kind else {
+            continue;
+        };
+
+        // Joints are children of the blocks and go with them. The held piece wasn't dealt after
+        // all, by kind either.
+        for block_entity in std::mem::take(&mut active_piece.blocks) {
+            commands.entity(block_entity).despawn_recursive();
+            stats.generated_blocks -= 1;
+            *stats.generated_by_kind.entry(kind).or_insert(0) -= 1;
+        }
+
+        active_pieces.unheld = active_pieces.held.replace(kind);
+        spawn_tetromino(
+            &mut commands,
+            board_entity,
+            board,
+            &mut active_pieces,
+            0,
+            &mut stats,
+            &mut rng,
+        );
+        active_pieces.pieces[0].hold_used = true;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn tetromino_sleep_detection(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut time_scale: ResMut<GameTimeScale>,
+    fonts: Res<Fonts>,
+    rapier_context: Res<RapierContext>,
+    mut settled_events: EventWriter<PieceSettled>,
+    mut row_cleared_events: EventWriter<RowCleared>,
+    mut board_query: Query<(
+        Entity,
+        &Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut CascadeChain,
+        &mut GameRng,
+    )>,
+    mut block_query: Query<(
+        Entity,
+        &Block,
+        &Transform,
+        &mut Sleeping,
+        &RapierRigidBodyHandle,
+    )>,
+    growth_query: Query<(), With<SpawnGrowth>>,
+    junk_query: Query<(), With<Junk>>,
+    fragment_query: Query<&Fragment>,
+) {
+    for (board_entity, board, mut active_pieces, mut stats, mut cascade, mut rng) in
+        board_query.iter_mut()
+    {
+        cascade.remaining -= time_scale.delta_seconds(&time);
+
+        // Each piece settles on its own
+        let settled: Vec<usize> = active_pieces
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, active_piece)| {
+                // A growing piece is held in place, not resting
+                let growing = active_piece
+                    .blocks
+                    .iter()
+                    .any(|block_entity| growth_query.contains(*block_entity));
+
+                // When the supply has run out there is no piece, but rows may still complete
+                !growing
+                    && !active_piece.blocks.is_empty()
+                    && active_piece.blocks.iter().all(|block_entity| {
+                        block_query
+                            .get(*block_entity)
+                            .ok()
+                            .map(|(_, _, _, sleep, _)| sleep.sleeping)
+                            .unwrap_or(false)
+                    })
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        // Blocks coming to rest after a clear may complete more rows
+        if settled.is_empty() && cascade.remaining <= 0.0 {
+            continue;
+        }
+
+        for index in &settled {
+            let active_piece = &active_pieces.pieces[*index];
+
+            for joint in &active_piece.joints {
+                commands.entity(*joint).despawn();
+            }
+
+            settled_events.send(PieceSettled {
+                board: board_entity,
+                blocks: active_piece.blocks.iter().copied().collect(),
+            });
+
+            for block_entity in &active_piece.blocks {
+                if let Ok((_, _, transform, _, _)) = block_query.get(*block_entity) {
+                    stats.record_placement(board.placement_precision(transform));
+                }
+            }
+        }
+
+        // A new piece settling starts a new chain
+        if !settled.is_empty() {
+            cascade.remaining = 0.0;
+        }
+
+        let cleared = clear_filled_rows(
+            &mut commands,
+            board_entity,
+            board,
+            &mut stats,
+            &mut cascade,
+            &block_query,
+            &junk_query,
+            &fragment_query,
+            &mut row_cleared_events,
+        );
+
+        if !settled.is_empty() {
+            for (_, block, _, mut sleeping, _) in &mut block_query {
+                if block.board == board_entity {
+                    sleeping.sleeping = false;
+                }
+            }
+        }
+
+        // Replace the settled pieces, and any other piece that was cleared away completely
+        let mut replaced = settled;
+        for (index, active_piece) in active_pieces.pieces.iter_mut().enumerate() {
+            let live = !active_piece.blocks.is_empty();
+            active_piece
+                .blocks
+                .retain(|block_entity| !cleared.contains(block_entity));
+
+            if live && active_piece.blocks.is_empty() && !replaced.contains(&index) {
+                replaced.push(index);
+            }
+        }
+
+        // Blocks of the pieces still in play are not in the way of the new ones
+        let live: BTreeSet<Entity> = active_pieces
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !replaced.contains(index))
+            .flat_map(|(_, active_piece)| active_piece.blocks.iter().copied())
+            .collect();
+
+        for index in replaced {
+            // The opponent's run online only ends by the opponent's word, see `online`
+            let spawn_area = board.spawn_area(index, active_pieces.spawned);
+            let topped_out = !board.remote
+                && stats.game_over_duration.is_none()
+                && spawn_area_obstructed(&rapier_context, spawn_area, |entity| {
+                    !live.contains(&entity)
+                        && matches!(
+                            block_query.get(entity),
+                            Ok((_, block, _, _, _)) if block.board == board_entity
+                        )
+                });
+
+            if !active_pieces.has_next() {
+                active_pieces.pieces[index] = ActivePiece::default();
+            } else if topped_out {
+                active_pieces.pieces[index] = ActivePiece::default();
+                stats.game_over_duration = Some(0.0);
+                time_scale.set(TimeScaleSource::GameOver, GAME_OVER_TIME_SCALE);
+                spawn_toast(&mut commands, &fonts, "Topped out");
+            } else if stats.health() > 0.0 {
+                spawn_tetromino(
+                    &mut commands,
+                    board_entity,
+                    board,
+                    &mut active_pieces,
+                    index,
+                    &mut stats,
+                    &mut rng,
+                );
+            }
+        }
+    }
+}
+
+/// Whether any of the blocks passing `in_the_way` is in the spawn area, short of touching its
+/// edges, so that a new piece there would spawn into them.
+pub(crate) fn spawn_area_obstructed(
+    rapier_context: &RapierContext,
+    spawn_area: Rect,
+    in_the_way: impl Fn(Entity) -> bool,
+) -> bool {
+    let half_size = (spawn_area.half_size() - Vec2::splat(SPAWN_AREA_MARGIN)).max(Vec2::ZERO);
+    let mut obstructed = false;
+
+    rapier_context.intersections_with_shape(
+        spawn_area.center(),
+        0.0,
+        &Collider::cuboid(half_size.x, half_size.y),
+        QueryFilter::default(),
+        |entity| {
+            obstructed = in_the_way(entity);
+            // Stop at the first block in the way
+            !obstructed
+        },
+    );
+
+    obstructed
+}
+
+pub(crate) fn toggle_fuse_modifier(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::FuseModifier) {
+        return;
+    }
+
+    settings.fuse_modifier = !settings.fuse_modifier;
+    settings.save();
+
+    let state = if settings.fuse_modifier { "on" } else { "off" };
+    spawn_toast(&mut commands, &fonts, &format!("Next run: fuse {state}"));
+}
+
+pub(crate) fn cycle_mirror_modifier(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::MirrorModifier) {
+        return;
+    }
+
+    settings.mirror_modifier = settings.mirror_modifier.next();
+    settings.save();
+
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("Next run: mirror {}", settings.mirror_modifier.name()),
+    );
+}
+
+/// Age the blocks that are no longer part of a live piece. Junk and garbage keep their grey, so
+/// they stay apart from the blocks of placed pieces.
+#[allow(clippy::type_complexity)]
+pub(crate) fn block_weathering(
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    board_query: Query<(&Board, &ActivePieces)>,
+    mut block_query: Query<
+        (Entity, &Block, &mut Weathering, &mut Sprite),
+        (Without<Bomb>, Without<Junk>, Without<Garbage>),
+    >,
+) {
+    let delta = time_scale.delta_seconds(&time);
+
+    for (block_entity, block, mut weathering, mut sprite) in block_query.iter_mut() {
+        let Ok((board, active_pieces)) = board_query.get(block.board) else {
+            continue;
+        };
+        if active_pieces.contains(&block_entity) || weathering.age >= WEATHERING_DURATION {
+            continue;
+        }
+
+        // Keep the alpha, balloon blocks are translucent
+        let fresh = block
+            .kind
+            .color(&board.piece_definitions)
+            .with_a(sprite.color.a());
+        weathering.age += delta;
+        sprite.color = weathering.color(fresh);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn attach_kind_glyphs(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+    board_query: Query<&Board>,
+    block_query: Query<(Entity, &Block), (Added<Block>, Without<Junk>)>,
+) {
+    for (entity, block) in block_query.iter() {
+        let letter = board_query
+            .get(block.board)
+            .map_or("?", |board| block.kind.letter(&board.piece_definitions));
+        commands.entity(entity).with_children(|parent| {
+            parent
+                .spawn(Text2dBundle {
+                    text: fonts
+                        .text(
+                            letter,
+                            WORLD_TEXT_FONT_SIZE,
+                            Color::rgba(0.0, 0.0, 0.0, 0.6),
+                        )
+                        .with_alignment(TextAlignment::Center),
+                    transform: Transform::from_xyz(0.0, 0.0, 0.1).with_scale(Vec3::new(
+                        WORLD_TEXT_SCALE,
+                        WORLD_TEXT_SCALE,
+                        1.0,
+                    )),
+                    visibility: kind_glyph_visibility(&settings),
+                    ..Default::default()
+                })
+                .insert(KindGlyph);
+        });
+    }
+}
+
+pub(crate) fn kind_glyph_visibility(settings: &Settings) -> Visibility {
+    if settings.kind_glyphs {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    }
+}
+
+pub(crate) fn toggle_kind_glyphs(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+    mut glyph_query: Query<&mut Visibility, With<KindGlyph>>,
+) {
+    if !input_map.just_pressed(&input, Action::KindGlyphs) {
+        return;
+    }
+
+    settings.kind_glyphs = !settings.kind_glyphs;
+    settings.save();
+
+    for mut visibility in glyph_query.iter_mut() {
+        *visibility = kind_glyph_visibility(&settings);
+    }
+
+    let state = if settings.kind_glyphs { "on" } else { "off" };
+    spawn_toast(&mut commands, &fonts, &format!("Block letters {state}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier2d::rapier::prelude::ColliderBuilder;
+
+    use super::*;
+    use crate::board::{BoardLayout, SPAWN_AREA_WIDTH};
+    use crate::settings::{test_app, test_world};
+
+    /// The built-in definitions with the piece sets in the assets.
+    fn asset_piece_definitions() -> PieceDefinitions {
+        let sets = PIECE_SET_PATHS
+            .iter()
+            .map(|path| {
+                let source = std::fs::read_to_string(format!("assets/{path}")).unwrap();
+                Some(ron::from_str(&source).expect("invalid piece set"))
+            })
+            .collect();
+
+        PieceDefinitions {
+            sets,
+            ..PieceDefinitions::built_in()
+        }
+    }
+
+    /// Whether the joints hold all blocks together, each joint between adjacent blocks.
+    fn held_together(layout: &TetrominoLayout) -> bool {
+        let adjacent = layout.joints.iter().all(|(i, j)| {
+            let ((x1, y1), (x2, y2)) = (layout.coords[*i], layout.coords[*j]);
+            (x1 - x2).abs() + (y1 - y2).abs() == 1
+        });
+
+        let mut held = vec![false; layout.coords.len()];
+        held[0] = true;
+        loop {
+            let mut grown = false;
+            for (i, j) in &layout.joints {
+                if held[*i] != held[*j] {
+                    held[*i] = true;
+                    held[*j] = true;
+                    grown = true;
+                }
+            }
+            if !grown {
+                break;
+            }
+        }
+
+        adjacent && held.into_iter().all(|held| held)
+    }
+
+    fn width(layout: &TetrominoLayout) -> i32 {
+        let columns = layout.coords.iter().map(|(x, _)| *x);
+        columns.clone().max().unwrap() - columns.min().unwrap() + 1
+    }
+
+    #[test]
+    fn ice_slides_and_rubber_grips_whatever_they_touch() {
+        // Combined with a block of the standard material
+        let standard = Friction::default();
+        let combine = |friction: Friction| match friction.combine_rule {
+            CoefficientCombineRule::Min => friction.coefficient.min(standard.coefficient),
+            CoefficientCombineRule::Max => friction.coefficient.max(standard.coefficient),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(combine(BlockMaterial::Ice.friction()), 0.0);
+        assert!(combine(BlockMaterial::Rubber.friction()) > standard.coefficient);
+        assert_eq!(BlockMaterial::Ice.restitution().coefficient, 0.0);
+        assert!(BlockMaterial::Rubber.restitution().coefficient > 0.0);
+    }
+
+    #[test]
+    fn practice_deals_mostly_the_practiced_kind_after_a_held_piece() {
+        let definitions = PieceDefinitions::built_in();
+        let mut rng = GameRng::new(Some(0));
+        let mut active_pieces = ActivePieces {
+            unheld: Some(TetrominoKind::I),
+            ..Default::default()
+        };
+
+        let kinds: Vec<TetrominoKind> = (0..1000)
+            .map(|_| {
+                active_pieces.next_kind(
+                    Some(TetrominoKind::S),
+                    PieceSet::All,
+                    &definitions,
+                    &mut rng,
+                )
+            })
+            .collect();
+        let practiced = kinds
+            .iter()
+            .filter(|kind| **kind == TetrominoKind::S)
+            .count();
+
+        assert_eq!(kinds[0], TetrominoKind::I);
+        assert!((700..850).contains(&practiced), "{practiced}");
+    }
+
+    #[test]
+    fn piece_sets_deal_only_their_kinds() {
+        let definitions = PieceDefinitions::built_in();
+        let mut rng = GameRng::new(Some(0));
+        let mut active_pieces = ActivePieces::default();
+
+        for pieces in PieceSet::ALL {
+            for _ in 0..100 {
+                let kind = active_pieces.next_kind(None, pieces, &definitions, &mut rng);
+                assert!(pieces.kinds(&definitions).contains(&kind), "{kind:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_span_every_point() {
+        let points = [
+            Vec2::new(1.0, 2.0),
+            Vec2::new(-1.0, 0.5),
+            Vec2::new(0.0, -3.0),
+        ];
+
+        assert_eq!(
+            bounds(&points),
+            (Vec2::new(-1.0, -3.0), Vec2::new(1.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn piece_sets_are_valid_and_deal_their_pieces() {
+        let definitions = asset_piece_definitions();
+        assert_eq!(definitions.sets.len(), PIECE_SET_PATHS.len());
+        for set in definitions.sets.iter().flatten() {
+            assert_eq!(set.validate(), Ok(()));
+        }
+
+        let pentominoes = PieceSet::Custom(0).kinds(&definitions);
+        assert_eq!(
+            pentominoes.len(),
+            definitions.sets[0].as_ref().unwrap().pieces.len()
+        );
+        assert_eq!(PieceSet::Custom(0).name(&definitions), "Pentominoes");
+        assert_eq!(
+            PieceSet::Custom(0).kinds(&PieceDefinitions::built_in()),
+            TetrominoKind::ALL
+        );
+    }
+
+    #[test]
+    fn every_piece_is_held_together_and_fits_the_spawn_area() {
+        let definitions = asset_piece_definitions();
+        let kinds = TetrominoKind::ALL.into_iter().chain(
+            (0..definitions.sets.len())
+                .flat_map(|index| definitions.set_kinds(index).unwrap_or_default()),
+        );
+
+        for kind in kinds {
+            let letter = kind.letter(&definitions);
+            for layout in [
+                kind.layout(&definitions),
+                kind.layout(&definitions).mirrored(),
+            ] {
+                assert!(!layout.coords.is_empty(), "{letter}");
+                assert!(held_together(&layout), "{letter} falls apart");
+                assert!(
+                    width(&layout) as f32 <= SPAWN_AREA_WIDTH,
+                    "{letter} is wider than the spawn area"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn spanning_joints_hold_any_connected_polyomino() {
+        let coords = vec![(0, 0), (2, 0), (0, -1), (1, -1), (2, -1), (1, -2)];
+        let layout = TetrominoLayout {
+            joints: spanning_joints(&coords),
+            coords,
+        };
+
+        assert_eq!(layout.joints.len(), layout.coords.len() - 1);
+        assert!(held_together(&layout));
+    }
+
+    #[test]
+    fn mirrored_stays_in_the_same_columns() {
+        let definitions = PieceDefinitions::built_in();
+        let layout = TetrominoKind::L.layout(&definitions);
+        let mirrored = TetrominoKind::L.layout(&definitions).mirrored();
+
+        assert_eq!(width(&mirrored), width(&layout));
+        assert_eq!(mirrored.joints, layout.joints);
+        assert_ne!(mirrored.coords, layout.coords);
+    }
+
+    #[test]
+    fn spawn_area_reaches_as_deep_as_the_tallest_piece_of_the_set() {
+        let mut board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Classic,
+            Vec2::ZERO,
+        );
+        board.piece_definitions = asset_piece_definitions();
+
+        board.pieces = PieceSet::Simple;
+        assert_eq!(board.spawn_area(0, 0).min.y, board.top_y() - 3.0);
+        board.pieces = PieceSet::Snakes;
+        assert_eq!(board.spawn_area(0, 0).min.y, board.top_y() - 2.0);
+        board.pieces = PieceSet::Custom(0);
+        assert_eq!(board.spawn_area(0, 0).min.y, board.top_y() - 4.0);
+    }
+
+    #[test]
+    fn spawn_area_is_obstructed_by_blocks_in_it_but_not_by_blocks_touching_it() {
+        let board = Board::new(
+            BoardLayout::named("Standard"),
+            GameMode::Classic,
+            Vec2::ZERO,
+        );
+        let spawn_area = board.spawn_area(0, 0);
+        assert_eq!(spawn_area.width(), SPAWN_AREA_WIDTH);
+        // From the top of the upright I to the bottom of the J and L
+        assert_eq!(spawn_area.max.y, board.top_y() + 1.0);
+        assert_eq!(spawn_area.min.y, board.top_y() - 3.0);
+
+        let mut rapier_context = RapierContext::default();
+        let mut add_block = |entity: Entity, position: Vec2| {
+            rapier_context.colliders.insert(
+                ColliderBuilder::cuboid(0.5, 0.5)
+                    .translation(position.into())
+                    .user_data(entity.to_bits() as u128)
+                    .build(),
+            );
+        };
+        let inside = Entity::from_raw(1);
+        let touching = Entity::from_raw(2);
+        add_block(inside, spawn_area.center());
+        add_block(
+            touching,
+            Vec2::new(spawn_area.center().x, spawn_area.min.y - 0.5),
+        );
+        rapier_context
+            .query_pipeline
+            .update(&rapier_context.bodies, &rapier_context.colliders);
+
+        assert!(spawn_area_obstructed(
+            &rapier_context,
+            spawn_area,
+            |entity| { entity == inside }
+        ));
+        assert!(!spawn_area_obstructed(
+            &rapier_context,
+            spawn_area,
+            |entity| { entity == touching }
+        ));
+    }
+
+    #[test]
+    fn still_pieces_settle_only_while_resting_on_something() {
+        let mut world = test_world();
+        let mut time = Time::default();
+        let startup = time.startup();
+        time.update_with_instant(startup);
+        time.update_with_instant(startup + std::time::Duration::from_secs_f32(0.1));
+        world.insert_resource(time);
+        world.init_resource::<GameTimeScale>();
+        world.insert_resource(RapierContext::default());
+        let board = world.spawn(BoardStats::default()).id();
+        let block = |world: &mut World| {
+            world
+                .spawn((
+                    Block {
+                        board,
+                        kind: TetrominoKind::O,
+                        size: 1.0,
+                    },
+                    Velocity::zero(),
+                    Sleeping {
+                        sleeping: false,
+                        ..Default::default()
+                    },
+                    Stillness {
+                        seconds: STILL_SETTLE_DURATION,
+                    },
+                ))
+                .id()
+        };
+        let piece_block = block(&mut world);
+        let stack_block = block(&mut world);
+        world.entity_mut(board).insert(ActivePieces {
+            pieces: vec![ActivePiece {
+                blocks: BTreeSet::from([piece_block]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let mut schedule = Schedule::new();
+        schedule.add_system(settle_still_pieces);
+
+        // Hanging in the air, without contacts
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Stillness>(piece_block).unwrap().seconds, 0.0);
+        for block in [piece_block, stack_block] {
+            assert!(!world.get::<Sleeping>(block).unwrap().sleeping);
+        }
+    }
+
+    #[test]
+    fn still_pieces_settle_with_the_stack_they_rest_on() {
+        let mut app = test_app();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Fixed {
+                    dt: 1.0 / 60.0,
+                    substeps: 1,
+                },
+                ..Default::default()
+            })
+            .init_resource::<GameTimeScale>();
+        let world = &mut app.world;
+        let board = world.spawn(BoardStats::default()).id();
+        let other_board = world.spawn(BoardStats::default()).id();
+        world.spawn((
+            RigidBody::Fixed,
+            Collider::cuboid(5.0, 0.5),
+            TransformBundle::from(Transform::from_xyz(0.0, -0.5, 0.0)),
+        ));
+        // Resting on the floor, still as far as the check goes
+        let mut block = |board: Entity, x: f32| {
+            world
+                .spawn((
+                    Block {
+                        board,
+                        kind: TetrominoKind::O,
+                        size: 1.0,
+                    },
+                    RigidBody::Dynamic,
+                    Collider::cuboid(0.5, 0.5),
+                    TransformBundle::from(Transform::from_xyz(x, 0.5, 0.0)),
+                    Velocity::zero(),
+                    Sleeping {
+                        sleeping: false,
+                        ..Default::default()
+                    },
+                    Stillness {
+                        seconds: STILL_SETTLE_DURATION,
+                    },
+                ))
+                .id()
+        };
+        let piece_block = block(board, 0.0);
+        let stack_block = block(board, 2.0);
+        let other_board_block = block(other_board, -2.0);
+        world.entity_mut(board).insert(ActivePieces {
+            pieces: vec![ActivePiece {
+                blocks: BTreeSet::from([piece_block]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        world
+            .entity_mut(other_board)
+            .insert(ActivePieces::default());
+
+        // Stepped until the blocks have found the floor
+        for _ in 0..10 {
+            app.update();
+        }
+        let mut schedule = Schedule::new();
+        schedule.add_system(settle_still_pieces);
+        schedule.run(&mut app.world);
+
+        for block in [piece_block, stack_block] {
+            assert!(app.world.get::<Sleeping>(block).unwrap().sleeping);
+            assert_eq!(*app.world.get::<Velocity>(block).unwrap(), Velocity::zero());
+        }
+        assert!(
+            !app.world
+                .get::<Sleeping>(other_board_block)
+                .unwrap()
+                .sleeping
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! Touch controls for phones and tablets: gestures anywhere on the screen, or on-screen buttons.
+//!
+//! Touches are turned into the same actions as keys and gamepads, see `update_action_state`.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use crate::input::{update_action_state, Action};
+use crate::settings::Settings;
+use crate::ui::{Fonts, Menu};
+use crate::AppState;
+
+/// Touch gestures and on-screen buttons.
+pub struct TouchInputPlugin;
+
+impl Plugin for TouchInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TouchActions::default())
+            .insert_resource(TouchGestures::default())
+            .add_system(touch_input.before(update_action_state))
+            .add_system(touch_buttons_overlay);
+    }
+}
+
+// In logical pixels. A touch moved further than this from where it started is a swipe...
+pub(crate) const SWIPE_THRESHOLD: f32 = 24.0;
+// ...at full strength this far from where it started
+pub(crate) const SWIPE_FULL_DISTANCE: f32 = 120.0;
+// In seconds. A touch held still for longer than this is a hold, a shorter one a tap
+pub(crate) const HOLD_DURATION: f32 = 0.2;
+
+pub(crate) const TOUCH_BUTTON_SIZE: f32 = 72.0;
+pub(crate) const TOUCH_BUTTON_MARGIN: f32 = 16.0;
+pub(crate) const TOUCH_BUTTON_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+
+/// How strongly each action is activated by touches this frame, in `0.0..=1.0`.
+#[derive(Resource, Default)]
+pub(crate) struct TouchActions {
+    pub(crate) values: HashMap<Action, f32>,
+}
+
+impl TouchActions {
+    pub(crate) fn value(&self, action: Action) -> f32 {
+        self.values.get(&action).copied().unwrap_or(0.0)
+    }
+}
+
+/// What a touch has turned out to be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Gesture {
+    /// Too short and too still to tell yet, a tap if lifted now
+    Pending,
+    Hold,
+    Swipe,
+}
+
+impl Gesture {
+    /// The gesture of a touch that has moved `distance` from where it started and been held for
+    /// `held_for` seconds. Once a swipe, a touch stays one until it is lifted.
+    pub(crate) fn classify(self, distance: f32, held_for: f32) -> Self {
+        if self == Self::Swipe || distance > SWIPE_THRESHOLD {
+            Self::Swipe
+        } else if held_for >= HOLD_DURATION {
+            Self::Hold
+        } else {
+            self
+        }
+    }
+}
+
+/// The gesture of each touch on the screen, and when it started in seconds since startup.
+#[derive(Resource, Default)]
+pub(crate) struct TouchGestures {
+    pub(crate) touches: HashMap<u64, (Gesture, f32)>,
+}
+
+/// The on-screen buttons, shown during a run when chosen over gestures in the settings.
+#[derive(Component)]
+pub(crate) struct TouchButtons;
+
+#[derive(Component)]
+pub(crate) struct TouchButton {
+    pub(crate) action: Action,
+}
+
+/// The actions of a touch held at `position`, having started at `start`, in window coordinates
+/// from the top left. Swiping sideways pushes the piece, more the further the swipe, and swiping
+/// down soft drops it. A touch held still turns the piece, counter-clockwise on the left half of
+/// the window and clockwise on the right half. A touch not yet told apart does nothing.
+pub(crate) fn gesture_actions(
+    start: Vec2,
+    position: Vec2,
+    window_width: f32,
+    gesture: Gesture,
+) -> Vec<(Action, f32)> {
+    match gesture {
+        Gesture::Pending => return Vec::new(),
+        Gesture::Hold => return vec![(turn_action(position, window_width), 1.0)],
+        Gesture::Swipe => {}
+    }
+
+    let offset = position - start;
+    let strength = |distance: f32| (distance / SWIPE_FULL_DISTANCE).min(1.0);
+
+    if offset.x.abs() > offset.y.abs() {
+        let action = if offset.x < 0.0 {
+            Action::MoveLeft
+        } else {
+            Action::MoveRight
+        };
+        vec![(action, strength(offset.x.abs()))]
+    } else if offset.y > 0.0 {
+        vec![
+            (Action::SoftDrop, strength(offset.y)),
+            (Action::MenuDown, 1.0),
+        ]
+    } else {
+        vec![(Action::MenuUp, 1.0)]
+    }
+}
+
+/// The turn of a touch at `position`, counter-clockwise on the left half of the window.
+fn turn_action(position: Vec2, window_width: f32) -> Action {
+    if position.x < window_width * 0.5 {
+        Action::RotateCcw
+    } else {
+        Action::RotateCw
+    }
+}
+
+/// The actions of a tap lifted at `position`: confirming while a menu is open, turning the piece
+/// otherwise.
+pub(crate) fn tap_actions(
+    position: Vec2,
+    window_width: f32,
+    menu_open: bool,
+) -> Vec<(Action, f32)> {
+    if menu_open {
+        vec![(Action::MenuConfirm, 1.0)]
+    } else {
+        vec![(turn_action(position, window_width), 1.0)]
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn touch_input(
+    time: Res<Time>,
+    touches: Res<Touches>,
+    state: Res<State<AppState>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    button_query: Query<(&TouchButton, &Node, &GlobalTransform)>,
+    menu_query: Query<(), With<Menu>>,
+    mut gestures: ResMut<TouchGestures>,
+    mut touch_actions: ResMut<TouchActions>,
+) {
+    touch_actions.values.clear();
+
+    let mut activate = |action: Action, value: f32| {
+        let current = touch_actions.values.entry(action).or_insert(0.0);
+        *current = current.max(value);
+    };
+
+    // On-screen buttons replace the gestures while they are shown
+    if !button_query.is_empty() {
+        for touch in touches.iter() {
+            for (button, node, transform) in button_query.iter() {
+                let rect = Rect::from_center_size(transform.translation().truncate(), node.size());
+                if rect.contains(touch.position()) {
+                    activate(button.action, 1.0);
+                }
+            }
+        }
+        gestures.touches.clear();
+        return;
+    }
+
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+
+    let now = time.elapsed_seconds();
+    for touch in touches.iter() {
+        let (gesture, started) = gestures
+            .touches
+            .entry(touch.id())
+            .or_insert((Gesture::Pending, now));
+        *gesture = gesture.classify(touch.distance().length(), now - *started);
+
+        for (action, value) in gesture_actions(
+            touch.start_position(),
+            touch.position(),
+            window.width(),
+            *gesture,
+        ) {
+            activate(action, value);
+        }
+    }
+
+    // The results screen takes confirmations like a menu
+    let menu_open = !menu_query.is_empty() || state.0 != AppState::Playing;
+    for touch in touches.iter_just_released() {
+        let gesture = gestures.touches.remove(&touch.id());
+        if gesture.is_none_or(|(gesture, _)| gesture == Gesture::Pending) {
+            for (action, value) in tap_actions(touch.position(), window.width(), menu_open) {
+                activate(action, value);
+            }
+        }
+    }
+    for touch in touches.iter_just_cancelled() {
+        gestures.touches.remove(&touch.id());
+    }
+}
+
+pub(crate) fn touch_buttons_overlay(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+    state: Res<State<AppState>>,
+    overlay_query: Query<Entity, With<TouchButtons>>,
+) {
+    let wanted = settings.touch_buttons && state.0 == AppState::Playing;
+
+    match (wanted, overlay_query.get_single()) {
+        (true, Err(_)) => spawn_touch_buttons(&mut commands, &fonts),
+        (false, Ok(entity)) => commands.entity(entity).despawn_recursive(),
+        _ => {}
+    }
+}
+
+fn spawn_touch_buttons(commands: &mut Commands, fonts: &Fonts) {
+    // Moving on the left, turning and dropping on the right
+    let groups: [&[(Action, &str)]; 2] = [
+        &[(Action::MoveLeft, "<"), (Action::MoveRight, ">")],
+        &[
+            (Action::RotateCcw, "CCW"),
+            (Action::RotateCw, "CW"),
+            (Action::SoftDrop, "Drop"),
+        ],
+    ];
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::width(Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(TOUCH_BUTTON_MARGIN),
+                    ..Default::default()
+                },
+                justify_content: JustifyContent::SpaceBetween,
+                padding: UiRect::horizontal(Val::Px(TOUCH_BUTTON_MARGIN)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(TouchButtons)
+        .with_children(|parent| {
+            for group in groups {
+                parent.spawn(NodeBundle::default()).with_children(|parent| {
+                    for (action, label) in group {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    size: Size::all(Val::Px(TOUCH_BUTTON_SIZE)),
+                                    margin: UiRect::all(Val::Px(TOUCH_BUTTON_MARGIN * 0.5)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..Default::default()
+                                },
+                                background_color: TOUCH_BUTTON_COLOR.into(),
+                                ..Default::default()
+                            })
+                            .insert(TouchButton { action: *action })
+                            .with_children(|parent| {
+                                parent.spawn(fonts.text_bundle(label, 24.0, Color::WHITE));
+                            });
+                    }
+                });
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touches_become_holds_when_held_still_and_swipes_when_moved() {
+        assert_eq!(Gesture::Pending.classify(0.0, 0.0), Gesture::Pending);
+        assert_eq!(
+            Gesture::Pending.classify(SWIPE_THRESHOLD * 0.5, HOLD_DURATION * 0.5),
+            Gesture::Pending
+        );
+        assert_eq!(Gesture::Pending.classify(0.0, HOLD_DURATION), Gesture::Hold);
+        assert_eq!(
+            Gesture::Pending.classify(SWIPE_THRESHOLD * 2.0, 0.0),
+            Gesture::Swipe
+        );
+        assert_eq!(
+            Gesture::Hold.classify(SWIPE_THRESHOLD * 2.0, HOLD_DURATION),
+            Gesture::Swipe
+        );
+        assert_eq!(
+            Gesture::Swipe.classify(0.0, HOLD_DURATION * 2.0),
+            Gesture::Swipe
+        );
+    }
+
+    #[test]
+    fn gestures_turn_on_a_hold_and_push_or_drop_on_a_swipe() {
+        let start = Vec2::new(100.0, 300.0);
+
+        assert_eq!(gesture_actions(start, start, 800.0, Gesture::Pending), []);
+        assert_eq!(
+            gesture_actions(start, start, 800.0, Gesture::Hold),
+            [(Action::RotateCcw, 1.0)]
+        );
+        assert_eq!(
+            gesture_actions(
+                Vec2::new(700.0, 300.0),
+                Vec2::new(700.0, 300.0),
+                800.0,
+                Gesture::Hold
+            ),
+            [(Action::RotateCw, 1.0)]
+        );
+
+        let half_push = start + Vec2::new(SWIPE_FULL_DISTANCE * 0.5, 10.0);
+        assert_eq!(
+            gesture_actions(start, half_push, 800.0, Gesture::Swipe),
+            [(Action::MoveRight, 0.5)]
+        );
+        let far_left = start - Vec2::new(SWIPE_FULL_DISTANCE * 2.0, 0.0);
+        assert_eq!(
+            gesture_actions(start, far_left, 800.0, Gesture::Swipe),
+            [(Action::MoveLeft, 1.0)]
+        );
+
+        let down = start + Vec2::new(0.0, SWIPE_FULL_DISTANCE);
+        assert!(
+            gesture_actions(start, down, 800.0, Gesture::Swipe).contains(&(Action::SoftDrop, 1.0))
+        );
+    }
+
+    #[test]
+    fn taps_confirm_only_in_menus() {
+        let left = Vec2::new(100.0, 300.0);
+
+        assert_eq!(tap_actions(left, 800.0, true), [(Action::MenuConfirm, 1.0)]);
+        assert_eq!(tap_actions(left, 800.0, false), [(Action::RotateCcw, 1.0)]);
+    }
+}
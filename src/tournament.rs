@@ -0,0 +1,774 @@
+//! Local tournaments: 3 to 8 players entered by name, drawn into a knockout bracket and playing
+//! its matches against each other on the two boards of Versus mode, until one is crowned. The
+//! bracket is saved next to the profile, so a tournament carries on after a restart.
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{start_run, Board, FixedSeed, GameRng, RunStarted};
+use crate::coop::CoopSession;
+use crate::input::{Action, InputMap};
+use crate::modes::GameMode;
+use crate::online::OnlineSession;
+use crate::physics::block_death_detection;
+use crate::pieces::PieceDefinitions;
+use crate::scoring::BoardStats;
+use crate::settings::{load_ron, project_dirs, save_ron, Settings};
+use crate::tetromino::{ActivePieces, Block};
+use crate::ui::{
+    menu_navigation, spawn_toast, Fonts, Menu, MenuCancelled, MenuConfirmed, MenuItem,
+    CONTROLS_LAYER,
+};
+use crate::versus::versus_knockout;
+use crate::{AppState, GameplaySet};
+
+/// The tournament menu, and the results of tournament matches.
+pub struct TournamentPlugin;
+
+impl Plugin for TournamentPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Tournament::load())
+            .add_startup_system(seed_tournament_rng)
+            .add_system(tournament_input.after(menu_navigation))
+            .add_system(tournament_labels.after(tournament_input))
+            .add_system(
+                tournament_name_entry
+                    .after(tournament_input)
+                    .run_if(resource_exists::<TournamentNameEntry>()),
+            )
+            .add_system(abandon_tournament_match.in_schedule(OnEnter(AppState::MainMenu)))
+            .add_system(
+                tournament_result
+                    .after(block_death_detection)
+                    .before(versus_knockout)
+                    .in_set(GameplaySet),
+            );
+    }
+}
+
+pub(crate) const TOURNAMENT_MIN_PLAYERS: usize = 3;
+pub(crate) const TOURNAMENT_MAX_PLAYERS: usize = 8;
+pub(crate) const TOURNAMENT_NAME_MAX_LENGTH: usize = 12;
+
+/// A match of the bracket, a Versus run of the two players side by side, both dealt the same
+/// pieces. The one still playing when the other is knocked out wins.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct TournamentMatch {
+    /// Indices into `Tournament::players`, the first on the left board. `None` is a bye in the
+    /// first round, and a player yet to be decided in later rounds.
+    pub(crate) players: [Option<usize>; 2],
+    /// Score of each player in the run that decided the match
+    pub(crate) scores: [Option<i32>; 2],
+    pub(crate) seed: u64,
+    pub(crate) winner: Option<usize>,
+}
+
+/// The tournament in progress, if any, saved next to the profile.
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Tournament {
+    /// In the order entered
+    pub(crate) players: Vec<String>,
+    /// The first round first and the final last, empty until the tournament starts
+    pub(crate) rounds: Vec<Vec<TournamentMatch>>,
+    /// Round and index of the match whose run is being played
+    #[serde(skip)]
+    pub(crate) playing: Option<(usize, usize)>,
+}
+
+impl Tournament {
+    pub(crate) fn path() -> Option<PathBuf> {
+        project_dirs().map(|dirs| dirs.data_dir().join("tournament.ron"))
+    }
+
+    /// Load the saved tournament. One with a bracket that doesn't fit its players, e.g. from
+    /// an edited file, is dropped.
+    pub(crate) fn load() -> Self {
+        Self::path()
+            .and_then(|path| load_ron(&path))
+            .map(Self::validated)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn validated(self) -> Self {
+        if self.is_valid() {
+            self
+        } else {
+            warn!("ignoring a tournament with an invalid bracket");
+            Self::default()
+        }
+    }
+
+    /// Every match refers to players that exist, and the rounds halve down to the final.
+    pub(crate) fn is_valid(&self) -> bool {
+        let n_players = self.players.len();
+        if n_players > TOURNAMENT_MAX_PLAYERS {
+            return false;
+        }
+        if !self.is_started() {
+            return true;
+        }
+        if n_players < TOURNAMENT_MIN_PLAYERS {
+            return false;
+        }
+
+        let mut n_matches = n_players.next_power_of_two() / 2;
+        for matches in &self.rounds {
+            let valid_player = |player: Option<usize>| player.iter().all(|p| *p < n_players);
+            let valid_matches = matches.len() == n_matches
+                && matches.iter().all(|tournament_match| {
+                    tournament_match.players.into_iter().all(valid_player)
+                        && tournament_match
+                            .winner
+                            .iter()
+                            .all(|winner| tournament_match.players.contains(&Some(*winner)))
+                });
+            if !valid_matches {
+                return false;
+            }
+            n_matches /= 2;
+        }
+
+        self.rounds.last().map(Vec::len) == Some(1)
+    }
+
+    pub(crate) fn save(&self) {
+        if let Some(path) = Self::path() {
+            save_ron(&path, self);
+        }
+    }
+
+    pub(crate) fn is_started(&self) -> bool {
+        !self.rounds.is_empty()
+    }
+
+    /// Draw the players into a bracket. Short of a power of two, the first players drawn get
+    /// a bye through the first round.
+    pub(crate) fn start(&mut self, rng: &mut impl Rng) {
+        let mut order: Vec<usize> = (0..self.players.len()).collect();
+        order.shuffle(rng);
+
+        let size = order.len().next_power_of_two();
+        let seat = |index: usize| order.get(index).copied();
+        let first_round = (0..size / 2)
+            .map(|index| TournamentMatch {
+                players: [seat(index), seat(size - 1 - index)],
+                seed: rng.gen(),
+                ..Default::default()
+            })
+            .collect();
+
+        self.rounds = vec![first_round];
+        let mut n_matches = size / 2;
+        while n_matches > 1 {
+            n_matches /= 2;
+            self.rounds.push(
+                (0..n_matches)
+                    .map(|_| TournamentMatch {
+                        seed: rng.gen(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            );
+        }
+
+        for index in 0..size / 2 {
+            if let [Some(player), None] = self.rounds[0][index].players {
+                self.advance((0, index), player);
+            }
+        }
+    }
+
+    /// Record the winner of a match, moving them on to their next match.
+    pub(crate) fn advance(&mut self, (round, index): (usize, usize), winner: usize) {
+        self.rounds[round][index].winner = Some(winner);
+        if let Some(next) = self.rounds.get_mut(round + 1) {
+            next[index / 2].players[index % 2] = Some(winner);
+        }
+    }
+
+    /// Record the run of a match, by the scores of both players and which of them is knocked
+    /// out. The other one wins, and when both are out at once the match is played again on a
+    /// new seed.
+    pub(crate) fn record_run(
+        &mut self,
+        (round, index): (usize, usize),
+        scores: [i32; 2],
+        knocked_out: [bool; 2],
+        rng: &mut impl Rng,
+    ) {
+        let tournament_match = &mut self.rounds[round][index];
+        if tournament_match.winner.is_some() {
+            return;
+        }
+
+        let side = match knocked_out {
+            [false, true] => 0,
+            [true, false] => 1,
+            [true, true] => {
+                tournament_match.seed = rng.gen();
+                return;
+            }
+            [false, false] => return,
+        };
+        tournament_match.scores = scores.map(Some);
+        if let Some(winner) = tournament_match.players[side] {
+            self.advance((round, index), winner);
+        }
+    }
+
+    /// The first match of the earliest round that is ready to be played.
+    pub(crate) fn next_match(&self) -> Option<(usize, usize)> {
+        self.rounds.iter().enumerate().find_map(|(round, matches)| {
+            matches
+                .iter()
+                .position(|tournament_match| {
+                    tournament_match.winner.is_none()
+                        && tournament_match.players.iter().all(Option::is_some)
+                })
+                .map(|index| (round, index))
+        })
+    }
+
+    pub(crate) fn champion(&self) -> Option<&str> {
+        let winner = self.rounds.last()?.first()?.winner?;
+        Some(&self.players[winner])
+    }
+
+    pub(crate) fn round_name(&self, round: usize) -> String {
+        match self.rounds.len() - round {
+            1 => "Final".to_string(),
+            2 => "Semifinals".to_string(),
+            _ => format!("Round {}", round + 1),
+        }
+    }
+
+    pub(crate) fn match_name(&self, (round, index): (usize, usize)) -> String {
+        let name = |player: Option<usize>| player.map_or("?", |player| &self.players[player]);
+        let [left, right] = self.rounds[round][index].players;
+        format!("{} vs {}", name(left), name(right))
+    }
+
+    /// The bracket as text, a line per match under the name of each round.
+    pub(crate) fn bracket_lines(&self) -> Vec<String> {
+        if !self.is_started() {
+            return self
+                .players
+                .iter()
+                .enumerate()
+                .map(|(index, name)| format!("{}. {name}", index + 1))
+                .collect();
+        }
+
+        let score = |score: Option<i32>| score.map_or("-".to_string(), |score| score.to_string());
+        let mut lines = vec![];
+        for (round, matches) in self.rounds.iter().enumerate() {
+            lines.push(self.round_name(round));
+            for (index, tournament_match) in matches.iter().enumerate() {
+                let [first, second] = tournament_match.scores;
+                let line = match (tournament_match.players, tournament_match.winner) {
+                    ([Some(player), None], _) if round == 0 => {
+                        format!("{} (bye)", self.players[player])
+                    }
+                    (_, Some(winner)) => format!(
+                        "{}   {} : {}   winner {}",
+                        self.match_name((round, index)),
+                        score(first),
+                        score(second),
+                        self.players[winner]
+                    ),
+                    _ => self.match_name((round, index)),
+                };
+                lines.push(format!("  {line}"));
+            }
+        }
+        lines
+    }
+}
+
+/// Draws the bracket, and the seeds of matches, so that `--seed` reproduces a tournament too.
+#[derive(Resource)]
+pub(crate) struct TournamentRng(pub(crate) GameRng);
+
+pub(crate) fn seed_tournament_rng(mut commands: Commands, fixed_seed: Res<FixedSeed>) {
+    commands.insert_resource(TournamentRng(GameRng::new(fixed_seed.0)));
+}
+
+/// The tournament menu.
+#[derive(Component)]
+pub(crate) struct TournamentScreen;
+
+#[derive(Component)]
+pub(crate) struct TournamentLabel {
+    pub(crate) item: TournamentItem,
+}
+
+/// Text of the entered players, or of the bracket once the tournament has started.
+#[derive(Component)]
+pub(crate) struct TournamentBracket;
+
+/// Typing the name of a player to enter. Menus don't take input meanwhile.
+#[derive(Resource, Default)]
+pub(crate) struct TournamentNameEntry {
+    pub(crate) name: String,
+}
+
+/// Entries of the tournament menu.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TournamentItem {
+    AddPlayer,
+    Play,
+    New,
+    Back,
+}
+
+impl TournamentItem {
+    pub(crate) const ALL: [Self; 4] = [Self::AddPlayer, Self::Play, Self::New, Self::Back];
+
+    pub(crate) fn label(&self, tournament: &Tournament) -> String {
+        match self {
+            Self::AddPlayer if tournament.is_started() => "Add player: closed".to_string(),
+            Self::AddPlayer => format!(
+                "Add player ({}/{TOURNAMENT_MAX_PLAYERS})",
+                tournament.players.len()
+            ),
+            Self::Play => match (tournament.champion(), tournament.next_match()) {
+                (Some(champion), _) => format!("Champion: {champion}"),
+                (None, Some(next_match)) => format!(
+                    "Play {}: {}",
+                    tournament.round_name(next_match.0),
+                    tournament.match_name(next_match),
+                ),
+                (None, None) if tournament.players.len() < TOURNAMENT_MIN_PLAYERS => {
+                    format!("Start (at least {TOURNAMENT_MIN_PLAYERS} players)")
+                }
+                (None, None) => "Start".to_string(),
+            },
+            Self::New => "New tournament".to_string(),
+            Self::Back => "Back".to_string(),
+        }
+    }
+}
+
+/// Opened from the main menu.
+pub(crate) fn spawn_tournament_screen(commands: &mut Commands, fonts: &Fonts) {
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.95).into(),
+            ..Default::default()
+        })
+        .insert(TournamentScreen)
+        .insert(Menu {
+            focused: 0,
+            layer: CONTROLS_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Tournament", 40.0, Color::WHITE));
+        // Filled in by `tournament_labels`
+        parent
+            .spawn(fonts.text_bundle("", 18.0, Color::WHITE).with_style(Style {
+                margin: UiRect::all(Val::Px(16.0)),
+                ..Default::default()
+            }))
+            .insert(TournamentBracket);
+        for (index, item) in TournamentItem::ALL.into_iter().enumerate() {
+            parent
+                .spawn(fonts.text_bundle("", 24.0, Color::WHITE))
+                .insert(MenuItem { menu, index })
+                .insert(TournamentLabel { item });
+        }
+        parent.spawn(fonts.text_bundle(
+            "The players of a match play side by side in Versus mode, the last one standing wins",
+            16.0,
+            Color::WHITE,
+        ));
+    });
+}
+
+/// Names may be in any script, so labels are rebuilt with a section per script when they change.
+pub(crate) fn tournament_labels(
+    fonts: Res<Fonts>,
+    tournament: Res<Tournament>,
+    name_entry: Option<Res<TournamentNameEntry>>,
+    screen_query: Query<(), With<TournamentScreen>>,
+    mut label_query: Query<(&TournamentLabel, &mut Text)>,
+    mut bracket_query: Query<&mut Text, (With<TournamentBracket>, Without<TournamentLabel>)>,
+) {
+    if screen_query.is_empty() {
+        return;
+    }
+
+    let unchanged = |text: &Text, value: &str| {
+        text.sections
+            .iter()
+            .map(|section| section.value.as_str())
+            .eq(fonts
+                .sections(value, 0.0, Color::WHITE)
+                .iter()
+                .map(|section| section.value.as_str()))
+    };
+
+    for (label, mut text) in label_query.iter_mut() {
+        let value = match (label.item, &name_entry) {
+            (TournamentItem::AddPlayer, Some(entry)) => format!("Name: {}_", entry.name),
+            (item, _) => item.label(&tournament),
+        };
+
+        // The color belongs to `menu_focus_highlight`
+        if !unchanged(&text, &value) {
+            *text = fonts.text(&value, 24.0, Color::WHITE);
+        }
+    }
+
+    let bracket = match tournament.bracket_lines() {
+        lines if lines.is_empty() => "No players yet".to_string(),
+        lines => lines.join("\n"),
+    };
+    for mut text in bracket_query.iter_mut() {
+        if !unchanged(&text, &bracket) {
+            *text = fonts.text(&bracket, 18.0, Color::WHITE);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn tournament_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    coop: Option<Res<CoopSession>>,
+    online: Option<Res<OnlineSession>>,
+    mut tournament: ResMut<Tournament>,
+    mut tournament_rng: ResMut<TournamentRng>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut run_started_events: EventWriter<RunStarted>,
+    screen_query: Query<Entity, With<TournamentScreen>>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    block_query: Query<(Entity, &Block)>,
+) {
+    let Ok(screen_entity) = screen_query.get_single() else {
+        return;
+    };
+
+    let mut close = cancelled_events
+        .iter()
+        .any(|event| event.menu == screen_entity);
+
+    for event in confirmed_events.iter() {
+        if event.menu != screen_entity {
+            continue;
+        }
+
+        match TournamentItem::ALL[event.item] {
+            TournamentItem::AddPlayer => {
+                if !tournament.is_started() && tournament.players.len() < TOURNAMENT_MAX_PLAYERS {
+                    commands.insert_resource(TournamentNameEntry::default());
+                }
+            }
+            TournamentItem::Play => {
+                if tournament.champion().is_some() {
+                    continue;
+                }
+                if coop.is_some() || online.is_some() {
+                    spawn_toast(
+                        &mut commands,
+                        &fonts,
+                        "Tournaments are played on one computer",
+                    );
+                    continue;
+                }
+                // The boards of Versus mode are only set up at launch
+                let n_boards = board_query
+                    .iter()
+                    .filter(|(_, board, ..)| board.player.is_some())
+                    .count();
+                if n_boards != 2 {
+                    settings.mode = GameMode::Versus;
+                    settings.save();
+                    spawn_toast(
+                        &mut commands,
+                        &fonts,
+                        "Matches are played in Versus mode, restart the game to play them",
+                    );
+                    continue;
+                }
+                if !tournament.is_started() {
+                    if tournament.players.len() < TOURNAMENT_MIN_PLAYERS {
+                        continue;
+                    }
+                    tournament.start(&mut tournament_rng.0);
+                    tournament.save();
+                }
+                let Some(next_match) = tournament.next_match() else {
+                    continue;
+                };
+
+                // Both boards are dealt the same, without fixing the seed of later runs
+                let seed = tournament.rounds[next_match.0][next_match.1].seed;
+                for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in
+                    board_query.iter_mut()
+                {
+                    let fixed_seed = rng.fixed_seed;
+                    rng.fixed_seed = Some(seed);
+                    run_started_events.send(start_run(
+                        &mut commands,
+                        board_entity,
+                        &mut board,
+                        &mut active_pieces,
+                        &mut stats,
+                        &settings,
+                        &piece_definitions,
+                        &mut rng,
+                        block_query
+                            .iter()
+                            .filter(|(_, block)| block.board == board_entity)
+                            .map(|(entity, _)| entity),
+                    ));
+                    rng.fixed_seed = fixed_seed;
+                    // Both players are at the keyboard
+                    board.bot = false;
+                }
+
+                tournament.playing = Some(next_match);
+                spawn_toast(
+                    &mut commands,
+                    &fonts,
+                    &format!(
+                        "{}: {}",
+                        tournament.round_name(next_match.0),
+                        tournament.match_name(next_match),
+                    ),
+                );
+                next_state.set(AppState::Playing);
+                close = true;
+            }
+            TournamentItem::New => {
+                *tournament = Tournament::default();
+                tournament.save();
+            }
+            TournamentItem::Back => close = true,
+        }
+    }
+
+    if close {
+        commands.entity(screen_entity).despawn_recursive();
+        commands.remove_resource::<TournamentNameEntry>();
+    }
+}
+
+/// Type the name of a player to enter. Return enters them, unless the name is taken, and
+/// escape gives up.
+pub(crate) fn tournament_name_entry(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    mut character_events: EventReader<ReceivedCharacter>,
+    fonts: Res<Fonts>,
+    mut entry: ResMut<TournamentNameEntry>,
+    mut tournament: ResMut<Tournament>,
+) {
+    for event in character_events.iter() {
+        let allowed = event.char.is_alphanumeric() || event.char == ' ' || event.char == '-';
+        if allowed && entry.name.chars().count() < TOURNAMENT_NAME_MAX_LENGTH {
+            entry.name.push(event.char);
+        }
+    }
+
+    if input_map.just_pressed(&input, Action::EntryDelete) {
+        entry.name.pop();
+    }
+
+    if input_map.just_pressed(&input, Action::EntryCancel) {
+        commands.remove_resource::<TournamentNameEntry>();
+        return;
+    }
+
+    let name = entry.name.trim().to_string();
+    if !input_map.just_pressed(&input, Action::EntryConfirm) || name.is_empty() {
+        return;
+    }
+
+    if tournament.players.contains(&name) {
+        spawn_toast(&mut commands, &fonts, &format!("{name} is already entered"));
+        return;
+    }
+
+    tournament.players.push(name);
+    tournament.save();
+    commands.remove_resource::<TournamentNameEntry>();
+}
+
+/// Leaving the run of a match for the main menu gives it up. The match is played again from the
+/// tournament menu.
+pub(crate) fn abandon_tournament_match(mut tournament: ResMut<Tournament>) {
+    tournament.playing = None;
+}
+
+/// Once a player of a match is knocked out, before `versus_knockout` ends the other board,
+/// record the run.
+pub(crate) fn tournament_result(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    mut tournament: ResMut<Tournament>,
+    mut tournament_rng: ResMut<TournamentRng>,
+    board_query: Query<(&Board, &BoardStats)>,
+) {
+    let Some(playing) = tournament.playing else {
+        return;
+    };
+
+    let mut scores = [0; 2];
+    let mut knocked_out = [false; 2];
+    for (board, stats) in board_query.iter() {
+        if let Some(player @ 0..=1) = board.player {
+            scores[player] = stats.score();
+            knocked_out[player] = stats.game_over_duration.is_some();
+        }
+    }
+    if knocked_out == [false, false] {
+        return;
+    }
+
+    tournament.record_run(playing, scores, knocked_out, &mut tournament_rng.0);
+    tournament.playing = None;
+    tournament.save();
+
+    let (round, index) = playing;
+    let tournament_match = &tournament.rounds[round][index];
+    let message = match (tournament.champion(), tournament_match.winner) {
+        (Some(champion), _) => format!("{champion} wins the tournament!"),
+        (None, Some(winner)) => format!(
+            "{} wins, next: {}",
+            tournament.players[winner],
+            tournament
+                .next_match()
+                .map_or(String::new(), |next_match| tournament
+                    .match_name(next_match))
+        ),
+        (None, None) => "Both are out at once, the match is played again".to_string(),
+    };
+    spawn_toast(&mut commands, &fonts, &message);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::settings::test_world;
+
+    fn tournament(n_players: usize) -> Tournament {
+        let mut tournament = Tournament {
+            players: (0..n_players).map(|index| format!("P{index}")).collect(),
+            ..Default::default()
+        };
+        tournament.start(&mut StdRng::seed_from_u64(n_players as u64));
+        tournament
+    }
+
+    #[test]
+    fn every_bracket_crowns_a_champion() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for n_players in TOURNAMENT_MIN_PLAYERS..=TOURNAMENT_MAX_PLAYERS {
+            let mut tournament = tournament(n_players);
+            assert!(tournament.is_valid());
+
+            let mut played = 0;
+            while let Some(next_match) = tournament.next_match() {
+                let knocked_out = [played % 2 == 1, played % 2 == 0];
+                tournament.record_run(next_match, [100, 50], knocked_out, &mut rng);
+                played += 1;
+            }
+
+            // Everyone but the champion is knocked out in a match, byes aren't played
+            assert_eq!(played, n_players - 1, "{n_players} players");
+            assert!(tournament.champion().is_some(), "{n_players} players");
+            assert!(tournament.is_valid());
+        }
+    }
+
+    #[test]
+    fn last_one_standing_wins_and_both_out_at_once_is_played_again_on_a_new_seed() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tournament = tournament(4);
+        let first_match = tournament.next_match().unwrap();
+        let [first, second] = tournament.rounds[0][0].players;
+        let seed = tournament.rounds[0][0].seed;
+
+        tournament.record_run(first_match, [300, 300], [true, true], &mut rng);
+        assert_eq!(tournament.rounds[0][0].winner, None);
+        assert_eq!(tournament.rounds[0][0].scores, [None, None]);
+        assert_ne!(tournament.rounds[0][0].seed, seed);
+
+        // Knocked out with the higher score still loses
+        tournament.record_run(first_match, [400, 200], [true, false], &mut rng);
+        assert_eq!(tournament.rounds[0][0].winner, second);
+        assert_eq!(tournament.rounds[0][0].scores, [Some(400), Some(200)]);
+        assert_eq!(tournament.rounds[1][0].players[0], second);
+        assert_ne!(tournament.rounds[1][0].players[0], first);
+        assert_eq!(tournament.next_match(), Some((0, 1)));
+    }
+
+    #[test]
+    fn leaving_a_match_for_the_main_menu_abandons_its_run() {
+        let mut world = test_world();
+        let mut tournament = tournament(3);
+        let next_match = tournament.next_match().unwrap();
+        tournament.playing = Some(next_match);
+        world.insert_resource(tournament);
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(abandon_tournament_match);
+        schedule.run(&mut world);
+
+        let tournament = world.resource::<Tournament>();
+        assert_eq!(tournament.playing, None);
+        assert_eq!(tournament.next_match(), Some(next_match));
+        assert_eq!(tournament.rounds[0][0].scores, [None, None]);
+    }
+
+    #[test]
+    fn saved_brackets_with_invalid_players_are_dropped() {
+        let saved = ron::to_string(&tournament(3)).unwrap();
+        let loaded: Tournament = ron::from_str(&saved).unwrap();
+        assert_eq!(loaded.validated().players.len(), 3);
+
+        let mut tampered = tournament(3);
+        tampered.rounds[1][0].players[0] = Some(7);
+        assert!(!tampered.is_valid());
+        assert!(!tampered.validated().is_started());
+
+        let mut missing_round = tournament(5);
+        missing_round.rounds.pop();
+        assert!(!missing_round.is_valid());
+
+        let mut bad_winner = tournament(4);
+        bad_winner.rounds[0][0].winner = bad_winner.rounds[0][1].players[0];
+        assert!(!bad_winner.is_valid());
+    }
+}
@@ -0,0 +1,1641 @@
+//! Fonts, menus, dialogs and other screens.
+
+use std::collections::HashMap;
+
+use bevy::app::AppExit;
+#[cfg(not(feature = "wasm"))]
+use bevy::asset::FileAssetIo;
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowCloseRequested, WindowResized};
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::{start_run, Board, GameRng, RunStarted};
+use crate::custom::{spawn_custom_game_screen, CustomGameNameEntry, CustomGameScreen};
+use crate::hazards::hazard_forces;
+use crate::input::{
+    update_action_state, Action, ActionState, GamepadProfiles, InputMap, KeyCapture,
+};
+use crate::online::{OnlineRating, OnlineSession};
+use crate::physics::{balloon_buoyancy, tetromino_movement, GameTimeScale, TimeScaleSource};
+use crate::pieces::PieceDefinitions;
+use crate::scoring::{BoardStats, Profile};
+use crate::settings::{Settings, SettingsItem};
+use crate::tetromino::{ActivePieces, Block};
+use crate::tournament::{spawn_tournament_screen, TournamentNameEntry, TournamentScreen};
+use crate::AppState;
+
+/// Menus, dialogs, toasts and UI scaling. Named so as not to clash with Bevy's `UiPlugin`.
+pub struct GameUiPlugin;
+
+impl Plugin for GameUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Shutdown>()
+            .add_event::<MenuConfirmed>()
+            .add_event::<MenuCancelled>()
+            .add_startup_system(load_fonts.in_base_set(StartupSet::PreStartup))
+            .add_startup_system(setup_onboarding)
+            .add_system(fall_back_from_missing_fonts)
+            .add_startup_system(setup_clean_feed_overlay)
+            .add_system(spawn_main_menu.in_schedule(OnEnter(AppState::MainMenu)))
+            .add_system(despawn_main_menu.in_schedule(OnExit(AppState::MainMenu)))
+            .add_system(main_menu_input.after(menu_navigation))
+            .add_system(key_bindings_input.after(menu_navigation))
+            .add_system(settings_input.after(menu_navigation))
+            .add_system(settings_labels.after(settings_input))
+            .add_system(key_binding_labels.after(key_bindings_input))
+            .add_system(spawn_pause_screen.in_schedule(OnEnter(AppState::Paused)))
+            .add_system(despawn_pause_screen.in_schedule(OnExit(AppState::Paused)))
+            .add_system(
+                pause_requests
+                    .after(update_action_state)
+                    .run_if(in_state(AppState::Playing).or_else(in_state(AppState::Paused))),
+            )
+            .add_system(pause_menu_input.after(menu_navigation))
+            .add_system(toggle_telemetry)
+            .add_system(toggle_clean_feed.after(update_action_state))
+            .add_system(apply_clean_feed)
+            .add_system(
+                telemetry_hud
+                    .after(tetromino_movement)
+                    .after(hazard_forces)
+                    .after(balloon_buoyancy),
+            )
+            .add_system(quit_requests.after(update_action_state))
+            .add_system(quit_dialog.after(menu_navigation))
+            .add_system(flush_on_shutdown)
+            .add_system(exit_after_shutdown.after(flush_on_shutdown))
+            .add_system(menu_navigation.after(update_action_state))
+            .add_system(menu_focus_highlight.after(menu_navigation))
+            .add_system(
+                onboarding_input
+                    .after(menu_navigation)
+                    .run_if(resource_exists::<Onboarding>()),
+            )
+            .add_system(toast_fade)
+            .add_system(adjust_ui_scale.after(update_action_state))
+            .add_system(apply_ui_scale.after(adjust_ui_scale));
+    }
+}
+
+// World-space text is rendered large and scaled down to stay crisp
+pub(crate) const WORLD_TEXT_FONT_SIZE: f32 = 40.0;
+pub(crate) const WORLD_TEXT_SCALE: f32 = 1.0 / 60.0;
+
+// Font stack config, relative to the asset folder
+pub(crate) const LOCALIZATION_FONTS_PATH: &str = "localization/fonts.ron";
+
+pub(crate) const TOAST_DURATION: f32 = 2.0;
+
+// Readouts are larger in the clean feed, to stay legible in footage and on projectors
+pub(crate) const CLEAN_FEED_SCORE_FONT_SIZE: f32 = 44.0;
+pub(crate) const CLEAN_FEED_TOAST_FONT_SIZE: f32 = 40.0;
+// The vignette is made of stacked translucent strips along the window edges, in logical pixels
+pub(crate) const VIGNETTE_STEPS: usize = 8;
+pub(crate) const VIGNETTE_STEP_WIDTH: f32 = 14.0;
+pub(crate) const VIGNETTE_STEP_ALPHA: f32 = 0.07;
+
+pub(crate) const UI_SCALE_STEP: f32 = 0.05;
+pub(crate) const UI_SCALE_MIN: f32 = 0.75;
+pub(crate) const UI_SCALE_MAX: f32 = 2.0;
+// The UI is at the scale of the setting in a window this size, and scales with smaller and
+// larger windows within the limits below
+pub(crate) const UI_REFERENCE_WINDOW_SIZE: Vec2 = Vec2::new(1280.0, 720.0);
+pub(crate) const UI_WINDOW_SCALE_MIN: f32 = 0.5;
+pub(crate) const UI_WINDOW_SCALE_MAX: f32 = 2.0;
+// Windows narrower than this, width over height, get the narrow layout of the HUD
+pub(crate) const NARROW_WINDOW_ASPECT: f32 = 0.8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+pub(crate) enum Language {
+    #[default]
+    English,
+    Japanese,
+    ChineseSimplified,
+    Korean,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Script {
+    Latin,
+    Cjk,
+}
+
+impl Script {
+    /// The script of a character, or `None` for characters any font can render (spaces, digits, punctuation).
+    pub(crate) fn of(c: char) -> Option<Self> {
+        match c as u32 {
+            0x0000..=0x0040 | 0x005B..=0x0060 | 0x007B..=0x007F => None,
+            0x1100..=0x11FF // Hangul Jamo
+            | 0x2E80..=0x9FFF // CJK radicals, kana, ideographs
+            | 0xAC00..=0xD7AF // Hangul syllables
+            | 0xF900..=0xFAFF // CJK compatibility ideographs
+            | 0xFF00..=0xFFEF // Full-width forms
+            => Some(Self::Cjk),
+            _ => Some(Self::Latin),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct FontOverride {
+    pub(crate) latin: Option<String>,
+    pub(crate) cjk: Option<String>,
+}
+
+/// Font configuration from the localization config.
+#[derive(Deserialize)]
+pub(crate) struct LocalizationFonts {
+    pub(crate) latin: String,
+    /// CJK runs are rendered with the Latin font without one
+    #[serde(default)]
+    pub(crate) cjk: Option<String>,
+    #[serde(default)]
+    pub(crate) overrides: HashMap<Language, FontOverride>,
+}
+
+impl LocalizationFonts {
+    /// The config the game was built with.
+    pub(crate) fn built_in() -> Self {
+        ron::from_str(include_str!("../assets/localization/fonts.ron"))
+            .expect("invalid localization font config")
+    }
+
+    /// The config in the asset folder, so that fonts can be added without rebuilding. Falls back
+    /// to the built-in config if it can't be read.
+    #[cfg(not(feature = "wasm"))]
+    pub(crate) fn load() -> Self {
+        let path = FileAssetIo::get_base_path()
+            .join("assets")
+            .join(LOCALIZATION_FONTS_PATH);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("could not read {path:?}: {err}");
+                return Self::built_in();
+            }
+        };
+
+        ron::from_str(&contents).unwrap_or_else(|err| {
+            warn!("invalid {path:?}: {err}");
+            Self::built_in()
+        })
+    }
+
+    /// There is no asset folder to read from in the browser.
+    #[cfg(feature = "wasm")]
+    pub(crate) fn load() -> Self {
+        Self::built_in()
+    }
+}
+
+/// Font stack for UI text, with per-script fallback.
+#[derive(Resource)]
+pub(crate) struct Fonts {
+    pub(crate) latin: Handle<Font>,
+    pub(crate) cjk: Handle<Font>,
+}
+
+impl Fonts {
+    pub(crate) fn font(&self, script: Script) -> Handle<Font> {
+        match script {
+            Script::Latin => self.latin.clone(),
+            Script::Cjk => self.cjk.clone(),
+        }
+    }
+
+    /// Split text into sections, rendering each run of characters with the font for its script.
+    pub(crate) fn sections(&self, value: &str, font_size: f32, color: Color) -> Vec<TextSection> {
+        let mut runs: Vec<(Script, String)> = vec![];
+
+        for c in value.chars() {
+            match (Script::of(c), runs.last_mut()) {
+                (None, Some((_, run))) => run.push(c),
+                (Some(script), Some((run_script, run))) if *run_script == script => run.push(c),
+                (script, _) => runs.push((script.unwrap_or(Script::Latin), c.to_string())),
+            }
+        }
+
+        if runs.is_empty() {
+            runs.push((Script::Latin, String::new()));
+        }
+
+        runs.into_iter()
+            .map(|(script, run)| {
+                TextSection::new(
+                    run,
+                    TextStyle {
+                        font: self.font(script),
+                        font_size,
+                        color,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) fn text(&self, value: &str, font_size: f32, color: Color) -> Text {
+        Text::from_sections(self.sections(value, font_size, color))
+    }
+
+    pub(crate) fn text_bundle(&self, value: &str, font_size: f32, color: Color) -> TextBundle {
+        TextBundle::from_sections(self.sections(value, font_size, color))
+    }
+}
+
+/// Sent when the focused item of a menu is confirmed.
+pub(crate) struct MenuConfirmed {
+    pub(crate) menu: Entity,
+    pub(crate) item: usize,
+}
+
+pub(crate) struct MenuCancelled {
+    pub(crate) menu: Entity,
+}
+
+/// Sent once before exiting, giving every system a chance to persist its state.
+pub(crate) struct Shutdown;
+
+/// Present while shutting down, the app exits the frame after `Shutdown` was sent.
+#[derive(Resource)]
+pub(crate) struct ShuttingDown;
+
+/// Present while the onboarding screen is shown, before the first game.
+#[derive(Resource)]
+pub(crate) struct Onboarding {
+    pub(crate) dont_show_again: bool,
+}
+
+pub(crate) fn load_fonts(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    profile: Res<Profile>,
+) {
+    let config = LocalizationFonts::load();
+    let language_override = config.overrides.get(&profile.language);
+
+    let latin = language_override
+        .and_then(|font_override| font_override.latin.as_ref())
+        .unwrap_or(&config.latin);
+    let cjk = language_override
+        .and_then(|font_override| font_override.cjk.as_ref())
+        .or(config.cjk.as_ref());
+
+    let latin = asset_server.load(latin.as_str());
+    commands.insert_resource(Fonts {
+        cjk: cjk.map_or_else(|| latin.clone(), |cjk| asset_server.load(cjk.as_str())),
+        latin,
+    });
+}
+
+/// No CJK font is bundled: if one added to the localization config fails to load, CJK text is
+/// rendered with the Latin font instead of not at all.
+pub(crate) fn fall_back_from_missing_fonts(
+    asset_server: Res<AssetServer>,
+    mut fonts: ResMut<Fonts>,
+    mut text_query: Query<&mut Text>,
+) {
+    if fonts.cjk == fonts.latin || asset_server.get_load_state(&fonts.cjk) != LoadState::Failed {
+        return;
+    }
+
+    let latin = fonts.latin.clone();
+    let missing = std::mem::replace(&mut fonts.cjk, latin);
+    let path = asset_server
+        .get_handle_path(&missing)
+        .map(|path| path.path().display().to_string())
+        .unwrap_or_default();
+    warn!("font {path} is missing, falling back to the Latin font");
+
+    for mut text in text_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            if section.style.font == missing {
+                section.style.font = fonts.latin.clone();
+            }
+        }
+    }
+}
+
+/// HUD text living in world space, scaled with the UI scale setting.
+#[derive(Component)]
+pub(crate) struct WorldHudText;
+
+/// Physics readout of the controlled piece.
+#[derive(Component)]
+pub(crate) struct TelemetryHud {
+    pub(crate) board: Entity,
+}
+
+/// Vignette and logo of the clean feed, shown only in that mode.
+#[derive(Component)]
+pub(crate) struct CleanFeedOverlay;
+
+/// Short-lived message that fades out.
+#[derive(Component)]
+pub(crate) struct Toast {
+    pub(crate) timer: Timer,
+}
+
+/// Root of a menu navigable with the menu actions. Only the menu on
+/// the highest layer receives input.
+#[derive(Component, Default)]
+pub(crate) struct Menu {
+    pub(crate) focused: usize,
+    pub(crate) layer: u32,
+}
+
+#[derive(Component)]
+pub(crate) struct MenuItem {
+    pub(crate) menu: Entity,
+    pub(crate) index: usize,
+}
+
+#[derive(Component)]
+pub(crate) struct OnboardingScreen;
+
+#[derive(Component)]
+pub(crate) struct QuitDialog;
+
+#[derive(Component)]
+pub(crate) struct IdlePauseScreen;
+
+#[derive(Component)]
+pub(crate) struct MainMenuScreen;
+
+#[derive(Component)]
+pub(crate) struct ControlsScreen;
+
+#[derive(Component)]
+pub(crate) struct KeyBindingsScreen;
+
+#[derive(Component)]
+pub(crate) struct SettingsScreen;
+
+/// Menu item showing a setting and its value.
+#[derive(Component)]
+pub(crate) struct SettingsLabel {
+    pub(crate) item: SettingsItem,
+}
+
+/// Menu item showing the keys bound to an action.
+#[derive(Component)]
+pub(crate) struct KeyBindingLabel {
+    pub(crate) action: Action,
+}
+
+#[derive(Component)]
+pub(crate) struct PauseScreen;
+
+pub(crate) const MAIN_MENU_NEW_GAME_ITEM: usize = 0;
+pub(crate) const MAIN_MENU_CUSTOM_GAME_ITEM: usize = 1;
+pub(crate) const MAIN_MENU_TOURNAMENT_ITEM: usize = 2;
+pub(crate) const MAIN_MENU_CONTROLS_ITEM: usize = 3;
+pub(crate) const MAIN_MENU_SETTINGS_ITEM: usize = 4;
+pub(crate) const MAIN_MENU_QUIT_ITEM: usize = 5;
+pub(crate) const PAUSE_RESUME_ITEM: usize = 0;
+pub(crate) const PAUSE_MAIN_MENU_ITEM: usize = 1;
+pub(crate) const PAUSE_QUIT_ITEM: usize = 2;
+pub(crate) const CONTROLS_LAYER: u32 = 1;
+pub(crate) const ONBOARDING_LAYER: u32 = 2;
+// Opened from the settings screen
+pub(crate) const KEY_BINDINGS_LAYER: u32 = 3;
+
+pub(crate) const QUIT_DIALOG_LAYER: u32 = 10;
+pub(crate) const QUIT_DIALOG_KEEP_PLAYING_ITEM: usize = 0;
+pub(crate) const QUIT_DIALOG_QUIT_ITEM: usize = 1;
+
+pub(crate) fn onboarding_checkbox_text(dont_show_again: bool) -> String {
+    let mark = if dont_show_again { "x" } else { " " };
+    format!("[{mark}] Don't show this again")
+}
+
+pub(crate) const ONBOARDING_START_ITEM: usize = 0;
+pub(crate) const ONBOARDING_DONT_SHOW_ITEM: usize = 1;
+
+pub(crate) fn setup_onboarding(
+    mut commands: Commands,
+    profile: Res<Profile>,
+    fonts: Res<Fonts>,
+    mut time_scale: ResMut<GameTimeScale>,
+) {
+    if profile.show_onboarding {
+        spawn_onboarding(&mut commands, &fonts, &profile, &mut time_scale);
+    }
+}
+
+/// Show the onboarding screen on top of the main menu.
+pub(crate) fn spawn_onboarding(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    profile: &Profile,
+    time_scale: &mut GameTimeScale,
+) {
+    // Reopening it (e.g. when idle) keeps the player's choice
+    let dont_show_again = !profile.show_onboarding;
+
+    commands.insert_resource(Onboarding { dont_show_again });
+    time_scale.set(TimeScaleSource::Onboarding, 0.0);
+
+    let assist_text = if profile.assist_mode {
+        "Assist Mode is on to get you started."
+    } else {
+        ""
+    };
+
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+            ..Default::default()
+        })
+        .insert(OnboardingScreen)
+        .insert(Menu {
+            focused: ONBOARDING_START_ITEM,
+            layer: ONBOARDING_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+            parent.spawn(fonts.text_bundle("Newtonian Tetris", 48.0, Color::WHITE));
+            parent.spawn(
+                fonts
+                    .text_bundle(
+                        "This is Tetris following the laws of physics.\n\
+                         Pieces don't snap to a grid: you push them with forces and twist them with torque.\n\
+                         They tumble, slide and topple. Blocks falling off the board hurt your health.\n\
+                         Rows are cleared when they are physically full.",
+                        20.0,
+                        Color::WHITE,
+                    )
+                .with_text_alignment(TextAlignment::Center)
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(24.0)),
+                    ..Default::default()
+                }),
+            );
+            parent.spawn(fonts.text_bundle(assist_text, 20.0, Color::WHITE));
+            parent
+                .spawn(fonts.text_bundle("Continue", 28.0, Color::WHITE))
+                .insert(MenuItem {
+                    menu,
+                    index: ONBOARDING_START_ITEM,
+                });
+            parent
+                .spawn(fonts.text_bundle(
+                    &onboarding_checkbox_text(dont_show_again),
+                    20.0,
+                    Color::WHITE,
+                ))
+                .insert(MenuItem {
+                    menu,
+                    index: ONBOARDING_DONT_SHOW_ITEM,
+                });
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn onboarding_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    mut onboarding: ResMut<Onboarding>,
+    mut profile: ResMut<Profile>,
+    mut time_scale: ResMut<GameTimeScale>,
+    fonts: Res<Fonts>,
+    screen_query: Query<Entity, With<OnboardingScreen>>,
+    mut item_query: Query<(&MenuItem, &mut Text)>,
+) {
+    let mut start = false;
+
+    for event in confirmed_events.iter() {
+        if !screen_query.contains(event.menu) {
+            continue;
+        }
+
+        match event.item {
+            ONBOARDING_DONT_SHOW_ITEM => {
+                onboarding.dont_show_again = !onboarding.dont_show_again;
+
+                for (item, mut text) in item_query.iter_mut() {
+                    if item.menu == event.menu && item.index == ONBOARDING_DONT_SHOW_ITEM {
+                        *text = fonts.text(
+                            &onboarding_checkbox_text(onboarding.dont_show_again),
+                            20.0,
+                            Color::WHITE,
+                        );
+                    }
+                }
+            }
+            _ => start = true,
+        }
+    }
+
+    // Backing out of onboarding also starts the game
+    if cancelled_events
+        .iter()
+        .any(|event| screen_query.contains(event.menu))
+    {
+        start = true;
+    }
+
+    if start {
+        // Saving here also marks the first launch as done
+        profile.show_onboarding = !onboarding.dont_show_again;
+        profile.save();
+
+        for entity in screen_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        commands.remove_resource::<Onboarding>();
+        time_scale.clear(TimeScaleSource::Onboarding);
+    }
+}
+
+pub(crate) fn spawn_toast(commands: &mut Commands, fonts: &Fonts, message: &str) {
+    spawn_sized_toast(commands, fonts, message, 24.0);
+}
+
+pub(crate) fn spawn_sized_toast(
+    commands: &mut Commands,
+    fonts: &Fonts,
+    message: &str,
+    font_size: f32,
+) {
+    commands
+        .spawn(
+            fonts
+                .text_bundle(message, font_size, Color::WHITE)
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(16.0),
+                        left: Val::Px(16.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }),
+        )
+        .insert(Toast {
+            timer: Timer::from_seconds(TOAST_DURATION, TimerMode::Once),
+        });
+}
+
+pub(crate) fn toast_fade(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut toast_query: Query<(Entity, &mut Toast, &mut Text)>,
+) {
+    for (entity, mut toast, mut text) in toast_query.iter_mut() {
+        toast.timer.tick(time.delta());
+
+        if toast.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let alpha = (1.0 - toast.timer.percent()).min(0.25) * 4.0;
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(alpha);
+        }
+    }
+}
+
+/// Move focus between menu items with wrap-around, and send confirm/cancel events
+/// for the topmost menu.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn menu_navigation(
+    action_state: Res<ActionState>,
+    key_capture: Option<Res<KeyCapture>>,
+    name_entry: Option<Res<CustomGameNameEntry>>,
+    player_entry: Option<Res<TournamentNameEntry>>,
+    mut menu_query: Query<(Entity, &mut Menu)>,
+    item_query: Query<&MenuItem>,
+    mut confirmed_events: EventWriter<MenuConfirmed>,
+    mut cancelled_events: EventWriter<MenuCancelled>,
+) {
+    // The key being bound, or the name being typed, is not a menu key
+    if key_capture.is_some() || name_entry.is_some() || player_entry.is_some() {
+        return;
+    }
+
+    let Some((menu_entity, mut menu)) = menu_query.iter_mut().max_by_key(|(_, menu)| menu.layer)
+    else {
+        return;
+    };
+
+    let n_items = item_query
+        .iter()
+        .filter(|item| item.menu == menu_entity)
+        .count();
+
+    if n_items == 0 {
+        return;
+    }
+
+    if action_state.just_pressed(Action::MenuDown) {
+        menu.focused = (menu.focused + 1) % n_items;
+    }
+
+    if action_state.just_pressed(Action::MenuUp) {
+        menu.focused = (menu.focused + n_items - 1) % n_items;
+    }
+
+    if action_state.just_pressed(Action::MenuConfirm) {
+        confirmed_events.send(MenuConfirmed {
+            menu: menu_entity,
+            item: menu.focused,
+        });
+    }
+
+    if action_state.just_pressed(Action::MenuCancel) {
+        cancelled_events.send(MenuCancelled { menu: menu_entity });
+    }
+}
+
+pub(crate) fn menu_focus_highlight(
+    menu_query: Query<&Menu>,
+    mut item_query: Query<(&MenuItem, &mut Text)>,
+) {
+    for (item, mut text) in item_query.iter_mut() {
+        let Ok(menu) = menu_query.get(item.menu) else {
+            continue;
+        };
+
+        let color = if menu.focused == item.index {
+            Color::rgb_u8(238, 243, 0)
+        } else {
+            Color::WHITE
+        };
+
+        for section in text.sections.iter_mut() {
+            if section.style.color != color {
+                section.style.color = color;
+            }
+        }
+    }
+}
+
+/// Handle window close and the quit action: ask for confirmation during an active run,
+/// otherwise shut down right away.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn quit_requests(
+    mut commands: Commands,
+    mut close_events: EventReader<WindowCloseRequested>,
+    action_state: Res<ActionState>,
+    board_query: Query<&BoardStats>,
+    fonts: Res<Fonts>,
+    mut time_scale: ResMut<GameTimeScale>,
+    mut shutdown_events: EventWriter<Shutdown>,
+    shutting_down: Option<Res<ShuttingDown>>,
+    menu_query: Query<(), With<Menu>>,
+    dialog_query: Query<(), With<QuitDialog>>,
+    state: Res<State<AppState>>,
+) {
+    // Going back with no menu open means leaving the game, except during a run where it pauses
+    let quit_action = action_state.just_pressed(Action::MenuCancel)
+        && menu_query.is_empty()
+        && state.0 != AppState::Playing;
+    let close_requested = close_events.iter().count() > 0;
+
+    if !(quit_action || close_requested) || shutting_down.is_some() || !dialog_query.is_empty() {
+        return;
+    }
+
+    let active_run = board_query
+        .iter()
+        .any(|stats| stats.game_over_duration.is_none() && stats.generated_blocks > 0);
+
+    if !active_run {
+        shutdown_events.send(Shutdown);
+        commands.insert_resource(ShuttingDown);
+        return;
+    }
+
+    time_scale.set(TimeScaleSource::QuitDialog, 0.0);
+
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            ..Default::default()
+        })
+        .insert(QuitDialog)
+        .insert(Menu {
+            focused: QUIT_DIALOG_KEEP_PLAYING_ITEM,
+            layer: QUIT_DIALOG_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Quit the current run?", 40.0, Color::WHITE));
+        parent
+            .spawn(fonts.text_bundle("Keep playing", 28.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: QUIT_DIALOG_KEEP_PLAYING_ITEM,
+            });
+        parent
+            .spawn(fonts.text_bundle("Quit", 28.0, Color::WHITE))
+            .insert(MenuItem {
+                menu,
+                index: QUIT_DIALOG_QUIT_ITEM,
+            });
+    });
+}
+
+pub(crate) fn quit_dialog(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    mut shutdown_events: EventWriter<Shutdown>,
+    mut time_scale: ResMut<GameTimeScale>,
+    dialog_query: Query<Entity, With<QuitDialog>>,
+) {
+    let mut close = cancelled_events
+        .iter()
+        .any(|event| dialog_query.contains(event.menu));
+
+    for event in confirmed_events.iter() {
+        if !dialog_query.contains(event.menu) {
+            continue;
+        }
+
+        if event.item == QUIT_DIALOG_QUIT_ITEM {
+            shutdown_events.send(Shutdown);
+            commands.insert_resource(ShuttingDown);
+        }
+        close = true;
+    }
+
+    if close {
+        for entity in dialog_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        time_scale.clear(TimeScaleSource::QuitDialog);
+    }
+}
+
+pub(crate) fn flush_on_shutdown(
+    mut shutdown_events: EventReader<Shutdown>,
+    profile: Res<Profile>,
+    settings: Res<Settings>,
+    gamepad_profiles: Res<GamepadProfiles>,
+) {
+    if shutdown_events.iter().count() > 0 {
+        profile.save();
+        settings.save();
+        gamepad_profiles.save();
+    }
+}
+
+pub(crate) fn exit_after_shutdown(
+    shutting_down: Option<Res<ShuttingDown>>,
+    mut frames_since_shutdown: Local<u32>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    if shutting_down.is_none() {
+        return;
+    }
+
+    // Wait one frame so that every `Shutdown` reader has run
+    *frames_since_shutdown += 1;
+    if *frames_since_shutdown > 1 {
+        exit_events.send(AppExit);
+    }
+}
+
+pub(crate) fn adjust_ui_scale(
+    mut commands: Commands,
+    action_state: Res<ActionState>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    let step = action_state.just_pressed(Action::UiScaleUp) as i32
+        - action_state.just_pressed(Action::UiScaleDown) as i32;
+
+    if step == 0 {
+        return;
+    }
+
+    settings.ui_scale =
+        (settings.ui_scale + step as f32 * UI_SCALE_STEP).clamp(UI_SCALE_MIN, UI_SCALE_MAX);
+    settings.save();
+
+    spawn_toast(
+        &mut commands,
+        &fonts,
+        &format!("UI scale {:.0}%", settings.ui_scale * 100.0),
+    );
+}
+
+/// Scale the UI by the setting and the size of the window, and lay out the HUD for narrow or
+/// wide windows. The camera fits itself to the window, see `camera_scaling_mode`.
+pub(crate) fn apply_ui_scale(
+    settings: Res<Settings>,
+    mut resized_events: EventReader<WindowResized>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut ui_scale: ResMut<UiScale>,
+    mut world_text_query: Query<&mut Transform, With<WorldHudText>>,
+    added_anchor_query: Query<(), Added<ResponsiveAnchor>>,
+    mut anchor_query: Query<(&ResponsiveAnchor, &mut Style)>,
+) {
+    let resized = resized_events.iter().count() > 0;
+    if !settings.is_changed() && !resized && added_anchor_query.is_empty() {
+        return;
+    }
+
+    let window_size = window_query
+        .get_single()
+        .ok()
+        .map(|window| Vec2::new(window.width(), window.height()))
+        .filter(|size| size.x > 0.0 && size.y > 0.0)
+        .unwrap_or(UI_REFERENCE_WINDOW_SIZE);
+
+    let window_scale = (window_size / UI_REFERENCE_WINDOW_SIZE)
+        .min_element()
+        .clamp(UI_WINDOW_SCALE_MIN, UI_WINDOW_SCALE_MAX);
+    ui_scale.scale = (settings.ui_scale * window_scale) as f64;
+
+    let narrow = window_size.x / window_size.y < NARROW_WINDOW_ASPECT;
+    for (anchor, mut style) in anchor_query.iter_mut() {
+        style.position = if narrow { anchor.narrow } else { anchor.wide };
+    }
+
+    let scale = WORLD_TEXT_SCALE * settings.ui_scale;
+    for mut transform in world_text_query.iter_mut() {
+        transform.scale = Vec3::new(scale, scale, 1.0);
+    }
+}
+
+/// HUD element positioned differently in narrow windows, where there is room above and below
+/// the boards rather than beside them.
+#[derive(Component)]
+pub(crate) struct ResponsiveAnchor {
+    pub(crate) wide: UiRect,
+    pub(crate) narrow: UiRect,
+}
+
+pub(crate) fn toggle_telemetry(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    input_map: Res<InputMap>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !input_map.just_pressed(&input, Action::Telemetry) {
+        return;
+    }
+
+    settings.show_telemetry = !settings.show_telemetry;
+    settings.save();
+
+    let state = if settings.show_telemetry { "on" } else { "off" };
+    spawn_toast(&mut commands, &fonts, &format!("Telemetry {state}"));
+}
+
+pub(crate) fn toggle_clean_feed(
+    mut commands: Commands,
+    action_state: Res<ActionState>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+) {
+    if !action_state.just_pressed(Action::CleanFeed) {
+        return;
+    }
+
+    settings.clean_feed = !settings.clean_feed;
+    settings.save();
+
+    let state = if settings.clean_feed { "on" } else { "off" };
+    spawn_toast(&mut commands, &fonts, &format!("Clean feed {state}"));
+}
+
+/// Spawn the vignette and the corner logo of the clean feed, hidden until it is turned on.
+pub(crate) fn setup_clean_feed_overlay(mut commands: Commands, fonts: Res<Fonts>) {
+    for step in 0..VIGNETTE_STEPS {
+        let width = Val::Px(VIGNETTE_STEP_WIDTH * (step + 1) as f32);
+        let edges = [
+            (
+                Size::new(Val::Percent(100.0), width),
+                UiRect {
+                    top: Val::Px(0.0),
+                    left: Val::Px(0.0),
+                    ..Default::default()
+                },
+            ),
+            (
+                Size::new(Val::Percent(100.0), width),
+                UiRect {
+                    bottom: Val::Px(0.0),
+                    left: Val::Px(0.0),
+                    ..Default::default()
+                },
+            ),
+            (
+                Size::new(width, Val::Percent(100.0)),
+                UiRect {
+                    top: Val::Px(0.0),
+                    left: Val::Px(0.0),
+                    ..Default::default()
+                },
+            ),
+            (
+                Size::new(width, Val::Percent(100.0)),
+                UiRect {
+                    top: Val::Px(0.0),
+                    right: Val::Px(0.0),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        for (size, position) in edges {
+            commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        size,
+                        position,
+                        position_type: PositionType::Absolute,
+                        ..Default::default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, VIGNETTE_STEP_ALPHA).into(),
+                    visibility: Visibility::Hidden,
+                    ..Default::default()
+                })
+                .insert(CleanFeedOverlay);
+        }
+    }
+
+    let mut logo = fonts.text_bundle("NEWTONIAN TETRIS", 20.0, Color::rgba(1.0, 1.0, 1.0, 0.5));
+    logo.style = Style {
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            bottom: Val::Px(16.0),
+            right: Val::Px(20.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    logo.visibility = Visibility::Hidden;
+    commands.spawn(logo).insert(CleanFeedOverlay);
+}
+
+pub(crate) fn apply_clean_feed(
+    settings: Res<Settings>,
+    mut overlay_query: Query<&mut Visibility, With<CleanFeedOverlay>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut visibility in overlay_query.iter_mut() {
+        *visibility = if settings.clean_feed {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+pub(crate) fn spawn_main_menu(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    online: Option<Res<OnlineSession>>,
+) {
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+            ..Default::default()
+        })
+        .insert(MainMenuScreen)
+        .insert(Menu::default())
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Newtonian Tetris", 48.0, Color::WHITE));
+        for (index, label) in [
+            (MAIN_MENU_NEW_GAME_ITEM, "New game"),
+            (MAIN_MENU_CUSTOM_GAME_ITEM, "Custom game"),
+            (MAIN_MENU_TOURNAMENT_ITEM, "Tournament"),
+            (MAIN_MENU_CONTROLS_ITEM, "Controls"),
+            (MAIN_MENU_SETTINGS_ITEM, "Settings"),
+            (MAIN_MENU_QUIT_ITEM, "Quit"),
+        ] {
+            parent
+                .spawn(fonts.text_bundle(label, 28.0, Color::WHITE))
+                .insert(MenuItem { menu, index });
+        }
+
+        // The lobby of online Versus, the ratings the next match is played for
+        if let Some(online) = &online {
+            parent
+                .spawn(fonts.text_bundle(&online.rating_text(&profile), 24.0, Color::GOLD))
+                .insert(OnlineRating);
+        }
+
+        if !profile.high_scores.is_empty() {
+            parent.spawn(fonts.text_bundle("High scores", 24.0, Color::GOLD));
+            parent.spawn(fonts.text_bundle(
+                &profile.high_score_lines().join("\n"),
+                18.0,
+                Color::WHITE,
+            ));
+        }
+    });
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn despawn_main_menu(
+    mut commands: Commands,
+    screen_query: Query<
+        Entity,
+        Or<(
+            With<MainMenuScreen>,
+            With<ControlsScreen>,
+            With<KeyBindingsScreen>,
+            With<SettingsScreen>,
+            With<CustomGameScreen>,
+            With<TournamentScreen>,
+        )>,
+    >,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<KeyCapture>();
+    commands.remove_resource::<CustomGameNameEntry>();
+    commands.remove_resource::<TournamentNameEntry>();
+}
+
+pub(crate) fn spawn_controls_screen(commands: &mut Commands, fonts: &Fonts, input_map: &InputMap) {
+    let mut lines: Vec<String> = Action::REBINDABLE
+        .iter()
+        .map(|action| format!("{}: {}", action.name(), input_map.describe(*action)))
+        .collect();
+    lines.push(
+        "Gamepad: move with the stick or D-pad, rotate with the shoulders or triggers, \
+        hard drop with North, hold with West"
+            .to_string(),
+    );
+    lines.push("Pause also with Escape".to_string());
+
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.95).into(),
+            ..Default::default()
+        })
+        .insert(ControlsScreen)
+        .insert(Menu {
+            focused: 0,
+            layer: CONTROLS_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Controls", 40.0, Color::WHITE));
+        parent.spawn(
+            fonts
+                .text_bundle(&lines.join("\n"), 20.0, Color::WHITE)
+                .with_text_alignment(TextAlignment::Center)
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(24.0)),
+                    ..Default::default()
+                }),
+        );
+        parent
+            .spawn(fonts.text_bundle("Back", 28.0, Color::WHITE))
+            .insert(MenuItem { menu, index: 0 });
+    });
+}
+
+pub(crate) fn spawn_key_bindings_screen(commands: &mut Commands, fonts: &Fonts) {
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.95).into(),
+            ..Default::default()
+        })
+        .insert(KeyBindingsScreen)
+        .insert(Menu {
+            focused: 0,
+            layer: KEY_BINDINGS_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Key bindings", 40.0, Color::WHITE));
+        // Filled in by `key_binding_labels`
+        for (index, action) in Action::REBINDABLE.into_iter().enumerate() {
+            parent
+                .spawn(fonts.text_bundle("", 20.0, Color::WHITE))
+                .insert(MenuItem { menu, index })
+                .insert(KeyBindingLabel { action });
+        }
+        for (index, label) in ["Reset to preset", "Back"].into_iter().enumerate() {
+            parent
+                .spawn(fonts.text_bundle(label, 28.0, Color::WHITE))
+                .insert(MenuItem {
+                    menu,
+                    index: Action::REBINDABLE.len() + index,
+                });
+        }
+    });
+}
+
+pub(crate) fn spawn_settings_screen(commands: &mut Commands, fonts: &Fonts) {
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.95).into(),
+            ..Default::default()
+        })
+        .insert(SettingsScreen)
+        .insert(Menu {
+            focused: 0,
+            layer: CONTROLS_LAYER,
+        })
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Settings", 40.0, Color::WHITE));
+        // Filled in by `settings_labels`
+        for (index, item) in SettingsItem::ALL.into_iter().enumerate() {
+            parent
+                .spawn(fonts.text_bundle("", 24.0, Color::WHITE))
+                .insert(MenuItem { menu, index })
+                .insert(SettingsLabel { item });
+        }
+        parent.spawn(fonts.text_bundle(
+            "Left/right or confirm to change a value",
+            16.0,
+            Color::WHITE,
+        ));
+    });
+}
+
+pub(crate) fn settings_labels(
+    settings: Res<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut label_query: Query<(&SettingsLabel, &mut Text)>,
+) {
+    for (label, mut text) in label_query.iter_mut() {
+        let value = match label.item.value(&settings, &piece_definitions) {
+            Some(value) => format!("{}: {value}", label.item.name()),
+            None => label.item.name().to_string(),
+        };
+
+        // Only the value, the color belongs to `menu_focus_highlight`
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+/// Values are changed with confirm, or stepped up and down with the movement keys.
+/// Changes are saved right away.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn settings_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    action_state: Res<ActionState>,
+    fonts: Res<Fonts>,
+    mut settings: ResMut<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    screen_query: Query<(Entity, &Menu), With<SettingsScreen>>,
+    key_bindings_query: Query<(), With<KeyBindingsScreen>>,
+) {
+    let Ok((screen_entity, menu)) = screen_query.get_single() else {
+        return;
+    };
+
+    let mut close = cancelled_events
+        .iter()
+        .any(|event| event.menu == screen_entity);
+
+    for event in confirmed_events.iter() {
+        if event.menu != screen_entity {
+            continue;
+        }
+
+        match SettingsItem::ALL[event.item] {
+            SettingsItem::KeyBindings => spawn_key_bindings_screen(&mut commands, &fonts),
+            SettingsItem::Back => close = true,
+            item => {
+                item.adjust(&mut settings, &piece_definitions, 1);
+                settings.save();
+            }
+        }
+    }
+
+    // Only while the key bindings screen isn't on top
+    let direction = action_state.just_pressed(Action::MoveRight) as i32
+        - action_state.just_pressed(Action::MoveLeft) as i32;
+    if direction != 0 && key_bindings_query.is_empty() {
+        SettingsItem::ALL[menu.focused].adjust(&mut settings, &piece_definitions, direction);
+        settings.save();
+    }
+
+    if close {
+        commands.entity(screen_entity).despawn_recursive();
+    }
+}
+
+pub(crate) fn key_binding_labels(
+    input_map: Res<InputMap>,
+    key_capture: Option<Res<KeyCapture>>,
+    mut label_query: Query<(&KeyBindingLabel, &mut Text)>,
+) {
+    for (label, mut text) in label_query.iter_mut() {
+        let keys = match &key_capture {
+            Some(capture) if capture.action == label.action => "press a key".to_string(),
+            _ => input_map.describe(label.action),
+        };
+        let value = format!("{}: {keys}", label.action.name());
+
+        // Only the value, the color belongs to `menu_focus_highlight`
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+pub(crate) fn key_bindings_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    fonts: Res<Fonts>,
+    profile: Res<Profile>,
+    mut input_map: ResMut<InputMap>,
+    screen_query: Query<Entity, With<KeyBindingsScreen>>,
+) {
+    let mut close = cancelled_events
+        .iter()
+        .any(|event| screen_query.contains(event.menu));
+
+    for event in confirmed_events.iter() {
+        if !screen_query.contains(event.menu) {
+            continue;
+        }
+
+        if let Some(action) = Action::REBINDABLE.get(event.item) {
+            commands.insert_resource(KeyCapture { action: *action });
+        } else if event.item == Action::REBINDABLE.len() {
+            *input_map = InputMap::from_preset(profile.control_preset);
+            input_map.save();
+            spawn_toast(
+                &mut commands,
+                &fonts,
+                &format!("Controls: {}", profile.control_preset.name()),
+            );
+        } else {
+            close = true;
+        }
+    }
+
+    if close {
+        for entity in screen_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn main_menu_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    fonts: Res<Fonts>,
+    input_map: Res<InputMap>,
+    mut settings: ResMut<Settings>,
+    piece_definitions: Res<PieceDefinitions>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut shutdown_events: EventWriter<Shutdown>,
+    mut run_started_events: EventWriter<RunStarted>,
+    mut board_query: Query<(
+        Entity,
+        &mut Board,
+        &mut ActivePieces,
+        &mut BoardStats,
+        &mut GameRng,
+    )>,
+    block_query: Query<(Entity, &Block)>,
+    main_menu_query: Query<(), With<MainMenuScreen>>,
+    controls_query: Query<Entity, With<ControlsScreen>>,
+) {
+    let mut close_controls = cancelled_events
+        .iter()
+        .any(|event| controls_query.contains(event.menu));
+
+    for event in confirmed_events.iter() {
+        if controls_query.contains(event.menu) {
+            close_controls = true;
+            continue;
+        }
+
+        if !main_menu_query.contains(event.menu) {
+            continue;
+        }
+
+        match event.item {
+            MAIN_MENU_NEW_GAME_ITEM => {
+                settings.practice = None;
+                for (board_entity, mut board, mut active_pieces, mut stats, mut rng) in
+                    board_query.iter_mut()
+                {
+                    // Started by the opponent online
+                    if board.remote {
+                        continue;
+                    }
+                    run_started_events.send(start_run(
+                        &mut commands,
+                        board_entity,
+                        &mut board,
+                        &mut active_pieces,
+                        &mut stats,
+                        &settings,
+                        &piece_definitions,
+                        &mut rng,
+                        block_query
+                            .iter()
+                            .filter(|(_, block)| block.board == board_entity)
+                            .map(|(entity, _)| entity),
+                    ));
+                }
+                next_state.set(AppState::Playing);
+            }
+            MAIN_MENU_CUSTOM_GAME_ITEM => {
+                spawn_custom_game_screen(&mut commands, &fonts, &settings)
+            }
+            MAIN_MENU_TOURNAMENT_ITEM => spawn_tournament_screen(&mut commands, &fonts),
+            MAIN_MENU_CONTROLS_ITEM => spawn_controls_screen(&mut commands, &fonts, &input_map),
+            MAIN_MENU_SETTINGS_ITEM => spawn_settings_screen(&mut commands, &fonts),
+            _ => {
+                shutdown_events.send(Shutdown);
+                commands.insert_resource(ShuttingDown);
+            }
+        }
+    }
+
+    if close_controls {
+        for entity in controls_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Going back or pressing pause during a run pauses it, pressing pause again resumes.
+pub(crate) fn pause_requests(
+    action_state: Res<ActionState>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    menu_query: Query<(), With<Menu>>,
+) {
+    match state.0 {
+        AppState::Playing => {
+            let requested = action_state.just_pressed(Action::MenuCancel)
+                || action_state.just_pressed(Action::Pause);
+            if requested && menu_query.is_empty() {
+                next_state.set(AppState::Paused);
+            }
+        }
+        AppState::Paused if action_state.just_pressed(Action::Pause) => {
+            next_state.set(AppState::Playing);
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn pause_menu_input(
+    mut commands: Commands,
+    mut confirmed_events: EventReader<MenuConfirmed>,
+    mut cancelled_events: EventReader<MenuCancelled>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut shutdown_events: EventWriter<Shutdown>,
+    screen_query: Query<(), With<PauseScreen>>,
+) {
+    if cancelled_events
+        .iter()
+        .any(|event| screen_query.contains(event.menu))
+    {
+        next_state.set(AppState::Playing);
+    }
+
+    for event in confirmed_events.iter() {
+        if !screen_query.contains(event.menu) {
+            continue;
+        }
+
+        match event.item {
+            PAUSE_RESUME_ITEM => next_state.set(AppState::Playing),
+            PAUSE_MAIN_MENU_ITEM => next_state.set(AppState::MainMenu),
+            _ => {
+                shutdown_events.send(Shutdown);
+                commands.insert_resource(ShuttingDown);
+            }
+        }
+    }
+}
+
+/// Overlay of a paused run. The physics is frozen through the time scale while paused.
+pub(crate) fn spawn_pause_screen(mut commands: Commands, fonts: Res<Fonts>) {
+    let menu = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..Default::default()
+        })
+        .insert(PauseScreen)
+        .insert(Menu::default())
+        .id();
+
+    commands.entity(menu).with_children(|parent| {
+        parent.spawn(fonts.text_bundle("Paused", 40.0, Color::WHITE));
+        for (index, label) in [
+            (PAUSE_RESUME_ITEM, "Resume"),
+            (PAUSE_MAIN_MENU_ITEM, "Main menu"),
+            (PAUSE_QUIT_ITEM, "Quit"),
+        ] {
+            parent
+                .spawn(fonts.text_bundle(label, 28.0, Color::WHITE))
+                .insert(MenuItem { menu, index });
+        }
+    });
+}
+
+pub(crate) fn despawn_pause_screen(
+    mut commands: Commands,
+    screen_query: Query<Entity, With<PauseScreen>>,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Show mass, net applied force and angular velocity of the piece being controlled.
+pub(crate) fn telemetry_hud(
+    settings: Res<Settings>,
+    fonts: Res<Fonts>,
+    piece_query: Query<&ActivePieces>,
+    block_query: Query<(&ReadMassProperties, &ExternalForce, &Velocity)>,
+    mut hud_query: Query<(&TelemetryHud, &mut Text, &mut Visibility)>,
+) {
+    for (hud, mut text, mut visibility) in hud_query.iter_mut() {
+        if !settings.show_telemetry || settings.clean_feed {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        }
+
+        // The first piece, the one controlled with the gamepad
+        let Some(active_piece) = piece_query
+            .get(hud.board)
+            .ok()
+            .and_then(|active_pieces| active_pieces.pieces.first())
+        else {
+            continue;
+        };
+
+        let mut mass = 0.0;
+        let mut force = Vec2::ZERO;
+        let mut angular_velocity = 0.0;
+        let mut n_blocks = 0;
+
+        for (mass_properties, external_force, velocity) in active_piece
+            .blocks
+            .iter()
+            .filter_map(|block_entity| block_query.get(*block_entity).ok())
+        {
+            mass += mass_properties.0.mass;
+            force += external_force.force;
+            angular_velocity += velocity.angvel;
+            n_blocks += 1;
+        }
+
+        // The blocks are joined, so they spin together
+        if n_blocks > 0 {
+            angular_velocity /= n_blocks as f32;
+        }
+
+        *visibility = Visibility::Inherited;
+        *text = fonts.text(
+            &format!(
+                "Mass {mass:.2}   Force {:.1}   Spin {angular_velocity:+.2} rad/s",
+                force.length()
+            ),
+            18.0,
+            Color::WHITE,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::test_world;
+
+    #[test]
+    fn narrow_windows_scale_the_ui_down_and_move_anchored_elements() {
+        let mut world = test_world();
+        world.insert_resource(Settings::default());
+        world.insert_resource(UiScale::default());
+        world.init_resource::<Events<WindowResized>>();
+
+        let mut window = Window::default();
+        window.resolution.set(640.0, 1000.0);
+        world.spawn((window, PrimaryWindow));
+
+        let wide = UiRect::top(Val::Px(16.0));
+        let narrow = UiRect::bottom(Val::Px(16.0));
+        let anchor = world
+            .spawn((
+                Style {
+                    position: wide,
+                    ..Default::default()
+                },
+                ResponsiveAnchor { wide, narrow },
+            ))
+            .id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(apply_ui_scale);
+        schedule.run(&mut world);
+
+        assert_eq!(world.resource::<UiScale>().scale, 0.5);
+        assert_eq!(world.get::<Style>(anchor).unwrap().position, narrow);
+    }
+
+    #[test]
+    fn the_built_in_font_config_names_only_bundled_fonts() {
+        let config = LocalizationFonts::built_in();
+        let fonts = config
+            .overrides
+            .values()
+            .flat_map(|font_override| [&font_override.latin, &font_override.cjk])
+            .chain([&config.cjk])
+            .flatten()
+            .chain([&config.latin]);
+
+        for font in fonts {
+            assert!(
+                std::path::Path::new("assets").join(font).exists(),
+                "{font} is not bundled"
+            );
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Versus mode: two players on boards side by side, each sending garbage rows to the other by
+//! clearing rows. Garbage rises from the bottom of the board, lifting everything on it. The boards
+//! are set up at launch, see `setup_game`.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+
+use crate::board::{Board, GameRng, RunStarted};
+use crate::modes::{GameMode, JUNK_COLOR};
+use crate::physics::{block_death_detection, GameTimeScale};
+use crate::scoring::{BoardStats, RowCleared};
+use crate::tetromino::{spawn_loose_block, tetromino_sleep_detection, Block};
+use crate::ui::{spawn_toast, Fonts};
+use crate::GameplaySet;
+
+/// Garbage rows and knockouts of Versus mode.
+pub struct VersusPlugin;
+
+impl Plugin for VersusPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            send_garbage
+                .after(tetromino_sleep_detection)
+                .in_set(GameplaySet),
+        )
+        .add_system(drop_garbage.after(send_garbage).in_set(GameplaySet))
+        .add_system(
+            versus_knockout
+                .after(block_death_detection)
+                .after(tetromino_sleep_detection)
+                .in_set(GameplaySet),
+        );
+    }
+}
+
+// Garbage rows sent for the number of rows cleared at once, the last entry for four or more
+pub(crate) const GARBAGE_ROWS: [usize; 5] = [0, 0, 1, 2, 4];
+// Scaled seconds before sent garbage drops onto the opponent's board, in which clearing rows
+// cancels it
+pub(crate) const GARBAGE_DELAY: f32 = 2.0;
+// Upward speed given to everything on a board as garbage rows push it up, the same for every
+// block so that stacks move as one
+pub(crate) const GARBAGE_LIFT_SPEED: f32 = 1.5;
+
+/// Gray block of a garbage row sent by the opponent in Versus mode.
+#[derive(Component)]
+pub(crate) struct Garbage;
+
+/// Garbage rows on their way to a board in Versus mode.
+#[derive(Component, Default)]
+pub(crate) struct GarbageQueue {
+    /// Scaled seconds left for each pending garbage row, soonest first
+    pub(crate) pending: Vec<f32>,
+}
+
+/// Send garbage rows to the opponent for rows cleared together, cancelling the garbage on its
+/// way to the sender first.
+pub(crate) fn send_garbage(
+    mut row_cleared_events: EventReader<RowCleared>,
+    mut board_query: Query<(Entity, &Board, &mut BoardStats, &mut GarbageQueue)>,
+) {
+    let mut cleared: BTreeMap<Entity, usize> = BTreeMap::new();
+    for event in row_cleared_events.iter() {
+        *cleared.entry(event.board).or_insert(0) += 1;
+    }
+
+    for (sender, n_rows) in cleared {
+        let Ok((_, board, mut stats, mut queue)) = board_query.get_mut(sender) else {
+            continue;
+        };
+        // The opponent online sends its own garbage, see `online`
+        if board.mode != GameMode::Versus || board.remote || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        let mut n_garbage = GARBAGE_ROWS[n_rows.min(GARBAGE_ROWS.len() - 1)];
+        let n_cancelled = n_garbage.min(queue.pending.len());
+        queue.pending.drain(..n_cancelled);
+        n_garbage -= n_cancelled;
+        stats.garbage_sent += n_garbage as i32;
+
+        let opponent = board_query
+            .iter_mut()
+            .find(|(entity, board, _, _)| *entity != sender && board.player.is_some());
+        if let Some((_, _, _, mut opponent_queue)) = opponent {
+            opponent_queue
+                .pending
+                .extend(vec![GARBAGE_DELAY; n_garbage]);
+        }
+    }
+}
+
+/// Push garbage rows in at the bottom of a board once their delay is up, each with a gap at
+/// random. Everything on the board is first moved up by the rows, so that nothing overlaps the
+/// garbage and stacks don't explode, then nudged up by an impulse. The garbage starts out asleep,
+/// as if settled.
+pub(crate) fn drop_garbage(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<GameTimeScale>,
+    mut run_started_events: EventReader<RunStarted>,
+    mut board_query: Query<(
+        Entity,
+        &Board,
+        &mut BoardStats,
+        &mut GarbageQueue,
+        &mut GameRng,
+    )>,
+    mut block_query: Query<(
+        &Block,
+        &mut Transform,
+        &ReadMassProperties,
+        &mut ExternalImpulse,
+    )>,
+) {
+    let delta = time_scale.delta_seconds(&time);
+
+    // A new run starts with no garbage on its way
+    for event in run_started_events.iter() {
+        if let Ok((_, _, _, mut queue, _)) = board_query.get_mut(event.board) {
+            queue.pending.clear();
+        }
+    }
+
+    for (board_entity, board, mut stats, mut queue, mut rng) in board_query.iter_mut() {
+        if board.mode != GameMode::Versus || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        for remaining in queue.pending.iter_mut() {
+            *remaining -= delta;
+        }
+
+        let n_ready = queue
+            .pending
+            .iter()
+            .filter(|remaining| **remaining <= 0.0)
+            .count();
+        queue.pending.retain(|remaining| *remaining > 0.0);
+
+        if n_ready == 0 {
+            continue;
+        }
+
+        // Moved by whole rows, blocks keep resting on each other and their joints stay intact
+        for (block, mut transform, mass_properties, mut impulse) in block_query.iter_mut() {
+            if block.board == board_entity {
+                transform.translation.y += n_ready as f32;
+                impulse.impulse += Vec2::Y * GARBAGE_LIFT_SPEED * mass_properties.0.mass;
+            }
+        }
+
+        let slots = board.slot_centers();
+
+        for row in 0..n_ready {
+            let gap = rng.gen_range(0..slots.len());
+
+            for (slot, x) in slots.iter().enumerate() {
+                // No floor to rise from between floors
+                let Some(floor_y) = board.floor_y_at(*x) else {
+                    continue;
+                };
+                if slot == gap {
+                    continue;
+                }
+
+                let position = Vec2::new(*x, floor_y + row as f32 + 0.5);
+                let garbage_entity =
+                    spawn_loose_block(&mut commands, board_entity, board, position, true, &mut rng);
+                commands.entity(garbage_entity).insert((
+                    Garbage,
+                    Sprite {
+                        color: JUNK_COLOR,
+                        custom_size: Some(Vec2::ONE),
+                        ..Default::default()
+                    },
+                ));
+            }
+
+            stats.garbage_received += 1;
+        }
+    }
+}
+
+/// Once a player's run is over in Versus mode, end the other player's run too as the winner.
+pub(crate) fn versus_knockout(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    mut board_query: Query<(&Board, &mut BoardStats)>,
+) {
+    let knocked_out = board_query
+        .iter()
+        .any(|(board, stats)| board.mode == GameMode::Versus && stats.game_over_duration.is_some());
+    if !knocked_out {
+        return;
+    }
+
+    for (board, mut stats) in board_query.iter_mut() {
+        if board.mode != GameMode::Versus || stats.game_over_duration.is_some() {
+            continue;
+        }
+
+        // The time scale is already slowed down by the run that ended
+        stats.game_over_duration = Some(0.0);
+
+        if let Some(player) = board.player {
+            spawn_toast(
+                &mut commands,
+                &fonts,
+                &format!("Player {} wins", player + 1),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::board::BoardLayout;
+    use crate::settings::test_world;
+    use crate::tetromino::TetrominoKind;
+
+    fn spawn_player(world: &mut World, player: usize) -> Entity {
+        let board = Board {
+            player: Some(player),
+            ..Board::new(BoardLayout::named("Standard"), GameMode::Versus, Vec2::ZERO)
+        };
+        world
+            .spawn((board, BoardStats::default(), GarbageQueue::default()))
+            .id()
+    }
+
+    fn clear_rows(world: &mut World, schedule: &mut Schedule, board: Entity, n_rows: usize) {
+        for _ in 0..n_rows {
+            world.send_event(RowCleared {
+                board,
+                blocks: 10,
+                chain: 1,
+            });
+        }
+        schedule.run(world);
+        world.resource_mut::<Events<RowCleared>>().update();
+    }
+
+    #[test]
+    fn clearing_rows_together_sends_garbage_after_cancelling_incoming_garbage() {
+        let mut world = test_world();
+        world.init_resource::<Events<RowCleared>>();
+        let first = spawn_player(&mut world, 0);
+        let second = spawn_player(&mut world, 1);
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(send_garbage);
+
+        // A single row sends nothing
+        clear_rows(&mut world, &mut schedule, first, 1);
+        assert!(world
+            .get::<GarbageQueue>(second)
+            .unwrap()
+            .pending
+            .is_empty());
+
+        clear_rows(&mut world, &mut schedule, first, 3);
+        assert_eq!(
+            world.get::<GarbageQueue>(second).unwrap().pending,
+            [GARBAGE_DELAY; 2]
+        );
+        assert_eq!(world.get::<BoardStats>(first).unwrap().garbage_sent, 2);
+
+        // Two rows would send one, which cancels one of the two on their way instead
+        clear_rows(&mut world, &mut schedule, second, 2);
+        assert_eq!(world.get::<GarbageQueue>(second).unwrap().pending.len(), 1);
+        assert!(world.get::<GarbageQueue>(first).unwrap().pending.is_empty());
+        assert_eq!(world.get::<BoardStats>(second).unwrap().garbage_sent, 0);
+    }
+
+    #[test]
+    fn boards_on_the_same_seed_drop_the_same_garbage_rows() {
+        let mut world = test_world();
+        world.init_resource::<Time>();
+        world.init_resource::<GameTimeScale>();
+        world.init_resource::<Events<RunStarted>>();
+        let boards = [spawn_player(&mut world, 0), spawn_player(&mut world, 1)];
+        for board in boards {
+            world.entity_mut(board).insert(GameRng::new(Some(7)));
+            world.get_mut::<GarbageQueue>(board).unwrap().pending = vec![0.0; 3];
+        }
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(drop_garbage);
+        schedule.run(&mut world);
+
+        let rows_of = |world: &mut World, board: Entity| {
+            let mut blocks: Vec<(i32, i32)> = world
+                .query_filtered::<(&Block, &Transform), With<Garbage>>()
+                .iter(world)
+                .filter(|(block, _)| block.board == board)
+                .map(|(_, transform)| {
+                    let position = transform.translation.truncate() * 2.0;
+                    (position.y as i32, position.x as i32)
+                })
+                .collect();
+            blocks.sort();
+            blocks
+        };
+        let first = rows_of(&mut world, boards[0]);
+        let n_slots = world.get::<Board>(boards[0]).unwrap().slot_centers().len();
+        assert_eq!(first.len(), 3 * (n_slots - 1));
+        assert_eq!(first, rows_of(&mut world, boards[1]));
+        assert_eq!(
+            world.get::<BoardStats>(boards[1]).unwrap().garbage_received,
+            3
+        );
+    }
+
+    #[test]
+    fn garbage_rises_from_the_floor_and_lifts_the_stack() {
+        let mut world = test_world();
+        world.init_resource::<Time>();
+        world.init_resource::<GameTimeScale>();
+        world.init_resource::<Events<RunStarted>>();
+        let board_entity = spawn_player(&mut world, 0);
+        world.entity_mut(board_entity).insert(GameRng::new(Some(7)));
+        world.get_mut::<GarbageQueue>(board_entity).unwrap().pending = vec![0.0; 2];
+
+        let floor_y = world.get::<Board>(board_entity).unwrap().floor_y();
+        let stacked = world
+            .spawn((
+                Block {
+                    board: board_entity,
+                    kind: TetrominoKind::O,
+                    size: 1.0,
+                },
+                Transform::from_xyz(0.0, floor_y + 0.5, 0.0),
+                ReadMassProperties(MassProperties {
+                    mass: 2.0,
+                    ..Default::default()
+                }),
+                ExternalImpulse::default(),
+            ))
+            .id();
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(drop_garbage);
+        schedule.run(&mut world);
+
+        assert_eq!(
+            world.get::<Transform>(stacked).unwrap().translation.y,
+            floor_y + 2.5
+        );
+        assert_eq!(
+            world.get::<ExternalImpulse>(stacked).unwrap().impulse,
+            Vec2::Y * GARBAGE_LIFT_SPEED * 2.0
+        );
+
+        let garbage: Vec<(f32, bool)> = world
+            .query_filtered::<(&Transform, &Sleeping), With<Garbage>>()
+            .iter(&world)
+            .map(|(transform, sleeping)| (transform.translation.y, sleeping.sleeping))
+            .collect();
+        assert!(!garbage.is_empty());
+        for (y, sleeping) in garbage {
+            assert!(y == floor_y + 0.5 || y == floor_y + 1.5, "{y}");
+            assert!(sleeping);
+        }
+    }
+}
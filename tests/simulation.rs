@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+
+use newtonian_tetris::headless::{self, BoardSummary, HeadlessPlugin};
+
+fn app() -> App {
+    let mut app = App::new();
+    app.add_plugin(HeadlessPlugin);
+    // Startup, and the run starting
+    app.update();
+    app.update();
+    app
+}
+
+fn summary(app: &mut App) -> BoardSummary {
+    headless::board_summaries(app)
+        .into_iter()
+        .next()
+        .expect("no board")
+}
+
+#[test]
+fn piece_spawns_when_run_starts() {
+    let mut app = app();
+
+    let summary = summary(&mut app);
+    assert_eq!(summary.generated_blocks, 4);
+    assert_eq!(summary.live_blocks, 4);
+    assert!(!summary.game_over);
+}
+
+#[test]
+fn settled_piece_is_followed_by_next() {
+    let mut app = app();
+
+    headless::run_for(&mut app, 20.0);
+
+    let summary = summary(&mut app);
+    assert!(summary.placed_blocks >= 4, "{summary:?}");
+    assert!(summary.generated_blocks > 4, "{summary:?}");
+}
+
+#[test]
+fn full_row_is_cleared() {
+    let mut app = app();
+    headless::fill_bottom_row(&mut app);
+
+    // The first piece settling on the row clears it
+    headless::run_for(&mut app, 20.0);
+
+    let summary = summary(&mut app);
+    assert!(summary.cleared_rows >= 1, "{summary:?}");
+    assert!(summary.score > 0, "{summary:?}");
+}
+
+#[test]
+fn piece_pushed_off_the_board_ends_the_run() {
+    let mut app = app();
+    app.world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::Right);
+
+    // The run restarts shortly after it's over, so watch every frame
+    let lost = (0..(20.0 / headless::TIMESTEP) as u32).any(|_| {
+        app.update();
+        let summary = summary(&mut app);
+        summary.lost_blocks > 0 && summary.game_over
+    });
+
+    assert!(lost);
+}
+
+#[test]
+fn runs_with_the_same_seed_play_out_the_same() {
+    let mut first = app();
+    let mut second = app();
+
+    headless::run_for(&mut first, 30.0);
+    headless::run_for(&mut second, 30.0);
+
+    assert_eq!(summary(&mut first), summary(&mut second));
+    assert_eq!(
+        headless::physics_checksum(&mut first),
+        headless::physics_checksum(&mut second)
+    );
+    assert!(summary(&mut first).placed_blocks > 0);
+}